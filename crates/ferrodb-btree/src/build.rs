@@ -0,0 +1,232 @@
+//! Building a tree bottom-up out of entries that are already sorted, which
+//! fills each leaf in turn from left to right, instead of finding the right
+//! leaf for every entry and splitting leaves in half as they fill up.
+//!
+//! Each level of the tree has the node that's being filled, and the one before
+//! it, which already has a page but isn't written until the next one is
+//! started. Holding it back is what lets the last node of a level even out
+//! with it, rather than be left with too few entries. Whenever a node is
+//! written, its separator and page go to the level above, which starts out
+//! with the node's page as its `left`. The root is whatever node a level ends
+//! up with when it only ever had the one, and is written to the root page.
+
+use std::cmp::Ordering;
+use std::ops::RangeInclusive;
+
+use ferrodb_fs::{PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_wal::Txn;
+
+use crate::node::{self, Entry, Node, NodeRef, HEADER_SIZE};
+use crate::{body_len, BTree, Error, ROOT_PAGE};
+
+/// How full [`BTree::build`] fills nodes when it's not told otherwise, as a
+/// percentage, which leaves some room for entries that are added later.
+pub const DEFAULT_FILL_FACTOR: u8 = 90;
+
+/// The fill factors that [`BTree::build`] takes. Any less than half full, and
+/// nodes would be merged with their siblings as soon as an entry is deleted.
+const FILL_FACTORS: RangeInclusive<u8> = 50..=100;
+
+/// One level of a tree that's being built.
+struct Level {
+    /// The node before `node`, with its page and the separator that the level
+    /// above needs for it, if it isn't the first node of the level.
+    held: Option<(PageIndex, Node, Option<Entry>)>,
+    node: Node,
+    /// The separator that the level above needs for `node`, if it isn't the
+    /// first node of the level.
+    separator: Option<Entry>,
+    /// The page of the last node of the level that was written, which a
+    /// leaf's `left` points at.
+    written: PageIndex,
+}
+
+impl Level {
+    fn new(level: u8) -> Level {
+        Level {
+            held: None,
+            node: Node {
+                level,
+                ..Node::default()
+            },
+            separator: None,
+            written: 0,
+        }
+    }
+}
+
+impl BTree {
+    /// Fill a new, empty tree with `entries`, which have to be sorted by key
+    /// and then by value, filling every node `fill_factor` percent full (from
+    /// 50 to 100). Entries that are the same as the one before them are left
+    /// out. Returns how many entries the tree ends up with.
+    ///
+    /// Nothing else may use the tree until this returns.
+    pub fn build(
+        &self,
+        txn: &Txn<'_>,
+        fill_factor: u8,
+        entries: impl IntoIterator<Item = Result<(Vec<u8>, Vec<u8>), Error>>,
+    ) -> Result<usize, Error> {
+        if !FILL_FACTORS.contains(&fill_factor) {
+            return Err(Error::FillFactor(fill_factor));
+        }
+
+        let root_len = NodeRef::new(&self.files.latest(self.file, ROOT_PAGE)?.read()[PAGE_HEADER_SIZE..]).len();
+        if self.files.page_count(self.file)? > ROOT_PAGE + 1 || root_len > 0 {
+            return Err(Error::NotEmpty);
+        }
+
+        let limit = HEADER_SIZE + (body_len() - HEADER_SIZE) * fill_factor as usize / 100;
+        let mut levels = vec![Level::new(0)];
+        let mut count = 0;
+
+        for entry in entries {
+            let (key, value) = entry?;
+
+            let len = key.len() + value.len();
+            if len > BTree::max_entry_len() {
+                return Err(Error::EntryTooLarge {
+                    len,
+                    max: BTree::max_entry_len(),
+                });
+            }
+
+            let leaves = &mut levels[0];
+            let entry = Entry::new(&key, &value);
+            if let Some(last) = leaves.node.entries.last() {
+                match (&*last.key, &*last.value).cmp(&(&*key, &*value)) {
+                    Ordering::Less => {},
+                    Ordering::Equal => continue,
+                    Ordering::Greater => panic!("Expected the entries of a tree being built to be sorted"),
+                }
+            }
+
+            // Another entry can make the prefix shorter, which makes every entry
+            // take up more room, so it's only known to fit once it's there.
+            count += 1;
+            leaves.node.entries.push(entry);
+            if leaves.node.entries.len() == 1 || leaves.node.size() <= limit {
+                continue;
+            }
+
+            let entry = leaves.node.entries.pop().unwrap();
+            let separator = node::separator(leaves.node.entries.last().unwrap(), &entry);
+            let next = Node {
+                entries: vec![entry],
+                ..Node::default()
+            };
+            self.start_node(txn, &mut levels, 0, next, separator, limit)?;
+        }
+
+        self.finish_levels(txn, &mut levels, limit)?;
+        Ok(count)
+    }
+
+    /// Add a node that was just written, at `page`, to the level at `depth`.
+    fn add_child(
+        &self,
+        txn: &Txn<'_>,
+        levels: &mut Vec<Level>,
+        depth: usize,
+        separator: Option<Entry>,
+        page: PageIndex,
+        limit: usize,
+    ) -> Result<(), Error> {
+        if depth == levels.len() {
+            levels.push(Level::new(depth as u8));
+        }
+
+        let level = &mut levels[depth];
+        let Some(separator) = separator
+            else {
+                level.node.left = page;
+                return Ok(());
+            };
+
+        level.node.entries.push(Entry {
+            child: page,
+            ..separator
+        });
+        if level.node.size() <= limit {
+            return Ok(());
+        }
+        let entry = level.node.entries.pop().unwrap();
+
+        // The separator goes up to the level above rather than into either
+        // node, and the child it points at is the new node's `left`.
+        let next = Node {
+            level: depth as u8,
+            left: page,
+            ..Node::default()
+        };
+        self.start_node(txn, levels, depth, next, Entry { child: 0, ..entry }, limit)
+    }
+
+    /// Hold back the node that the level at `depth` was filling, writing the
+    /// one that was held back before it, and start filling `next`.
+    fn start_node(
+        &self,
+        txn: &Txn<'_>,
+        levels: &mut Vec<Level>,
+        depth: usize,
+        next: Node,
+        separator: Entry,
+        limit: usize,
+    ) -> Result<(), Error> {
+        let page = self.allocate(txn)?;
+
+        let level = &mut levels[depth];
+        let node = std::mem::replace(&mut level.node, next);
+        let node_separator = level.separator.replace(separator);
+        let Some((held_page, mut held, held_separator)) = level.held.replace((page, node, node_separator))
+            else { return Ok(()); };
+
+        if held.is_leaf() {
+            held.left = level.written;
+            held.right = page;
+        }
+        self.write_new(txn, held_page, &held)?;
+        level.written = held_page;
+
+        self.add_child(txn, levels, depth + 1, held_separator, held_page, limit)
+    }
+
+    /// Write out what every level has left, from the leaves up, and the root.
+    fn finish_levels(&self, txn: &Txn<'_>, levels: &mut Vec<Level>, limit: usize) -> Result<(), Error> {
+        let mut depth = 0;
+
+        loop {
+            let level = &mut levels[depth];
+            let mut node = std::mem::take(&mut level.node);
+            // A level that only ever had the one node is the root.
+            let Some((held_page, mut held, held_separator)) = level.held.take()
+                else { return self.write_new(txn, ROOT_PAGE, &node); };
+
+            let mut separator = level.separator.take().unwrap();
+            if node.content() < node::min_content(body_len()) {
+                // The two of them never fit in one node, since `node` was only
+                // started once `held` was full, so even them out instead.
+                held.merge(separator, node);
+                let (right_separator, right) = held.split();
+                separator = right_separator;
+                node = right;
+            }
+
+            let page = self.allocate(txn)?;
+            if held.is_leaf() {
+                held.left = level.written;
+                held.right = page;
+                node.left = held_page;
+                node.right = 0;
+            }
+
+            self.write_new(txn, held_page, &held)?;
+            self.write_new(txn, page, &node)?;
+
+            self.add_child(txn, levels, depth + 1, held_separator, held_page, limit)?;
+            self.add_child(txn, levels, depth + 1, Some(separator), page, limit)?;
+            depth += 1;
+        }
+    }
+}
@@ -0,0 +1,245 @@
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::ops::{Bound, Range};
+
+use ferrodb_fs::{page_lsn, Lsn, PageIndex, PAGE_HEADER_SIZE};
+
+use crate::node::{NodeRef, Target};
+use crate::{BTree, Error, ROOT_PAGE};
+
+/// Walks over a range of a tree's entries from either end, a leaf at a time,
+/// without keeping anything latched in between.
+///
+/// Going forward, the cursor moves on to the next leaf through the one it last
+/// read, if that leaf hasn't changed since. Going backward, or if it has, it
+/// looks the next entry up from the root again.
+pub struct Cursor<'t> {
+    tree: &'t BTree,
+    /// Everything up to here has been returned from the front (or isn't in
+    /// the range).
+    front: Edge,
+    back: Edge,
+    /// Entries read ahead from the front, in order.
+    front_buf: VecDeque<(Vec<u8>, Vec<u8>)>,
+    /// Entries read ahead from the back, in order.
+    back_buf: VecDeque<(Vec<u8>, Vec<u8>)>,
+    /// The leaf that `front_buf` was read from, and its LSN at the time.
+    front_leaf: Option<(PageIndex, Lsn)>,
+    /// There's nothing left to read from the front, past what's in
+    /// `front_buf`.
+    front_done: bool,
+    back_done: bool,
+}
+
+/// One end of a cursor's range.
+enum Edge {
+    Unbounded,
+    Included(Vec<u8>),
+    Excluded(Vec<u8>),
+    /// The last entry that was returned from this end.
+    Entry(Vec<u8>, Vec<u8>),
+}
+
+impl Edge {
+    fn new(bound: Bound<&[u8]>) -> Edge {
+        match bound {
+            Bound::Unbounded => Edge::Unbounded,
+            Bound::Included(key) => Edge::Included(key.to_vec()),
+            Bound::Excluded(key) => Edge::Excluded(key.to_vec()),
+        }
+    }
+
+    /// Where this is as the front edge: every entry after it is left.
+    fn front_target(&self) -> Target<'_> {
+        match self {
+            Edge::Unbounded => Target::Start,
+            Edge::Included(key) => Target::KeyStart(key),
+            Edge::Excluded(key) => Target::KeyEnd(key),
+            Edge::Entry(key, value) => Target::Entry(key, value),
+        }
+    }
+
+    /// Where this is as the back edge: every entry before it is left.
+    fn back_target(&self) -> Target<'_> {
+        match self {
+            Edge::Unbounded => Target::End,
+            Edge::Included(key) => Target::KeyEnd(key),
+            Edge::Excluded(key) => Target::KeyStart(key),
+            Edge::Entry(key, value) => Target::Entry(key, value),
+        }
+    }
+}
+
+/// What a cursor took out of a leaf.
+struct LeafRead {
+    page: PageIndex,
+    lsn: Lsn,
+    left: PageIndex,
+    right: PageIndex,
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl LeafRead {
+    fn new(page: PageIndex, buf: &[u8], range: impl FnOnce(&NodeRef<'_>) -> Range<usize>) -> LeafRead {
+        let node = NodeRef::new(&buf[PAGE_HEADER_SIZE..]);
+
+        LeafRead {
+            page,
+            lsn: page_lsn(buf),
+            left: node.left(),
+            right: node.right(),
+            entries: range(&node)
+                .map(|index| {
+                    let (key, value) = node.entry(index);
+                    (key, value.to_vec())
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<'t> Cursor<'t> {
+    pub(crate) fn new(tree: &'t BTree, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Cursor<'t> {
+        Cursor {
+            tree,
+            front: Edge::new(start),
+            back: Edge::new(end),
+            front_buf: VecDeque::new(),
+            back_buf: VecDeque::new(),
+            front_leaf: None,
+            front_done: false,
+            back_done: false,
+        }
+    }
+
+    /// The two ends met, so there's nothing left.
+    fn finish(&mut self) {
+        self.front_buf.clear();
+        self.back_buf.clear();
+        self.front_done = true;
+        self.back_done = true;
+    }
+
+    fn fill_front(&mut self) -> Result<(), Error> {
+        let target = self.front.front_target();
+
+        let leaf = match self.front_leaf {
+            Some((page, lsn)) => self.next_leaf(page, lsn, target)?,
+            None => None,
+        };
+        let leaf = match leaf {
+            Some(leaf) => leaf,
+            None => self.tree.with_leaf(ROOT_PAGE, None, target, |page, buf| {
+                LeafRead::new(page, buf, |node| node.up_to(target)..node.len())
+            })?,
+        };
+
+        self.front_buf.extend(leaf.entries);
+        self.front_leaf = Some((leaf.page, leaf.lsn));
+        self.front_done = leaf.right == 0;
+        Ok(())
+    }
+
+    /// Read the leaf after `page`, if `page` is still at `lsn`, latching it
+    /// before letting go of `page` so that it can't change in between.
+    fn next_leaf(&self, page: PageIndex, lsn: Lsn, target: Target<'_>) -> Result<Option<LeafRead>, Error> {
+        let files = &self.tree.files;
+
+        let page_ref = files.latest(self.tree.file, page)?;
+        let buf = page_ref.read();
+        if page_lsn(&buf) != lsn {
+            return Ok(None);
+        }
+
+        let right = NodeRef::new(&buf[PAGE_HEADER_SIZE..]).right();
+        let right_ref = files.latest(self.tree.file, right)?;
+        let right_buf = right_ref.read();
+        drop(buf);
+
+        Ok(Some(LeafRead::new(right, &right_buf, |node| node.up_to(target)..node.len())))
+    }
+
+    fn fill_back(&mut self) -> Result<(), Error> {
+        let target = self.back.back_target();
+        let files = &self.tree.files;
+
+        let read = |page, buf: &[u8]| LeafRead::new(page, buf, |node| 0..node.before(target));
+        let mut leaf = self.tree.with_leaf(ROOT_PAGE, None, target, read)?;
+
+        while leaf.entries.is_empty() {
+            if leaf.left == 0 {
+                self.back_done = true;
+                return Ok(());
+            }
+
+            // Latching leaves from right to left could deadlock with everyone
+            // going the other way, so let go of this one first, and then make
+            // sure the one to its left still is.
+            let left_ref = files.latest(self.tree.file, leaf.left)?;
+            let buf = left_ref.read();
+            let node = NodeRef::new(&buf[PAGE_HEADER_SIZE..]);
+
+            leaf = if !node.is_free() && node.is_leaf() && node.right() == leaf.page {
+                read(leaf.left, &buf)
+            } else {
+                drop(buf);
+                self.tree.with_leaf(ROOT_PAGE, None, target, read)?
+            };
+        }
+
+        self.back_buf.extend(leaf.entries);
+        Ok(())
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.front_buf.pop_front() {
+                if self.back.back_target().cmp_entry(&key, &value) != Ordering::Less {
+                    self.finish();
+                    return None;
+                }
+
+                self.front = Edge::Entry(key.clone(), value.clone());
+                return Some(Ok((key, value)));
+            }
+
+            if self.front_done {
+                return None;
+            }
+
+            if let Err(e) = self.fill_front() {
+                self.front_done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+impl DoubleEndedIterator for Cursor<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.back_buf.pop_back() {
+                if self.front.front_target().cmp_entry(&key, &value) != Ordering::Greater {
+                    self.finish();
+                    return None;
+                }
+
+                self.back = Edge::Entry(key.clone(), value.clone());
+                return Some(Ok((key, value)));
+            }
+
+            if self.back_done {
+                return None;
+            }
+
+            if let Err(e) = self.fill_back() {
+                self.back_done = true;
+                return Some(Err(e));
+            }
+        }
+    }
+}
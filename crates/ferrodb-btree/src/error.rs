@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Fs(#[from] ferrodb_fs::Error),
+    #[error(transparent)]
+    Wal(#[from] ferrodb_wal::Error),
+    #[error("Index entry is {len} bytes, but an entry can be at most {max} bytes")]
+    EntryTooLarge { len: usize, max: usize },
+    #[error("Fill factor is {0}%, but it has to be from 50% to 100%")]
+    FillFactor(u8),
+    #[error("Only a new, empty tree can be built")]
+    NotEmpty,
+}
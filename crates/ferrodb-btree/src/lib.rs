@@ -0,0 +1,574 @@
+//! Indexes stored as B+Trees: entries of a key and a value, kept in order in
+//! the leaves, with internal nodes above them to find the right leaf.
+//!
+//! Page 1 of the file holds the head of the list of free pages, and page 2 is
+//! always the root. Every change goes through a [`Txn`], like for heaps, once
+//! [`BTree::register_undo`] has set up how to roll them back.
+//!
+//! Concurrent readers and writers latch their way down the tree by crabbing:
+//! a child is latched before its parent is let go. A change to an entry does
+//! that with read latches, keeping the root latched, and only latches its leaf
+//! for writing. Rolling it back changes the entry back, in whichever leaf it's
+//! in by then. If the leaf needs splitting or merging first or after, that's
+//! done with write latches on the whole way down, as a nested top action that
+//! stays done even if the transaction rolls back. Siblings are always latched
+//! from left to right.
+//!
+//! A new tree can also be built bottom-up out of entries that are already
+//! sorted, by [`BTree::build`], and a [`Sorter`] sorts entries that don't fit
+//! in memory for it.
+
+mod build;
+mod cursor;
+mod error;
+mod node;
+mod sort;
+mod undo;
+
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use ferrodb_fs::{FileId, FileKind, FileManager, PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_page::{page_size, PageReadGuard};
+use ferrodb_wal::Txn;
+
+pub use self::build::DEFAULT_FILL_FACTOR;
+pub use self::cursor::Cursor;
+pub use self::error::Error;
+use self::node::{Entry, Node, NodeRef, Target};
+pub use self::sort::{Sorted, Sorter};
+
+/// Holds the first page of the free list, as a `u64` at the start of its body,
+/// or 0 if there are no free pages.
+const META_PAGE: PageIndex = 1;
+/// The root never moves, so that nothing has to point at it.
+const ROOT_PAGE: PageIndex = 2;
+
+pub struct BTree {
+    files: Arc<FileManager>,
+    file: FileId,
+}
+
+#[derive(Copy, Clone)]
+enum Op<'a> {
+    Insert(&'a [u8], &'a [u8]),
+    Delete(&'a [u8], &'a [u8]),
+}
+
+impl Op<'_> {
+    fn target(&self) -> Target<'_> {
+        match *self {
+            Op::Insert(key, value) | Op::Delete(key, value) => Target::Entry(key, value),
+        }
+    }
+
+    /// The change that undoes this one.
+    fn inverse(&self) -> Op<'_> {
+        match *self {
+            Op::Insert(key, value) => Op::Delete(key, value),
+            Op::Delete(key, value) => Op::Insert(key, value),
+        }
+    }
+
+    /// Apply the change to the leaf it belongs in, returning whether that
+    /// changed anything.
+    fn apply(&self, leaf: &mut Node) -> bool {
+        match *self {
+            Op::Insert(key, value) => match leaf.find(key, value) {
+                Ok(_) => false,
+                Err(index) => {
+                    leaf.entries.insert(index, Entry::new(key, value));
+                    true
+                },
+            },
+            Op::Delete(key, value) => match leaf.find(key, value) {
+                Ok(index) => {
+                    leaf.entries.remove(index);
+                    true
+                },
+                Err(_) => false,
+            },
+        }
+    }
+}
+
+/// How far a change got on the way down to its leaf.
+enum Outcome {
+    /// It was made, returning whether it changed anything.
+    Done(bool),
+    /// It was made, but left its leaf too empty.
+    Underflow,
+    /// There's no room for it in its leaf.
+    Overflow,
+    /// Its leaf is the root, which it takes a write latch on the root to
+    /// change.
+    Root,
+}
+
+/// What a change to a node left for its parent to deal with.
+enum Fixup {
+    None,
+    /// The node was split, and the parent needs this separator for the new
+    /// node.
+    Split(Entry),
+    /// The node got too empty, and needs to be merged with or evened out
+    /// with a sibling.
+    Underflow,
+}
+
+impl BTree {
+    pub fn open(files: Arc<FileManager>, name: &str) -> Result<BTree, Error> {
+        let file = files.open(name, FileKind::BTree)?;
+
+        // An all-zero meta page and root are an empty tree, so a new tree only
+        // needs them to exist.
+        while files.page_count(file)? <= ROOT_PAGE {
+            files.allocate(file)?;
+        }
+
+        Ok(BTree { files, file })
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    /// The longest key and value, together, that an entry can have.
+    pub fn max_entry_len() -> usize {
+        node::max_entry_len(body_len())
+    }
+
+    /// Add an entry, returning whether it wasn't already there. Any number of
+    /// entries can have the same key, as long as their values differ.
+    pub fn insert(&self, txn: &Txn<'_>, key: &[u8], value: &[u8]) -> Result<bool, Error> {
+        let len = key.len() + value.len();
+        if len > BTree::max_entry_len() {
+            return Err(Error::EntryTooLarge {
+                len,
+                max: BTree::max_entry_len(),
+            });
+        }
+
+        self.modify(txn, Op::Insert(key, value))
+    }
+
+    /// Remove an entry, returning whether it was there.
+    pub fn delete(&self, txn: &Txn<'_>, key: &[u8], value: &[u8]) -> Result<bool, Error> {
+        self.modify(txn, Op::Delete(key, value))
+    }
+
+    /// The values of every entry with this key, in order.
+    pub fn get(&self, key: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        self.range((Bound::Included(key), Bound::Included(key)))
+            .map(|entry| entry.map(|(_, value)| value))
+            .collect()
+    }
+
+    /// Every entry with a key in `range`, from either end.
+    pub fn range(&self, range: impl RangeBounds<[u8]>) -> Cursor<'_> {
+        Cursor::new(self, range.start_bound(), range.end_bound())
+    }
+
+    /// Every entry, from either end.
+    pub fn iter(&self) -> Cursor<'_> {
+        self.range(..)
+    }
+
+    fn modify(&self, txn: &Txn<'_>, op: Op<'_>) -> Result<bool, Error> {
+        loop {
+            match self.modify_leaf(txn, op)? {
+                Outcome::Done(changed) => return Ok(changed),
+                Outcome::Underflow => {
+                    self.restructure(txn, op)?;
+                    return Ok(true);
+                },
+                Outcome::Overflow => self.restructure(txn, op)?,
+                Outcome::Root =>
+                    if let Some(changed) = self.modify_root(txn, op)? {
+                        return Ok(changed);
+                    },
+            }
+        }
+    }
+
+    /// Crab down to the leaf that `op` belongs in with read latches, and apply
+    /// it there if there's room for it.
+    ///
+    /// The root stays latched until this is done, so that no split or merge
+    /// can be partway done while anything else changes the tree.
+    fn modify_leaf(&self, txn: &Txn<'_>, op: Op<'_>) -> Result<Outcome, Error> {
+        let root_ref = self.files.latest(self.file, ROOT_PAGE)?;
+        let root = root_ref.read();
+
+        // There's no upgrading the root's read latch to a write latch.
+        let node = NodeRef::new(&root[PAGE_HEADER_SIZE..]);
+        if node.is_leaf() {
+            return Ok(Outcome::Root);
+        }
+
+        let child = node.child(node.child_index(op.target()));
+        self.modify_below(txn, child, node.level() == 1, None, op)
+    }
+
+    /// The rest of [`BTree::modify_leaf`], from `page` down, where `parent` is
+    /// the latch on the node above it, if it's not the root.
+    fn modify_below(
+        &self,
+        txn: &Txn<'_>,
+        page: PageIndex,
+        is_leaf: bool,
+        parent: Option<PageReadGuard<'_>>,
+        op: Op<'_>,
+    ) -> Result<Outcome, Error> {
+        if !is_leaf {
+            let page_ref = self.files.latest(self.file, page)?;
+            let buf = page_ref.read();
+            drop(parent);
+
+            let node = NodeRef::new(&buf[PAGE_HEADER_SIZE..]);
+            let child = node.child(node.child_index(op.target()));
+            return self.modify_below(txn, child, node.level() == 1, Some(buf), op);
+        }
+
+        let leaf_ref = self.files.dirty(self.file, page)?;
+        let mut leaf_buf = leaf_ref.write();
+        drop(parent);
+
+        let mut leaf = Node::read(&leaf_buf[PAGE_HEADER_SIZE..]);
+        if !op.apply(&mut leaf) {
+            return Ok(Outcome::Done(false));
+        }
+
+        if leaf.size() > body_len() {
+            return Ok(Outcome::Overflow);
+        }
+
+        self.write_entry(txn, page, &mut leaf_buf, &leaf, op)?;
+        if leaf.content() < node::min_content(body_len()) {
+            Ok(Outcome::Underflow)
+        } else {
+            Ok(Outcome::Done(true))
+        }
+    }
+
+    /// Apply `op` to the root, if it's a leaf with room for it. Returns `None`
+    /// if it isn't, after splitting it if that's why.
+    fn modify_root(&self, txn: &Txn<'_>, op: Op<'_>) -> Result<Option<bool>, Error> {
+        let root_ref = self.files.dirty(self.file, ROOT_PAGE)?;
+        let mut buf = root_ref.write();
+
+        let mut root = Node::read(&buf[PAGE_HEADER_SIZE..]);
+        if !root.is_leaf() {
+            return Ok(None);
+        }
+
+        if !op.apply(&mut root) {
+            return Ok(Some(false));
+        }
+
+        if root.size() > body_len() {
+            let before = Node::read(&buf[PAGE_HEADER_SIZE..]);
+            txn.nested_top_action(|| self.split(txn, ROOT_PAGE, &mut buf, before))?;
+            return Ok(None);
+        }
+
+        self.write_entry(txn, ROOT_PAGE, &mut buf, &root, op)?;
+        Ok(Some(true))
+    }
+
+    /// Make the splits or merges that the leaf `op` belongs in needs, without
+    /// making `op` itself: split it if `op` doesn't fit in it, or merge it with
+    /// or even it out with a sibling if it's too empty.
+    ///
+    /// It's done as a nested top action, which stays done even if `txn` rolls
+    /// back, since other transactions can go on to change the same nodes.
+    /// Rolling back `op` itself finds its entry wherever it's ended up. The
+    /// root stays write-latched until the action's done, so that nothing else
+    /// changes the tree in the meantime.
+    fn restructure(&self, txn: &Txn<'_>, op: Op<'_>) -> Result<(), Error> {
+        let root_ref = self.files.dirty(self.file, ROOT_PAGE)?;
+        let mut buf = root_ref.write();
+
+        txn.nested_top_action(|| self.restructure_node(txn, ROOT_PAGE, &mut buf, op))?;
+        Ok(())
+    }
+
+    /// Like [`BTree::restructure`], under `page`, which is write-latched as
+    /// `buf`, holding write latches the whole way down so that any splits and
+    /// merges can make their way back up.
+    fn restructure_node(
+        &self,
+        txn: &Txn<'_>,
+        page: PageIndex,
+        buf: &mut [u8],
+        op: Op<'_>,
+    ) -> Result<Fixup, Error> {
+        let mut node = Node::read(&buf[PAGE_HEADER_SIZE..]);
+
+        if node.is_leaf() {
+            let mut changed = node.clone();
+            op.apply(&mut changed);
+
+            return if changed.size() > body_len() {
+                self.split(txn, page, buf, node)
+            } else if page != ROOT_PAGE && node.content() < node::min_content(body_len()) {
+                Ok(Fixup::Underflow)
+            } else {
+                Ok(Fixup::None)
+            };
+        }
+
+        let index = NodeRef::new(&buf[PAGE_HEADER_SIZE..]).child_index(op.target());
+        let child = node.child(index);
+        let fixup = {
+            let child_ref = self.files.dirty(self.file, child)?;
+            let mut child_buf = child_ref.write();
+            self.restructure_node(txn, child, &mut child_buf, op)?
+        };
+
+        match fixup {
+            Fixup::None => return Ok(Fixup::None),
+            Fixup::Split(separator) => node.entries.insert(index, separator),
+            Fixup::Underflow => self.rebalance(txn, &mut node, index)?,
+        }
+
+        self.fix(txn, page, buf, node)
+    }
+
+    /// Write back a node that was just changed, splitting it first if it's
+    /// gotten too big for its page.
+    fn fix(&self, txn: &Txn<'_>, page: PageIndex, buf: &mut [u8], node: Node) -> Result<Fixup, Error> {
+        if node.size() > body_len() {
+            return self.split(txn, page, buf, node);
+        }
+
+        if page == ROOT_PAGE {
+            self.write_root(txn, buf, node)?;
+            return Ok(Fixup::None);
+        }
+
+        self.write_node(txn, page, buf, &node)?;
+
+        if node.content() < node::min_content(body_len()) {
+            Ok(Fixup::Underflow)
+        } else {
+            Ok(Fixup::None)
+        }
+    }
+
+    /// Move the second half of a node out to a new page, returning the
+    /// separator its parent needs for it. The root has to stay where it is, so
+    /// it grows instead, by moving both of its halves out to new pages.
+    fn split(&self, txn: &Txn<'_>, page: PageIndex, buf: &mut [u8], mut node: Node) -> Result<Fixup, Error> {
+        let (separator, mut right) = node.split();
+
+        if page == ROOT_PAGE {
+            let left_page = self.allocate(txn)?;
+            let right_page = self.allocate(txn)?;
+
+            if node.is_leaf() {
+                node.right = right_page;
+                right.left = left_page;
+            }
+
+            self.write_new(txn, left_page, &node)?;
+            self.write_new(txn, right_page, &right)?;
+
+            let root = Node {
+                level: node.level + 1,
+                left: left_page,
+                right: 0,
+                entries: vec![Entry {
+                    child: right_page,
+                    ..separator
+                }],
+            };
+            self.write_node(txn, ROOT_PAGE, buf, &root)?;
+            return Ok(Fixup::None);
+        }
+
+        let right_page = self.allocate(txn)?;
+
+        if node.is_leaf() {
+            right.left = page;
+            right.right = node.right;
+            node.right = right_page;
+
+            if right.right != 0 {
+                self.set_left(txn, right.right, right_page)?;
+            }
+        }
+
+        self.write_new(txn, right_page, &right)?;
+        self.write_node(txn, page, buf, &node)?;
+
+        Ok(Fixup::Split(Entry {
+            child: right_page,
+            ..separator
+        }))
+    }
+
+    /// Write back the root, which shrinks by taking over its only child once
+    /// it's down to one.
+    fn write_root(&self, txn: &Txn<'_>, buf: &mut [u8], mut node: Node) -> Result<(), Error> {
+        if !node.is_leaf() && node.entries.is_empty() {
+            let child = node.left;
+            let child_ref = self.files.dirty(self.file, child)?;
+            let mut child_buf = child_ref.write();
+
+            node = Node::read(&child_buf[PAGE_HEADER_SIZE..]);
+            self.free(txn, child, &mut child_buf)?;
+        }
+
+        self.write_node(txn, ROOT_PAGE, buf, &node)
+    }
+
+    /// The child at `index` of `parent` has gotten too empty, so merge it with
+    /// a sibling, or if they don't fit in one page together, split what they
+    /// have evenly between them.
+    fn rebalance(&self, txn: &Txn<'_>, parent: &mut Node, index: usize) -> Result<(), Error> {
+        if parent.entries.is_empty() {
+            return Ok(());
+        }
+
+        let left_index = index.saturating_sub(1);
+        let left_page = parent.child(left_index);
+        let right_page = parent.child(left_index + 1);
+
+        let left_ref = self.files.dirty(self.file, left_page)?;
+        let mut left_buf = left_ref.write();
+        let right_ref = self.files.dirty(self.file, right_page)?;
+        let mut right_buf = right_ref.write();
+
+        let mut left = Node::read(&left_buf[PAGE_HEADER_SIZE..]);
+        let right = Node::read(&right_buf[PAGE_HEADER_SIZE..]);
+        let separator = parent.entries.remove(left_index);
+        left.merge(separator, right);
+
+        if left.size() <= body_len() {
+            if left.is_leaf() && left.right != 0 {
+                self.set_left(txn, left.right, left_page)?;
+            }
+
+            self.write_node(txn, left_page, &mut left_buf, &left)?;
+            self.free(txn, right_page, &mut right_buf)?;
+            return Ok(());
+        }
+
+        let (separator, mut right) = left.split();
+        if left.is_leaf() {
+            right.left = left_page;
+            right.right = left.right;
+            left.right = right_page;
+        }
+
+        self.write_node(txn, left_page, &mut left_buf, &left)?;
+        self.write_node(txn, right_page, &mut right_buf, &right)?;
+        parent.entries.insert(left_index, Entry {
+            child: right_page,
+            ..separator
+        });
+
+        Ok(())
+    }
+
+    /// Crab down from `page` to the leaf that `target` is in with read latches,
+    /// and call `f` with that leaf's page number and whole page, still latched.
+    fn with_leaf<T>(
+        &self,
+        page: PageIndex,
+        parent: Option<PageReadGuard<'_>>,
+        target: Target<'_>,
+        f: impl FnOnce(PageIndex, &[u8]) -> T,
+    ) -> Result<T, Error> {
+        let page_ref = self.files.latest(self.file, page)?;
+        let buf = page_ref.read();
+        drop(parent);
+
+        let node = NodeRef::new(&buf[PAGE_HEADER_SIZE..]);
+        if node.is_leaf() {
+            return Ok(f(page, &buf));
+        }
+
+        let child = node.child(node.child_index(target));
+        self.with_leaf(child, Some(buf), target, f)
+    }
+
+    /// Take a page off the free list, or add one to the end of the file if
+    /// there aren't any.
+    fn allocate(&self, txn: &Txn<'_>) -> Result<PageIndex, Error> {
+        let meta_ref = self.files.dirty(self.file, META_PAGE)?;
+        let mut meta = meta_ref.write();
+
+        let head = read_page_index(&meta[PAGE_HEADER_SIZE..]);
+        if head == 0 {
+            return Ok(self.files.allocate(self.file)?);
+        }
+
+        let next = NodeRef::new(&self.files.latest(self.file, head)?.read()[PAGE_HEADER_SIZE..]).right();
+        txn.modify_latched(&self.files, self.file, META_PAGE, &mut meta, |body| {
+            write_page_index(body, next)
+        })?;
+
+        Ok(head)
+    }
+
+    /// Put a page that nothing points at anymore on the free list.
+    fn free(&self, txn: &Txn<'_>, page: PageIndex, buf: &mut [u8]) -> Result<(), Error> {
+        let meta_ref = self.files.dirty(self.file, META_PAGE)?;
+        let mut meta = meta_ref.write();
+
+        let head = read_page_index(&meta[PAGE_HEADER_SIZE..]);
+        txn.modify_latched(&self.files, self.file, page, buf, |body| Node::write_free(body, head))?;
+        txn.modify_latched(&self.files, self.file, META_PAGE, &mut meta, |body| {
+            write_page_index(body, page)
+        })?;
+
+        Ok(())
+    }
+
+    fn set_left(&self, txn: &Txn<'_>, page: PageIndex, left: PageIndex) -> Result<(), Error> {
+        txn.modify(&self.files, self.file, page, |body| Node::write_left(body, left))?;
+        Ok(())
+    }
+
+    /// Write a node to a page that nobody else can reach yet.
+    fn write_new(&self, txn: &Txn<'_>, page: PageIndex, node: &Node) -> Result<(), Error> {
+        txn.modify(&self.files, self.file, page, |body| node.write(body))?;
+        Ok(())
+    }
+
+    /// Write back a leaf that `op` was just applied to, so that rolling back
+    /// only undoes `op`, wherever its entry's ended up by then.
+    fn write_entry(
+        &self,
+        txn: &Txn<'_>,
+        page: PageIndex,
+        buf: &mut [u8],
+        leaf: &Node,
+        op: Op<'_>,
+    ) -> Result<(), Error> {
+        txn.modify_logical(&self.files, self.file, page, buf, |body| {
+            leaf.write(body);
+            ((), op.inverse().encode())
+        })?;
+        Ok(())
+    }
+
+    fn write_node(&self, txn: &Txn<'_>, page: PageIndex, buf: &mut [u8], node: &Node) -> Result<(), Error> {
+        txn.modify_latched(&self.files, self.file, page, buf, |body| node.write(body))?;
+        Ok(())
+    }
+}
+
+fn body_len() -> usize {
+    page_size() - PAGE_HEADER_SIZE
+}
+
+fn read_page_index(body: &[u8]) -> PageIndex {
+    u64::from_le_bytes(body[..8].try_into().unwrap()) as PageIndex
+}
+
+fn write_page_index(body: &mut [u8], page: PageIndex) {
+    body[..8].copy_from_slice(&(page as u64).to_le_bytes());
+}
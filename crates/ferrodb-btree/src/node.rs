@@ -0,0 +1,546 @@
+//! The body of a tree page (everything past the page header) is a node:
+//!
+//! ```text
+//! [level: u8][flags: u8][entry count: u16][prefix len: u16][unused: u16]
+//! [left: u64][right: u64]
+//! [cell offset: u16] * entry count
+//! ...free space...
+//! cells, packed against the prefix, the first entry's last
+//! the prefix, at the end of the page
+//! ```
+//!
+//! Every key in a node starts with the node's prefix, which is the longest one
+//! they have in common, and is only stored once: cells hold what comes after
+//! it. Entries are sorted, so that's what the first and last keys have in
+//! common. Lookups compare the target against the prefix once, and then only
+//! against what's in the cells.
+//!
+//! A leaf (level 0) cell is `[key len: u16][value len: u16][key][value]`, and
+//! its `left` and `right` are its sibling leaves, or 0 if there aren't any. An
+//! internal cell is a separator, which is the same followed by `[child: u64]`:
+//! the child holding every entry from that separator up to the next one. The
+//! internal node's `left` is the child holding everything before its first
+//! separator. A separator between two leaves is cut down to the shortest one
+//! that still comes between them (see [`separator`]), so it's usually much
+//! shorter than an entry.
+//!
+//! An all-zero page is an empty leaf. Pages on the free list have the
+//! [`FLAG_FREE`] flag, and the next free page as their `right`.
+//!
+//! Entries are ordered by key, then by value, so that any number of entries
+//! can share a key.
+
+use std::cmp::Ordering;
+
+use ferrodb_fs::PageIndex;
+
+pub(crate) const HEADER_SIZE: usize = 24;
+const SLOT_SIZE: usize = 2;
+const CELL_HEADER_SIZE: usize = 4;
+const CHILD_SIZE: usize = 8;
+
+const FLAG_FREE: u8 = 1 << 0;
+
+/// The longest key and value (together) that can go in a node with a
+/// `body_len` byte body, which makes sure that at least four of the biggest
+/// separators fit in a node.
+pub(crate) fn max_entry_len(body_len: usize) -> usize {
+    (body_len - HEADER_SIZE) / 4 - SLOT_SIZE - CELL_HEADER_SIZE - CHILD_SIZE
+}
+
+/// A node with fewer bytes of entries than this (out of a `body_len` byte
+/// body) gets merged with, or borrows from, one of its siblings.
+pub(crate) fn min_content(body_len: usize) -> usize {
+    (body_len - HEADER_SIZE) / 4
+}
+
+/// The shortest separator that comes after `left` and no later than `right`,
+/// which are the last entry of a leaf and the first entry of the next one.
+/// That's as much of `right`'s key as it takes to tell it apart from `left`'s,
+/// with no value, unless their keys are the same.
+pub(crate) fn separator(left: &Entry, right: &Entry) -> Entry {
+    if left.key != right.key {
+        let len = common_prefix_len(&left.key, &right.key) + 1;
+        return Entry::new(&right.key[..len], &[]);
+    }
+
+    let len = common_prefix_len(&left.value, &right.value) + 1;
+    Entry::new(&right.key, &right.value[..len])
+}
+
+/// Somewhere among the entries of the tree.
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum Target<'a> {
+    /// Before every entry.
+    Start,
+    /// After every entry.
+    End,
+    /// Right before the first entry with this key.
+    KeyStart(&'a [u8]),
+    /// Right after the last entry with this key.
+    KeyEnd(&'a [u8]),
+    /// Exactly at this entry, whether or not it's in the tree.
+    Entry(&'a [u8], &'a [u8]),
+}
+
+impl<'a> Target<'a> {
+    /// Where the entry `(key, value)` is compared to this target.
+    pub fn cmp_entry(&self, key: &[u8], value: &[u8]) -> Ordering {
+        match *self {
+            Target::Start => Ordering::Greater,
+            Target::End => Ordering::Less,
+            Target::KeyStart(target) => key.cmp(target).then(Ordering::Greater),
+            Target::KeyEnd(target) => key.cmp(target).then(Ordering::Less),
+            Target::Entry(target_key, target_value) =>
+                (key, value).cmp(&(target_key, target_value)),
+        }
+    }
+
+    /// The target among keys that all start with `prefix`, with the prefix
+    /// taken off. If it isn't among them, how every one of them compares to it
+    /// instead, which is never equal.
+    pub fn without_prefix(self, prefix: &[u8]) -> Result<Target<'a>, Ordering> {
+        let (Target::KeyStart(key) | Target::KeyEnd(key) | Target::Entry(key, _)) = self
+            else { return Ok(self); };
+        let Some(rest) = key.strip_prefix(prefix)
+            else { return Err(prefix.cmp(key)); };
+
+        Ok(match self {
+            Target::KeyStart(_) => Target::KeyStart(rest),
+            Target::KeyEnd(_) => Target::KeyEnd(rest),
+            Target::Entry(_, value) => Target::Entry(rest, value),
+            Target::Start | Target::End => unreachable!(),
+        })
+    }
+}
+
+/// A node, read in place.
+pub(crate) struct NodeRef<'p> {
+    body: &'p [u8],
+}
+
+impl<'p> NodeRef<'p> {
+    pub fn new(body: &'p [u8]) -> NodeRef<'p> {
+        NodeRef { body }
+    }
+
+    pub fn level(&self) -> u8 {
+        self.body[0]
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.level() == 0
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.body[1] & FLAG_FREE != 0
+    }
+
+    pub fn len(&self) -> usize {
+        read_u16(self.body, 2) as usize
+    }
+
+    pub fn left(&self) -> PageIndex {
+        read_u64(self.body, 8) as PageIndex
+    }
+
+    pub fn right(&self) -> PageIndex {
+        read_u64(self.body, 16) as PageIndex
+    }
+
+    /// What every key in the node starts with.
+    pub fn prefix(&self) -> &'p [u8] {
+        let len = read_u16(self.body, 4) as usize;
+        &self.body[self.body.len() - len..]
+    }
+
+    /// The entry at `index`, with its whole key.
+    pub fn entry(&self, index: usize) -> (Vec<u8>, &'p [u8]) {
+        let (suffix, value) = self.stored_entry(index);
+        ([self.prefix(), suffix].concat(), value)
+    }
+
+    /// The child at `index`, where 0 is the one before the first separator and
+    /// `i` is the one after the `i`th separator.
+    pub fn child(&self, index: usize) -> PageIndex {
+        if index == 0 {
+            return self.left();
+        }
+
+        let cell = self.cell(index - 1);
+        let at = CELL_HEADER_SIZE + read_u16(cell, 0) as usize + read_u16(cell, 2) as usize;
+        read_u64(cell, at) as PageIndex
+    }
+
+    /// The index of the child that `target` is in, i.e. the number of
+    /// separators up to it.
+    pub fn child_index(&self, target: Target<'_>) -> usize {
+        self.up_to(target)
+    }
+
+    /// How many entries come before `target`.
+    pub fn before(&self, target: Target<'_>) -> usize {
+        self.partition(target, |ordering| ordering == Ordering::Less)
+    }
+
+    /// How many entries come before `target`, or are it.
+    pub fn up_to(&self, target: Target<'_>) -> usize {
+        self.partition(target, |ordering| ordering != Ordering::Greater)
+    }
+
+    /// How many entries `pred` is true for how they compare to `target`,
+    /// given that it's true for every entry before those and false for every
+    /// entry after them.
+    fn partition(&self, target: Target<'_>, pred: impl Fn(Ordering) -> bool) -> usize {
+        let target = match target.without_prefix(self.prefix()) {
+            Ok(target) => target,
+            Err(ordering) => return if pred(ordering) { self.len() } else { 0 },
+        };
+
+        partition_point(self.len(), |index| {
+            let (suffix, value) = self.stored_entry(index);
+            pred(target.cmp_entry(suffix, value))
+        })
+    }
+
+    /// The entry at `index`, with only the part of its key past the prefix.
+    fn stored_entry(&self, index: usize) -> (&'p [u8], &'p [u8]) {
+        let cell = self.cell(index);
+        let key_len = read_u16(cell, 0) as usize;
+        let value_len = read_u16(cell, 2) as usize;
+        let key = &cell[CELL_HEADER_SIZE..CELL_HEADER_SIZE + key_len];
+        let value = &cell[CELL_HEADER_SIZE + key_len..CELL_HEADER_SIZE + key_len + value_len];
+        (key, value)
+    }
+
+    fn cell(&self, index: usize) -> &'p [u8] {
+        let offset = read_u16(self.body, HEADER_SIZE + index * SLOT_SIZE) as usize;
+        &self.body[offset..]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Entry {
+    pub key: Vec<u8>,
+    pub value: Vec<u8>,
+    /// Only used in internal nodes.
+    pub child: PageIndex,
+}
+
+impl Entry {
+    pub fn new(key: &[u8], value: &[u8]) -> Entry {
+        Entry {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            child: 0,
+        }
+    }
+}
+
+/// A node, read out of its page to be changed and written back.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Node {
+    pub level: u8,
+    pub left: PageIndex,
+    pub right: PageIndex,
+    pub entries: Vec<Entry>,
+}
+
+impl Node {
+    pub fn read(body: &[u8]) -> Node {
+        let node = NodeRef::new(body);
+
+        let entries = (0..node.len())
+            .map(|index| {
+                let (key, value) = node.entry(index);
+                Entry {
+                    key,
+                    value: value.to_vec(),
+                    child: if node.is_leaf() { 0 } else { node.child(index + 1) },
+                }
+            })
+            .collect();
+
+        Node {
+            level: node.level(),
+            left: node.left(),
+            right: node.right(),
+            entries,
+        }
+    }
+
+    pub fn write(&self, body: &mut [u8]) {
+        assert!(self.size() <= body.len(), "Expected node to fit in its page");
+
+        let prefix_len = self.prefix_len();
+        body.fill(0);
+        body[0] = self.level;
+        write_u16(body, 2, self.entries.len() as u16);
+        write_u16(body, 4, prefix_len as u16);
+        write_u64(body, 8, self.left as u64);
+        write_u64(body, 16, self.right as u64);
+
+        let mut end = body.len() - prefix_len;
+        if let Some(first) = self.entries.first() {
+            body[end..].copy_from_slice(&first.key[..prefix_len]);
+        }
+
+        for (index, entry) in self.entries.iter().enumerate() {
+            let suffix = &entry.key[prefix_len..];
+            let start = end - self.cell_size(entry, prefix_len);
+            let cell = &mut body[start..end];
+
+            write_u16(cell, 0, suffix.len() as u16);
+            write_u16(cell, 2, entry.value.len() as u16);
+            let key_end = CELL_HEADER_SIZE + suffix.len();
+            cell[CELL_HEADER_SIZE..key_end].copy_from_slice(suffix);
+            cell[key_end..key_end + entry.value.len()].copy_from_slice(&entry.value);
+            if !self.is_leaf() {
+                write_u64(cell, key_end + entry.value.len(), entry.child as u64);
+            }
+
+            write_u16(body, HEADER_SIZE + index * SLOT_SIZE, start as u16);
+            end = start;
+        }
+    }
+
+    /// Point a leaf at a different left sibling, without touching the rest of it.
+    pub fn write_left(body: &mut [u8], left: PageIndex) {
+        write_u64(body, 8, left as u64);
+    }
+
+    /// Turn the page into a free page, pointing at the `next` one.
+    pub fn write_free(body: &mut [u8], next: PageIndex) {
+        body.fill(0);
+        body[1] = FLAG_FREE;
+        write_u64(body, 16, next as u64);
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.level == 0
+    }
+
+    pub fn child(&self, index: usize) -> PageIndex {
+        match index {
+            0 => self.left,
+            _ => self.entries[index - 1].child,
+        }
+    }
+
+    /// Where the entry `(key, value)` is, or else where it would go.
+    pub fn find(&self, key: &[u8], value: &[u8]) -> Result<usize, usize> {
+        self.entries
+            .binary_search_by(|entry| (&*entry.key, &*entry.value).cmp(&(key, value)))
+    }
+
+    /// How many bytes of the page this node takes up.
+    pub fn size(&self) -> usize {
+        HEADER_SIZE + self.content()
+    }
+
+    /// How many bytes of the page this node's entries take up, prefix and
+    /// all.
+    pub fn content(&self) -> usize {
+        let prefix_len = self.prefix_len();
+        let cells: usize = self
+            .entries
+            .iter()
+            .map(|entry| SLOT_SIZE + self.cell_size(entry, prefix_len))
+            .sum();
+
+        prefix_len + cells
+    }
+
+    /// How long the prefix that every key has in common is.
+    fn prefix_len(&self) -> usize {
+        match (self.entries.first(), self.entries.last()) {
+            (Some(first), Some(last)) => common_prefix_len(&first.key, &last.key),
+            _ => 0,
+        }
+    }
+
+    /// How many bytes an entry's cell takes up, if its key starts with a
+    /// `prefix_len` byte prefix that isn't in the cell.
+    fn cell_size(&self, entry: &Entry, prefix_len: usize) -> usize {
+        let child = if self.is_leaf() { 0 } else { CHILD_SIZE };
+        CELL_HEADER_SIZE + entry.key.len() - prefix_len + entry.value.len() + child
+    }
+
+    /// Move every entry of `right`, the next node over, into this one. `separator`
+    /// is the parent's separator between the two, which has to come down into
+    /// an internal node to point at `right`'s first child.
+    pub fn merge(&mut self, separator: Entry, right: Node) {
+        if !self.is_leaf() {
+            self.entries.push(Entry {
+                child: right.left,
+                ..separator
+            });
+        }
+
+        self.entries.extend(right.entries);
+        self.right = right.right;
+    }
+
+    /// Move the second half (by size) of this node's entries into a new node,
+    /// returning the separator that the parent should have for it (with no child
+    /// filled in yet). For an internal node, the separator is moved out of the
+    /// node rather than copied.
+    ///
+    /// Each half ends up with a prefix of its own, which can be a lot longer
+    /// than the one they had together, so the halves are picked by how big
+    /// they'd be then: whichever way leaves the bigger one the smallest.
+    pub fn split(&mut self) -> (Entry, Node) {
+        let child = if self.is_leaf() { 0 } else { CHILD_SIZE };
+        let mut sums = vec![0];
+        for entry in &self.entries {
+            let size = SLOT_SIZE + CELL_HEADER_SIZE + entry.key.len() + entry.value.len() + child;
+            sums.push(sums.last().unwrap() + size);
+        }
+
+        // How much of the page the entries in `range` would take up on their
+        // own.
+        let content = |range: std::ops::Range<usize>| {
+            let Some(last) = range.end.checked_sub(1).filter(|&last| last >= range.start)
+                else { return 0; };
+
+            let prefix_len = common_prefix_len(&self.entries[range.start].key, &self.entries[last].key);
+            prefix_len + sums[range.end] - sums[range.start] - (range.end - range.start) * prefix_len
+        };
+
+        // Leave at least one entry on either side, and for an internal node, one
+        // more to be the separator.
+        let len = self.entries.len();
+        let last = if self.is_leaf() { len - 1 } else { len - 2 };
+        let moved = if self.is_leaf() { 0 } else { 1 };
+        let index = (1..=last)
+            .min_by_key(|&index| content(0..index).max(content(index + moved..len)))
+            .unwrap();
+
+        let mut right = Node {
+            level: self.level,
+            left: 0,
+            right: 0,
+            entries: self.entries.split_off(index),
+        };
+
+        let separator = if self.is_leaf() {
+            separator(self.entries.last().unwrap(), &right.entries[0])
+        } else {
+            let first = right.entries.remove(0);
+            right.left = first.child;
+            Entry { child: 0, ..first }
+        };
+
+        (separator, right)
+    }
+}
+
+/// How many bytes `a` and `b` start with that are the same.
+fn common_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+/// The first index in `0..len` that `pred` is false for, given that it's true
+/// for every index before that and false for every index after it.
+fn partition_point(len: usize, pred: impl Fn(usize) -> bool) -> usize {
+    let (mut low, mut high) = (0, len);
+
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if pred(mid) {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+
+    low
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn write_u16(bytes: &mut [u8], at: usize, value: u16) {
+    bytes[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn write_u64(bytes: &mut [u8], at: usize, value: u64) {
+    bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY_LEN: usize = 4096 - 32;
+
+    fn leaf(keys: &[&str]) -> Node {
+        Node {
+            entries: keys.iter().map(|key| Entry::new(key.as_bytes(), b"v")).collect(),
+            ..Node::default()
+        }
+    }
+
+    fn target(key: &str) -> Target<'_> {
+        Target::KeyStart(key.as_bytes())
+    }
+
+    #[test]
+    fn keys_only_keep_what_comes_after_the_prefix() {
+        let keys = ["https://a.com/x/1", "https://a.com/x/2", "https://a.com/x/2", "https://a.com/y"];
+        let node = leaf(&keys);
+        let uncompressed: usize = keys.iter().map(|key| SLOT_SIZE + CELL_HEADER_SIZE + key.len() + 1).sum();
+        assert_eq!(node.content(), uncompressed - 3 * "https://a.com/".len());
+
+        let mut body = vec![0; BODY_LEN];
+        node.write(&mut body);
+        let read = NodeRef::new(&body);
+        assert_eq!(read.prefix(), b"https://a.com/");
+        assert_eq!(read.entry(3), (b"https://a.com/y".to_vec(), &b"v"[..]));
+        let entries: Vec<_> = Node::read(&body).entries.into_iter().map(|entry| entry.key).collect();
+        assert_eq!(entries, keys.map(|key| key.as_bytes().to_vec()));
+
+        // Targets that don't start with the prefix are before or after all of
+        // them.
+        assert_eq!(read.before(target("https://a.com/x/2")), 1);
+        assert_eq!(read.up_to(Target::KeyEnd(b"https://a.com/x/2")), 3);
+        assert_eq!(read.before(target("https://a.co")), 0);
+        assert_eq!(read.before(target("https://a.com")), 0);
+        assert_eq!(read.before(target("https://b")), 4);
+        assert_eq!(read.before(Target::End), 4);
+    }
+
+    #[test]
+    fn separators_are_as_short_as_they_can_be() {
+        let entry = |key: &str, value: &str| Entry::new(key.as_bytes(), value.as_bytes());
+
+        let between = separator(&entry("/usr/lib/a.so", "1"), &entry("/usr/local/bin", "2"));
+        assert_eq!((&*between.key, &*between.value), (&b"/usr/lo"[..], &b""[..]));
+
+        // Entries with the same key are told apart by their values.
+        let same = separator(&entry("/usr", "abc"), &entry("/usr", "abd"));
+        assert_eq!((&*same.key, &*same.value), (&b"/usr"[..], &b"abd"[..]));
+    }
+
+    #[test]
+    fn splitting_leaves_halves_that_each_fit_with_their_own_prefix() {
+        let mut keys: Vec<_> = (0..100).map(|i| format!("/home/alice/photos/{i:04}.jpg")).collect();
+        keys.extend((0..10).map(|i| format!("/var/log/{i}")));
+        let keys: Vec<_> = keys.iter().map(String::as_str).collect();
+
+        let mut left = leaf(&keys);
+        let size = left.size();
+        let (separator, right) = left.split();
+
+        assert!(left.entries.last().unwrap().key < separator.key);
+        assert!(separator.key <= right.entries[0].key);
+        assert_eq!(left.entries.len() + right.entries.len(), keys.len());
+        assert!(left.size() < size && right.size() < size);
+        assert!(left.prefix_len() >= "/home/alice/photos/".len());
+    }
+}
@@ -0,0 +1,241 @@
+//! An external merge sort of entries, for building a tree out of more of them
+//! than fit in memory. Entries are sorted in memory until they take up more
+//! than the sorter is allowed, then written out as a sorted run, and the runs
+//! are merged together at the end.
+//!
+//! A run is the file named `<sorter>.<number>`, where each page from page 1
+//! on is `[count: u16][entry] * count`, and an entry is
+//! `[key len: u16][value len: u16][key][value]`. Nothing about runs is logged
+//! or synced, since they're deleted once the sort is done, and the runs that a
+//! crash left behind are deleted the next time a sorter with the same name is
+//! made.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, VecDeque};
+use std::mem;
+use std::sync::Arc;
+
+use ferrodb_fs::{FileId, FileKind, FileManager, PageIndex, PAGE_HEADER_SIZE};
+
+use crate::{body_len, BTree, Error};
+
+const FIRST_PAGE: PageIndex = 1;
+const COUNT_SIZE: usize = 2;
+const ENTRY_HEADER_SIZE: usize = 4;
+/// About how many bytes of memory an entry takes up on top of its key and
+/// value.
+const ENTRY_OVERHEAD: usize = 2 * mem::size_of::<Vec<u8>>();
+
+type SortEntry = (Vec<u8>, Vec<u8>);
+
+pub struct Sorter {
+    files: Arc<FileManager>,
+    name: String,
+    /// About how many bytes of entries to hold in memory before writing them
+    /// out as a run.
+    memory: usize,
+    entries: Vec<SortEntry>,
+    /// About how many bytes `entries` takes up.
+    size: usize,
+    runs: Vec<FileId>,
+}
+
+impl Sorter {
+    /// Make a sorter that holds about `memory` bytes of entries in memory, and
+    /// writes the rest out to files named after `name`.
+    pub fn new(files: Arc<FileManager>, name: &str, memory: usize) -> Result<Sorter, Error> {
+        for number in 0.. {
+            let run_name = run_name(name, number);
+            if !files.exists(&run_name)? {
+                break;
+            }
+            files.delete(files.id(&run_name))?;
+        }
+
+        Ok(Sorter {
+            files,
+            name: name.to_owned(),
+            memory,
+            entries: vec![],
+            size: 0,
+            runs: vec![],
+        })
+    }
+
+    /// Add an entry, which has to fit in a tree.
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let len = key.len() + value.len();
+        if len > BTree::max_entry_len() {
+            return Err(Error::EntryTooLarge {
+                len,
+                max: BTree::max_entry_len(),
+            });
+        }
+
+        self.entries.push((key.to_vec(), value.to_vec()));
+        self.size += len + ENTRY_OVERHEAD;
+        if self.size > self.memory {
+            self.write_run()?;
+        }
+
+        Ok(())
+    }
+
+    /// How many runs have been written out so far.
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Every entry that was added, sorted by key and then by value.
+    pub fn sorted(&mut self) -> Result<Sorted<'_>, Error> {
+        self.entries.sort_unstable();
+
+        let mut sources: Vec<Source<'_>> = self
+            .runs
+            .iter()
+            .map(|&file| Source::Run {
+                files: &self.files,
+                file,
+                next_page: FIRST_PAGE,
+                entries: VecDeque::new(),
+            })
+            .collect();
+        sources.push(Source::Memory(mem::take(&mut self.entries).into_iter()));
+        self.size = 0;
+
+        let mut heap = BinaryHeap::new();
+        for (index, source) in sources.iter_mut().enumerate() {
+            if let Some(entry) = source.next()? {
+                heap.push(Reverse((entry, index)));
+            }
+        }
+
+        Ok(Sorted { sources, heap })
+    }
+
+    fn write_run(&mut self) -> Result<(), Error> {
+        self.entries.sort_unstable();
+
+        let file = self.files.open(&run_name(&self.name, self.runs.len()), FileKind::SortRun)?;
+        self.runs.push(file);
+
+        let mut page = vec![0; COUNT_SIZE];
+        let mut count: u16 = 0;
+        for (key, value) in mem::take(&mut self.entries) {
+            if page.len() + ENTRY_HEADER_SIZE + key.len() + value.len() > body_len() {
+                self.write_page(file, &mut page, count)?;
+                count = 0;
+            }
+
+            page.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            page.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            page.extend_from_slice(&key);
+            page.extend_from_slice(&value);
+            count += 1;
+        }
+        if count > 0 {
+            self.write_page(file, &mut page, count)?;
+        }
+
+        self.size = 0;
+        Ok(())
+    }
+
+    fn write_page(&self, file: FileId, page: &mut Vec<u8>, count: u16) -> Result<(), Error> {
+        page[..COUNT_SIZE].copy_from_slice(&count.to_le_bytes());
+
+        let index = self.files.allocate(file)?;
+        let page_ref = self.files.dirty(file, index)?;
+        page_ref.write()[PAGE_HEADER_SIZE..][..page.len()].copy_from_slice(page);
+
+        page.truncate(COUNT_SIZE);
+        Ok(())
+    }
+}
+
+impl Drop for Sorter {
+    fn drop(&mut self) {
+        // Anything that's left is deleted when the next sorter with this name
+        // is made.
+        for &file in &self.runs {
+            let _ = self.files.delete(file);
+        }
+    }
+}
+
+/// Where [`Sorted`] merges entries from.
+enum Source<'s> {
+    Run {
+        files: &'s FileManager,
+        file: FileId,
+        next_page: PageIndex,
+        /// What's left of the last page that was read.
+        entries: VecDeque<SortEntry>,
+    },
+    Memory(std::vec::IntoIter<SortEntry>),
+}
+
+impl Source<'_> {
+    fn next(&mut self) -> Result<Option<SortEntry>, Error> {
+        match self {
+            Source::Run {
+                files,
+                file,
+                next_page,
+                entries,
+            } => {
+                if entries.is_empty() && *next_page < files.page_count(*file)? {
+                    let page_ref = files.latest(*file, *next_page)?;
+                    read_page(&page_ref.read()[PAGE_HEADER_SIZE..], entries);
+                    *next_page += 1;
+                }
+
+                Ok(entries.pop_front())
+            },
+            Source::Memory(entries) => Ok(entries.next()),
+        }
+    }
+}
+
+/// The entries of a [`Sorter`], in order.
+pub struct Sorted<'s> {
+    sources: Vec<Source<'s>>,
+    /// The next entry of each source that has any left, and which source it's
+    /// from.
+    heap: BinaryHeap<Reverse<(SortEntry, usize)>>,
+}
+
+impl Iterator for Sorted<'_> {
+    type Item = Result<SortEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((entry, index)) = self.heap.pop()?;
+
+        match self.sources[index].next() {
+            Ok(Some(next)) => self.heap.push(Reverse((next, index))),
+            Ok(None) => {},
+            Err(e) => return Some(Err(e)),
+        }
+
+        Some(Ok(entry))
+    }
+}
+
+fn run_name(sorter: &str, number: usize) -> String {
+    format!("{sorter}.{number}")
+}
+
+fn read_page(body: &[u8], entries: &mut VecDeque<SortEntry>) {
+    let count = u16::from_le_bytes(body[..COUNT_SIZE].try_into().unwrap());
+    let mut at = COUNT_SIZE;
+
+    for _ in 0..count {
+        let key_len = u16::from_le_bytes(body[at..at + 2].try_into().unwrap()) as usize;
+        let value_len = u16::from_le_bytes(body[at + 2..at + 4].try_into().unwrap()) as usize;
+        let key_start = at + ENTRY_HEADER_SIZE;
+        let value_start = key_start + key_len;
+
+        entries.push_back((body[key_start..value_start].to_vec(), body[value_start..value_start + value_len].to_vec()));
+        at = value_start + value_len;
+    }
+}
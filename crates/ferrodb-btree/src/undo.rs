@@ -0,0 +1,69 @@
+//! Changes to entries are rolled back by making the opposite change, logged as
+//! `[op: u8][key len: u16][key][value]`, which finds the entry wherever splits
+//! and merges have moved it since.
+
+use std::sync::{Arc, Weak};
+
+use ferrodb_fs::{FileId, FileKind, FileManager, PageIndex};
+use ferrodb_wal::{Txn, Undo, Wal};
+
+use crate::{BTree, Error, Op};
+
+const OP_INSERT: u8 = 0;
+const OP_DELETE: u8 = 1;
+
+impl BTree {
+    /// Set up `wal` to roll back changes to trees that are in `files`, which
+    /// has to happen before it recovers or anything changes a tree.
+    pub fn register_undo(wal: &Wal, files: &Arc<FileManager>) {
+        wal.set_undo(FileKind::BTree, Arc::new(BTreeUndo(Arc::downgrade(files))));
+    }
+}
+
+impl Op<'_> {
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let (op, key, value) = match *self {
+            Op::Insert(key, value) => (OP_INSERT, key, value),
+            Op::Delete(key, value) => (OP_DELETE, key, value),
+        };
+
+        let mut bytes = vec![op];
+        bytes.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(key);
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    fn decode(bytes: &[u8]) -> Op<'_> {
+        let key_len = u16::from_le_bytes(bytes[1..3].try_into().unwrap()) as usize;
+        let (key, value) = bytes[3..].split_at(key_len);
+
+        match bytes[0] {
+            OP_INSERT => Op::Insert(key, value),
+            OP_DELETE => Op::Delete(key, value),
+            op => panic!("Unknown tree undo op {op}"),
+        }
+    }
+}
+
+/// Only points back at `files` weakly, since `files` keeps the log that keeps
+/// this.
+struct BTreeUndo(Weak<FileManager>);
+
+impl Undo for BTreeUndo {
+    fn undo(&self, txn: &Txn<'_>, file: FileId, _page: PageIndex, undo: &[u8]) -> Result<(), ferrodb_wal::Error> {
+        let tree = BTree {
+            files: self.0.upgrade().expect("Changes are only rolled back while their files are open"),
+            file,
+        };
+
+        // Making the same change again changes nothing, if a crash stopped
+        // this after it was made.
+        match tree.modify(txn, Op::decode(undo)) {
+            Ok(_) => Ok(()),
+            Err(Error::Fs(e)) => Err(e.into()),
+            Err(Error::Wal(e)) => Err(e),
+            Err(e) => unreachable!("Changing an entry only fails to do I/O, but got: {e}"),
+        }
+    }
+}
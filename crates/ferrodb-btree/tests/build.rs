@@ -0,0 +1,136 @@
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use ferrodb_btree::{BTree, Error, Sorter};
+use ferrodb_fs::vfs::MemoryVfs;
+use ferrodb_fs::FileManager;
+use ferrodb_wal::Wal;
+
+const PAGE_SIZE: usize = 512;
+
+fn open() -> (Arc<Wal>, Arc<FileManager>) {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let vfs = Arc::new(MemoryVfs::default());
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+    (wal, files)
+}
+
+/// Entries in no particular order, with some keys that have several values
+/// and some entries that are added twice.
+fn entries(count: u32) -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut entries = vec![];
+    for i in 0..count {
+        // 7919 is prime, so this visits every key once, out of order.
+        let key = (i * 7919 % count / 3).to_be_bytes().to_vec();
+        let value = format!("value {i}").into_bytes();
+
+        if i % 10 == 0 {
+            entries.push((key.clone(), value.clone()));
+        }
+        entries.push((key, value));
+    }
+    entries
+}
+
+fn collect(tree: &BTree) -> Vec<(Vec<u8>, Vec<u8>)> {
+    tree.iter().collect::<Result<_, _>>().unwrap()
+}
+
+#[test]
+fn builds_a_tree_from_spilled_runs() {
+    let (wal, files) = open();
+    let entries = entries(5000);
+    let model: BTreeSet<_> = entries.iter().cloned().collect();
+
+    for fill_factor in [50, 75, 100] {
+        let mut sorter = Sorter::new(files.clone(), "sort", 4 * PAGE_SIZE).unwrap();
+        for (key, value) in &entries {
+            sorter.add(key, value).unwrap();
+        }
+        assert!(sorter.runs() > 1, "Expected the sorter to spill more than one run");
+
+        let tree = BTree::open(files.clone(), &format!("tree {fill_factor}")).unwrap();
+        let txn = wal.begin().unwrap();
+        let count = tree.build(&txn, fill_factor, sorter.sorted().unwrap()).unwrap();
+        txn.commit().unwrap();
+        drop(sorter);
+
+        assert_eq!(count, model.len());
+        assert_eq!(collect(&tree), model.iter().cloned().collect::<Vec<_>>());
+        assert!(!files.exists("sort.0").unwrap(), "Expected the runs to be deleted");
+
+        // Backwards, and a range in the middle, go through the same leaves.
+        let mut backwards: Vec<_> = tree.iter().rev().collect::<Result<_, _>>().unwrap();
+        backwards.reverse();
+        assert_eq!(backwards, collect(&tree));
+
+        let (start, end) = (100u32.to_be_bytes(), 200u32.to_be_bytes());
+        let range: Vec<_> = tree
+            .range((Bound::Included(&start[..]), Bound::Excluded(&end[..])))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected: Vec<_> = model
+            .iter()
+            .filter(|(key, _)| key[..] >= start[..] && key[..] < end[..])
+            .cloned()
+            .collect();
+        assert_eq!(range, expected);
+
+        let values = tree.get(&7u32.to_be_bytes()).unwrap();
+        let expected: Vec<_> = model
+            .iter()
+            .filter(|(key, _)| key[..] == 7u32.to_be_bytes())
+            .map(|(_, value)| value.clone())
+            .collect();
+        assert_eq!(values, expected);
+    }
+}
+
+#[test]
+fn a_built_tree_takes_changes() {
+    let (wal, files) = open();
+    let mut model: BTreeSet<_> = entries(2000).into_iter().collect();
+
+    let mut sorter = Sorter::new(files.clone(), "changes", 4 * PAGE_SIZE).unwrap();
+    for (key, value) in &model {
+        sorter.add(key, value).unwrap();
+    }
+
+    // Filling every node means the first insert into any of them splits it.
+    let tree = BTree::open(files.clone(), "tree").unwrap();
+    let txn = wal.begin().unwrap();
+    tree.build(&txn, 100, sorter.sorted().unwrap()).unwrap();
+
+    for i in 0..1000u32 {
+        let entry = ((i * 3).to_be_bytes().to_vec(), b"new".to_vec());
+        assert!(tree.insert(&txn, &entry.0, &entry.1).unwrap());
+        model.insert(entry);
+    }
+
+    let removed: Vec<_> = model.iter().step_by(3).cloned().collect();
+    for (key, value) in removed {
+        assert!(tree.delete(&txn, &key, &value).unwrap());
+        model.remove(&(key, value));
+    }
+    txn.commit().unwrap();
+
+    assert_eq!(collect(&tree), model.into_iter().collect::<Vec<_>>());
+}
+
+#[test]
+fn only_builds_new_trees() {
+    let (wal, files) = open();
+    let tree = BTree::open(files, "tree").unwrap();
+    let txn = wal.begin().unwrap();
+
+    assert!(matches!(tree.build(&txn, 40, []), Err(Error::FillFactor(40))));
+
+    tree.insert(&txn, b"key", b"value").unwrap();
+    let entries = [Ok((b"other".to_vec(), b"value".to_vec()))];
+    assert!(matches!(tree.build(&txn, 100, entries), Err(Error::NotEmpty)));
+    txn.commit().unwrap();
+}
@@ -0,0 +1,79 @@
+use std::collections::BTreeSet;
+use std::ops::Bound;
+use std::sync::Arc;
+use std::thread;
+
+use ferrodb_btree::BTree;
+use ferrodb_fs::vfs::MemoryVfs;
+use ferrodb_fs::{FileManager, PAGE_HEADER_SIZE};
+use ferrodb_wal::{recover, Wal};
+
+const PAGE_SIZE: usize = 1024;
+
+fn open() -> (Arc<Wal>, Arc<FileManager>, Arc<BTree>) {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let vfs = Arc::new(MemoryVfs::default());
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+    BTree::register_undo(&wal, &files);
+
+    recover(&wal, &files, |_, _| {}).unwrap();
+    let tree = Arc::new(BTree::open(files.clone(), "tree").unwrap());
+    (wal, files, tree)
+}
+
+fn url(writer: usize, i: usize) -> Vec<u8> {
+    format!("https://www.example.com/catalog/section-{writer}/products/item-{i:06}/details").into_bytes()
+}
+
+#[test]
+fn long_keys_with_a_lot_in_common_take_up_fewer_pages_than_they_would_whole() {
+    let (wal, files, tree) = open();
+
+    // Scans go on while the tree grows, and never see entries out of order.
+    let writers: Vec<_> = (0..4)
+        .map(|writer| {
+            let (wal, tree) = (wal.clone(), tree.clone());
+            thread::spawn(move || {
+                for i in 0..1500 {
+                    let txn = wal.begin().unwrap();
+                    assert!(tree.insert(&txn, &url(writer, i), &(i as u32).to_be_bytes()).unwrap());
+                    txn.commit().unwrap();
+
+                    if i % 100 == 0 {
+                        let keys: Vec<_> = tree.iter().map(|entry| entry.unwrap().0).collect();
+                        assert!(keys.is_sorted());
+                    }
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    let expected: BTreeSet<_> = (0..4)
+        .flat_map(|writer| (0..1500).map(move |i| (url(writer, i), (i as u32).to_be_bytes().to_vec())))
+        .collect();
+    let entries: Vec<_> = tree.iter().collect::<Result<_, _>>().unwrap();
+    assert!(entries.iter().eq(expected.iter()));
+
+    let whole: usize = expected.iter().map(|(key, value)| key.len() + value.len()).sum();
+    let pages = files.page_count(tree.file()).unwrap();
+    assert!(pages * (PAGE_SIZE - PAGE_HEADER_SIZE) < whole, "{pages} pages for {whole} bytes");
+
+    // Cursors cross leaves whose prefixes are nothing like each other.
+    let (start, end) = (url(1, 1400), url(2, 100));
+    let range: Vec<_> = tree
+        .range((Bound::Included(&start[..]), Bound::Excluded(&end[..])))
+        .map(|entry| entry.unwrap().0)
+        .collect();
+    let keys = expected.iter().map(|(key, _)| key.clone());
+    let in_range: Vec<_> = keys.filter(|key| *key >= start && *key < end).collect();
+    assert_eq!(range, in_range);
+    assert_eq!(range.len(), 200);
+    assert_eq!(tree.get(&url(3, 1499)).unwrap(), [1499u32.to_be_bytes()]);
+    assert!(tree.get(&url(3, 1500)).unwrap().is_empty());
+}
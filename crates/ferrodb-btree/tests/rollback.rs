@@ -0,0 +1,136 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use ferrodb_btree::BTree;
+use ferrodb_fs::vfs::{CrashMode, CrashVfs, Vfs};
+use ferrodb_fs::FileManager;
+use ferrodb_wal::{recover, Wal};
+
+const PAGE_SIZE: usize = 512;
+
+type Entries = BTreeSet<(Vec<u8>, Vec<u8>)>;
+
+fn open(vfs: Arc<dyn Vfs>) -> (Arc<Wal>, Arc<FileManager>, BTree) {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+    BTree::register_undo(&wal, &files);
+
+    recover(&wal, &files, |_, _| {}).unwrap();
+    let tree = BTree::open(files.clone(), "tree").unwrap();
+    (wal, files, tree)
+}
+
+fn entry(i: u32, txn: u8) -> (Vec<u8>, Vec<u8>) {
+    (i.to_be_bytes().to_vec(), vec![txn; 20])
+}
+
+fn check(tree: &BTree, expected: &Entries) {
+    let forward: Vec<_> = tree.iter().collect::<Result<_, _>>().unwrap();
+    assert_eq!(forward, expected.iter().cloned().collect::<Vec<_>>());
+
+    let backward: Vec<_> = tree.iter().rev().collect::<Result<_, _>>().unwrap();
+    assert_eq!(backward, expected.iter().rev().cloned().collect::<Vec<_>>());
+
+    for (key, value) in expected {
+        assert_eq!(tree.get(key).unwrap(), std::slice::from_ref(value));
+    }
+}
+
+#[test]
+fn rolling_back_leaves_entries_other_transactions_put_in_the_nodes_it_split() {
+    let (wal, files, tree) = open(Arc::new(CrashVfs::default()));
+    let mut expected = Entries::new();
+
+    // The two of them split the same leaves, over and over.
+    let aborted = wal.begin().unwrap();
+    let committed = wal.begin().unwrap();
+    for i in 0..300 {
+        let (key, value) = entry(i, (i % 2) as u8);
+        let txn = if i % 2 == 0 { &aborted } else { &committed };
+        assert!(tree.insert(txn, &key, &value).unwrap());
+        if i % 2 == 1 {
+            expected.insert((key, value));
+        }
+    }
+    committed.commit().unwrap();
+
+    aborted.abort(&files).unwrap();
+    check(&tree, &expected);
+}
+
+#[test]
+fn rolling_back_deletes_puts_entries_back_wherever_merges_left_their_leaves() {
+    let (wal, files, tree) = open(Arc::new(CrashVfs::default()));
+    let mut expected = Entries::new();
+
+    let setup = wal.begin().unwrap();
+    for i in 0..300 {
+        let (key, value) = entry(i, 0);
+        tree.insert(&setup, &key, &value).unwrap();
+        expected.insert((key, value));
+    }
+    setup.commit().unwrap();
+
+    // Between them, they empty out most of the tree.
+    let aborted = wal.begin().unwrap();
+    let committed = wal.begin().unwrap();
+    for i in 0..280 {
+        let (key, value) = entry(i, 0);
+        let txn = if i % 2 == 0 { &aborted } else { &committed };
+        assert!(tree.delete(txn, &key, &value).unwrap());
+        if i % 2 == 1 {
+            expected.remove(&(key, value));
+        }
+    }
+    committed.commit().unwrap();
+
+    aborted.abort(&files).unwrap();
+    check(&tree, &expected);
+}
+
+#[test]
+fn recovery_rolls_back_without_touching_committed_entries_after_any_crash() {
+    let vfs = Arc::new(CrashVfs::default());
+    let (wal, files, tree) = open(vfs.clone());
+    let mut expected = Entries::new();
+
+    let setup = wal.begin().unwrap();
+    for i in 0..60 {
+        let (key, value) = entry(i, 0);
+        tree.insert(&setup, &key, &value).unwrap();
+        expected.insert((key, value));
+    }
+    setup.commit().unwrap();
+
+    let running = wal.begin().unwrap();
+    let committed = wal.begin().unwrap();
+    for i in 0..60 {
+        let (key, value) = entry(i, 0);
+        let (new_key, new_value) = entry(1000 + i, 1);
+        if i % 2 == 0 {
+            tree.delete(&running, &key, &value).unwrap();
+            tree.insert(&running, &new_key, &new_value).unwrap();
+        } else {
+            tree.delete(&committed, &key, &value).unwrap();
+            tree.insert(&committed, &new_key, &new_value).unwrap();
+            expected.remove(&(key, value));
+            expected.insert((new_key, new_value));
+        }
+    }
+    committed.commit().unwrap();
+    files.sync_all().unwrap();
+
+    // Recovery can crash partway through rolling back too.
+    let crashed = Arc::new(vfs.crash(CrashMode::DropUnsynced));
+    let (_wal, files, tree) = open(crashed.clone());
+    check(&tree, &expected);
+    drop((tree, files));
+
+    for events in 0..=crashed.events().len() {
+        let (_wal, _files, tree) = open(Arc::new(crashed.crash_at(events, CrashMode::DropUnsynced)));
+        check(&tree, &expected);
+    }
+}
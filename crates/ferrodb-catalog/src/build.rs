@@ -0,0 +1,142 @@
+//! Filling a new index with the rows that its table already has. The key of a
+//! row's entry is its values of the index's columns, as encoded by
+//! [`encode_key`], and the value is its record ID.
+//!
+//! A B+Tree's entries are sorted first, spilling to disk once they take up
+//! more memory than the build is allowed, and the tree is built bottom-up out
+//! of them. A hash index doesn't keep its entries in order, so they're just
+//! inserted one at a time, and so are an R-tree's, whose entries are keyed by
+//! the box that a row's columns make up instead (see [`crate::spatial`]).
+
+use std::collections::HashSet;
+
+use ferrodb_btree::{BTree, Sorter, DEFAULT_FILL_FACTOR};
+use ferrodb_hash::HashIndex;
+use ferrodb_heap::RecordId;
+use ferrodb_row::{encode_key, RowRef};
+use ferrodb_rtree::RTree;
+
+use crate::spatial::row_rect;
+use crate::{Ddl, Error, IndexKind};
+
+/// What an index's entries are sorted in, while it's being built. There's one
+/// `Ddl` at a time, so there's only ever one sort going on.
+const SORT_FILE_NAME: &str = "index.sort";
+
+/// How [`Ddl::build_index`] builds an index.
+#[derive(Clone, Debug)]
+pub struct BuildOptions {
+    /// How full to fill a B+Tree's nodes, as a percentage from 50 to 100. The
+    /// rest is left for rows that are added later, so that they don't split
+    /// nodes straight away.
+    pub fill_factor: u8,
+    /// About how many bytes of entries to sort in memory before spilling them
+    /// to disk.
+    pub sort_memory: usize,
+}
+
+impl Default for BuildOptions {
+    fn default() -> BuildOptions {
+        BuildOptions {
+            fill_factor: DEFAULT_FILL_FACTOR,
+            sort_memory: 64 << 20,
+        }
+    }
+}
+
+impl Ddl<'_> {
+    /// Fill an index that was just created with the rows that its table
+    /// already has, which has to be stored in a heap. Returns how many entries
+    /// the index ends up with.
+    ///
+    /// Nothing may change the table's rows while this runs.
+    pub fn build_index(&mut self, name: &str, options: &BuildOptions) -> Result<usize, Error> {
+        let Some(table) = self.cache.index_table(name).cloned()
+            else { return Err(Error::NoSuchIndex(name.to_owned())); };
+        let index = table.index(name).unwrap();
+        let heap = self.catalog.heap(&table)?;
+
+        // Each row's key, and whether it has a NULL in it, since rows like that
+        // don't clash with each other, even in a unique index.
+        let keyed_rows = || {
+            Ok::<_, Error>(heap.scan()?.map(|row| {
+                let (rid, row) = row?;
+                let row = RowRef::new(&table.schema, &row)?;
+
+                let values = index.columns.iter().map(|&column| row.get(column));
+                let null = values.clone().any(|value| value.is_null());
+                Ok::<_, Error>((encode_key(values), rid, null))
+            }))
+        };
+
+        match index.kind {
+            IndexKind::BTree => {
+                let tree = BTree::open(self.catalog.files.clone(), &index.file_name)?;
+                let mut sorter = Sorter::new(self.catalog.files.clone(), SORT_FILE_NAME, options.sort_memory)?;
+                let mut nulls = HashSet::new();
+                for entry in keyed_rows()? {
+                    let (key, rid, null) = entry?;
+                    sorter.add(&key, &rid.to_bytes())?;
+                    if index.unique && null {
+                        nulls.insert(rid);
+                    }
+                }
+
+                let mut duplicate = false;
+                let mut last_key: Option<Vec<u8>> = None;
+                let sorted = sorter.sorted()?.map_while(|entry| match entry {
+                    Ok((key, value)) => {
+                        let rid = RecordId::from_bytes(&value).unwrap();
+                        if index.unique && last_key.as_ref() == Some(&key) && !nulls.contains(&rid) {
+                            duplicate = true;
+                            return None;
+                        }
+
+                        last_key = Some(key.clone());
+                        Some(Ok((key, value)))
+                    },
+                    Err(e) => Some(Err(e)),
+                });
+
+                let count = tree.build(&self.txn, options.fill_factor, sorted)?;
+                if duplicate {
+                    return Err(Error::DuplicateKey(index.name.clone()));
+                }
+
+                Ok(count)
+            },
+            IndexKind::Hash => {
+                let hash = HashIndex::open(self.catalog.files.clone(), &index.file_name)?;
+                let mut count = 0;
+                for entry in keyed_rows()? {
+                    let (key, rid, null) = entry?;
+                    if index.unique && !null && !hash.get(&key)?.is_empty() {
+                        return Err(Error::DuplicateKey(index.name.clone()));
+                    }
+
+                    hash.insert(&self.txn, &key, &rid.to_bytes())?;
+                    count += 1;
+                }
+
+                Ok(count)
+            },
+            IndexKind::RTree => {
+                let tree = RTree::open(self.catalog.files.clone(), &index.file_name)?;
+                let mut count = 0;
+                for row in heap.scan()? {
+                    let (rid, row) = row?;
+                    let row = RowRef::new(&table.schema, &row)?;
+
+                    // Rows without a valid box are left out, rather than failing the
+                    // build, the same way that searches without the index skip them.
+                    if let Some(rect) = row_rect(&row, &index.columns) {
+                        tree.insert(&self.txn, rect, &rid.to_bytes())?;
+                        count += 1;
+                    }
+                }
+
+                Ok(count)
+            },
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::fmt;
+use std::str::FromStr;
+
+use ferrodb_fs::FileId;
+use ferrodb_row::{Schema, Value};
+
+use crate::Error;
+
+/// What a table, index or sequence is known by in the catalog, and what files
+/// are named after. IDs of dropped objects can be handed out again.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId(pub u64);
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TableDef {
+    pub id: ObjectId,
+    pub name: String,
+    pub schema: Schema,
+    /// What every column is set to when an insert doesn't give it a value.
+    pub defaults: Vec<ColumnDefault>,
+    pub engine: StorageEngine,
+    /// The file that the table's rows are in, which is opened by name: its
+    /// heap file, or its LSM tree's manifest.
+    pub file_name: String,
+    pub file: FileId,
+    pub indexes: Vec<IndexDef>,
+    pub constraints: Vec<Constraint>,
+}
+
+impl TableDef {
+    /// The index on this table with this name, ignoring case.
+    pub fn index(&self, name: &str) -> Option<&IndexDef> {
+        self.indexes
+            .iter()
+            .find(|index| index.name.eq_ignore_ascii_case(name))
+    }
+
+    /// The constraint on this table with this name, ignoring case.
+    pub fn constraint(&self, name: &str) -> Option<&Constraint> {
+        self.constraints
+            .iter()
+            .find(|constraint| constraint.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn primary_key(&self) -> Option<&Constraint> {
+        self.constraints
+            .iter()
+            .find(|constraint| constraint.kind == ConstraintKind::PrimaryKey)
+    }
+}
+
+/// How a table's rows are stored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum StorageEngine {
+    Heap,
+    /// An LSM tree, for tables that mostly get written to.
+    Lsm,
+    /// Column by column, for tables that are mostly scanned a few columns at
+    /// a time.
+    Columnar,
+}
+
+impl fmt::Display for StorageEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageEngine::Heap => f.write_str("heap"),
+            StorageEngine::Lsm => f.write_str("lsm"),
+            StorageEngine::Columnar => f.write_str("columnar"),
+        }
+    }
+}
+
+impl FromStr for StorageEngine {
+    type Err = Error;
+
+    /// Parse the name of a storage engine, like in `USING lsm`, ignoring case.
+    fn from_str(name: &str) -> Result<StorageEngine, Error> {
+        match &*name.to_ascii_lowercase() {
+            "heap" => Ok(StorageEngine::Heap),
+            "lsm" => Ok(StorageEngine::Lsm),
+            "columnar" => Ok(StorageEngine::Columnar),
+            _ => Err(Error::UnknownStorageEngine(name.to_owned())),
+        }
+    }
+}
+
+/// What a column is set to when an insert doesn't give it a value, which
+/// [`Catalog::fill_defaults`](crate::Catalog::fill_defaults) works out.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColumnDefault {
+    /// A constant, which is NULL for columns without a default.
+    Value(Value),
+    /// The next value of a sequence, like for `SERIAL` columns.
+    NextValue(ObjectId),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexDef {
+    pub id: ObjectId,
+    pub name: String,
+    pub kind: IndexKind,
+    /// The columns of the table that make up the key, in key order.
+    pub columns: Vec<usize>,
+    /// Whether no two rows can have the same key.
+    pub unique: bool,
+    /// The file that the index is in, which is opened by name.
+    pub file_name: String,
+    pub file: FileId,
+}
+
+/// Which structure an index is stored as.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IndexKind {
+    BTree,
+    Hash,
+    /// Over the points or boxes that two or four number columns make up, for
+    /// spatial searches.
+    RTree,
+}
+
+impl fmt::Display for IndexKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexKind::BTree => f.write_str("btree"),
+            IndexKind::Hash => f.write_str("hash"),
+            IndexKind::RTree => f.write_str("rtree"),
+        }
+    }
+}
+
+impl FromStr for IndexKind {
+    type Err = Error;
+
+    /// Parse the name of an index kind, like in `USING btree`, ignoring case.
+    fn from_str(name: &str) -> Result<IndexKind, Error> {
+        match &*name.to_ascii_lowercase() {
+            "btree" => Ok(IndexKind::BTree),
+            "hash" => Ok(IndexKind::Hash),
+            "rtree" => Ok(IndexKind::RTree),
+            _ => Err(Error::UnknownIndexKind(name.to_owned())),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Constraint {
+    pub name: String,
+    pub kind: ConstraintKind,
+    /// The columns of the table that the constraint is on.
+    pub columns: Vec<usize>,
+    /// The unique index on the same columns that the constraint is checked
+    /// with, if it has one.
+    pub index: Option<ObjectId>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ConstraintKind {
+    /// The columns are unique and never NULL, and there's at most one of these
+    /// per table.
+    PrimaryKey,
+    /// The columns are unique, among rows where none of them are NULL.
+    Unique,
+}
+
+impl fmt::Display for ConstraintKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstraintKind::PrimaryKey => f.write_str("PRIMARY KEY"),
+            ConstraintKind::Unique => f.write_str("UNIQUE"),
+        }
+    }
+}
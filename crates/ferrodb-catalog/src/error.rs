@@ -0,0 +1,99 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Fs(#[from] ferrodb_fs::Error),
+    #[error(transparent)]
+    Wal(#[from] ferrodb_wal::Error),
+    #[error(transparent)]
+    Heap(#[from] ferrodb_heap::Error),
+    #[error(transparent)]
+    BTree(#[from] ferrodb_btree::Error),
+    #[error(transparent)]
+    Hash(#[from] ferrodb_hash::Error),
+    #[error(transparent)]
+    RTree(#[from] ferrodb_rtree::Error),
+    #[error(transparent)]
+    Columnar(#[from] ferrodb_columnar::Error),
+    #[error(transparent)]
+    Lsm(#[from] ferrodb_lsm::Error),
+    #[error(transparent)]
+    Row(#[from] ferrodb_row::Error),
+    #[error("There's already a table named `{0}`")]
+    TableExists(String),
+    #[error("There's no table named `{0}`")]
+    NoSuchTable(String),
+    #[error("Table `{table}` has no column named `{column}`")]
+    NoSuchColumn { table: String, column: String },
+    #[error("Expected a default for each of the {expected} columns, got {got}")]
+    WrongDefaultCount { expected: usize, got: usize },
+    #[error("Table `{table}` has {expected} columns, but a row for it has {got}")]
+    WrongValueCount {
+        table: String,
+        expected: usize,
+        got: usize,
+    },
+    #[error("Table `{table}` can't have indexes, since it's stored as {engine}")]
+    UnindexableEngine {
+        table: String,
+        engine: crate::StorageEngine,
+    },
+    #[error("There's already an index named `{0}`")]
+    IndexExists(String),
+    #[error("There's no index named `{0}`")]
+    NoSuchIndex(String),
+    #[error("Table `{0}` isn't stored in a heap")]
+    NotAHeap(String),
+    #[error("Table `{0}` isn't stored in an LSM tree")]
+    NotAnLsmTree(String),
+    #[error("Table `{0}` isn't stored by column")]
+    NotColumnar(String),
+    #[error("There's no storage engine called `{0}`")]
+    UnknownStorageEngine(String),
+    #[error("There's no index kind called `{0}`")]
+    UnknownIndexKind(String),
+    #[error("Index `{0}` has to be on at least one column")]
+    NoIndexColumns(String),
+    #[error("R-tree index `{0}` has to be on two number columns, for a point, or four, for a box")]
+    RTreeColumns(String),
+    #[error("R-tree index `{0}` can't be unique")]
+    UniqueRTree(String),
+    #[error("Columns ({columns}) of `{table}` aren't a point or a box, which take two number columns or four")]
+    NotABox { table: String, columns: String },
+    #[error("Index `{0}` is corrupt")]
+    CorruptIndex(String),
+    #[error("Unique index `{0}` can't be built, since more than one row has the same key")]
+    DuplicateKey(String),
+    #[error("Index `{index}` is used by constraint `{constraint}`")]
+    IndexInUse { index: String, constraint: String },
+    #[error("Table `{table}` already has a constraint named `{constraint}`")]
+    ConstraintExists { table: String, constraint: String },
+    #[error("Table `{0}` already has a primary key")]
+    MultiplePrimaryKeys(String),
+    #[error("Column `{0}` is part of a primary key, so it can't be nullable")]
+    NullablePrimaryKey(String),
+    #[error("Index `{index}` can't be used for constraint `{constraint}`, since it isn't a unique index on the same columns")]
+    WrongConstraintIndex { index: String, constraint: String },
+    #[error("There's already a sequence named `{0}`")]
+    SequenceExists(String),
+    #[error("There's no sequence named `{0}`")]
+    NoSuchSequence(String),
+    #[error("Sequence `{sequence}` is invalid: {reason}")]
+    InvalidSequence {
+        sequence: String,
+        reason: &'static str,
+    },
+    #[error("Sequence `{sequence}` is the default of column `{column}` of `{table}`")]
+    SequenceInUse {
+        sequence: String,
+        table: String,
+        column: String,
+    },
+    #[error("Sequence `{sequence}` has reached its limit of {limit}")]
+    SequenceExhausted { sequence: String, limit: i64 },
+    #[error("{value} is out of range for sequence `{sequence}`")]
+    SequenceValueOutOfRange { sequence: String, value: i64 },
+    #[error("The catalog is corrupt: {0}")]
+    Corrupt(&'static str),
+}
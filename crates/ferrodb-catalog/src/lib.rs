@@ -0,0 +1,753 @@
+//! The system catalog: what tables there are, their columns, indexes and
+//! constraints, which files they're stored in, and what sequences there are.
+//!
+//! The catalog is kept in heaps of its own, so it survives restarts, and is
+//! read into memory once when it's opened. Every change to it is made by a
+//! [`Ddl`] transaction, which works on its own copy of the catalog and only
+//! replaces the one everyone else sees once it commits. There's one `Ddl` at a
+//! time.
+//!
+//! A table or index is stored in a file named after its ID, which is only
+//! opened by whoever uses it, apart from heaps, which are opened once for
+//! everyone by [`Catalog::heap`], so that they can be vacuumed, and LSM trees,
+//! which are opened once by [`Catalog::lsm`], since each one has a worker
+//! thread of its own. Files of dropped tables and indexes are deleted once the
+//! drop commits, and must not be in use by then.
+
+mod build;
+mod defs;
+mod error;
+mod sequence;
+mod spatial;
+mod system;
+mod vacuum;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ferrodb_btree::BTree;
+use ferrodb_columnar::{ColumnScan, ColumnarTable, Filter};
+use ferrodb_fs::{FileManager, Lsn};
+use ferrodb_heap::{HeapFile, RecordId};
+use ferrodb_lsm::{LsmOptions, LsmTree};
+use ferrodb_row::{Column, DataType, Schema, Value};
+use ferrodb_wal::{Txn, Wal};
+use parking_lot::{Mutex, MutexGuard, RwLock};
+
+pub use self::build::BuildOptions;
+pub use self::defs::{
+    ColumnDefault, Constraint, ConstraintKind, IndexDef, IndexKind, ObjectId, StorageEngine,
+    TableDef,
+};
+pub use self::error::Error;
+pub use self::sequence::{SequenceDef, SequenceOptions};
+use self::sequence::SequenceState;
+use self::system::{SystemTables, TableRows};
+
+pub struct Catalog {
+    files: Arc<FileManager>,
+    wal: Arc<Wal>,
+    system: SystemTables,
+    cache: RwLock<Arc<Cache>>,
+    /// Held by the running `Ddl`.
+    ddl: Mutex<()>,
+    /// Where each sequence that's been used since the catalog was opened is up
+    /// to.
+    sequences: Mutex<HashMap<ObjectId, SequenceState>>,
+    /// Every heap table's heap that's been opened, by its file name.
+    heaps: Mutex<HashMap<String, Arc<HeapFile>>>,
+    /// Every LSM table's tree that's been opened, by its file name. Each one's
+    /// worker is stopped when it's dropped, along with the catalog.
+    trees: Mutex<HashMap<String, Arc<LsmTree>>>,
+}
+
+/// Everything in the catalog, as of the last `Ddl` to commit.
+#[derive(Clone, Default)]
+struct Cache {
+    /// Every table, by its name in lowercase.
+    tables: HashMap<String, Arc<TableDef>>,
+    rows: HashMap<ObjectId, TableRows>,
+    /// The ID of every sequence, by its name in lowercase.
+    sequence_ids: HashMap<String, ObjectId>,
+    sequences: HashMap<ObjectId, SequenceEntry>,
+    next_id: u64,
+}
+
+#[derive(Clone)]
+struct SequenceEntry {
+    def: Arc<SequenceDef>,
+    /// Where the sequence is in the system tables.
+    row: RecordId,
+}
+
+impl Cache {
+    fn table(&self, name: &str) -> Result<&Arc<TableDef>, Error> {
+        self.tables
+            .get(&name.to_lowercase())
+            .ok_or_else(|| Error::NoSuchTable(name.to_owned()))
+    }
+
+    fn sequence(&self, name: &str) -> Result<&SequenceEntry, Error> {
+        self.sequence_ids
+            .get(&name.to_lowercase())
+            .map(|id| &self.sequences[id])
+            .ok_or_else(|| Error::NoSuchSequence(name.to_owned()))
+    }
+
+    /// The table with an index with this name, ignoring case.
+    fn index_table(&self, name: &str) -> Option<&Arc<TableDef>> {
+        self.tables
+            .values()
+            .find(|table| table.index(name).is_some())
+    }
+
+    fn next_id(&mut self) -> ObjectId {
+        self.next_id += 1;
+        ObjectId(self.next_id - 1)
+    }
+}
+
+impl Catalog {
+    /// Set up `wal` to roll back changes to the files of every kind of table and
+    /// index in `files`, which has to happen before it recovers.
+    pub fn register_undo(wal: &Wal, files: &Arc<FileManager>) {
+        HeapFile::register_undo(wal, files);
+        BTree::register_undo(wal, files);
+    }
+
+    /// Open the catalog kept with the files of `files`, which has to have been
+    /// recovered already, with [`Catalog::register_undo`] done first, and read
+    /// it into memory.
+    pub fn open(files: Arc<FileManager>, wal: Arc<Wal>) -> Result<Catalog, Error> {
+        let system = SystemTables::open(&files)?;
+
+        let mut cache = Cache {
+            next_id: 1,
+            ..Cache::default()
+        };
+        for (table, rows) in system.load(&files)? {
+            let last_id = table.indexes.iter().map(|index| index.id).fold(table.id, Ord::max);
+            cache.next_id = cache.next_id.max(last_id.0 + 1);
+            cache.rows.insert(table.id, rows);
+            cache.tables.insert(table.name.to_lowercase(), Arc::new(table));
+        }
+        for (sequence, row) in system.load_sequences()? {
+            cache.next_id = cache.next_id.max(sequence.id.0 + 1);
+            cache.sequence_ids.insert(sequence.name.to_lowercase(), sequence.id);
+            cache.sequences.insert(sequence.id, SequenceEntry {
+                def: Arc::new(sequence),
+                row,
+            });
+        }
+
+        let defaults = cache.tables.values().flat_map(|table| &table.defaults);
+        for default in defaults {
+            if let ColumnDefault::NextValue(sequence) = default {
+                if !cache.sequences.contains_key(sequence) {
+                    return Err(Error::Corrupt("a column defaults to a sequence that doesn't exist"));
+                }
+            }
+        }
+
+        Ok(Catalog {
+            files,
+            wal,
+            system,
+            cache: RwLock::new(Arc::new(cache)),
+            ddl: Mutex::new(()),
+            sequences: Mutex::default(),
+            heaps: Mutex::default(),
+            trees: Mutex::default(),
+        })
+    }
+
+    /// The table with this name, ignoring case.
+    pub fn table(&self, name: &str) -> Option<Arc<TableDef>> {
+        self.cache.read().table(name).ok().cloned()
+    }
+
+    /// Every table, in the order they were created.
+    pub fn tables(&self) -> Vec<Arc<TableDef>> {
+        let mut tables: Vec<_> = self.cache.read().tables.values().cloned().collect();
+        tables.sort_by_key(|table| table.id);
+        tables
+    }
+
+    /// The sequence with this name, ignoring case.
+    pub fn sequence(&self, name: &str) -> Option<Arc<SequenceDef>> {
+        let cache = self.cache.read();
+        let entry = cache.sequence(name).ok()?;
+        Some(entry.def.clone())
+    }
+
+    /// The heap that the rows of a heap table are in, which is opened the first
+    /// time it's asked for.
+    pub fn heap(&self, table: &TableDef) -> Result<Arc<HeapFile>, Error> {
+        if table.engine != StorageEngine::Heap {
+            return Err(Error::NotAHeap(table.name.clone()));
+        }
+
+        let mut heaps = self.heaps.lock();
+        if let Some(heap) = heaps.get(&table.file_name) {
+            return Ok(heap.clone());
+        }
+
+        let heap = Arc::new(HeapFile::open(self.files.clone(), &table.file_name)?);
+        heaps.insert(table.file_name.clone(), heap.clone());
+        Ok(heap)
+    }
+
+    /// The LSM tree that the rows of an LSM table are in, which is opened, and
+    /// its worker started, the first time it's asked for.
+    pub fn lsm(&self, table: &TableDef) -> Result<Arc<LsmTree>, Error> {
+        if table.engine != StorageEngine::Lsm {
+            return Err(Error::NotAnLsmTree(table.name.clone()));
+        }
+
+        let mut trees = self.trees.lock();
+        if let Some(tree) = trees.get(&table.file_name) {
+            return Ok(tree.clone());
+        }
+
+        let tree = Arc::new(LsmTree::open(
+            self.files.clone(),
+            self.wal.clone(),
+            &table.file_name,
+            LsmOptions::default(),
+        )?);
+        trees.insert(table.file_name.clone(), tree.clone());
+        Ok(tree)
+    }
+
+    /// The columnar table that the rows of a columnar table are in. It keeps
+    /// nothing in memory, so it's opened afresh, with the table's columns as
+    /// they are now, every time.
+    pub fn columnar(&self, table: &TableDef) -> Result<ColumnarTable, Error> {
+        if table.engine != StorageEngine::Columnar {
+            return Err(Error::NotColumnar(table.name.clone()));
+        }
+
+        Ok(ColumnarTable::open(
+            self.files.clone(),
+            &table.file_name,
+            table.schema.clone(),
+        )?)
+    }
+
+    /// Scan just the values of `columns` of a columnar table, in every row
+    /// that passes every filter. See [`ColumnarTable::scan`].
+    pub fn scan_columns(
+        &self,
+        table: &TableDef,
+        columns: &[&str],
+        filters: &[Filter],
+    ) -> Result<ColumnScan, Error> {
+        let columns = column_positions(table, columns)?;
+        Ok(self.columnar(table)?.scan(&columns, filters)?)
+    }
+
+    /// Start changing the catalog, waiting for any other `Ddl` to finish first.
+    pub fn begin(&self) -> Result<Ddl<'_>, Error> {
+        let guard = self.ddl.lock();
+        let cache = Cache::clone(&self.cache.read());
+
+        Ok(Ddl {
+            catalog: self,
+            txn: self.wal.begin()?,
+            cache,
+            dropped: vec![],
+            _guard: guard,
+        })
+    }
+}
+
+/// A transaction that changes the catalog. Its changes are seen by the
+/// catalog once it's committed, and by its own methods straight away.
+///
+/// Every method checks that its change can be made before making any of it,
+/// so one that fails has changed nothing, unless it failed because of I/O.
+/// A `Ddl` that's dropped without being committed or aborted is rolled back
+/// by recovery, like a [`Txn`].
+pub struct Ddl<'c> {
+    catalog: &'c Catalog,
+    txn: Txn<'c>,
+    cache: Cache,
+    /// Files to delete once this commits.
+    dropped: Vec<DroppedFile>,
+    _guard: MutexGuard<'c, ()>,
+}
+
+enum DroppedFile {
+    Heap(String),
+    Lsm(String),
+    Columnar(String),
+    Index(String),
+}
+
+impl<'c> Ddl<'c> {
+    /// The transaction that the catalog is changed in, which anything else that
+    /// should commit or abort along with the change can be done in too.
+    pub fn txn(&self) -> &Txn<'c> {
+        &self.txn
+    }
+
+    /// The table with this name, ignoring case, with this transaction's
+    /// changes.
+    pub fn table(&self, name: &str) -> Option<&TableDef> {
+        self.cache.table(name).ok().map(|table| &**table)
+    }
+
+    /// The sequence with this name, ignoring case, with this transaction's
+    /// changes.
+    pub fn sequence(&self, name: &str) -> Option<&SequenceDef> {
+        self.cache.sequence(name).ok().map(|entry| &*entry.def)
+    }
+
+    /// Add a table with the columns of `schema`, and a default for each of them,
+    /// which is NULL for none, stored by `engine`.
+    pub fn create_table(
+        &mut self,
+        name: &str,
+        schema: Schema,
+        defaults: Vec<Value>,
+        engine: StorageEngine,
+    ) -> Result<&TableDef, Error> {
+        if self.cache.tables.contains_key(&name.to_lowercase()) {
+            return Err(Error::TableExists(name.to_owned()));
+        }
+        if defaults.len() != schema.len() {
+            return Err(Error::WrongDefaultCount {
+                expected: schema.len(),
+                got: defaults.len(),
+            });
+        }
+        for (column, default) in schema.columns().iter().zip(&defaults) {
+            match default.data_type() {
+                Some(data_type) if data_type != column.data_type =>
+                    return Err(ferrodb_row::Error::TypeMismatch {
+                        column: column.name.clone(),
+                        expected: column.data_type,
+                        got: data_type,
+                    }
+                    .into()),
+                _ => {},
+            }
+        }
+
+        let id = self.cache.next_id();
+        let file_name = format!("table.{id}");
+
+        // A file that's already there is left over from a drop of a table
+        // with the same ID that crashed before it could delete it, which could
+        // have been stored any of the ways. An LSM tree's SSTables are only
+        // found through its manifest, so they go first, and the heap's delete
+        // takes care of a columnar table's one file.
+        LsmTree::delete_files(&self.catalog.files, &file_name)?;
+        HeapFile::delete_files(&self.catalog.files, &file_name)?;
+
+        let table = TableDef {
+            id,
+            name: name.to_owned(),
+            schema,
+            defaults: defaults.into_iter().map(ColumnDefault::Value).collect(),
+            engine,
+            file: self.catalog.files.id(&file_name),
+            file_name,
+            indexes: vec![],
+            constraints: vec![],
+        };
+        let rows = self.catalog.system.insert_table(&self.txn, &table)?;
+
+        self.cache.rows.insert(id, rows);
+        let table = self
+            .cache
+            .tables
+            .entry(name.to_lowercase())
+            .or_insert(Arc::new(table));
+        Ok(table)
+    }
+
+    /// Drop a table, along with its indexes, constraints, and the sequences
+    /// that were made for its columns.
+    pub fn drop_table(&mut self, name: &str) -> Result<(), Error> {
+        let table = self.cache.table(name)?.clone();
+
+        let owned: Vec<_> = self
+            .cache
+            .sequences
+            .values()
+            .filter(|entry| entry.def.owner == Some(table.id))
+            .map(|entry| entry.def.name.clone())
+            .collect();
+        for sequence in owned {
+            self.delete_sequence(&sequence)?;
+        }
+
+        let rows = self.cache.rows.remove(&table.id).unwrap();
+        self.catalog.system.delete_table(&self.txn, &rows)?;
+
+        self.cache.tables.remove(&name.to_lowercase());
+        self.dropped.push(match table.engine {
+            StorageEngine::Heap => DroppedFile::Heap(table.file_name.clone()),
+            StorageEngine::Lsm => DroppedFile::Lsm(table.file_name.clone()),
+            StorageEngine::Columnar => DroppedFile::Columnar(table.file_name.clone()),
+        });
+        for index in &table.indexes {
+            self.dropped.push(DroppedFile::Index(index.file_name.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Add a column to the end of a table. Rows that are already in the table
+    /// have it as NULL, so it has to be nullable, and it has no default.
+    pub fn add_column(&mut self, table: &str, column: Column) -> Result<(), Error> {
+        let mut def = TableDef::clone(self.cache.table(table)?);
+        def.schema.add_column(column.clone())?;
+        def.defaults.push(ColumnDefault::Value(Value::Null));
+
+        let position = def.schema.len() - 1;
+        let row = self.catalog.system.insert_column(
+            &self.txn,
+            def.id,
+            position,
+            &column,
+            &def.defaults[position],
+        )?;
+
+        self.cache.rows.get_mut(&def.id).unwrap().columns.push(row);
+        self.cache.tables.insert(table.to_lowercase(), Arc::new(def));
+        Ok(())
+    }
+
+    /// Add an index on `columns` of a table, which is empty. It's filled with
+    /// the rows that are already in the table by [`Ddl::build_index`]. Only
+    /// heap tables can have indexes, since nothing else keeps them up to date.
+    pub fn create_index(
+        &mut self,
+        table: &str,
+        name: &str,
+        kind: IndexKind,
+        columns: &[&str],
+        unique: bool,
+    ) -> Result<&IndexDef, Error> {
+        if self.cache.index_table(name).is_some() {
+            return Err(Error::IndexExists(name.to_owned()));
+        }
+        if columns.is_empty() {
+            return Err(Error::NoIndexColumns(name.to_owned()));
+        }
+
+        let mut def = TableDef::clone(self.cache.table(table)?);
+        if def.engine != StorageEngine::Heap {
+            return Err(Error::UnindexableEngine {
+                table: def.name.clone(),
+                engine: def.engine,
+            });
+        }
+
+        let columns = column_positions(&def, columns)?;
+        if kind == IndexKind::RTree {
+            if unique {
+                return Err(Error::UniqueRTree(name.to_owned()));
+            }
+            if !spatial::is_box(&def.schema, &columns) {
+                return Err(Error::RTreeColumns(name.to_owned()));
+            }
+        }
+
+        let id = self.cache.next_id();
+        let file_name = format!("index.{id}");
+
+        // Like for a table, anything that's already there is left over from a
+        // drop that crashed.
+        let files = &self.catalog.files;
+        files.delete(files.id(&file_name))?;
+
+        let index = IndexDef {
+            id,
+            name: name.to_owned(),
+            kind,
+            columns,
+            unique,
+            file: files.id(&file_name),
+            file_name,
+        };
+        let row = self.catalog.system.insert_index(&self.txn, def.id, &index)?;
+
+        self.cache.rows.get_mut(&def.id).unwrap().indexes.push(row);
+        def.indexes.push(index);
+        let def = Arc::new(def);
+        self.cache.tables.insert(table.to_lowercase(), def);
+
+        Ok(self.cache.table(table)?.indexes.last().unwrap())
+    }
+
+    /// Drop an index, which mustn't be used by a constraint.
+    pub fn drop_index(&mut self, name: &str) -> Result<(), Error> {
+        let Some(table) = self.cache.index_table(name)
+            else { return Err(Error::NoSuchIndex(name.to_owned())); };
+
+        let mut def = TableDef::clone(table);
+        let position = def
+            .indexes
+            .iter()
+            .position(|index| index.name.eq_ignore_ascii_case(name))
+            .unwrap();
+        let index = &def.indexes[position];
+
+        if let Some(constraint) = def
+            .constraints
+            .iter()
+            .find(|constraint| constraint.index == Some(index.id))
+        {
+            return Err(Error::IndexInUse {
+                index: index.name.clone(),
+                constraint: constraint.name.clone(),
+            });
+        }
+
+        let rows = self.cache.rows.get_mut(&def.id).unwrap();
+        self.catalog.system.indexes.delete(&self.txn, rows.indexes[position])?;
+        rows.indexes.remove(position);
+
+        let index = def.indexes.remove(position);
+        self.dropped.push(DroppedFile::Index(index.file_name));
+        self.cache.tables.insert(def.name.to_lowercase(), Arc::new(def));
+        Ok(())
+    }
+
+    /// Add a constraint on `columns` of a table, checked with `index` if
+    /// there's one. Whether the rows that are already in the table meet it is
+    /// up to the caller.
+    pub fn add_constraint(
+        &mut self,
+        table: &str,
+        name: &str,
+        kind: ConstraintKind,
+        columns: &[&str],
+        index: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut def = TableDef::clone(self.cache.table(table)?);
+
+        if def.constraint(name).is_some() {
+            return Err(Error::ConstraintExists {
+                table: def.name,
+                constraint: name.to_owned(),
+            });
+        }
+
+        let columns = column_positions(&def, columns)?;
+        if kind == ConstraintKind::PrimaryKey {
+            if def.primary_key().is_some() {
+                return Err(Error::MultiplePrimaryKeys(def.name));
+            }
+            if let Some(&column) = columns.iter().find(|&&column| def.schema.columns()[column].nullable) {
+                return Err(Error::NullablePrimaryKey(def.schema.columns()[column].name.clone()));
+            }
+        }
+
+        let index = match index {
+            Some(index_name) => {
+                let Some(index) = def.index(index_name)
+                    else { return Err(Error::NoSuchIndex(index_name.to_owned())); };
+
+                if !index.unique || index.columns != columns {
+                    return Err(Error::WrongConstraintIndex {
+                        index: index.name.clone(),
+                        constraint: name.to_owned(),
+                    });
+                }
+
+                Some(index.id)
+            },
+            None => None,
+        };
+
+        let constraint = Constraint {
+            name: name.to_owned(),
+            kind,
+            columns,
+            index,
+        };
+        let row = self
+            .catalog
+            .system
+            .insert_constraint(&self.txn, def.id, &constraint)?;
+
+        self.cache.rows.get_mut(&def.id).unwrap().constraints.push(row);
+        def.constraints.push(constraint);
+        self.cache.tables.insert(table.to_lowercase(), Arc::new(def));
+        Ok(())
+    }
+
+    /// Add a sequence. If there's an `owner`, a table and one of its columns,
+    /// the sequence becomes the column's default, like for `SERIAL` columns,
+    /// and is dropped along with the table.
+    pub fn create_sequence(
+        &mut self,
+        name: &str,
+        options: &SequenceOptions,
+        owner: Option<(&str, &str)>,
+    ) -> Result<&SequenceDef, Error> {
+        if self.cache.sequence_ids.contains_key(&name.to_lowercase()) {
+            return Err(Error::SequenceExists(name.to_owned()));
+        }
+
+        let mut owner = match owner {
+            Some((table, column)) => {
+                let table = TableDef::clone(self.cache.table(table)?);
+                let position = column_positions(&table, &[column])?[0];
+                Some((table, position))
+            },
+            None => None,
+        };
+
+        let id = self.cache.next_id();
+        let def = options.to_def(id, name, owner.as_ref().map(|(table, _)| table.id))?;
+
+        if let Some((table, position)) = &owner {
+            let column = &table.schema.columns()[*position];
+            let range = match column.data_type {
+                DataType::Int => i32::MIN as i64..=i32::MAX as i64,
+                DataType::BigInt => i64::MIN..=i64::MAX,
+                data_type => {
+                    return Err(ferrodb_row::Error::TypeMismatch {
+                        column: column.name.clone(),
+                        expected: data_type,
+                        got: DataType::BigInt,
+                    }
+                    .into())
+                },
+            };
+
+            if !range.contains(&def.min) || !range.contains(&def.max) {
+                return Err(Error::InvalidSequence {
+                    sequence: name.to_owned(),
+                    reason: "its values don't all fit in its column",
+                });
+            }
+        }
+
+        let row = self.catalog.system.insert_sequence(&self.txn, &def)?;
+
+        if let Some((mut table, position)) = owner.take() {
+            table.defaults[position] = ColumnDefault::NextValue(id);
+
+            let rows = &self.cache.rows[&table.id];
+            self.catalog.system.update_column(
+                &self.txn,
+                rows.columns[position],
+                table.id,
+                position,
+                &table.schema.columns()[position],
+                &table.defaults[position],
+            )?;
+
+            self.cache
+                .tables
+                .insert(table.name.to_lowercase(), Arc::new(table));
+        }
+
+        self.cache.sequence_ids.insert(name.to_lowercase(), id);
+        let entry = self.cache.sequences.entry(id).or_insert(SequenceEntry {
+            def: Arc::new(def),
+            row,
+        });
+        Ok(&entry.def)
+    }
+
+    /// Drop a sequence, which mustn't be the default of any column.
+    pub fn drop_sequence(&mut self, name: &str) -> Result<(), Error> {
+        let id = self.cache.sequence(name)?.def.id;
+
+        for table in self.cache.tables.values() {
+            let position = table
+                .defaults
+                .iter()
+                .position(|default| *default == ColumnDefault::NextValue(id));
+
+            if let Some(position) = position {
+                return Err(Error::SequenceInUse {
+                    sequence: self.cache.sequences[&id].def.name.clone(),
+                    table: table.name.clone(),
+                    column: table.schema.columns()[position].name.clone(),
+                });
+            }
+        }
+
+        self.delete_sequence(name)
+    }
+
+    fn delete_sequence(&mut self, name: &str) -> Result<(), Error> {
+        let entry = self.cache.sequence(name)?.clone();
+
+        // Anything that's using the sequence while this is running has to find
+        // its row gone, rather than writing it back.
+        let mut states = self.catalog.sequences.lock();
+        self.catalog.system.sequences.delete(&self.txn, entry.row)?;
+        states.remove(&entry.def.id);
+
+        self.cache.sequence_ids.remove(&name.to_lowercase());
+        self.cache.sequences.remove(&entry.def.id);
+        Ok(())
+    }
+
+    /// Commit the changes, and then delete the files of anything that was
+    /// dropped.
+    pub fn commit(self) -> Result<Lsn, Error> {
+        let Ddl {
+            catalog,
+            txn,
+            cache,
+            dropped,
+            _guard,
+        } = self;
+
+        let lsn = txn.commit()?;
+        *catalog.cache.write() = Arc::new(cache);
+
+        if !dropped.is_empty() {
+            // Recovery would bring the files back if it redid changes to them,
+            // so it has to start from after they're gone.
+            catalog.wal.checkpoint(&catalog.files)?;
+
+            for file in dropped {
+                match file {
+                    DroppedFile::Heap(name) => {
+                        catalog.heaps.lock().remove(&name);
+                        HeapFile::delete_files(&catalog.files, &name)?
+                    },
+                    DroppedFile::Lsm(name) => {
+                        // Nobody else can be using the tree by now, so this stops its
+                        // worker before any of its files are gone.
+                        catalog.trees.lock().remove(&name);
+                        LsmTree::delete_files(&catalog.files, &name)?
+                    },
+                    DroppedFile::Columnar(name) => ColumnarTable::delete_files(&catalog.files, &name)?,
+                    DroppedFile::Index(name) => catalog.files.delete(catalog.files.id(&name))?,
+                }
+            }
+        }
+
+        Ok(lsn)
+    }
+
+    /// Roll back the changes.
+    pub fn abort(self) -> Result<Lsn, Error> {
+        Ok(self.txn.abort(&self.catalog.files)?)
+    }
+}
+
+/// Where each of `columns` is in a table.
+fn column_positions(table: &TableDef, columns: &[&str]) -> Result<Vec<usize>, Error> {
+    columns
+        .iter()
+        .map(|&column| {
+            table.schema.position(column).ok_or_else(|| Error::NoSuchColumn {
+                table: table.name.clone(),
+                column: column.to_owned(),
+            })
+        })
+        .collect()
+}
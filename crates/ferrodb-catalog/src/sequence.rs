@@ -0,0 +1,238 @@
+//! Sequences hand out values from batches reserved in memory. Each sequence's
+//! row in the catalog says where it carries on from after a restart, which is
+//! moved past a batch, durably, before any of the batch is handed out. So a
+//! value is never handed out twice, but the rest of a batch is skipped when
+//! the server crashes.
+//!
+//! Moving a sequence along isn't part of any transaction: values that are
+//! handed out stay handed out, whatever happens to what they were used for.
+
+use std::collections::hash_map::Entry;
+use std::sync::Arc;
+
+use ferrodb_heap::RecordId;
+use ferrodb_row::{DataType, Value};
+
+use crate::{Catalog, ColumnDefault, Error, ObjectId, TableDef};
+
+/// How many values are reserved at a time by default.
+const DEFAULT_CACHE: i64 = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SequenceDef {
+    pub id: ObjectId,
+    pub name: String,
+    pub start: i64,
+    /// What's added to each value to get the next one, which is negative for
+    /// a descending sequence.
+    pub increment: i64,
+    pub min: i64,
+    pub max: i64,
+    /// How many values are reserved at a time, which is also how many can be
+    /// skipped by a crash.
+    pub cache: i64,
+    /// Whether the sequence goes back to the start of its range once it runs
+    /// out, rather than failing.
+    pub cycle: bool,
+    /// The table that the sequence is dropped along with, if it was made for
+    /// one of its columns.
+    pub owner: Option<ObjectId>,
+}
+
+impl SequenceDef {
+    /// The value after `value`, or `None` if the sequence has run out.
+    pub fn step(&self, value: i64) -> Option<i64> {
+        match value.checked_add(self.increment) {
+            Some(next) if (self.min..=self.max).contains(&next) => Some(next),
+            _ if !self.cycle => None,
+            _ if self.increment > 0 => Some(self.min),
+            _ => Some(self.max),
+        }
+    }
+}
+
+/// How a sequence counts, where `None` is the default: counting up by one
+/// from 1, or down by one from -1 if the increment is negative.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SequenceOptions {
+    pub increment: Option<i64>,
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    pub start: Option<i64>,
+    pub cache: Option<i64>,
+    pub cycle: bool,
+}
+
+impl SequenceOptions {
+    pub(crate) fn to_def(&self, id: ObjectId, name: &str, owner: Option<ObjectId>) -> Result<SequenceDef, Error> {
+        let invalid = |reason| Error::InvalidSequence {
+            sequence: name.to_owned(),
+            reason,
+        };
+
+        let increment = self.increment.unwrap_or(1);
+        if increment == 0 {
+            return Err(invalid("its increment can't be 0"));
+        }
+
+        let (min, max) = if increment > 0 {
+            (self.min.unwrap_or(1), self.max.unwrap_or(i64::MAX))
+        } else {
+            (self.min.unwrap_or(i64::MIN), self.max.unwrap_or(-1))
+        };
+        if min >= max {
+            return Err(invalid("its minimum has to be less than its maximum"));
+        }
+
+        let start = self.start.unwrap_or(if increment > 0 { min } else { max });
+        if !(min..=max).contains(&start) {
+            return Err(invalid("it has to start between its minimum and maximum"));
+        }
+
+        let cache = self.cache.unwrap_or(DEFAULT_CACHE);
+        if cache < 1 {
+            return Err(invalid("its cache has to be at least 1"));
+        }
+
+        Ok(SequenceDef {
+            id,
+            name: name.to_owned(),
+            start,
+            increment,
+            min,
+            max,
+            cache,
+            cycle: self.cycle,
+            owner,
+        })
+    }
+}
+
+/// Where a sequence is up to.
+pub(crate) struct SequenceState {
+    /// The next value to hand out, or `None` if the sequence has run out.
+    next: Option<i64>,
+    /// How many values from `next` on are reserved, and can be handed out
+    /// without writing anything.
+    reserved: i64,
+}
+
+impl Catalog {
+    /// Hand out the next value of a sequence.
+    pub fn next_value(&self, sequence: ObjectId) -> Result<i64, Error> {
+        let (def, row) = self.sequence_entry(sequence)?;
+        let mut states = self.sequences.lock();
+
+        let state = match states.entry(sequence) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(SequenceState {
+                next: self.system.sequence_next(row, &def)?,
+                reserved: 0,
+            }),
+        };
+
+        if state.reserved == 0 {
+            let Some(first) = state.next
+                else {
+                    let limit = if def.increment > 0 { def.max } else { def.min };
+                    return Err(Error::SequenceExhausted {
+                        sequence: def.name.clone(),
+                        limit,
+                    });
+                };
+
+            // Reserve a batch, or whatever's left if that's less.
+            let mut after = Some(first);
+            let mut count = 0;
+            while let Some(value) = after {
+                if count == def.cache {
+                    break;
+                }
+
+                after = def.step(value);
+                count += 1;
+            }
+
+            self.write_sequence_next(&def, row, after)?;
+            state.reserved = count;
+        }
+
+        let value = state.next.unwrap();
+        state.next = def.step(value);
+        state.reserved -= 1;
+        Ok(value)
+    }
+
+    /// Fill in the columns of a new row of `table` that weren't given a value
+    /// (`None`) with their defaults. A column that defaults to a sequence
+    /// takes its next value, so this is called once for every row that's
+    /// built, right before it's stored.
+    pub fn fill_defaults(&self, table: &TableDef, row: Vec<Option<Value>>) -> Result<Vec<Value>, Error> {
+        let columns = table.schema.columns();
+        if row.len() != columns.len() {
+            return Err(Error::WrongValueCount {
+                table: table.name.clone(),
+                expected: columns.len(),
+                got: row.len(),
+            });
+        }
+
+        row.into_iter()
+            .zip(columns.iter().zip(&table.defaults))
+            .map(|(value, (column, default))| match (value, default) {
+                (Some(value), _) => Ok(value),
+                (None, ColumnDefault::Value(value)) => Ok(value.clone()),
+                (None, ColumnDefault::NextValue(sequence)) => {
+                    let value = self.next_value(*sequence)?;
+
+                    // A sequence that a column defaults to was checked to only have
+                    // values that fit in it when it was created.
+                    Ok(match column.data_type {
+                        DataType::Int => Value::Int(value as i32),
+                        _ => Value::BigInt(value),
+                    })
+                },
+            })
+            .collect()
+    }
+
+    /// Make `value` the last value that was handed out, so the next one is
+    /// the value after it, or, if `is_called` is false, `value` itself.
+    pub fn set_value(&self, sequence: ObjectId, value: i64, is_called: bool) -> Result<(), Error> {
+        let (def, row) = self.sequence_entry(sequence)?;
+        if !(def.min..=def.max).contains(&value) {
+            return Err(Error::SequenceValueOutOfRange {
+                sequence: def.name.clone(),
+                value,
+            });
+        }
+
+        let next = if is_called { def.step(value) } else { Some(value) };
+
+        let mut states = self.sequences.lock();
+        self.write_sequence_next(&def, row, next)?;
+        states.insert(sequence, SequenceState { next, reserved: 0 });
+        Ok(())
+    }
+
+    fn sequence_entry(&self, sequence: ObjectId) -> Result<(Arc<SequenceDef>, RecordId), Error> {
+        let cache = self.cache.read();
+        let Some(entry) = cache.sequences.get(&sequence)
+            else { return Err(Error::NoSuchSequence(sequence.to_string())); };
+
+        Ok((entry.def.clone(), entry.row))
+    }
+
+    /// Durably change where a sequence carries on from after a restart.
+    fn write_sequence_next(&self, def: &SequenceDef, row: RecordId, next: Option<i64>) -> Result<(), Error> {
+        let txn = self.wal.begin()?;
+
+        if let Err(e) = self.system.update_sequence(&txn, row, def, next) {
+            txn.abort(&self.files)?;
+            return Err(e);
+        }
+
+        txn.commit()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+//! Spatial searches of a table's rows, by the box that some of their number
+//! columns make up: two columns are the `x` and `y` of a point, and four are
+//! the `x` and `y` of two opposite corners of a box. An R-tree index is always
+//! on columns like that, and its entries are each row's box, with the row's
+//! record ID as the value.
+//!
+//! A search uses an R-tree index on the same columns if the table has one,
+//! and otherwise checks every row. Rows with a NULL in any of the columns, or
+//! a `Double` that's NaN or infinite, have no box, so they're left out of
+//! R-tree indexes, and searches never find them.
+
+use ferrodb_heap::{HeapFile, RecordId};
+use ferrodb_row::{DataType, RowRef, Schema, Value, ValueRef};
+use ferrodb_rtree::{Query, RTree, Rect};
+
+use crate::{column_positions, Catalog, Error, IndexDef, IndexKind, TableDef};
+
+impl Catalog {
+    /// Every row of a heap table whose box, made up of `columns`, is one that
+    /// `query` is looking for, in no particular order.
+    pub fn search_boxes(&self, table: &str, columns: &[&str], query: Query) -> Result<Vec<Vec<Value>>, Error> {
+        let table = self.cache.read().table(table)?.clone();
+        let columns = box_columns(&table, columns)?;
+        let heap = self.heap(&table)?;
+
+        if let Some(index) = rtree_index(&table, &columns) {
+            let tree = RTree::open(self.files.clone(), &index.file_name)?;
+            let rids = tree.search(query)?.into_iter().map(|(_, value)| value);
+            return read_rows(&heap, &table, index, rids);
+        }
+
+        let mut rows = vec![];
+        for row in heap.scan()? {
+            let (_, row) = row?;
+            let row = RowRef::new(&table.schema, &row)?;
+
+            if matches!(row_rect(&row, &columns), Some(rect) if query.matches(&rect)) {
+                rows.push(row.to_values());
+            }
+        }
+
+        Ok(rows)
+    }
+
+    /// The `k` rows of a heap table whose boxes, made up of `columns`, are the
+    /// nearest to the point `(x, y)`, nearest first.
+    pub fn nearest(&self, table: &str, columns: &[&str], x: f64, y: f64, k: usize) -> Result<Vec<Vec<Value>>, Error> {
+        let table = self.cache.read().table(table)?.clone();
+        let columns = box_columns(&table, columns)?;
+        let heap = self.heap(&table)?;
+
+        let point = Rect::point(x, y);
+        if !point.is_valid() {
+            return Err(ferrodb_rtree::Error::InvalidRect(point).into());
+        }
+
+        if let Some(index) = rtree_index(&table, &columns) {
+            let tree = RTree::open(self.files.clone(), &index.file_name)?;
+            let rids = tree.nearest(x, y, k)?.into_iter().map(|(_, value)| value);
+            return read_rows(&heap, &table, index, rids);
+        }
+
+        let mut rows = vec![];
+        for row in heap.scan()? {
+            let (_, row) = row?;
+            let row = RowRef::new(&table.schema, &row)?;
+
+            if let Some(rect) = row_rect(&row, &columns) {
+                rows.push((rect.distance(x, y), row.to_values()));
+            }
+        }
+
+        rows.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        Ok(rows.into_iter().take(k).map(|(_, values)| values).collect())
+    }
+}
+
+/// Whether `columns` of a table with this schema make up a point or a box.
+pub(crate) fn is_box(schema: &Schema, columns: &[usize]) -> bool {
+    let numbers = columns.iter().all(|&column| {
+        matches!(
+            schema.columns()[column].data_type,
+            DataType::Int | DataType::BigInt | DataType::Double
+        )
+    });
+
+    numbers && matches!(columns.len(), 2 | 4)
+}
+
+/// The box that `columns` of a row make up, which have to be ones that
+/// [`is_box`] is true for, or `None` if any of them are NULL, or it isn't a
+/// box that can go in an R-tree (see [`Rect::is_valid`]).
+pub(crate) fn row_rect(row: &RowRef<'_>, columns: &[usize]) -> Option<Rect> {
+    let coordinates = columns
+        .iter()
+        .map(|&column| match row.get(column) {
+            ValueRef::Int(value) => Some(value as f64),
+            ValueRef::BigInt(value) => Some(value as f64),
+            ValueRef::Double(value) => Some(value),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    // `Rect::new` would quietly take the other corner's coordinate for a NaN,
+    // so they're checked before it sees them.
+    if !coordinates.iter().all(|coordinate| coordinate.is_finite()) {
+        return None;
+    }
+
+    let rect = match coordinates[..] {
+        [x, y] => Rect::point(x, y),
+        [x1, y1, x2, y2] => Rect::new(x1, y1, x2, y2),
+        _ => return None,
+    };
+
+    rect.is_valid().then_some(rect)
+}
+
+/// Where each of `columns` is in a table, if they make up a point or a box.
+fn box_columns(table: &TableDef, columns: &[&str]) -> Result<Vec<usize>, Error> {
+    let positions = column_positions(table, columns)?;
+
+    if !is_box(&table.schema, &positions) {
+        return Err(Error::NotABox {
+            table: table.name.clone(),
+            columns: columns.join(", "),
+        });
+    }
+
+    Ok(positions)
+}
+
+/// An R-tree index of a table on exactly these columns, in this order.
+fn rtree_index<'t>(table: &'t TableDef, columns: &[usize]) -> Option<&'t IndexDef> {
+    table
+        .indexes
+        .iter()
+        .find(|index| index.kind == IndexKind::RTree && index.columns == columns)
+}
+
+/// The rows at `rids`, which `index` found, in order, leaving out any that
+/// aren't there anymore.
+fn read_rows(
+    heap: &HeapFile,
+    table: &TableDef,
+    index: &IndexDef,
+    rids: impl IntoIterator<Item = Vec<u8>>,
+) -> Result<Vec<Vec<Value>>, Error> {
+    let mut rows = vec![];
+
+    for rid in rids {
+        let Some(rid) = RecordId::from_bytes(&rid)
+            else { return Err(Error::CorruptIndex(index.name.clone())); };
+
+        if let Some(row) = heap.get(rid)? {
+            rows.push(table.schema.decode(&row)?);
+        }
+    }
+
+    Ok(rows)
+}
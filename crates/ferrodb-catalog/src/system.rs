@@ -0,0 +1,577 @@
+//! The catalog is kept in four heaps, with rows in the row format:
+//!
+//! ```text
+//! catalog.tables:      id BIGINT, name TEXT, engine TEXT, file TEXT
+//! catalog.columns:     table BIGINT, position INT, name TEXT, type TEXT,
+//!                      nullable BOOLEAN, default BLOB NULL,
+//!                      sequence BIGINT NULL
+//! catalog.indexes:     id BIGINT, table BIGINT, name TEXT, kind TEXT,
+//!                      columns BLOB, unique BOOLEAN, file TEXT
+//! catalog.constraints: table BIGINT, name TEXT, kind TEXT, columns BLOB,
+//!                      index BIGINT NULL
+//! catalog.sequences:   id BIGINT, name TEXT, start BIGINT, increment BIGINT,
+//!                      min BIGINT, max BIGINT, cache BIGINT, cycle BOOLEAN,
+//!                      owner BIGINT NULL, next BIGINT NULL
+//! ```
+//!
+//! Types, kinds and storage engines are kept as their names. A list of columns is their
+//! positions in the table, as a `u16` each, little-endian. A constant default
+//! is encoded as a row with just that column, and is NULL if there isn't one,
+//! and a column that defaults to the next value of a sequence has the
+//! sequence's ID. A sequence's `next` is the value that it carries on from
+//! after a restart, which is NULL once it's run out.
+
+use std::collections::BTreeMap;
+use std::slice;
+use std::sync::Arc;
+
+use ferrodb_fs::FileManager;
+use ferrodb_heap::{HeapFile, RecordId};
+use ferrodb_row::{Column, DataType, Schema, Value};
+use ferrodb_wal::Txn;
+
+use crate::{
+    ColumnDefault, Constraint, ConstraintKind, Error, IndexDef, ObjectId, SequenceDef, TableDef,
+};
+
+/// Where the rows that describe a table are in the system tables.
+#[derive(Clone, Debug)]
+pub(crate) struct TableRows {
+    pub(crate) table: RecordId,
+    pub(crate) columns: Vec<RecordId>,
+    /// One for each of the table's indexes, in the same order.
+    pub(crate) indexes: Vec<RecordId>,
+    /// One for each of the table's constraints, in the same order.
+    pub(crate) constraints: Vec<RecordId>,
+}
+
+pub(crate) struct SystemTables {
+    pub(crate) tables: SystemTable,
+    pub(crate) columns: SystemTable,
+    pub(crate) indexes: SystemTable,
+    pub(crate) constraints: SystemTable,
+    pub(crate) sequences: SystemTable,
+}
+
+impl SystemTables {
+    /// Every system table, with its name.
+    pub(crate) fn all(&self) -> [(&'static str, &SystemTable); 5] {
+        [
+            ("catalog.tables", &self.tables),
+            ("catalog.columns", &self.columns),
+            ("catalog.indexes", &self.indexes),
+            ("catalog.constraints", &self.constraints),
+            ("catalog.sequences", &self.sequences),
+        ]
+    }
+
+    pub(crate) fn open(files: &Arc<FileManager>) -> Result<SystemTables, Error> {
+        use DataType::*;
+
+        Ok(SystemTables {
+            tables: SystemTable::open(files, "catalog.tables", &[
+                ("id", BigInt, false),
+                ("name", Text, false),
+                ("engine", Text, false),
+                ("file", Text, false),
+            ])?,
+            columns: SystemTable::open(files, "catalog.columns", &[
+                ("table", BigInt, false),
+                ("position", Int, false),
+                ("name", Text, false),
+                ("type", Text, false),
+                ("nullable", Bool, false),
+                ("default", Blob, true),
+                ("sequence", BigInt, true),
+            ])?,
+            indexes: SystemTable::open(files, "catalog.indexes", &[
+                ("id", BigInt, false),
+                ("table", BigInt, false),
+                ("name", Text, false),
+                ("kind", Text, false),
+                ("columns", Blob, false),
+                ("unique", Bool, false),
+                ("file", Text, false),
+            ])?,
+            constraints: SystemTable::open(files, "catalog.constraints", &[
+                ("table", BigInt, false),
+                ("name", Text, false),
+                ("kind", Text, false),
+                ("columns", Blob, false),
+                ("index", BigInt, true),
+            ])?,
+            sequences: SystemTable::open(files, "catalog.sequences", &[
+                ("id", BigInt, false),
+                ("name", Text, false),
+                ("start", BigInt, false),
+                ("increment", BigInt, false),
+                ("min", BigInt, false),
+                ("max", BigInt, false),
+                ("cache", BigInt, false),
+                ("cycle", Bool, false),
+                ("owner", BigInt, true),
+                ("next", BigInt, true),
+            ])?,
+        })
+    }
+
+    /// Add the rows for a new table, along with its columns, but not its
+    /// indexes or constraints.
+    pub(crate) fn insert_table(&self, txn: &Txn<'_>, table: &TableDef) -> Result<TableRows, Error> {
+        let row = self.tables.insert(txn, vec![
+            Value::BigInt(table.id.0 as i64),
+            Value::Text(table.name.clone()),
+            Value::Text(table.engine.to_string()),
+            Value::Text(table.file_name.clone()),
+        ])?;
+
+        let mut columns = vec![];
+        for (position, (column, default)) in table.schema.columns().iter().zip(&table.defaults).enumerate() {
+            columns.push(self.insert_column(txn, table.id, position, column, default)?);
+        }
+
+        Ok(TableRows {
+            table: row,
+            columns,
+            indexes: vec![],
+            constraints: vec![],
+        })
+    }
+
+    pub(crate) fn insert_column(
+        &self,
+        txn: &Txn<'_>,
+        table: ObjectId,
+        position: usize,
+        column: &Column,
+        default: &ColumnDefault,
+    ) -> Result<RecordId, Error> {
+        self.columns
+            .insert(txn, column_row(table, position, column, default)?)
+    }
+
+    pub(crate) fn update_column(
+        &self,
+        txn: &Txn<'_>,
+        row: RecordId,
+        table: ObjectId,
+        position: usize,
+        column: &Column,
+        default: &ColumnDefault,
+    ) -> Result<(), Error> {
+        self.columns
+            .update(txn, row, column_row(table, position, column, default)?)
+    }
+
+    pub(crate) fn insert_index(&self, txn: &Txn<'_>, table: ObjectId, index: &IndexDef) -> Result<RecordId, Error> {
+        self.indexes.insert(txn, vec![
+            Value::BigInt(index.id.0 as i64),
+            Value::BigInt(table.0 as i64),
+            Value::Text(index.name.clone()),
+            Value::Text(index.kind.to_string()),
+            Value::Blob(encode_columns(&index.columns)),
+            Value::Bool(index.unique),
+            Value::Text(index.file_name.clone()),
+        ])
+    }
+
+    pub(crate) fn insert_constraint(
+        &self,
+        txn: &Txn<'_>,
+        table: ObjectId,
+        constraint: &Constraint,
+    ) -> Result<RecordId, Error> {
+        self.constraints.insert(txn, vec![
+            Value::BigInt(table.0 as i64),
+            Value::Text(constraint.name.clone()),
+            Value::Text(constraint.kind.to_string()),
+            Value::Blob(encode_columns(&constraint.columns)),
+            match constraint.index {
+                Some(index) => Value::BigInt(index.0 as i64),
+                None => Value::Null,
+            },
+        ])
+    }
+
+    pub(crate) fn insert_sequence(&self, txn: &Txn<'_>, sequence: &SequenceDef) -> Result<RecordId, Error> {
+        self.sequences
+            .insert(txn, sequence_row(sequence, Some(sequence.start)))
+    }
+
+    /// Change where a sequence carries on from after a restart.
+    pub(crate) fn update_sequence(
+        &self,
+        txn: &Txn<'_>,
+        row: RecordId,
+        sequence: &SequenceDef,
+        next: Option<i64>,
+    ) -> Result<(), Error> {
+        // Check that the sequence wasn't dropped by a `Ddl` that's still
+        // running.
+        self.sequence_next(row, sequence)?;
+        self.sequences
+            .update(txn, row, sequence_row(sequence, next))
+    }
+
+    /// Where a sequence carries on from after a restart.
+    pub(crate) fn sequence_next(&self, row: RecordId, sequence: &SequenceDef) -> Result<Option<i64>, Error> {
+        let values = self.sequences.get(row)?;
+
+        match values.as_deref() {
+            Some([Value::BigInt(id), .., next]) if *id as u64 == sequence.id.0 => match next {
+                Value::BigInt(next) => Ok(Some(*next)),
+                _ => Ok(None),
+            },
+            _ => Err(Error::NoSuchSequence(sequence.name.clone())),
+        }
+    }
+
+    /// Delete every row that describes a table.
+    pub(crate) fn delete_table(&self, txn: &Txn<'_>, rows: &TableRows) -> Result<(), Error> {
+        for &row in &rows.constraints {
+            self.constraints.delete(txn, row)?;
+        }
+        for &row in &rows.indexes {
+            self.indexes.delete(txn, row)?;
+        }
+        for &row in &rows.columns {
+            self.columns.delete(txn, row)?;
+        }
+        self.tables.delete(txn, rows.table)
+    }
+
+    /// Read every table out of the system tables, resolving the names of their
+    /// files with `files`.
+    pub(crate) fn load(&self, files: &FileManager) -> Result<Vec<(TableDef, TableRows)>, Error> {
+        let mut columns: BTreeMap<ObjectId, Vec<(usize, Column, ColumnDefault, RecordId)>> =
+            BTreeMap::new();
+        for (row, values) in self.columns.scan()? {
+            let [
+                Value::BigInt(table),
+                Value::Int(position),
+                Value::Text(name),
+                Value::Text(data_type),
+                Value::Bool(nullable),
+                default,
+                sequence,
+            ] = <[Value; 7]>::try_from(values).unwrap()
+                else { return Err(Error::Corrupt("a column's row doesn't match its schema")); };
+
+            let column = Column {
+                name,
+                data_type: data_type
+                    .parse()
+                    .map_err(|_| Error::Corrupt("a column has a type that doesn't exist"))?,
+                nullable,
+            };
+            let default = match (default, sequence) {
+                (_, Value::BigInt(sequence)) => ColumnDefault::NextValue(ObjectId(sequence as u64)),
+                (Value::Blob(default), _) => {
+                    ColumnDefault::Value(default_schema(&column)?.decode(&default)?.pop().unwrap())
+                },
+                _ => ColumnDefault::Value(Value::Null),
+            };
+
+            columns
+                .entry(ObjectId(table as u64))
+                .or_default()
+                .push((position as usize, column, default, row));
+        }
+
+        let mut tables = BTreeMap::new();
+        for (row, values) in self.tables.scan()? {
+            let [Value::BigInt(id), Value::Text(name), Value::Text(engine), Value::Text(file_name)] =
+                <[Value; 4]>::try_from(values).unwrap()
+                else { return Err(Error::Corrupt("a table's row doesn't match its schema")); };
+
+            let id = ObjectId(id as u64);
+            let mut table_columns = columns.remove(&id).unwrap_or_default();
+            table_columns.sort_by_key(|(position, ..)| *position);
+
+            let mut schema_columns = vec![];
+            let mut defaults = vec![];
+            let mut column_rows = vec![];
+            for (n, (position, column, default, row)) in table_columns.into_iter().enumerate() {
+                if position != n {
+                    return Err(Error::Corrupt("a table's columns aren't numbered in order"));
+                }
+
+                schema_columns.push(column);
+                defaults.push(default);
+                column_rows.push(row);
+            }
+
+            let table = TableDef {
+                id,
+                name,
+                schema: Schema::new(schema_columns)?,
+                defaults,
+                engine: engine
+                    .parse()
+                    .map_err(|_| Error::Corrupt("a table has a storage engine that doesn't exist"))?,
+                file: files.id(&file_name),
+                file_name,
+                indexes: vec![],
+                constraints: vec![],
+            };
+            let rows = TableRows {
+                table: row,
+                columns: column_rows,
+                indexes: vec![],
+                constraints: vec![],
+            };
+            tables.insert(id, (table, rows));
+        }
+
+        if !columns.is_empty() {
+            return Err(Error::Corrupt("there are columns of a table that doesn't exist"));
+        }
+
+        for (row, values) in self.indexes.scan()? {
+            let [
+                Value::BigInt(id),
+                Value::BigInt(table),
+                Value::Text(name),
+                Value::Text(kind),
+                Value::Blob(columns),
+                Value::Bool(unique),
+                Value::Text(file_name),
+            ] = <[Value; 7]>::try_from(values).unwrap()
+                else { return Err(Error::Corrupt("an index's row doesn't match its schema")); };
+
+            let Some((table, rows)) = tables.get_mut(&ObjectId(table as u64))
+                else { return Err(Error::Corrupt("there's an index on a table that doesn't exist")); };
+
+            table.indexes.push(IndexDef {
+                id: ObjectId(id as u64),
+                name,
+                kind: kind
+                    .parse()
+                    .map_err(|_| Error::Corrupt("an index has a kind that doesn't exist"))?,
+                columns: decode_columns(&columns, table.schema.len())?,
+                unique,
+                file: files.id(&file_name),
+                file_name,
+            });
+            rows.indexes.push(row);
+        }
+
+        for (row, values) in self.constraints.scan()? {
+            let [
+                Value::BigInt(table),
+                Value::Text(name),
+                Value::Text(kind),
+                Value::Blob(columns),
+                index,
+            ] = <[Value; 5]>::try_from(values).unwrap()
+                else { return Err(Error::Corrupt("a constraint's row doesn't match its schema")); };
+
+            let Some((table, rows)) = tables.get_mut(&ObjectId(table as u64))
+                else { return Err(Error::Corrupt("there's a constraint on a table that doesn't exist")); };
+
+            let index = match index {
+                Value::BigInt(index) => Some(ObjectId(index as u64)),
+                _ => None,
+            };
+            if let Some(index) = index {
+                if !table.indexes.iter().any(|def| def.id == index) {
+                    return Err(Error::Corrupt("a constraint uses an index that doesn't exist"));
+                }
+            }
+
+            table.constraints.push(Constraint {
+                name,
+                kind: match &*kind {
+                    "PRIMARY KEY" => ConstraintKind::PrimaryKey,
+                    "UNIQUE" => ConstraintKind::Unique,
+                    _ => return Err(Error::Corrupt("a constraint has a kind that doesn't exist")),
+                },
+                columns: decode_columns(&columns, table.schema.len())?,
+                index,
+            });
+            rows.constraints.push(row);
+        }
+
+        Ok(tables.into_values().collect())
+    }
+
+    pub(crate) fn load_sequences(&self) -> Result<Vec<(SequenceDef, RecordId)>, Error> {
+        let mut sequences = vec![];
+
+        for (row, values) in self.sequences.scan()? {
+            let [
+                Value::BigInt(id),
+                Value::Text(name),
+                Value::BigInt(start),
+                Value::BigInt(increment),
+                Value::BigInt(min),
+                Value::BigInt(max),
+                Value::BigInt(cache),
+                Value::Bool(cycle),
+                owner,
+                _,
+            ] = <[Value; 10]>::try_from(values).unwrap()
+                else { return Err(Error::Corrupt("a sequence's row doesn't match its schema")); };
+
+            let owner = match owner {
+                Value::BigInt(owner) => Some(ObjectId(owner as u64)),
+                _ => None,
+            };
+            sequences.push((
+                SequenceDef {
+                    id: ObjectId(id as u64),
+                    name,
+                    start,
+                    increment,
+                    min,
+                    max,
+                    cache,
+                    cycle,
+                    owner,
+                },
+                row,
+            ));
+        }
+
+        Ok(sequences)
+    }
+}
+
+/// One of the heaps that the catalog is kept in.
+pub(crate) struct SystemTable {
+    heap: HeapFile,
+    schema: Schema,
+}
+
+impl SystemTable {
+    fn open(
+        files: &Arc<FileManager>,
+        name: &str,
+        columns: &[(&str, DataType, bool)],
+    ) -> Result<SystemTable, Error> {
+        let columns = columns
+            .iter()
+            .map(|&(name, data_type, nullable)| Column {
+                name: name.to_owned(),
+                data_type,
+                nullable,
+            })
+            .collect();
+
+        Ok(SystemTable {
+            heap: HeapFile::open(files.clone(), name)?,
+            schema: Schema::new(columns)?,
+        })
+    }
+
+    pub(crate) fn heap(&self) -> &HeapFile {
+        &self.heap
+    }
+
+    fn insert(&self, txn: &Txn<'_>, values: Vec<Value>) -> Result<RecordId, Error> {
+        Ok(self.heap.insert(txn, &self.schema.encode(&values)?)?)
+    }
+
+    fn update(&self, txn: &Txn<'_>, row: RecordId, values: Vec<Value>) -> Result<(), Error> {
+        Ok(self.heap.update(txn, row, &self.schema.encode(&values)?)?)
+    }
+
+    fn get(&self, row: RecordId) -> Result<Option<Vec<Value>>, Error> {
+        match self.heap.get(row)? {
+            Some(row) => Ok(Some(self.schema.decode(&row)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) fn delete(&self, txn: &Txn<'_>, row: RecordId) -> Result<(), Error> {
+        if !self.heap.delete(txn, row)? {
+            return Err(Error::Corrupt("a row of the catalog is missing"));
+        }
+
+        Ok(())
+    }
+
+    fn scan(&self) -> Result<Vec<(RecordId, Vec<Value>)>, Error> {
+        let mut rows = vec![];
+
+        for row in self.heap.scan()? {
+            let (rid, row) = row?;
+            rows.push((rid, self.schema.decode(&row)?));
+        }
+
+        Ok(rows)
+    }
+}
+
+fn column_row(table: ObjectId, position: usize, column: &Column, default: &ColumnDefault) -> Result<Vec<Value>, Error> {
+    let (default, sequence) = match default {
+        ColumnDefault::Value(Value::Null) => (Value::Null, Value::Null),
+        ColumnDefault::Value(default) => (
+            Value::Blob(default_schema(column)?.encode(slice::from_ref(default))?),
+            Value::Null,
+        ),
+        ColumnDefault::NextValue(sequence) => (Value::Null, Value::BigInt(sequence.0 as i64)),
+    };
+
+    Ok(vec![
+        Value::BigInt(table.0 as i64),
+        Value::Int(position as i32),
+        Value::Text(column.name.clone()),
+        Value::Text(column.data_type.to_string()),
+        Value::Bool(column.nullable),
+        default,
+        sequence,
+    ])
+}
+
+fn sequence_row(sequence: &SequenceDef, next: Option<i64>) -> Vec<Value> {
+    vec![
+        Value::BigInt(sequence.id.0 as i64),
+        Value::Text(sequence.name.clone()),
+        Value::BigInt(sequence.start),
+        Value::BigInt(sequence.increment),
+        Value::BigInt(sequence.min),
+        Value::BigInt(sequence.max),
+        Value::BigInt(sequence.cache),
+        Value::Bool(sequence.cycle),
+        match sequence.owner {
+            Some(owner) => Value::BigInt(owner.0 as i64),
+            None => Value::Null,
+        },
+        match next {
+            Some(next) => Value::BigInt(next),
+            None => Value::Null,
+        },
+    ]
+}
+
+/// The schema that a column's default is encoded with.
+fn default_schema(column: &Column) -> Result<Schema, Error> {
+    Ok(Schema::new(vec![Column {
+        nullable: true,
+        ..column.clone()
+    }])?)
+}
+
+fn encode_columns(columns: &[usize]) -> Vec<u8> {
+    columns
+        .iter()
+        .flat_map(|&column| (column as u16).to_le_bytes())
+        .collect()
+}
+
+fn decode_columns(bytes: &[u8], column_count: usize) -> Result<Vec<usize>, Error> {
+    if !bytes.len().is_multiple_of(2) {
+        return Err(Error::Corrupt("a list of columns has an odd length"));
+    }
+
+    let columns: Vec<usize> = bytes
+        .chunks(2)
+        .map(|column| u16::from_le_bytes(column.try_into().unwrap()) as usize)
+        .collect();
+    if columns.iter().any(|&column| column >= column_count) {
+        return Err(Error::Corrupt("a list of columns has a column that doesn't exist"));
+    }
+
+    Ok(columns)
+}
@@ -0,0 +1,107 @@
+//! Vacuuming gives back the space in heaps that deleted and updated rows left
+//! behind, each heap in a transaction of its own. It holds the lock that a
+//! `Ddl` does, so that tables can't be dropped out from under it, and heaps
+//! that are left with empty pages at their end are cut short after taking a
+//! checkpoint, since recovery would bring the pages back otherwise.
+//!
+//! The catalog's own heaps are vacuumed along with the tables, when every
+//! table is.
+
+use std::sync::Arc;
+
+use ferrodb_heap::{HeapFile, VacuumStats};
+
+use crate::{Catalog, Error, StorageEngine};
+
+impl Catalog {
+    /// Vacuum the heap of `table`, or if there's no `table`, of every table
+    /// that's stored in one, and the catalog's own heaps. Returns what was
+    /// done to each heap, by its table's name.
+    pub fn vacuum(&self, table: Option<&str>) -> Result<Vec<(String, VacuumStats)>, Error> {
+        let _guard = self.ddl.lock();
+
+        let tables = match table {
+            Some(name) => vec![self.cache.read().table(name)?.clone()],
+            None => self
+                .tables()
+                .into_iter()
+                .filter(|table| table.engine == StorageEngine::Heap)
+                .collect(),
+        };
+        let heaps = tables
+            .iter()
+            .map(|table| Ok((table.name.clone(), self.heap(table)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        self.vacuum_heaps(heaps, table.is_none())
+    }
+
+    /// Vacuum every heap that's had at least `min_changes` of its rows updated
+    /// or deleted since it was last vacuumed, or opened. Returns what was done
+    /// to each heap, by its table's name.
+    pub fn autovacuum(&self, min_changes: usize) -> Result<Vec<(String, VacuumStats)>, Error> {
+        let _guard = self.ddl.lock();
+
+        let heaps = self.heaps.lock().clone();
+        let heaps = self
+            .tables()
+            .into_iter()
+            .filter_map(|table| Some((table.name.clone(), heaps.get(&table.file_name)?.clone())))
+            .filter(|(_, heap)| heap.changes_since_vacuum() >= min_changes)
+            .collect();
+
+        let catalog = self
+            .system
+            .all()
+            .iter()
+            .any(|(_, table)| table.heap().changes_since_vacuum() >= min_changes);
+
+        self.vacuum_heaps(heaps, catalog)
+    }
+
+    /// Vacuum `heaps`, and the catalog's own heaps too if `catalog` is set.
+    /// The caller has to hold the `Ddl` lock.
+    fn vacuum_heaps(
+        &self,
+        heaps: Vec<(String, Arc<HeapFile>)>,
+        catalog: bool,
+    ) -> Result<Vec<(String, VacuumStats)>, Error> {
+        let mut all: Vec<(String, &HeapFile)> =
+            heaps.iter().map(|(name, heap)| (name.clone(), &**heap)).collect();
+
+        // Sequences change their rows outside of any `Ddl`, so they have to be
+        // kept waiting too.
+        let _states = catalog.then(|| self.sequences.lock());
+        if catalog {
+            all.extend(self.system.all().map(|(name, table)| (name.to_owned(), table.heap())));
+        }
+
+        let mut vacuumed = vec![];
+        for (name, heap) in all {
+            let txn = self.wal.begin()?;
+
+            match heap.vacuum(&txn) {
+                Ok(stats) => {
+                    txn.commit()?;
+                    vacuumed.push((name, heap, stats));
+                },
+                Err(e) => {
+                    txn.abort(&self.files)?;
+                    return Err(e.into());
+                },
+            }
+        }
+
+        if vacuumed.iter().any(|(_, _, stats)| stats.empty_tail > 0) {
+            self.wal.checkpoint(&self.files)?;
+
+            for (_, heap, stats) in &vacuumed {
+                if stats.empty_tail > 0 {
+                    heap.truncate(&self.wal)?;
+                }
+            }
+        }
+
+        Ok(vacuumed.into_iter().map(|(name, _, stats)| (name, stats)).collect())
+    }
+}
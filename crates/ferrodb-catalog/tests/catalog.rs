@@ -0,0 +1,164 @@
+use std::sync::Arc;
+
+use ferrodb_catalog::{
+    Catalog, ColumnDefault, ConstraintKind, Error, IndexKind, ObjectId, StorageEngine, TableDef,
+};
+use ferrodb_fs::vfs::{CrashMode, CrashVfs, Vfs};
+use ferrodb_fs::FileManager;
+use ferrodb_row::{Column, DataType, Schema, Value};
+use ferrodb_wal::{recover, Wal};
+
+const PAGE_SIZE: usize = 512;
+
+fn open(vfs: Arc<dyn Vfs>) -> Catalog {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+    Catalog::register_undo(&wal, &files);
+
+    recover(&wal, &files, |_, _| {}).unwrap();
+    Catalog::open(files, wal).unwrap()
+}
+
+fn column(name: &str, data_type: DataType, nullable: bool) -> Column {
+    Column {
+        name: name.to_owned(),
+        data_type,
+        nullable,
+    }
+}
+
+fn schema() -> Schema {
+    Schema::new(vec![
+        column("id", DataType::BigInt, false),
+        column("name", DataType::Text, true),
+        column("score", DataType::Double, true),
+    ])
+    .unwrap()
+}
+
+fn create_users(catalog: &Catalog) {
+    let mut ddl = catalog.begin().unwrap();
+    let defaults = vec![Value::Null, Value::Text("nobody".to_owned()), Value::Double(0.5)];
+    ddl.create_table("Users", schema(), defaults, StorageEngine::Heap).unwrap();
+    ddl.create_index("users", "users_pkey", IndexKind::BTree, &["id"], true).unwrap();
+    ddl.create_index("users", "users_name", IndexKind::Hash, &["NAME"], false).unwrap();
+    ddl.add_constraint("users", "users_pkey", ConstraintKind::PrimaryKey, &["id"], Some("users_pkey"))
+        .unwrap();
+    ddl.add_column("users", column("email", DataType::Text, true)).unwrap();
+    ddl.commit().unwrap();
+}
+
+#[test]
+fn tables_indexes_and_constraints_are_there_after_a_restart() {
+    let vfs: Arc<dyn Vfs> = Arc::new(CrashVfs::default());
+    let catalog = open(vfs.clone());
+    create_users(&catalog);
+
+    let table = catalog.table("users").unwrap();
+    let heap = catalog.heap(&table).unwrap();
+    let txn = catalog.begin().unwrap();
+    let rid = heap.insert(txn.txn(), b"a row").unwrap();
+    txn.commit().unwrap();
+    drop((heap, catalog));
+
+    let catalog = open(vfs);
+    let reopened = catalog.table("USERS").unwrap();
+    assert_eq!(reopened.id, table.id);
+    assert_eq!(reopened.name, "Users");
+    assert_eq!(reopened.schema, table.schema);
+    assert_eq!(reopened.schema.len(), 4);
+    assert_eq!(reopened.defaults, table.defaults);
+    assert_eq!(reopened.defaults[1], ColumnDefault::Value(Value::Text("nobody".to_owned())));
+    assert_eq!(reopened.engine, StorageEngine::Heap);
+    assert_eq!(reopened.file_name, table.file_name);
+    // File ids are handed out afresh every time the files are opened.
+    fn indexes(table: &TableDef) -> Vec<(ObjectId, &str, &[usize], &str)> {
+        let indexes = table.indexes.iter();
+        indexes.map(|index| (index.id, &*index.name, &*index.columns, &*index.file_name)).collect()
+    }
+    assert_eq!(indexes(&reopened), indexes(&table));
+    assert_eq!(reopened.indexes[1].kind, IndexKind::Hash);
+    assert_eq!(reopened.index("USERS_NAME").unwrap().columns, [1]);
+    assert_eq!(reopened.constraints, table.constraints);
+    assert_eq!(reopened.primary_key().unwrap().index, Some(reopened.indexes[0].id));
+
+    // The table's rows are in the file the catalog says they are.
+    let heap = catalog.heap(&reopened).unwrap();
+    assert_eq!(heap.get(rid).unwrap(), Some(b"a row".to_vec()));
+}
+
+#[test]
+fn changes_are_only_seen_once_they_commit_and_not_at_all_if_they_dont() {
+    let vfs: Arc<dyn Vfs> = Arc::new(CrashVfs::default());
+    let catalog = open(vfs.clone());
+    create_users(&catalog);
+
+    let mut ddl = catalog.begin().unwrap();
+    ddl.create_table("orders", schema(), vec![Value::Null; 3], StorageEngine::Heap).unwrap();
+    ddl.drop_index("users_name").unwrap();
+    assert!(ddl.table("orders").is_some());
+    assert!(catalog.table("orders").is_none());
+    assert!(catalog.table("users").unwrap().index("users_name").is_some());
+    ddl.abort().unwrap();
+
+    let check = |catalog: &Catalog| {
+        assert!(catalog.table("orders").is_none());
+        assert!(catalog.table("users").unwrap().index("users_name").is_some());
+    };
+    check(&catalog);
+    drop(catalog);
+    check(&open(vfs));
+}
+
+#[test]
+fn dropped_tables_are_gone_along_with_their_files() {
+    let vfs: Arc<dyn Vfs> = Arc::new(CrashVfs::default());
+    let catalog = open(vfs.clone());
+    create_users(&catalog);
+    let table = catalog.table("users").unwrap();
+    let mut files = vec![table.file_name.clone()];
+    files.extend(table.indexes.iter().map(|index| index.file_name.clone()));
+
+    let mut ddl = catalog.begin().unwrap();
+    ddl.drop_table("users").unwrap();
+    assert!(matches!(ddl.drop_table("users"), Err(Error::NoSuchTable(_))));
+    ddl.commit().unwrap();
+    assert!(catalog.table("users").is_none());
+    assert!(catalog.tables().is_empty());
+
+    let names = |vfs: &Arc<dyn Vfs>| -> Vec<String> {
+        vfs.list(".".into()).unwrap().into_iter().map(String::from).collect()
+    };
+    for file in &files {
+        assert!(!names(&vfs).iter().any(|name| name.starts_with(&format!("./{file}"))), "{file}");
+    }
+    drop(catalog);
+
+    // The name can be used again, by a table that starts out empty.
+    let catalog = open(vfs);
+    assert!(catalog.table("users").is_none());
+    create_users(&catalog);
+    let table = catalog.table("users").unwrap();
+    assert_eq!(catalog.heap(&table).unwrap().scan().unwrap().count(), 0);
+}
+
+#[test]
+fn only_committed_changes_survive_a_crash() {
+    let vfs = Arc::new(CrashVfs::default());
+    let catalog = open(vfs.clone());
+    create_users(&catalog);
+
+    let mut ddl = catalog.begin().unwrap();
+    ddl.create_table("orders", schema(), vec![Value::Null; 3], StorageEngine::Heap).unwrap();
+    ddl.drop_table("users").unwrap();
+    let crashed = Arc::new(vfs.crash(CrashMode::DropUnsynced));
+    drop(ddl);
+    drop(catalog);
+
+    let catalog = open(crashed);
+    assert!(catalog.table("orders").is_none());
+    assert_eq!(catalog.table("users").unwrap().indexes.len(), 2);
+}
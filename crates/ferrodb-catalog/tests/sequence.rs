@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use ferrodb_catalog::{Catalog, ColumnDefault, Error, SequenceOptions, StorageEngine};
+use ferrodb_fs::vfs::{CrashMode, CrashVfs, Vfs};
+use ferrodb_fs::FileManager;
+use ferrodb_row::{Column, DataType, Schema, Value};
+use ferrodb_wal::{recover, Wal};
+
+const PAGE_SIZE: usize = 512;
+
+fn open(vfs: Arc<dyn Vfs>) -> Catalog {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+    Catalog::register_undo(&wal, &files);
+
+    recover(&wal, &files, |_, _| {}).unwrap();
+    Catalog::open(files, wal).unwrap()
+}
+
+fn create_sequence(catalog: &Catalog, name: &str, options: SequenceOptions) -> Result<(), Error> {
+    let mut ddl = catalog.begin().unwrap();
+    ddl.create_sequence(name, &options, None)?;
+    ddl.commit().unwrap();
+    Ok(())
+}
+
+fn next_values(catalog: &Catalog, name: &str, count: usize) -> Vec<i64> {
+    let id = catalog.sequence(name).unwrap().id;
+    (0..count).map(|_| catalog.next_value(id).unwrap()).collect()
+}
+
+#[test]
+fn values_step_to_the_limit_and_then_stop_or_cycle() {
+    let catalog = open(Arc::new(CrashVfs::default()));
+
+    create_sequence(&catalog, "up", SequenceOptions {
+        increment: Some(5),
+        max: Some(20),
+        cache: Some(2),
+        ..SequenceOptions::default()
+    })
+    .unwrap();
+    assert_eq!(next_values(&catalog, "up", 4), [1, 6, 11, 16]);
+    let up = catalog.sequence("UP").unwrap().id;
+    assert!(matches!(catalog.next_value(up), Err(Error::SequenceExhausted { limit: 20, .. })));
+
+    create_sequence(&catalog, "down", SequenceOptions {
+        increment: Some(-1),
+        min: Some(-3),
+        cycle: true,
+        ..SequenceOptions::default()
+    })
+    .unwrap();
+    assert_eq!(next_values(&catalog, "down", 5), [-1, -2, -3, -1, -2]);
+
+    for options in [
+        SequenceOptions { increment: Some(0), ..SequenceOptions::default() },
+        SequenceOptions { min: Some(10), max: Some(10), ..SequenceOptions::default() },
+        SequenceOptions { start: Some(0), ..SequenceOptions::default() },
+        SequenceOptions { cache: Some(0), ..SequenceOptions::default() },
+    ] {
+        let result = create_sequence(&catalog, "bad", options);
+        assert!(matches!(result, Err(Error::InvalidSequence { .. })), "{result:?}");
+    }
+    assert!(matches!(
+        create_sequence(&catalog, "UP", SequenceOptions::default()),
+        Err(Error::SequenceExists(_))
+    ));
+}
+
+#[test]
+fn a_crash_skips_the_rest_of_a_batch_but_never_hands_out_a_value_again() {
+    let vfs = Arc::new(CrashVfs::default());
+    let catalog = open(vfs.clone());
+    create_sequence(&catalog, "ids", SequenceOptions {
+        cache: Some(10),
+        ..SequenceOptions::default()
+    })
+    .unwrap();
+    assert_eq!(next_values(&catalog, "ids", 3), [1, 2, 3]);
+
+    let crashed = Arc::new(vfs.crash(CrashMode::DropUnsynced));
+    drop(catalog);
+    let catalog = open(crashed.clone());
+    assert_eq!(next_values(&catalog, "ids", 12), (11..23).collect::<Vec<_>>());
+
+    // A second batch was reserved for 21 and 22, so it's what gets skipped.
+    drop(catalog);
+    let catalog = open(Arc::new(crashed.crash(CrashMode::DropUnsynced)));
+    assert_eq!(next_values(&catalog, "ids", 1), [31]);
+}
+
+#[test]
+fn set_value_moves_where_the_sequence_carries_on_from() {
+    let vfs: Arc<dyn Vfs> = Arc::new(CrashVfs::default());
+    let catalog = open(vfs.clone());
+    create_sequence(&catalog, "ids", SequenceOptions {
+        max: Some(100),
+        ..SequenceOptions::default()
+    })
+    .unwrap();
+    let id = catalog.sequence("ids").unwrap().id;
+    assert_eq!(next_values(&catalog, "ids", 2), [1, 2]);
+
+    catalog.set_value(id, 50, true).unwrap();
+    assert_eq!(next_values(&catalog, "ids", 2), [51, 52]);
+    catalog.set_value(id, 50, false).unwrap();
+    assert_eq!(next_values(&catalog, "ids", 1), [50]);
+
+    assert!(matches!(
+        catalog.set_value(id, 101, false),
+        Err(Error::SequenceValueOutOfRange { value: 101, .. })
+    ));
+    catalog.set_value(id, 100, true).unwrap();
+    assert!(matches!(catalog.next_value(id), Err(Error::SequenceExhausted { .. })));
+
+    // Where it was set to lasts through a restart.
+    catalog.set_value(id, 70, false).unwrap();
+    drop(catalog);
+    let catalog = open(vfs);
+    assert_eq!(next_values(&catalog, "ids", 1), [70]);
+}
+
+#[test]
+fn serial_columns_are_filled_in_and_their_sequences_go_with_their_table() {
+    let catalog = open(Arc::new(CrashVfs::default()));
+    let schema = Schema::new(vec![
+        Column { name: "id".to_owned(), data_type: DataType::Int, nullable: false },
+        Column { name: "name".to_owned(), data_type: DataType::Text, nullable: true },
+    ])
+    .unwrap();
+
+    let mut ddl = catalog.begin().unwrap();
+    ddl.create_table("users", schema, vec![Value::Null; 2], StorageEngine::Heap).unwrap();
+    // Its values go past what the column can hold.
+    let options = SequenceOptions { max: Some(i64::MAX), ..SequenceOptions::default() };
+    assert!(matches!(
+        ddl.create_sequence("big", &options, Some(("users", "id"))),
+        Err(Error::InvalidSequence { .. })
+    ));
+    let options = SequenceOptions { max: Some(i32::MAX as i64), ..SequenceOptions::default() };
+    let sequence = ddl.create_sequence("users_id_seq", &options, Some(("users", "id"))).unwrap().id;
+    ddl.commit().unwrap();
+
+    let table = catalog.table("users").unwrap();
+    assert_eq!(table.defaults[0], ColumnDefault::NextValue(sequence));
+    for id in 1..=3 {
+        let row = catalog.fill_defaults(&table, vec![None, Some(Value::Text("a".to_owned()))]).unwrap();
+        assert_eq!(row, [Value::Int(id), Value::Text("a".to_owned())]);
+    }
+    let row = catalog.fill_defaults(&table, vec![Some(Value::Int(9)), None]).unwrap();
+    assert_eq!(row, [Value::Int(9), Value::Null]);
+    assert!(matches!(
+        catalog.fill_defaults(&table, vec![None]),
+        Err(Error::WrongValueCount { expected: 2, got: 1, .. })
+    ));
+
+    let mut ddl = catalog.begin().unwrap();
+    assert!(matches!(ddl.drop_sequence("users_id_seq"), Err(Error::SequenceInUse { .. })));
+    ddl.drop_table("users").unwrap();
+    ddl.commit().unwrap();
+    assert!(catalog.sequence("users_id_seq").is_none());
+}
@@ -1,8 +1,10 @@
 use std::io::{Read, Write};
 use std::thread::JoinHandle;
 
-use anyhow::Result;
-use ferrodb_protocol::{Ping, Pong, Transport, PROTOCOL_VERSION, PREAMBLE};
+use anyhow::{bail, Result};
+use ferrodb_protocol::{
+    Command, Ping, Pong, QueryResponse, Transport, PROTOCOL_VERSION, PREAMBLE,
+};
 
 pub fn spawn_client<C>(conn: C, transport: Transport) -> JoinHandle<Result<()>>
 where
@@ -15,7 +17,7 @@ fn client<C>(mut conn: C, transport: Transport) -> Result<()>
 where
     C: Read + Write,
 {
-    write!(conn, "{}{transport}.\n", PREAMBLE)?;
+    writeln!(conn, "{}{transport}.", PREAMBLE)?;
 
     let mut stream = transport.stream(conn);
 
@@ -24,7 +26,45 @@ where
     })?;
 
     let pong: Pong = stream.read()?;
+    if let Pong::WrongProtocol = pong {
+        bail!("Server doesn't speak protocol version {}", PROTOCOL_VERSION);
+    }
 
     println!("ok.");
+
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+
+    loop {
+        print!("ferrodb> ");
+        std::io::stdout().flush()?;
+
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            break;
+        }
+
+        let query = line.trim();
+        if query.is_empty() {
+            continue;
+        } else if query.eq_ignore_ascii_case("quit") || query.eq_ignore_ascii_case("exit") {
+            break;
+        }
+
+        stream.write(Command::Query(query.to_owned()))?;
+
+        loop {
+            match stream.read()? {
+                QueryResponse::SomeRows(rows) => println!("{rows}"),
+                QueryResponse::Error(e) => {
+                    println!("Error: {e}");
+                    break;
+                },
+                QueryResponse::Done => break,
+            }
+        }
+    }
+
+    stream.write(Command::Goodbye)?;
     Ok(())
 }
@@ -0,0 +1,152 @@
+//! The directory lists every column page of a table, in the order they were
+//! written, which is row order for each column. It starts on page 2 and
+//! carries on through as many pages as it takes, each of them:
+//!
+//! ```text
+//! [next page: u64][entries: u16][used: u16] entry * entries
+//! ```
+//!
+//! where `used` is how many bytes the entries take up, and an entry is:
+//!
+//! ```text
+//! [column: u16][first row: u64][rows: u32][page: u64]
+//! [min len: u8][min][max len: u8][max]
+//! ```
+//!
+//! The min and max are the page's smallest and biggest values, as a
+//! [`Datum`](crate::page::Datum) encodes them. Values longer than
+//! [`MAX_STAT_LEN`] aren't kept, and a min len of [`NO_STATS`] means the
+//! entry has neither.
+
+use ferrodb_fs::{FileId, FileManager, PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_wal::Txn;
+
+use crate::{body_len, read_u64, Error, DIRECTORY_PAGE};
+
+const HEADER_SIZE: usize = 12;
+const ENTRY_SIZE: usize = 22;
+
+pub(crate) const MAX_STAT_LEN: usize = 64;
+const NO_STATS: u8 = u8::MAX;
+
+#[derive(Clone, Debug)]
+pub(crate) struct PageEntry {
+    pub column: usize,
+    pub first_row: u64,
+    pub rows: u32,
+    pub page: PageIndex,
+    /// The encoded min and max of the page, if they're short enough to keep.
+    pub stats: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+impl PageEntry {
+    pub fn end_row(&self) -> u64 {
+        self.first_row + self.rows as u64
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&(self.column as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.first_row.to_le_bytes());
+        bytes.extend_from_slice(&self.rows.to_le_bytes());
+        bytes.extend_from_slice(&(self.page as u64).to_le_bytes());
+
+        match &self.stats {
+            Some((min, max)) =>
+                for stat in [min, max] {
+                    bytes.push(stat.len() as u8);
+                    bytes.extend_from_slice(stat);
+                },
+            None => bytes.push(NO_STATS),
+        }
+
+        bytes
+    }
+
+    fn decode(bytes: &[u8], at: &mut usize) -> Option<PageEntry> {
+        let fixed = bytes.get(*at..*at + ENTRY_SIZE)?;
+        let mut entry = PageEntry {
+            column: u16::from_le_bytes(fixed[0..2].try_into().unwrap()) as usize,
+            first_row: read_u64(fixed, 2),
+            rows: u32::from_le_bytes(fixed[10..14].try_into().unwrap()),
+            page: read_u64(fixed, 14) as PageIndex,
+            stats: None,
+        };
+        *at += ENTRY_SIZE;
+
+        if bytes.get(*at) == Some(&NO_STATS) {
+            *at += 1;
+        } else {
+            entry.stats = Some((read_stat(bytes, at)?, read_stat(bytes, at)?));
+        }
+
+        Some(entry)
+    }
+}
+
+fn read_stat(bytes: &[u8], at: &mut usize) -> Option<Vec<u8>> {
+    let len = *bytes.get(*at)? as usize;
+    let stat = bytes.get(*at + 1..*at + 1 + len)?.to_vec();
+    *at += 1 + len;
+    Some(stat)
+}
+
+/// Every entry in the directory, in order.
+pub(crate) fn read(files: &FileManager, file: FileId) -> Result<Vec<PageEntry>, Error> {
+    let mut entries = vec![];
+    let mut page = DIRECTORY_PAGE;
+
+    loop {
+        let page_ref = files.latest(file, page)?;
+        let buf = page_ref.read();
+        let body = &buf[PAGE_HEADER_SIZE..];
+
+        let count = u16::from_le_bytes(body[8..10].try_into().unwrap());
+        let mut at = HEADER_SIZE;
+        for _ in 0..count {
+            let entry = PageEntry::decode(body, &mut at).ok_or(Error::Corrupt(page))?;
+            entries.push(entry);
+        }
+
+        match read_u64(body, 0) as PageIndex {
+            0 => return Ok(entries),
+            next => page = next,
+        }
+    }
+}
+
+/// Add an entry to the end of the directory, which is on page `last` so far,
+/// starting a new page for it if it doesn't fit there.
+pub(crate) fn append(
+    txn: &Txn<'_>,
+    files: &FileManager,
+    file: FileId,
+    last: &mut PageIndex,
+    entry: &PageEntry,
+) -> Result<(), Error> {
+    let bytes = entry.encode();
+
+    let used = {
+        let page_ref = files.latest(file, *last)?;
+        let buf = page_ref.read();
+        u16::from_le_bytes(buf[PAGE_HEADER_SIZE + 10..PAGE_HEADER_SIZE + 12].try_into().unwrap()) as usize
+    };
+
+    if HEADER_SIZE + used + bytes.len() > body_len() {
+        let next = files.allocate(file)?;
+        txn.modify(files, file, *last, |body| {
+            body[0..8].copy_from_slice(&(next as u64).to_le_bytes())
+        })?;
+        *last = next;
+        return append(txn, files, file, last, entry);
+    }
+
+    txn.modify(files, file, *last, |body| {
+        let count = u16::from_le_bytes(body[8..10].try_into().unwrap()) + 1;
+        body[8..10].copy_from_slice(&count.to_le_bytes());
+        body[10..12].copy_from_slice(&((used + bytes.len()) as u16).to_le_bytes());
+        body[HEADER_SIZE + used..][..bytes.len()].copy_from_slice(&bytes);
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,388 @@
+//! The encodings that a column page's values can be in. Every page is encoded
+//! each way that suits its type, and keeps whichever comes out smallest:
+//!
+//! ```text
+//! plain:       value * count
+//! run-length:  [runs: u32] ([length: u32][value]) * runs
+//! dictionary:  [entries: u32][value] * entries [width: u8][code: width bits] * count
+//! bit-packed:  [min: i64][width: u8][value - min: width bits] * count
+//! delta:       [first: i64][min delta: i64][width: u8][delta - min delta: width bits] * (count - 1)
+//! ```
+//!
+//! Each starts with its [`Encoding`] as a byte. Integers, which bools and
+//! doubles are stored as too, are 8 bytes, and can be bit-packed or
+//! delta-encoded, and other values are `[len: u32][bytes]`. Numbers are
+//! little-endian, and bit-packed numbers are packed from the low bits of each
+//! byte up.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// The values of part of a column, without its NULLs, as they're stored.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Values {
+    Ints(Vec<i64>),
+    Bytes(Vec<Vec<u8>>),
+}
+
+/// Some of the values of a [`Values`].
+#[derive(Copy, Clone)]
+pub(crate) enum ValuesRef<'v> {
+    Ints(&'v [i64]),
+    Bytes(&'v [Vec<u8>]),
+}
+
+impl Values {
+    pub fn len(&self) -> usize {
+        match self {
+            Values::Ints(values) => values.len(),
+            Values::Bytes(values) => values.len(),
+        }
+    }
+
+    pub fn slice(&self, start: usize, end: usize) -> ValuesRef<'_> {
+        match self {
+            Values::Ints(values) => ValuesRef::Ints(&values[start..end]),
+            Values::Bytes(values) => ValuesRef::Bytes(&values[start..end]),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Encoding {
+    Plain,
+    RunLength,
+    Dictionary,
+    BitPacked,
+    Delta,
+}
+
+impl Encoding {
+    fn to_u8(self) -> u8 {
+        match self {
+            Encoding::Plain => 0,
+            Encoding::RunLength => 1,
+            Encoding::Dictionary => 2,
+            Encoding::BitPacked => 3,
+            Encoding::Delta => 4,
+        }
+    }
+
+    fn from_u8(encoding: u8) -> Option<Encoding> {
+        match encoding {
+            0 => Some(Encoding::Plain),
+            1 => Some(Encoding::RunLength),
+            2 => Some(Encoding::Dictionary),
+            3 => Some(Encoding::BitPacked),
+            4 => Some(Encoding::Delta),
+            _ => None,
+        }
+    }
+}
+
+/// Encode values whichever way makes them smallest.
+pub(crate) fn encode(values: ValuesRef<'_>) -> Vec<u8> {
+    let candidates = match values {
+        ValuesRef::Ints(values) => vec![
+            bit_packed(values),
+            delta(values),
+            run_length(values),
+            dictionary(values),
+        ],
+        ValuesRef::Bytes(values) => vec![plain(values), run_length(values), dictionary(values)],
+    };
+
+    candidates.into_iter().min_by_key(Vec::len).unwrap()
+}
+
+/// Decode `count` values, of integers if `ints`, or `None` if they're corrupt.
+pub(crate) fn decode(bytes: &[u8], ints: bool, count: usize) -> Option<Values> {
+    let encoding = Encoding::from_u8(*bytes.first()?)?;
+    let mut at = 1;
+
+    if ints {
+        let values = match encoding {
+            Encoding::BitPacked => {
+                let min = i64::read(bytes, &mut at)?;
+                let width = read_width(bytes, &mut at)?;
+                unpack(&bytes[at..], width, count)?
+                    .into_iter()
+                    .map(|offset| min.wrapping_add(offset as i64))
+                    .collect()
+            },
+            Encoding::Delta => {
+                let first = i64::read(bytes, &mut at)?;
+                let min_delta = i64::read(bytes, &mut at)?;
+                let width = read_width(bytes, &mut at)?;
+                let mut value = first;
+                let mut values = Vec::with_capacity(count);
+                if count > 0 {
+                    values.push(first);
+                    for offset in unpack(&bytes[at..], width, count - 1)? {
+                        value = value.wrapping_add(min_delta.wrapping_add(offset as i64));
+                        values.push(value);
+                    }
+                }
+                values
+            },
+            _ => decode_generic(encoding, bytes, at, count)?,
+        };
+        Some(Values::Ints(values))
+    } else {
+        Some(Values::Bytes(decode_generic(encoding, bytes, at, count)?))
+    }
+}
+
+/// A value that can be stored as-is.
+trait Plain: Sized + Clone + Eq + Hash {
+    fn write(&self, out: &mut Vec<u8>);
+    fn read(bytes: &[u8], at: &mut usize) -> Option<Self>;
+}
+
+impl Plain for i64 {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read(bytes: &[u8], at: &mut usize) -> Option<i64> {
+        let value = i64::from_le_bytes(bytes.get(*at..*at + 8)?.try_into().unwrap());
+        *at += 8;
+        Some(value)
+    }
+}
+
+impl Plain for Vec<u8> {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.len() as u32).to_le_bytes());
+        out.extend_from_slice(self);
+    }
+
+    fn read(bytes: &[u8], at: &mut usize) -> Option<Vec<u8>> {
+        let len = read_u32(bytes, at)? as usize;
+        let value = bytes.get(*at..*at + len)?.to_vec();
+        *at += len;
+        Some(value)
+    }
+}
+
+fn decode_generic<T: Plain>(encoding: Encoding, bytes: &[u8], mut at: usize, count: usize) -> Option<Vec<T>> {
+    let values = match encoding {
+        Encoding::Plain => (0..count)
+            .map(|_| T::read(bytes, &mut at))
+            .collect::<Option<_>>()?,
+        Encoding::RunLength => {
+            let runs = read_u32(bytes, &mut at)?;
+            let mut values = Vec::with_capacity(count);
+            for _ in 0..runs {
+                let len = read_u32(bytes, &mut at)? as usize;
+                let value = T::read(bytes, &mut at)?;
+                if values.len() + len > count {
+                    return None;
+                }
+                values.resize(values.len() + len, value);
+            }
+            values
+        },
+        Encoding::Dictionary => {
+            let entries = read_u32(bytes, &mut at)?;
+            let dictionary = (0..entries)
+                .map(|_| T::read(bytes, &mut at))
+                .collect::<Option<Vec<_>>>()?;
+            let width = read_width(bytes, &mut at)?;
+            unpack(&bytes[at..], width, count)?
+                .into_iter()
+                .map(|code| dictionary.get(code as usize).cloned())
+                .collect::<Option<_>>()?
+        },
+        Encoding::BitPacked | Encoding::Delta => return None,
+    };
+
+    (values.len() == count).then_some(values)
+}
+
+fn plain<T: Plain>(values: &[T]) -> Vec<u8> {
+    let mut out = vec![Encoding::Plain.to_u8()];
+    for value in values {
+        value.write(&mut out);
+    }
+    out
+}
+
+fn run_length<T: Plain>(values: &[T]) -> Vec<u8> {
+    let mut runs = vec![];
+    for value in values {
+        match runs.last_mut() {
+            Some((last, len)) if *last == value => *len += 1,
+            _ => runs.push((value, 1u32)),
+        }
+    }
+
+    let mut out = vec![Encoding::RunLength.to_u8()];
+    out.extend_from_slice(&(runs.len() as u32).to_le_bytes());
+    for (value, len) in runs {
+        out.extend_from_slice(&len.to_le_bytes());
+        value.write(&mut out);
+    }
+    out
+}
+
+fn dictionary<T: Plain>(values: &[T]) -> Vec<u8> {
+    let mut codes = HashMap::new();
+    let mut dictionary = vec![];
+    for value in values {
+        codes.entry(value).or_insert_with(|| {
+            dictionary.push(value);
+            dictionary.len() as u64 - 1
+        });
+    }
+
+    let mut out = vec![Encoding::Dictionary.to_u8()];
+    out.extend_from_slice(&(dictionary.len() as u32).to_le_bytes());
+    for value in &dictionary {
+        value.write(&mut out);
+    }
+
+    let width = width((dictionary.len() as u64).saturating_sub(1));
+    out.push(width as u8);
+    pack(values.iter().map(|value| codes[value]), width, &mut out);
+    out
+}
+
+fn bit_packed(values: &[i64]) -> Vec<u8> {
+    let min = values.iter().copied().min().unwrap_or(0);
+    let offsets = values.iter().map(|value| value.wrapping_sub(min) as u64);
+
+    let mut out = vec![Encoding::BitPacked.to_u8()];
+    out.extend_from_slice(&min.to_le_bytes());
+    let width = width(offsets.clone().max().unwrap_or(0));
+    out.push(width as u8);
+    pack(offsets, width, &mut out);
+    out
+}
+
+fn delta(values: &[i64]) -> Vec<u8> {
+    let deltas: Vec<i64> = values.windows(2).map(|pair| pair[1].wrapping_sub(pair[0])).collect();
+    let min_delta = deltas.iter().copied().min().unwrap_or(0);
+    let offsets = deltas.iter().map(|delta| delta.wrapping_sub(min_delta) as u64);
+
+    let mut out = vec![Encoding::Delta.to_u8()];
+    out.extend_from_slice(&values.first().copied().unwrap_or(0).to_le_bytes());
+    out.extend_from_slice(&min_delta.to_le_bytes());
+    let width = width(offsets.clone().max().unwrap_or(0));
+    out.push(width as u8);
+    pack(offsets, width, &mut out);
+    out
+}
+
+/// How many bits it takes to hold every number up to `max`.
+fn width(max: u64) -> u32 {
+    u64::BITS - max.leading_zeros()
+}
+
+fn pack(values: impl Iterator<Item = u64>, width: u32, out: &mut Vec<u8>) {
+    let mut buffer = 0u128;
+    let mut bits = 0;
+
+    for value in values {
+        buffer |= (value as u128) << bits;
+        bits += width;
+        while bits >= 8 {
+            out.push(buffer as u8);
+            buffer >>= 8;
+            bits -= 8;
+        }
+    }
+
+    if bits > 0 {
+        out.push(buffer as u8);
+    }
+}
+
+fn unpack(bytes: &[u8], width: u32, count: usize) -> Option<Vec<u64>> {
+    let mask = match width {
+        64 => u64::MAX,
+        width => (1 << width) - 1,
+    };
+    let mut bytes = bytes.iter();
+    let mut buffer = 0u128;
+    let mut bits = 0;
+
+    let mut values = Vec::with_capacity(count);
+    for _ in 0..count {
+        while bits < width {
+            buffer |= (*bytes.next()? as u128) << bits;
+            bits += 8;
+        }
+
+        values.push(buffer as u64 & mask);
+        buffer >>= width;
+        bits -= width;
+    }
+
+    Some(values)
+}
+
+fn read_width(bytes: &[u8], at: &mut usize) -> Option<u32> {
+    let width = *bytes.get(*at)? as u32;
+    *at += 1;
+    (width <= u64::BITS).then_some(width)
+}
+
+fn read_u32(bytes: &[u8], at: &mut usize) -> Option<u32> {
+    let value = u32::from_le_bytes(bytes.get(*at..*at + 4)?.try_into().unwrap());
+    *at += 4;
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(values: Values) -> Encoding {
+        let bytes = match &values {
+            Values::Ints(ints) => encode(ValuesRef::Ints(ints)),
+            Values::Bytes(bytes) => encode(ValuesRef::Bytes(bytes)),
+        };
+
+        let ints = matches!(values, Values::Ints(_));
+        assert_eq!(decode(&bytes, ints, values.len()), Some(values));
+        Encoding::from_u8(bytes[0]).unwrap()
+    }
+
+    #[test]
+    fn values_come_back_from_whichever_encoding_is_smallest() {
+        let runs = (0..1000).map(|i| i / 250 * 1_000_000_007).collect();
+        assert_eq!(round_trip(Values::Ints(runs)), Encoding::RunLength);
+
+        let small = (0..1000).map(|i| -50 + i * 37 % 100).collect();
+        assert_eq!(round_trip(Values::Ints(small)), Encoding::BitPacked);
+
+        let timestamps = (0..1000).map(|i| 1_700_000_000_000 + i * 1000 + i % 3).collect();
+        assert_eq!(round_trip(Values::Ints(timestamps)), Encoding::Delta);
+
+        let spread = [i64::MIN, i64::MAX, 0, -1, 1, i64::MAX, i64::MIN].repeat(50);
+        assert_eq!(round_trip(Values::Ints(spread)), Encoding::Dictionary);
+
+        let words = ["apple", "banana", "cherry"];
+        let repeated = (0..1000).map(|i| words[i * 7 % 3].as_bytes().to_vec()).collect();
+        assert_eq!(round_trip(Values::Bytes(repeated)), Encoding::Dictionary);
+
+        let unique = (0..100).map(|i: u32| i.to_le_bytes().repeat(3)).collect();
+        assert_eq!(round_trip(Values::Bytes(unique)), Encoding::Plain);
+
+        round_trip(Values::Ints(vec![]));
+        round_trip(Values::Ints(vec![42]));
+        round_trip(Values::Bytes(vec![vec![]]));
+    }
+
+    #[test]
+    fn corrupt_values_are_caught() {
+        let bytes = encode(ValuesRef::Ints(&(0..100).collect::<Vec<_>>()));
+        assert_eq!(decode(&bytes[..bytes.len() - 1], true, 100), None);
+        assert_eq!(decode(&[9], true, 0), None);
+        assert_eq!(decode(&[], false, 0), None);
+
+        let bytes = encode(ValuesRef::Bytes(&[b"text".to_vec()]));
+        assert_eq!(decode(&bytes, false, 2), None);
+    }
+}
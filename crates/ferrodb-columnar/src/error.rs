@@ -0,0 +1,20 @@
+use ferrodb_fs::PageIndex;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Fs(#[from] ferrodb_fs::Error),
+    #[error(transparent)]
+    Wal(#[from] ferrodb_wal::Error),
+    #[error(transparent)]
+    Row(#[from] ferrodb_row::Error),
+    #[error("A {len}-byte value of column `{column}` doesn't fit in a page")]
+    ValueTooLarge { column: String, len: usize },
+    #[error("There's no column {column}, since the table has {count}")]
+    NoSuchColumn { column: usize, count: usize },
+    #[error("Column `{column}` can't be filtered on NULL")]
+    NullBound { column: String },
+    #[error("Page {0} of a columnar table is corrupt")]
+    Corrupt(PageIndex),
+}
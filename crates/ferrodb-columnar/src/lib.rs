@@ -0,0 +1,200 @@
+//! Tables stored a column at a time, for analytic queries that only read a
+//! few of a table's columns. Each page holds the values of one column for a
+//! run of rows, in whichever of a few lightweight encodings suits them best,
+//! so a [`ColumnScan`] only reads the pages of the columns it's asked for, and
+//! skips the pages whose min and max rule them out of its [`Filter`]s.
+//!
+//! Page 1 of the file is the meta page:
+//!
+//! ```text
+//! [rows: u64][last directory page: u64]
+//! ```
+//!
+//! where a last directory page of 0 means page 2, the first, so that an
+//! all-zero file is an empty table. The [`directory`] lists the column pages,
+//! which are everywhere after it.
+//!
+//! Rows are appended in batches, and each batch's columns start on pages of
+//! their own, so a table should be loaded a lot of rows at a time. Rows can't
+//! be changed or deleted. Appends go through a [`Txn`], like for heaps, and
+//! take turns on a write latch on the meta page, which scans take a read latch
+//! on while they read the directory.
+
+mod directory;
+mod encoding;
+mod error;
+mod page;
+mod scan;
+
+use std::sync::Arc;
+
+use ferrodb_fs::{FileId, FileKind, FileManager, PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_page::page_size;
+use ferrodb_row::{Schema, Value};
+use ferrodb_wal::Txn;
+
+use self::directory::{PageEntry, MAX_STAT_LEN};
+pub use self::error::Error;
+use self::page::{ColumnData, Datum};
+pub use self::scan::{ColumnScan, Filter};
+
+const META_PAGE: PageIndex = 1;
+const DIRECTORY_PAGE: PageIndex = 2;
+
+pub struct ColumnarTable {
+    files: Arc<FileManager>,
+    file: FileId,
+    schema: Schema,
+}
+
+impl ColumnarTable {
+    /// Open the table called `name`, whose columns are `schema`. Columns past
+    /// the ones that rows were appended with read as NULL in those rows.
+    pub fn open(files: Arc<FileManager>, name: &str, schema: Schema) -> Result<ColumnarTable, Error> {
+        let file = files.open(name, FileKind::Columnar)?;
+        while files.page_count(file)? <= DIRECTORY_PAGE {
+            files.allocate(file)?;
+        }
+
+        Ok(ColumnarTable {
+            files,
+            file,
+            schema,
+        })
+    }
+
+    /// Delete the file of the table called `name`, which mustn't be open.
+    pub fn delete_files(files: &FileManager, name: &str) -> Result<(), Error> {
+        files.delete(files.id(name))?;
+        Ok(())
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// How many rows have been appended.
+    pub fn row_count(&self) -> Result<u64, Error> {
+        let meta_ref = self.files.latest(self.file, META_PAGE)?;
+        let meta = meta_ref.read();
+        Ok(read_u64(&meta[PAGE_HEADER_SIZE..], 0))
+    }
+
+    /// Append rows, each with a value for every column.
+    pub fn append(&self, txn: &Txn<'_>, rows: &[Vec<Value>]) -> Result<(), Error> {
+        for row in rows {
+            self.check(row)?;
+        }
+
+        // Everything's encoded before taking the latch.
+        let mut pages = vec![];
+        for (index, column) in self.schema.columns().iter().enumerate() {
+            let data = ColumnData::new(
+                column.data_type,
+                rows.iter().map(|row| Datum::from_value(&row[index])),
+            );
+            let column_pages = data.pages(body_len()).map_err(|row| Error::ValueTooLarge {
+                column: column.name.clone(),
+                len: Datum::from_value(&rows[row][index]).map_or(0, |datum| datum.encode().len()),
+            })?;
+
+            pages.extend(column_pages.into_iter().map(|page| (index, page)));
+        }
+
+        let meta_ref = self.files.dirty(self.file, META_PAGE)?;
+        let mut meta = meta_ref.write();
+        let first_row = read_u64(&meta[PAGE_HEADER_SIZE..], 0);
+        let mut last_directory = match read_u64(&meta[PAGE_HEADER_SIZE..], 8) as PageIndex {
+            0 => DIRECTORY_PAGE,
+            page => page,
+        };
+
+        for (column, data) in pages {
+            let page = self.files.allocate(self.file)?;
+            txn.modify(&self.files, self.file, page, |body| {
+                body[..data.body.len()].copy_from_slice(&data.body)
+            })?;
+
+            let (min, max) = (data.min.encode(), data.max.encode());
+            let entry = PageEntry {
+                column,
+                first_row: first_row + data.rows.start as u64,
+                rows: data.rows.len() as u32,
+                page,
+                stats: (min.len() <= MAX_STAT_LEN && max.len() <= MAX_STAT_LEN).then_some((min, max)),
+            };
+            directory::append(txn, &self.files, self.file, &mut last_directory, &entry)?;
+        }
+
+        let rows = first_row + rows.len() as u64;
+        txn.modify_latched(&self.files, self.file, META_PAGE, &mut meta, |body| {
+            body[0..8].copy_from_slice(&rows.to_le_bytes());
+            body[8..16].copy_from_slice(&(last_directory as u64).to_le_bytes());
+        })?;
+
+        Ok(())
+    }
+
+    /// Scan the values of `columns` in every row that passes every filter,
+    /// reading only the pages of those columns and the filtered ones.
+    pub fn scan(&self, columns: &[usize], filters: &[Filter]) -> Result<ColumnScan, Error> {
+        let (rows, entries) = {
+            let meta_ref = self.files.latest(self.file, META_PAGE)?;
+            let meta = meta_ref.read();
+            let rows = read_u64(&meta[PAGE_HEADER_SIZE..], 0);
+            (rows, directory::read(&self.files, self.file)?)
+        };
+
+        ColumnScan::new(
+            self.files.clone(),
+            self.file,
+            &self.schema,
+            columns,
+            filters,
+            rows,
+            entries,
+        )
+    }
+
+    fn check(&self, row: &[Value]) -> Result<(), Error> {
+        if row.len() != self.schema.len() {
+            return Err(ferrodb_row::Error::WrongValueCount {
+                expected: self.schema.len(),
+                got: row.len(),
+            }
+            .into());
+        }
+
+        for (column, value) in self.schema.columns().iter().zip(row) {
+            match value.data_type() {
+                None if !column.nullable =>
+                    return Err(ferrodb_row::Error::NotNullable {
+                        column: column.name.clone(),
+                    }
+                    .into()),
+                Some(data_type) if data_type != column.data_type =>
+                    return Err(ferrodb_row::Error::TypeMismatch {
+                        column: column.name.clone(),
+                        expected: column.data_type,
+                        got: data_type,
+                    }
+                    .into()),
+                _ => {},
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn body_len() -> usize {
+    page_size() - PAGE_HEADER_SIZE
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
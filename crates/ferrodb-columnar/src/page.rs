@@ -0,0 +1,264 @@
+//! A column page holds the values of one column for a run of rows:
+//!
+//! ```text
+//! [rows: u32][has nulls: u8][null bitmap: (rows + 7) / 8 bytes][values]
+//! ```
+//!
+//! The null bitmap is only there if some of the rows are NULL, and has a bit
+//! set for each of them. The values are the rest, in one of the
+//! [`encoding`](crate::encoding)s. A run of rows that are all NULL doesn't get
+//! a page at all.
+
+use std::cmp::Ordering;
+use std::ops::Range;
+
+use ferrodb_row::{DataType, Value};
+
+use crate::encoding::{self, Values, ValuesRef};
+
+const HEADER_SIZE: usize = 5;
+
+/// The most rows a page can have, however well they encode, so that decoding
+/// one doesn't take an unbounded amount of memory.
+const MAX_ROWS: usize = 1 << 16;
+
+/// A value as it's stored: an integer, which bools and doubles are stored as
+/// too, or bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Datum {
+    Int(i64),
+    Bytes(Vec<u8>),
+}
+
+impl Datum {
+    /// How `value` is stored, or `None` for NULL.
+    pub fn from_value(value: &Value) -> Option<Datum> {
+        match value {
+            Value::Null => None,
+            Value::Bool(value) => Some(Datum::Int(*value as i64)),
+            Value::Int(value) => Some(Datum::Int((*value).into())),
+            Value::BigInt(value) => Some(Datum::Int(*value)),
+            Value::Double(value) => Some(Datum::Int(value.to_bits() as i64)),
+            Value::Text(value) => Some(Datum::Bytes(value.as_bytes().to_vec())),
+            Value::Blob(value) => Some(Datum::Bytes(value.clone())),
+        }
+    }
+
+    pub fn to_value(&self, data_type: DataType) -> Option<Value> {
+        let value = match (data_type, self) {
+            (DataType::Bool, Datum::Int(value)) => Value::Bool(*value != 0),
+            (DataType::Int, Datum::Int(value)) => Value::Int(i32::try_from(*value).ok()?),
+            (DataType::BigInt, Datum::Int(value)) => Value::BigInt(*value),
+            (DataType::Double, Datum::Int(value)) => Value::Double(f64::from_bits(*value as u64)),
+            (DataType::Text, Datum::Bytes(value)) => Value::Text(String::from_utf8(value.clone()).ok()?),
+            (DataType::Blob, Datum::Bytes(value)) => Value::Blob(value.clone()),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// Compare two values of a column of type `data_type`. Doubles are in
+    /// their total order, with -0 before 0 and NaNs at the ends.
+    pub fn compare(&self, other: &Datum, data_type: DataType) -> Ordering {
+        match (self, other) {
+            (Datum::Int(a), Datum::Int(b)) if data_type == DataType::Double =>
+                f64::from_bits(*a as u64).total_cmp(&f64::from_bits(*b as u64)),
+            (Datum::Int(a), Datum::Int(b)) => a.cmp(b),
+            (Datum::Bytes(a), Datum::Bytes(b)) => a.cmp(b),
+            (Datum::Int(_), Datum::Bytes(_)) => Ordering::Less,
+            (Datum::Bytes(_), Datum::Int(_)) => Ordering::Greater,
+        }
+    }
+
+    /// The bytes of the value: 8 for an integer, and the bytes themselves
+    /// otherwise.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Datum::Int(value) => value.to_le_bytes().to_vec(),
+            Datum::Bytes(value) => value.clone(),
+        }
+    }
+
+    pub fn decode(bytes: &[u8], data_type: DataType) -> Option<Datum> {
+        match is_int(data_type) {
+            true => Some(Datum::Int(i64::from_le_bytes(bytes.try_into().ok()?))),
+            false => Some(Datum::Bytes(bytes.to_vec())),
+        }
+    }
+}
+
+/// Whether values of `data_type` are stored as integers.
+pub(crate) fn is_int(data_type: DataType) -> bool {
+    !matches!(data_type, DataType::Text | DataType::Blob)
+}
+
+/// One column's values for a batch of rows.
+pub(crate) struct ColumnData {
+    data_type: DataType,
+    nulls: Vec<bool>,
+    /// The values that aren't NULL.
+    values: Values,
+    /// For every row (and one past the last), how many of the rows before it
+    /// aren't NULL.
+    values_before: Vec<usize>,
+}
+
+/// A page's worth of a [`ColumnData`].
+pub(crate) struct PageData {
+    /// Which of the batch's rows the page has.
+    pub rows: Range<usize>,
+    pub body: Vec<u8>,
+    /// The smallest and biggest value in the page.
+    pub min: Datum,
+    pub max: Datum,
+}
+
+impl ColumnData {
+    pub fn new(data_type: DataType, values: impl Iterator<Item = Option<Datum>>) -> ColumnData {
+        let mut column = ColumnData {
+            data_type,
+            nulls: vec![],
+            values: match is_int(data_type) {
+                true => Values::Ints(vec![]),
+                false => Values::Bytes(vec![]),
+            },
+            values_before: vec![0],
+        };
+
+        for value in values {
+            column.nulls.push(value.is_none());
+            match (&mut column.values, value) {
+                (Values::Ints(values), Some(Datum::Int(value))) => values.push(value),
+                (Values::Bytes(values), Some(Datum::Bytes(value))) => values.push(value),
+                _ => {},
+            }
+            column.values_before.push(column.values.len());
+        }
+
+        column
+    }
+
+    /// Split the column into pages with bodies of at most `body_len` bytes,
+    /// or give the row of a value that doesn't fit in one on its own.
+    pub fn pages(&self, body_len: usize) -> Result<Vec<PageData>, usize> {
+        let mut pages = vec![];
+        let mut start = 0;
+
+        while start < self.nulls.len() {
+            if self.nulls[start] {
+                start += 1;
+                continue;
+            }
+
+            // Find how many rows fit by doubling until they don't, and then
+            // narrowing it down, since more rows never take up less space.
+            let most = (self.nulls.len() - start).min(MAX_ROWS);
+            let fits = |rows| self.encode(start..start + rows).len() <= body_len;
+            if !fits(1) {
+                return Err(start);
+            }
+
+            let mut low = 1;
+            let mut high = most + 1;
+            while low < most {
+                let rows = (low * 2).min(most);
+                if !fits(rows) {
+                    high = rows;
+                    break;
+                }
+                low = rows;
+            }
+            while high - low > 1 {
+                let rows = low + (high - low) / 2;
+                match fits(rows) {
+                    true => low = rows,
+                    false => high = rows,
+                }
+            }
+
+            let rows = start..start + low;
+            let (min, max) = self.min_max(rows.clone());
+            pages.push(PageData {
+                body: self.encode(rows.clone()),
+                rows,
+                min,
+                max,
+            });
+            start += low;
+        }
+
+        Ok(pages)
+    }
+
+    fn encode(&self, rows: Range<usize>) -> Vec<u8> {
+        let values = self.values_before[rows.start]..self.values_before[rows.end];
+        let has_nulls = values.len() < rows.len();
+
+        let mut body = (rows.len() as u32).to_le_bytes().to_vec();
+        body.push(has_nulls as u8);
+        if has_nulls {
+            let mut bitmap = vec![0; rows.len().div_ceil(8)];
+            for (i, _) in self.nulls[rows].iter().enumerate().filter(|(_, null)| **null) {
+                bitmap[i / 8] |= 1 << (i % 8);
+            }
+            body.extend_from_slice(&bitmap);
+        }
+
+        body.extend_from_slice(&encoding::encode(self.values.slice(values.start, values.end)));
+        body
+    }
+
+    fn min_max(&self, rows: Range<usize>) -> (Datum, Datum) {
+        match self.values.slice(self.values_before[rows.start], self.values_before[rows.end]) {
+            ValuesRef::Ints(values) => {
+                let compare = |a: &&i64, b: &&i64| Datum::Int(**a).compare(&Datum::Int(**b), self.data_type);
+                let min = values.iter().min_by(compare).unwrap();
+                let max = values.iter().max_by(compare).unwrap();
+                (Datum::Int(*min), Datum::Int(*max))
+            },
+            ValuesRef::Bytes(values) => {
+                let min = values.iter().min().unwrap();
+                let max = values.iter().max().unwrap();
+                (Datum::Bytes(min.clone()), Datum::Bytes(max.clone()))
+            },
+        }
+    }
+}
+
+/// The values of a page, NULLs and all, or `None` if it's corrupt.
+pub(crate) fn decode(body: &[u8], data_type: DataType) -> Option<Vec<Value>> {
+    let rows = u32::from_le_bytes(body.get(..4)?.try_into().unwrap()) as usize;
+    if rows > MAX_ROWS {
+        return None;
+    }
+
+    let (bitmap, values_at) = match body.get(4)? {
+        0 => (None, HEADER_SIZE),
+        _ => {
+            let len = rows.div_ceil(8);
+            (Some(body.get(HEADER_SIZE..HEADER_SIZE + len)?), HEADER_SIZE + len)
+        },
+    };
+    let is_null = |row: usize| bitmap.is_some_and(|bitmap| bitmap[row / 8] & (1 << (row % 8)) != 0);
+    let count = (0..rows).filter(|row| !is_null(*row)).count();
+
+    let mut values = match encoding::decode(&body[values_at..], is_int(data_type), count)? {
+        Values::Ints(values) => values
+            .into_iter()
+            .map(|value| Datum::Int(value).to_value(data_type))
+            .collect::<Option<Vec<_>>>()?,
+        Values::Bytes(values) => values
+            .into_iter()
+            .map(|value| Datum::Bytes(value).to_value(data_type))
+            .collect::<Option<Vec<_>>>()?,
+    }
+    .into_iter();
+
+    (0..rows)
+        .map(|row| match is_null(row) {
+            true => Some(Value::Null),
+            false => values.next(),
+        })
+        .collect()
+}
@@ -0,0 +1,308 @@
+use std::collections::VecDeque;
+use std::ops::{Bound, Range};
+use std::sync::Arc;
+
+use ferrodb_fs::{FileId, FileManager, PAGE_HEADER_SIZE};
+use ferrodb_row::{DataType, Schema, Value};
+
+use crate::directory::PageEntry;
+use crate::page::{self, Datum};
+use crate::Error;
+
+static NULL: Value = Value::Null;
+
+/// Only rows where a column's value is from `min` to `max` pass. NULL never
+/// does, and doubles compare in their total order, where -0 comes before 0.
+#[derive(Clone, Debug)]
+pub struct Filter {
+    pub column: usize,
+    pub min: Bound<Value>,
+    pub max: Bound<Value>,
+}
+
+/// The values of some of a table's columns, for each row that passes the
+/// scan's filters, in the order the rows were appended.
+pub struct ColumnScan {
+    files: Arc<FileManager>,
+    file: FileId,
+    /// The rows that could pass the filters, going by the min and max of the
+    /// pages they're in, in order.
+    ranges: VecDeque<Range<u64>>,
+    /// One for each column that's read, whether it's scanned or filtered on.
+    readers: Vec<ColumnReader>,
+    /// The reader of each column that's scanned.
+    outputs: Vec<usize>,
+    filters: Vec<ScanFilter>,
+    pages_read: u64,
+}
+
+/// Reads a column's pages, in order, one at a time.
+struct ColumnReader {
+    column: usize,
+    data_type: DataType,
+    /// The pages that have any rows that might be scanned.
+    entries: Vec<PageEntry>,
+    /// The first page that the scan hasn't got past yet.
+    at: usize,
+    /// The values of the `at` page, once it's been read.
+    values: Option<Vec<Value>>,
+}
+
+/// A [`Filter`] as it's checked against the stored values.
+struct ScanFilter {
+    reader: usize,
+    data_type: DataType,
+    min: Bound<Datum>,
+    max: Bound<Datum>,
+}
+
+impl ColumnScan {
+    pub(crate) fn new(
+        files: Arc<FileManager>,
+        file: FileId,
+        schema: &Schema,
+        columns: &[usize],
+        filters: &[Filter],
+        rows: u64,
+        entries: Vec<PageEntry>,
+    ) -> Result<ColumnScan, Error> {
+        let mut by_column = vec![vec![]; schema.len()];
+        for entry in entries {
+            if entry.column < schema.len() {
+                by_column[entry.column].push(entry);
+            }
+        }
+
+        let mut readers: Vec<ColumnReader> = vec![];
+        let mut reader = |column: usize| {
+            let Some(data_type) = schema.columns().get(column).map(|column| column.data_type)
+                else { return Err(Error::NoSuchColumn { column, count: schema.len() }); };
+
+            match readers.iter().position(|reader| reader.column == column) {
+                Some(reader) => Ok(reader),
+                None => {
+                    readers.push(ColumnReader {
+                        column,
+                        data_type,
+                        entries: vec![],
+                        at: 0,
+                        values: None,
+                    });
+                    Ok(readers.len() - 1)
+                },
+            }
+        };
+
+        let outputs = columns.iter().map(|&column| reader(column)).collect::<Result<Vec<_>, _>>()?;
+        let filters = filters
+            .iter()
+            .map(|filter| ScanFilter::new(schema, filter, reader(filter.column)?))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // A row can only pass if each filtered column has a page that its row
+        // is in that might, since rows without a page are NULL.
+        let mut ranges: Vec<Range<u64>> = std::iter::once(0..rows).filter(|rows| !rows.is_empty()).collect();
+        for filter in &filters {
+            let column = readers[filter.reader].column;
+            let matching: Vec<_> = by_column[column]
+                .iter()
+                .filter(|entry| filter.might_match(entry))
+                .map(|entry| entry.first_row..entry.end_row())
+                .collect();
+            ranges = intersect(&ranges, &matching);
+        }
+
+        for reader in &mut readers {
+            reader.entries = std::mem::take(&mut by_column[reader.column])
+                .into_iter()
+                .filter(|entry| overlaps(&ranges, entry.first_row..entry.end_row()))
+                .collect();
+        }
+
+        Ok(ColumnScan {
+            files,
+            file,
+            ranges: ranges.into(),
+            readers,
+            outputs,
+            filters,
+            pages_read: 0,
+        })
+    }
+
+    /// How many column pages the scan has read so far.
+    pub fn pages_read(&self) -> u64 {
+        self.pages_read
+    }
+
+    fn read_row(&mut self, row: u64) -> Result<Option<Vec<Value>>, Error> {
+        for filter in &self.filters {
+            let reader = &mut self.readers[filter.reader];
+            let value = reader.get(&self.files, self.file, row, &mut self.pages_read)?;
+            if !filter.matches(value) {
+                return Ok(None);
+            }
+        }
+
+        let mut values = Vec::with_capacity(self.outputs.len());
+        for &reader in &self.outputs {
+            let reader = &mut self.readers[reader];
+            values.push(reader.get(&self.files, self.file, row, &mut self.pages_read)?.clone());
+        }
+
+        Ok(Some(values))
+    }
+}
+
+impl Iterator for ColumnScan {
+    type Item = Result<Vec<Value>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let range = self.ranges.front_mut()?;
+            let row = range.start;
+            range.start += 1;
+            if range.is_empty() {
+                self.ranges.pop_front();
+            }
+
+            match self.read_row(row) {
+                Ok(Some(values)) => return Some(Ok(values)),
+                Ok(None) => {},
+                Err(e) => {
+                    self.ranges.clear();
+                    return Some(Err(e));
+                },
+            }
+        }
+    }
+}
+
+impl ColumnReader {
+    /// The value of the column in `row`, which can't be before any row it's
+    /// been asked for so far.
+    fn get(
+        &mut self,
+        files: &FileManager,
+        file: FileId,
+        row: u64,
+        pages_read: &mut u64,
+    ) -> Result<&Value, Error> {
+        while matches!(self.entries.get(self.at), Some(entry) if entry.end_row() <= row) {
+            self.at += 1;
+            self.values = None;
+        }
+
+        let Some(entry) = self.entries.get(self.at).filter(|entry| entry.first_row <= row)
+            else { return Ok(&NULL); };
+
+        if self.values.is_none() {
+            let page_ref = files.latest(file, entry.page)?;
+            let buf = page_ref.read();
+            let values = page::decode(&buf[PAGE_HEADER_SIZE..], self.data_type)
+                .filter(|values| values.len() == entry.rows as usize)
+                .ok_or(Error::Corrupt(entry.page))?;
+
+            self.values = Some(values);
+            *pages_read += 1;
+        }
+
+        Ok(&self.values.as_ref().unwrap()[(row - entry.first_row) as usize])
+    }
+}
+
+impl ScanFilter {
+    fn new(schema: &Schema, filter: &Filter, reader: usize) -> Result<ScanFilter, Error> {
+        let column = &schema.columns()[filter.column];
+        let datum = |value: &Value| match (value.data_type(), Datum::from_value(value)) {
+            (Some(data_type), Some(datum)) if data_type == column.data_type => Ok(datum),
+            (Some(data_type), _) => Err(Error::from(ferrodb_row::Error::TypeMismatch {
+                column: column.name.clone(),
+                expected: column.data_type,
+                got: data_type,
+            })),
+            (None, _) => Err(Error::NullBound {
+                column: column.name.clone(),
+            }),
+        };
+        let bound = |bound: &Bound<Value>| -> Result<_, Error> {
+            match bound {
+                Bound::Included(value) => Ok(Bound::Included(datum(value)?)),
+                Bound::Excluded(value) => Ok(Bound::Excluded(datum(value)?)),
+                Bound::Unbounded => Ok(Bound::Unbounded),
+            }
+        };
+
+        Ok(ScanFilter {
+            reader,
+            data_type: column.data_type,
+            min: bound(&filter.min)?,
+            max: bound(&filter.max)?,
+        })
+    }
+
+    fn matches(&self, value: &Value) -> bool {
+        match Datum::from_value(value) {
+            Some(datum) => self.above_min(&datum) && self.below_max(&datum),
+            None => false,
+        }
+    }
+
+    /// Whether a page might have values that pass, going by its min and max.
+    fn might_match(&self, entry: &PageEntry) -> bool {
+        let Some((min, max)) = &entry.stats
+            else { return true; };
+
+        match (Datum::decode(min, self.data_type), Datum::decode(max, self.data_type)) {
+            (Some(min), Some(max)) => self.above_min(&max) && self.below_max(&min),
+            _ => true,
+        }
+    }
+
+    fn above_min(&self, datum: &Datum) -> bool {
+        match &self.min {
+            Bound::Included(min) => datum.compare(min, self.data_type).is_ge(),
+            Bound::Excluded(min) => datum.compare(min, self.data_type).is_gt(),
+            Bound::Unbounded => true,
+        }
+    }
+
+    fn below_max(&self, datum: &Datum) -> bool {
+        match &self.max {
+            Bound::Included(max) => datum.compare(max, self.data_type).is_le(),
+            Bound::Excluded(max) => datum.compare(max, self.data_type).is_lt(),
+            Bound::Unbounded => true,
+        }
+    }
+}
+
+/// The rows that are in both `a` and `b`, which are each in order, and don't
+/// overlap.
+fn intersect(a: &[Range<u64>], b: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut ranges: Vec<Range<u64>> = vec![];
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() && j < b.len() {
+        let start = a[i].start.max(b[j].start);
+        let end = a[i].end.min(b[j].end);
+        if start < end {
+            match ranges.last_mut() {
+                Some(last) if last.end == start => last.end = end,
+                _ => ranges.push(start..end),
+            }
+        }
+
+        if a[i].end < b[j].end {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    ranges
+}
+
+fn overlaps(ranges: &[Range<u64>], range: Range<u64>) -> bool {
+    let index = ranges.partition_point(|other| other.end <= range.start);
+    ranges.get(index).is_some_and(|other| other.start < range.end)
+}
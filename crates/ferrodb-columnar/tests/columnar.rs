@@ -0,0 +1,167 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use ferrodb_columnar::{ColumnarTable, Error, Filter};
+use ferrodb_fs::vfs::{CrashMode, CrashVfs, Vfs};
+use ferrodb_fs::FileManager;
+use ferrodb_row::{Column, DataType, Schema, Value};
+use ferrodb_wal::{recover, Wal};
+
+const PAGE_SIZE: usize = 1024;
+
+fn open(vfs: Arc<dyn Vfs>, schema: Schema) -> (Arc<Wal>, Arc<FileManager>, ColumnarTable) {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+
+    recover(&wal, &files, |_, _| {}).unwrap();
+    let table = ColumnarTable::open(files.clone(), "table", schema).unwrap();
+    (wal, files, table)
+}
+
+fn schema(columns: usize) -> Schema {
+    let types = [DataType::BigInt, DataType::Text, DataType::Double, DataType::Bool, DataType::Int];
+    let columns = (0..columns)
+        .map(|i| Column {
+            name: format!("c{i}"),
+            data_type: types[i % types.len()],
+            nullable: i != 0,
+        })
+        .collect();
+    Schema::new(columns).unwrap()
+}
+
+fn row(id: i64, columns: usize) -> Vec<Value> {
+    (0..columns)
+        .map(|i| match i % 5 {
+            _ if i > 0 && (id + i as i64) % 13 == 0 => Value::Null,
+            0 => Value::BigInt(id * 1000 + id * 7919 % 1000),
+            1 => Value::Text(["north", "south", "east", "west"][(id % 4) as usize].to_owned()),
+            2 => Value::Double(id as f64 / 4.0),
+            3 => Value::Bool(id % 3 == 0),
+            _ => Value::Int((id % 100) as i32),
+        })
+        .collect()
+}
+
+fn append(wal: &Wal, table: &ColumnarTable, rows: &[Vec<Value>]) {
+    let txn = wal.begin().unwrap();
+    table.append(&txn, rows).unwrap();
+    txn.commit().unwrap();
+}
+
+fn scan(table: &ColumnarTable, columns: &[usize], filters: &[Filter]) -> (Vec<Vec<Value>>, u64) {
+    let mut scan = table.scan(columns, filters).unwrap();
+    let rows = scan.by_ref().collect::<Result<_, _>>().unwrap();
+    (rows, scan.pages_read())
+}
+
+fn project(rows: &[Vec<Value>], columns: &[usize]) -> Vec<Vec<Value>> {
+    rows.iter().map(|row| columns.iter().map(|&i| row[i].clone()).collect()).collect()
+}
+
+#[test]
+fn scans_only_read_the_pages_of_the_columns_they_ask_for() {
+    let (wal, _files, table) = open(Arc::new(CrashVfs::default()), schema(20));
+    let rows: Vec<_> = (0..3000).map(|id| row(id, 20)).collect();
+    for batch in rows.chunks(1000) {
+        append(&wal, &table, batch);
+    }
+    assert_eq!(table.row_count().unwrap(), 3000);
+
+    let all: Vec<_> = (0..20).collect();
+    let (scanned, all_pages) = scan(&table, &all, &[]);
+    assert_eq!(scanned, rows);
+
+    let columns = [12, 1, 0];
+    let (scanned, pages) = scan(&table, &columns, &[]);
+    assert_eq!(scanned, project(&rows, &columns));
+    assert!(pages * 4 < all_pages, "Read {pages} pages, but {all_pages} for every column");
+
+    // A column that's asked for twice is still only read once.
+    let (scanned, twice) = scan(&table, &[12, 1, 0, 12], &[]);
+    assert_eq!(scanned, project(&rows, &[12, 1, 0, 12]));
+    assert_eq!(twice, pages);
+}
+
+#[test]
+fn filters_skip_the_pages_that_cant_have_rows_that_pass() {
+    let (wal, _files, table) = open(Arc::new(CrashVfs::default()), schema(5));
+    let rows: Vec<_> = (0..5000).map(|id| row(id, 5)).collect();
+    append(&wal, &table, &rows);
+
+    let filter = |column, min, max| Filter { column, min, max };
+    let (min, max) = (Value::BigInt(2_000_000), Value::BigInt(2_100_000));
+    let halves = filter(2, Bound::Unbounded, Bound::Included(Value::Double(510.0)));
+    let filters = [filter(0, Bound::Included(min), Bound::Excluded(max)), halves];
+    let (scanned, pages) = scan(&table, &[0, 1], &filters[..1]);
+    assert_eq!(scanned, project(&rows[2000..2100], &[0, 1]));
+    let (_, all_pages) = scan(&table, &[0, 1], &[]);
+    assert!(pages * 3 < all_pages, "Read {pages} pages, but {all_pages} without a filter");
+
+    // Rows have to pass every filter, and NULLs never do.
+    let (scanned, _) = scan(&table, &[0], &filters);
+    let expected: Vec<_> = rows[2000..=2040]
+        .iter()
+        .filter(|row| row[2] != Value::Null)
+        .map(|row| vec![row[0].clone()])
+        .collect();
+    assert_eq!(scanned, expected);
+
+    let null = filter(1, Bound::Included(Value::Null), Bound::Unbounded);
+    assert!(matches!(table.scan(&[0], &[null]), Err(Error::NullBound { .. })));
+    let text = filter(0, Bound::Included(Value::Text("1".to_owned())), Bound::Unbounded);
+    assert!(matches!(table.scan(&[0], &[text]), Err(Error::Row(_))));
+    assert!(matches!(table.scan(&[5], &[]), Err(Error::NoSuchColumn { column: 5, count: 5 })));
+}
+
+#[test]
+fn rows_are_there_after_a_crash_unless_their_append_rolled_back() {
+    let vfs = Arc::new(CrashVfs::default());
+    let (wal, files, table) = open(vfs.clone(), schema(3));
+    let rows: Vec<_> = (0..500).map(|id| row(id, 3)).collect();
+    append(&wal, &table, &rows[..300]);
+
+    let txn = wal.begin().unwrap();
+    table.append(&txn, &rows[300..]).unwrap();
+    assert_eq!(table.row_count().unwrap(), 500);
+    txn.abort(&files).unwrap();
+    assert_eq!(table.row_count().unwrap(), 300);
+    assert_eq!(scan(&table, &[0, 1, 2], &[]).0, rows[..300]);
+
+    append(&wal, &table, &rows[300..400]);
+    let crashed = Arc::new(vfs.crash(CrashMode::DropUnsynced));
+    drop((wal, files, table));
+
+    // Columns added since read as NULL in the rows from before.
+    let (wal, _files, table) = open(crashed, schema(4));
+    let mut expected: Vec<_> = rows[..400].to_vec();
+    expected.iter_mut().for_each(|row| row.push(Value::Null));
+    assert_eq!(scan(&table, &[0, 1, 2, 3], &[]).0, expected);
+
+    append(&wal, &table, &[row(400, 4)]);
+    expected.push(row(400, 4));
+    assert_eq!(scan(&table, &[0, 1, 2, 3], &[]).0, expected);
+}
+
+#[test]
+fn rows_that_dont_fit_the_schema_are_refused() {
+    let (wal, _files, table) = open(Arc::new(CrashVfs::default()), schema(2));
+    let txn = wal.begin().unwrap();
+
+    let refused = [
+        vec![Value::BigInt(1)],
+        vec![Value::Null, Value::Null],
+        vec![Value::Int(1), Value::Null],
+    ];
+    for row in refused {
+        assert!(matches!(table.append(&txn, &[row]), Err(Error::Row(_))));
+    }
+
+    let long = vec![Value::BigInt(1), Value::Text("x".repeat(PAGE_SIZE))];
+    assert!(matches!(table.append(&txn, &[long]), Err(Error::ValueTooLarge { .. })));
+    txn.commit().unwrap();
+    assert_eq!(table.row_count().unwrap(), 0);
+}
@@ -0,0 +1,502 @@
+//! Compressed files store each page as a variable-length extent of the data
+//! file. Where each page's extent lives is kept in a separate extent map file:
+//!
+//! ```text
+//! [magic: 8 bytes][codec: u8][level: i32][flags: u8][padding: 2 bytes]
+//! ([offset: u64][len: u32][capacity: u32]) * pages
+//! ```
+//!
+//! The map of an encrypted file has the [`FLAG_SEALED`] flag, and each of its
+//! entries is sealed on its own, with the page index as its position. Sealed
+//! entries are padded out to [`SEALED_ENTRY_SIZE`] bytes (as is the header),
+//! so that none of them straddles a sector, and a torn write can only ever
+//! leave a whole entry behind, old or new, like with plain ones.
+//!
+//! A page whose extent has zero capacity has never been written. A page that
+//! doesn't get any smaller when compressed is stored as it is, which we can
+//! tell apart because it's a whole page long.
+//!
+//! A page is never written over in place, since a torn write would leave
+//! nothing to read it back from. Each write moves the page to an extent that's
+//! free, and the one it leaves is only free to be reused once the map that
+//! points at the new one has been synced. Which extents are free isn't stored
+//! anywhere, since it's just the gaps between the ones in the map.
+
+use std::io;
+
+use crate::vfs::VfsFile;
+use crate::{Cipher, PageIndex, SEAL_OVERHEAD};
+
+const MAGIC: &[u8; 8] = b"FERROEXT";
+const HEADER_SIZE: u64 = 16;
+const ENTRY_SIZE: u64 = 16;
+const SEALED_ENTRY_SIZE: u64 = 64;
+
+const FLAG_SEALED: u8 = 1 << 0;
+
+/// Extents are allocated in multiples of this, so that the extent a page moves
+/// out of usually fits whichever page is written next.
+const EXTENT_ALIGN: u32 = 512;
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd { level: i32 },
+}
+
+impl Compression {
+    /// How the codec is stored on disk. The zstd level isn't part of it, since
+    /// decompressing doesn't need it.
+    pub fn codec(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+            Compression::Zstd { .. } => 2,
+        }
+    }
+
+    pub fn from_codec(codec: u8, level: i32) -> Option<Compression> {
+        match codec {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lz4),
+            2 => Some(Compression::Zstd { level }),
+            _ => None,
+        }
+    }
+
+    /// Compress `bytes` as a single block.
+    pub fn compress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(bytes.to_vec()),
+            Compression::Lz4 => Ok(lz4_flex::compress(bytes)),
+            Compression::Zstd { level } => zstd::block::compress(bytes, level),
+        }
+    }
+
+    /// Returns `false` if `bytes` isn't something we compressed from exactly
+    /// `out.len()` bytes.
+    pub fn decompress(self, bytes: &[u8], out: &mut [u8]) -> bool {
+        match self {
+            Compression::None => false,
+            Compression::Lz4 =>
+                matches!(lz4_flex::decompress_into(bytes, out), Ok(n) if n == out.len()),
+            Compression::Zstd { .. } =>
+                matches!(zstd::block::decompress_to_buffer(bytes, out), Ok(n) if n == out.len()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct Extent {
+    offset: u64,
+    len: u32,
+    capacity: u32,
+}
+
+impl Extent {
+    fn end(&self) -> u64 {
+        self.offset + self.capacity as u64
+    }
+}
+
+pub(crate) struct ExtentMap {
+    file: Box<dyn VfsFile>,
+    compression: Compression,
+    /// Only the maps of encrypted files have ciphers.
+    cipher: Option<Cipher>,
+    extents: Vec<Extent>,
+    /// Extents that no page is in, sorted by offset, with no two of them next
+    /// to each other. New extents are taken from these before the data file is
+    /// made any longer.
+    free: Vec<Extent>,
+    /// The end of the last extent in the data file.
+    end: u64,
+}
+
+impl ExtentMap {
+    /// Start a new extent map, for a data file that has nothing in it yet.
+    /// Extents are allocated from `start` onwards.
+    pub fn create(
+        file: Box<dyn VfsFile>,
+        compression: Compression,
+        cipher: Option<Cipher>,
+        start: u64,
+    ) -> io::Result<ExtentMap> {
+        let level = match compression {
+            Compression::None => unreachable!("Uncompressed files don't have extent maps"),
+            Compression::Lz4 => 0,
+            Compression::Zstd { level } => level,
+        };
+
+        let mut header = [0; HEADER_SIZE as usize];
+        header[..8].copy_from_slice(MAGIC);
+        header[8] = compression.codec();
+        header[9..13].copy_from_slice(&level.to_le_bytes());
+        if cipher.is_some() {
+            header[13] = FLAG_SEALED;
+        }
+
+        file.truncate(0)?;
+        file.write_at(&header, 0)?;
+        file.sync()?;
+
+        Ok(ExtentMap {
+            file,
+            compression,
+            cipher,
+            extents: vec![],
+            free: vec![],
+            end: start,
+        })
+    }
+
+    pub fn load(file: Box<dyn VfsFile>, cipher: Option<Cipher>, start: u64) -> io::Result<ExtentMap> {
+        let mut bytes = vec![0; file.len()? as usize];
+        file.read_at(&mut bytes, 0)?;
+
+        let invalid = || invalid_data("Invalid extent map");
+
+        if bytes.len() < HEADER_SIZE as usize || !bytes.starts_with(MAGIC) {
+            return Err(invalid());
+        }
+
+        let level = i32::from_le_bytes(bytes[9..13].try_into().unwrap());
+        let compression = match Compression::from_codec(bytes[8], level) {
+            Some(Compression::None) | None => return Err(invalid()),
+            Some(compression) => compression,
+        };
+
+        match (bytes[13] & FLAG_SEALED != 0, cipher.is_some()) {
+            (true, false) => return Err(invalid_data("Extent map is encrypted, but no key was given")),
+            (false, true) => return Err(invalid_data("Extent map isn't encrypted, but a key was given")),
+            _ => {},
+        }
+
+        let entry_size = entry_size(cipher.is_some()) as usize;
+        let extents = bytes
+            .get(entry_size..)
+            .unwrap_or_default()
+            .chunks_exact(entry_size)
+            .enumerate()
+            .map(|(page, entry)| {
+                let entry = match &cipher {
+                    // An entry that's still all zeroes was never written.
+                    Some(_) if entry.iter().all(|b| *b == 0) => vec![0; ENTRY_SIZE as usize],
+                    Some(cipher) => cipher
+                        .open(page as u64, &entry[..ENTRY_SIZE as usize + SEAL_OVERHEAD])
+                        .ok_or_else(invalid)?,
+                    None => entry.to_vec(),
+                };
+
+                Ok(Extent {
+                    offset: u64::from_le_bytes(entry[..8].try_into().unwrap()),
+                    len: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+                    capacity: u32::from_le_bytes(entry[12..].try_into().unwrap()),
+                })
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let (free, end) = free_space(&extents, start);
+
+        Ok(ExtentMap {
+            file,
+            compression,
+            cipher,
+            extents,
+            free,
+            end,
+        })
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// How many pages have a slot in the map, written or not.
+    pub fn pages(&self) -> usize {
+        self.extents.len()
+    }
+
+    /// Read the extents of some pages out of `data`, or `None` for pages that
+    /// were never written.
+    pub fn read_extents(
+        &self,
+        data: &dyn VfsFile,
+        pages: &[PageIndex],
+    ) -> io::Result<Vec<Option<Vec<u8>>>> {
+        let extents: Vec<_> = pages
+            .iter()
+            .map(|page| self.extents.get(*page).copied().unwrap_or_default())
+            .collect();
+
+        let mut bytes: Vec<_> = extents
+            .iter()
+            .map(|extent| vec![0; extent.len as usize])
+            .collect();
+        let mut ops: Vec<_> = extents
+            .iter()
+            .zip(&mut bytes)
+            .filter(|(extent, _)| extent.capacity > 0)
+            .map(|(extent, bytes)| (extent.offset, &mut bytes[..]))
+            .collect();
+        data.read_batch(&mut ops)?;
+
+        Ok(extents
+            .iter()
+            .zip(bytes)
+            .map(|(extent, bytes)| (extent.capacity > 0).then_some(bytes))
+            .collect())
+    }
+
+    /// Forget the extents of every page from `pages` on, and cut `data` back
+    /// to the end of the last extent that's left, which is at least `start`.
+    pub fn truncate(&mut self, data: &dyn VfsFile, pages: usize, start: u64) -> io::Result<()> {
+        if self.extents.len() <= pages {
+            return Ok(());
+        }
+
+        self.extents.truncate(pages);
+        self.file.truncate(entry_offset(pages, self.cipher.is_some()))?;
+        self.file.sync()?;
+
+        (self.free, self.end) = free_space(&self.extents, start);
+        data.truncate(self.end)?;
+        data.sync()
+    }
+
+    /// Write the extents of some pages into `data`, each into an extent that
+    /// no page is in, then persist where they went. Both `data` and the map
+    /// are synced.
+    pub fn write_extents(&mut self, data: &dyn VfsFile, pages: &[(PageIndex, Vec<u8>)]) -> io::Result<()> {
+        let mut offsets = vec![];
+        // Extents that pages moved out of. Until the map that doesn't point at
+        // them anymore is synced, a crash could still leave a page in one, so
+        // nothing can be written over them before then.
+        let mut moved_out = vec![];
+
+        for (page, bytes) in pages {
+            if self.extents.len() <= *page {
+                self.extents.resize(page + 1, Extent::default());
+            }
+
+            let len = bytes.len() as u32;
+            let capacity = len.div_ceil(EXTENT_ALIGN) * EXTENT_ALIGN;
+            let offset = self.allocate(capacity);
+
+            let old = std::mem::replace(
+                &mut self.extents[*page],
+                Extent {
+                    offset,
+                    len,
+                    capacity,
+                },
+            );
+            if old.capacity > 0 {
+                moved_out.push(old);
+            }
+
+            offsets.push(offset);
+        }
+
+        let ops: Vec<_> = offsets
+            .iter()
+            .zip(pages)
+            .map(|(offset, (_, bytes))| (*offset, &bytes[..]))
+            .collect();
+        data.write_batch(&ops)?;
+        data.sync()?;
+
+        let entries: Vec<_> = pages
+            .iter()
+            .map(|(page, _)| {
+                let extent = self.extents[*page];
+                let mut entry = [0; ENTRY_SIZE as usize];
+                entry[..8].copy_from_slice(&extent.offset.to_le_bytes());
+                entry[8..12].copy_from_slice(&extent.len.to_le_bytes());
+                entry[12..].copy_from_slice(&extent.capacity.to_le_bytes());
+
+                let entry = match &self.cipher {
+                    Some(cipher) => {
+                        let mut sealed = cipher.seal(*page as u64, &entry);
+                        sealed.resize(SEALED_ENTRY_SIZE as usize, 0);
+                        sealed
+                    },
+                    None => entry.to_vec(),
+                };
+                (entry_offset(*page, self.cipher.is_some()), entry)
+            })
+            .collect();
+        let ops: Vec<_> = entries
+            .iter()
+            .map(|(offset, entry)| (*offset, &entry[..]))
+            .collect();
+        self.file.write_batch(&ops)?;
+        self.file.sync()?;
+
+        for extent in moved_out {
+            self.release(extent);
+        }
+
+        Ok(())
+    }
+
+    /// Find room for an extent of `capacity` bytes, returning its offset: the
+    /// smallest free extent that's big enough, or the end of the data file.
+    fn allocate(&mut self, capacity: u32) -> u64 {
+        let best = (0..self.free.len())
+            .filter(|&index| self.free[index].capacity >= capacity)
+            .min_by_key(|&index| self.free[index].capacity);
+
+        let Some(index) = best
+            else {
+                let offset = self.end;
+                self.end += capacity as u64;
+                return offset;
+            };
+
+        let free = &mut self.free[index];
+        let offset = free.offset;
+        if free.capacity == capacity {
+            self.free.remove(index);
+        } else {
+            free.offset += capacity as u64;
+            free.capacity -= capacity;
+        }
+
+        offset
+    }
+
+    /// Make an extent that no page is in anymore free to be reused, merging it
+    /// with any free extents on either side of it.
+    fn release(&mut self, extent: Extent) {
+        let index = self.free.partition_point(|free| free.offset < extent.offset);
+        self.free.insert(index, Extent { len: 0, ..extent });
+
+        if index + 1 < self.free.len() && self.free[index].end() == self.free[index + 1].offset {
+            self.free[index].capacity += self.free.remove(index + 1).capacity;
+        }
+        if index > 0 && self.free[index - 1].end() == self.free[index].offset {
+            self.free[index - 1].capacity += self.free.remove(index).capacity;
+        }
+
+        // Free space at the very end is just where the next extent goes.
+        if let Some(last) = self.free.last().copied() {
+            if last.end() == self.end {
+                self.free.pop();
+                self.end = last.offset;
+            }
+        }
+    }
+}
+
+/// How many bytes each page's entry takes up in the map, and the header does
+/// too.
+fn entry_size(sealed: bool) -> u64 {
+    if sealed {
+        SEALED_ENTRY_SIZE
+    } else {
+        ENTRY_SIZE
+    }
+}
+
+/// Where a page's entry is in the map.
+fn entry_offset(page: PageIndex, sealed: bool) -> u64 {
+    (page as u64 + 1) * entry_size(sealed)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// The gaps between `extents` (and before the first of them, down to
+/// `start`), and where the last of them ends.
+fn free_space(extents: &[Extent], start: u64) -> (Vec<Extent>, u64) {
+    let mut used: Vec<_> = extents.iter().filter(|extent| extent.capacity > 0).collect();
+    used.sort_by_key(|extent| extent.offset);
+
+    let mut free = vec![];
+    let mut end = start;
+    for extent in used {
+        if extent.offset > end {
+            free.push(Extent {
+                offset: end,
+                len: 0,
+                capacity: (extent.offset - end) as u32,
+            });
+        }
+        end = end.max(extent.end());
+    }
+
+    (free, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8Path;
+
+    use super::*;
+    use crate::vfs::{CrashMode, CrashVfs, MemoryVfs, Vfs};
+
+    const START: u64 = 512;
+
+    fn open(vfs: &dyn Vfs) -> (Box<dyn VfsFile>, ExtentMap) {
+        let data = vfs.open(Utf8Path::new("data")).unwrap();
+        let map = vfs.open(Utf8Path::new("map")).unwrap();
+        let map = if map.is_empty().unwrap() {
+            ExtentMap::create(map, Compression::Lz4, None, START).unwrap()
+        } else {
+            ExtentMap::load(map, None, START).unwrap()
+        };
+        (data, map)
+    }
+
+    #[test]
+    fn pages_read_back_after_reloading_the_map() {
+        let vfs = MemoryVfs::default();
+        let (data, mut map) = open(&vfs);
+        map.write_extents(&*data, &[(0, vec![1; 100]), (2, vec![2; 700])]).unwrap();
+        map.write_extents(&*data, &[(0, vec![3; 10])]).unwrap();
+
+        let (data, map) = open(&vfs);
+        assert_eq!(map.pages(), 3);
+        assert_eq!(
+            map.read_extents(&*data, &[0, 1, 2]).unwrap(),
+            [Some(vec![3; 10]), None, Some(vec![2; 700])],
+        );
+    }
+
+    #[test]
+    fn pages_move_out_of_their_extent_and_it_is_reused_after() {
+        let vfs = MemoryVfs::default();
+        let (data, mut map) = open(&vfs);
+
+        map.write_extents(&*data, &[(0, vec![1; 100])]).unwrap();
+        let first = map.extents[0].offset;
+        map.write_extents(&*data, &[(0, vec![2; 100])]).unwrap();
+        assert_ne!(map.extents[0].offset, first);
+
+        map.write_extents(&*data, &[(1, vec![3; 100])]).unwrap();
+        assert_eq!(map.extents[1].offset, first);
+    }
+
+    #[test]
+    fn a_crash_while_rewriting_a_page_leaves_the_old_or_new_one() {
+        let vfs = CrashVfs::default();
+        let (data, mut map) = open(&vfs);
+        map.write_extents(&*data, &[(0, vec![1; 1000])]).unwrap();
+
+        let before = vfs.events().len();
+        map.write_extents(&*data, &[(0, vec![2; 1000])]).unwrap();
+
+        for events in before..=vfs.events().len() {
+            for seed in 0..10 {
+                let crashed = vfs.crash_at(events, CrashMode::TornWrite { sector_size: 64, seed });
+                let (data, map) = open(&crashed);
+
+                let page = map.read_extents(&*data, &[0]).unwrap().remove(0).unwrap();
+                assert!(page == [1; 1000] || page == [2; 1000], "Expected a whole page after {events} events");
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! The double-write file holds a copy of the last batch of pages written,
+//! from before they were written to their home location:
+//!
+//! ```text
+//! [crc32 of the rest: u32][count: u32]
+//! ([name len: u16][name][page index: u64][page]) * count
+//! ```
+
+use crate::PageIndex;
+
+pub(crate) fn encode<'a>(pages: impl Iterator<Item = (&'a str, PageIndex, &'a [u8])>) -> Vec<u8> {
+    let mut bytes = vec![0; 8];
+    let mut count = 0u32;
+
+    for (name, page, buf) in pages {
+        bytes.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&(page as u64).to_le_bytes());
+        bytes.extend_from_slice(buf);
+        count += 1;
+    }
+
+    bytes[4..8].copy_from_slice(&count.to_le_bytes());
+    let crc = crc32fast::hash(&bytes[4..]);
+    bytes[..4].copy_from_slice(&crc.to_le_bytes());
+
+    bytes
+}
+
+/// `None` if the file is empty or torn.
+pub(crate) fn decode(bytes: &[u8], page_size: usize) -> Option<Vec<(&str, PageIndex, &[u8])>> {
+    let crc = u32::from_le_bytes(bytes.get(..4)?.try_into().unwrap());
+    let count = u32::from_le_bytes(bytes.get(4..8)?.try_into().unwrap());
+
+    if crc32fast::hash(&bytes[4..]) != crc {
+        return None;
+    }
+
+    let mut rest = &bytes[8..];
+    let mut pages = vec![];
+
+    for _ in 0..count {
+        let name_len = u16::from_le_bytes(rest.get(..2)?.try_into().unwrap()) as usize;
+        let name = std::str::from_utf8(rest.get(2..2 + name_len)?).ok()?;
+        rest = &rest[2 + name_len..];
+
+        let page = u64::from_le_bytes(rest.get(..8)?.try_into().unwrap()) as PageIndex;
+        let buf = rest.get(8..8 + page_size)?;
+        rest = &rest[8 + page_size..];
+
+        pages.push((name, page, buf));
+    }
+
+    Some(pages)
+}
@@ -0,0 +1,154 @@
+//! Pages of an encrypted file are sealed with XChaCha20-Poly1305 before they
+//! hit the disk, and stored as:
+//!
+//! ```text
+//! [write counter: u64][ciphertext][tag: 16 bytes]
+//! ```
+//!
+//! The 24-byte nonce is `[file tag: u64][page index: u64][write counter: u64]`,
+//! where the file tag is derived from the file's name. Only the counter is
+//! stored, so a sealed page that gets copied to a different page or file
+//! fails to authenticate instead of decrypting into the wrong place.
+//!
+//! Every file starts counting writes from a random point each time it's
+//! opened, rather than from some persisted counter that a crash could roll
+//! back into reusing a nonce.
+//!
+//! Everything else that holds what's in pages is sealed the same way, with a
+//! position standing in for the page index: extent map entries by their page,
+//! and log records by their LSN.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use camino::Utf8Path;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+use crate::Error;
+
+/// The environment variable that [`EncryptionKey::from_env`] reads.
+pub const KEY_ENV_VAR: &str = "FERRODB_KEY";
+
+/// How many more bytes a sealed page takes up than the page itself.
+pub const SEAL_OVERHEAD: usize = 8 + 16;
+
+/// A 256-bit key for encrypting data files at rest.
+#[derive(Clone)]
+pub struct EncryptionKey([u8; 32]);
+
+impl EncryptionKey {
+    pub fn new(bytes: [u8; 32]) -> EncryptionKey {
+        EncryptionKey(bytes)
+    }
+
+    /// Parse a key written as 64 hex digits.
+    pub fn from_hex(hex: &str) -> Result<EncryptionKey, Error> {
+        let hex = hex.trim();
+
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(Error::InvalidKey("expected 64 hex digits".to_owned()));
+        }
+
+        let mut bytes = [0; 32];
+        for (byte, digits) in bytes.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).unwrap();
+            *byte = u8::from_str_radix(digits, 16)
+                .map_err(|_| Error::InvalidKey(format!("`{digits}` isn't a hex byte")))?;
+        }
+
+        Ok(EncryptionKey(bytes))
+    }
+
+    /// Read the key from [`KEY_ENV_VAR`], if it's set.
+    pub fn from_env() -> Result<Option<EncryptionKey>, Error> {
+        match std::env::var(KEY_ENV_VAR) {
+            Ok(hex) => EncryptionKey::from_hex(&hex).map(Some),
+            Err(std::env::VarError::NotPresent) => Ok(None),
+            Err(std::env::VarError::NotUnicode(_)) =>
+                Err(Error::InvalidKey(format!("`{KEY_ENV_VAR}` isn't valid unicode"))),
+        }
+    }
+
+    /// Read the key from a local file, either as 32 raw bytes or as 64 hex
+    /// digits.
+    pub fn from_file(path: &Utf8Path) -> Result<EncryptionKey, Error> {
+        let bytes = std::fs::read(path)?;
+
+        if let Ok(bytes) = <[u8; 32]>::try_from(&bytes[..]) {
+            return Ok(EncryptionKey(bytes));
+        }
+
+        match std::str::from_utf8(&bytes) {
+            Ok(hex) => EncryptionKey::from_hex(hex),
+            Err(_) => Err(Error::InvalidKey(format!(
+                "`{path}` should hold 32 bytes or 64 hex digits"
+            ))),
+        }
+    }
+}
+
+impl fmt::Debug for EncryptionKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("EncryptionKey(..)")
+    }
+}
+
+/// Seals and opens the pages of one file, or anything else that only ever
+/// goes in one place in it.
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+    file_tag: u64,
+    counter: AtomicU64,
+}
+
+impl Cipher {
+    pub fn new(key: &EncryptionKey, name: &str) -> Cipher {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(name.as_bytes());
+        let low = hasher.clone().finalize();
+        hasher.update(&name.len().to_le_bytes());
+        let high = hasher.finalize();
+
+        Cipher {
+            aead: XChaCha20Poly1305::new(&Key::from(key.0)),
+            file_tag: (high as u64) << 32 | low as u64,
+            counter: AtomicU64::new(rand::random()),
+        }
+    }
+
+    fn nonce(&self, position: u64, counter: u64) -> XNonce {
+        let mut nonce = [0; 24];
+        nonce[..8].copy_from_slice(&self.file_tag.to_le_bytes());
+        nonce[8..16].copy_from_slice(&position.to_le_bytes());
+        nonce[16..].copy_from_slice(&counter.to_le_bytes());
+        nonce.into()
+    }
+
+    /// Seal `bytes` to go at `position`, which is usually a page index.
+    pub fn seal(&self, position: u64, bytes: &[u8]) -> Vec<u8> {
+        let counter = self.counter.fetch_add(1, Ordering::Relaxed);
+
+        let mut sealed = counter.to_le_bytes().to_vec();
+        sealed.extend(
+            self.aead
+                .encrypt(&self.nonce(position, counter), bytes)
+                .expect("Pages are much smaller than the XChaCha20-Poly1305 limit"),
+        );
+        sealed
+    }
+
+    /// Returns `None` if `sealed` wasn't sealed by us for this position,
+    /// whether because it was torn, tampered with, or encrypted with another
+    /// key.
+    pub fn open(&self, position: u64, sealed: &[u8]) -> Option<Vec<u8>> {
+        if sealed.len() < SEAL_OVERHEAD {
+            return None;
+        }
+
+        let (counter, ciphertext) = sealed.split_at(8);
+        let counter = u64::from_le_bytes(counter.try_into().unwrap());
+
+        self.aead.decrypt(&self.nonce(position, counter), ciphertext).ok()
+    }
+}
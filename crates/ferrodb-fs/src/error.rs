@@ -6,4 +6,55 @@ pub enum Error {
     Io(#[from] std::io::Error),
     #[error(transparent)]
     NoPages(#[from] ferrodb_page::NoPages),
+    #[error("Page {page} of `{file}` doesn't match its checksum, so it must have been torn")]
+    ChecksumMismatch { file: String, page: crate::PageIndex },
+    #[error("`{file}` isn't a ferrodb data file, or its header is corrupt")]
+    NotADataFile { file: String },
+    #[error(
+        "`{file}` was written with format version {version}, but this version of ferrodb only \
+         understands up to version {}",
+        crate::FORMAT_VERSION
+    )]
+    UnsupportedVersion { file: String, version: u32 },
+    #[error("`{file}` was written with {page_size} byte pages, but we're using {expected} byte pages")]
+    PageSizeMismatch {
+        file: String,
+        page_size: crate::PageIndex,
+        expected: crate::PageIndex,
+    },
+    #[error("`{file}` is a {kind} file, not a {expected} file")]
+    FileKindMismatch {
+        file: String,
+        kind: crate::FileKind,
+        expected: crate::FileKind,
+    },
+    #[error("`{file}` is encrypted, but no encryption key was given")]
+    MissingKey { file: String },
+    #[error("`{file}` isn't encrypted, but an encryption key was given")]
+    UnexpectedKey { file: String },
+    #[error("Page 0 of `{file}` is reserved for its header")]
+    HeaderPage { file: String },
+    #[error(
+        "The data directory is already in use{}",
+        pid.map(|pid| format!(" by process {pid}")).unwrap_or_default()
+    )]
+    Locked { pid: Option<u32> },
+    #[error("The data directory is open read-only by other processes, so it can't be opened for writing")]
+    LockedReadOnly,
+    #[error("The file manager was opened read-only")]
+    ReadOnly,
+    #[error("Invalid encryption key: {0}")]
+    InvalidKey(String),
+    #[error("`{file}` already has pages in it, so its compression can't be changed")]
+    CompressionChange { file: String },
+    #[error(
+        "Pages {pages:?} of `{file}` are at LSN {page_lsn}, but the log is only durable up to LSN \
+         {flushed_lsn}"
+    )]
+    UnflushedLog {
+        file: String,
+        pages: Vec<crate::PageIndex>,
+        page_lsn: crate::Lsn,
+        flushed_lsn: crate::Lsn,
+    },
 }
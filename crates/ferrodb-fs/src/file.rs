@@ -0,0 +1,398 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use ferrodb_page::page_size;
+use parking_lot::Mutex;
+
+use crate::compression::{Compression, ExtentMap};
+use crate::encryption::{Cipher, EncryptionKey, SEAL_OVERHEAD};
+use crate::header::{BadHeader, FileHeader, FileKind, HEADER_SIZE};
+use crate::segmented::SegmentedFile;
+use crate::vfs::{Vfs, VfsFile};
+use crate::{Error, FileManagerOptions, PageIndex};
+
+/// A file that the file manager has open, and knows how to find pages in.
+pub(crate) struct OpenFile {
+    name: String,
+    path: Utf8PathBuf,
+    data: SegmentedFile,
+    /// `None` until the file's header is first written.
+    header: Mutex<Option<FileHeader>>,
+    /// Only compressed files have extent maps.
+    extents: Option<Mutex<ExtentMap>>,
+    cipher: Option<Cipher>,
+    read_only: bool,
+    /// One past the last page that's either on disk or been handed out.
+    page_count: AtomicUsize,
+}
+
+impl OpenFile {
+    pub fn open(
+        vfs: &Arc<dyn Vfs>,
+        name: &str,
+        path: &Utf8Path,
+        options: &FileManagerOptions,
+    ) -> Result<OpenFile, Error> {
+        let key = options.encryption_key.as_ref();
+
+        let mut bytes = [0; HEADER_SIZE];
+        SegmentedFile::read_start(&**vfs, path, &mut bytes)?;
+        let header = FileHeader::decode(&bytes);
+
+        // An existing file keeps the segment size it was created with.
+        let segment_size = match &header {
+            Ok(header) => header.segment_size,
+            Err(_) => options.segment_size,
+        };
+        let data = SegmentedFile::open(vfs.clone(), path, segment_size)?;
+
+        let header_end = slot_size(key.is_some()) as u64;
+        // A file with nothing past its header has never had any pages written,
+        // so anything else that's wrong with it was a crash while setting it up.
+        let has_pages = data.len()? > header_end;
+
+        let header = match header {
+            Ok(header) => Some(header),
+            Err(BadHeader::Version(version)) =>
+                return Err(Error::UnsupportedVersion {
+                    file: name.to_owned(),
+                    version,
+                }),
+            Err(BadHeader::Invalid) if !has_pages => None,
+            Err(BadHeader::Invalid) =>
+                return Err(Error::NotADataFile {
+                    file: name.to_owned(),
+                }),
+        };
+
+        if let Some(header) = &header {
+            if header.page_size != page_size() {
+                return Err(Error::PageSizeMismatch {
+                    file: name.to_owned(),
+                    page_size: header.page_size,
+                    expected: page_size(),
+                });
+            }
+
+            match (header.encrypted, key) {
+                (true, None) =>
+                    return Err(Error::MissingKey {
+                        file: name.to_owned(),
+                    }),
+                (false, Some(_)) =>
+                    return Err(Error::UnexpectedKey {
+                        file: name.to_owned(),
+                    }),
+                _ => {},
+            }
+        }
+
+        let extents_path = extents_path(path);
+        let extents = if !vfs.exists(&extents_path)? {
+            None
+        } else {
+            match ExtentMap::load(vfs.open(&extents_path)?, extents_cipher(key, name), header_end) {
+                Ok(map) => Some(Mutex::new(map)),
+                // We crashed while setting up the map, so the file was never
+                // compressed.
+                Err(_) if !has_pages => {
+                    vfs.delete(&extents_path)?;
+                    None
+                },
+                Err(e) => return Err(e.into()),
+            }
+        };
+
+        let page_count = match &extents {
+            Some(extents) => extents.lock().pages(),
+            None => {
+                let slot_size = slot_size(key.is_some()) as u64;
+                data.len()?.div_ceil(slot_size) as usize
+            },
+        };
+
+        Ok(OpenFile {
+            name: name.to_owned(),
+            path: path.to_owned(),
+            data,
+            header: Mutex::new(header),
+            extents,
+            cipher: key.map(|key| Cipher::new(key, name)),
+            read_only: options.read_only,
+            // Page 0 is always there, even if its header hasn't been written yet.
+            page_count: AtomicUsize::new(page_count.max(1)),
+        })
+    }
+
+    pub fn page_count(&self) -> PageIndex {
+        self.page_count.load(Ordering::SeqCst)
+    }
+
+    /// Hand out a page past the end of the file.
+    pub fn allocate(&self) -> PageIndex {
+        self.page_count.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Remember that `page` exists, even if it's only in memory so far.
+    pub fn touch(&self, page: PageIndex) {
+        self.page_count.fetch_max(page + 1, Ordering::SeqCst);
+    }
+
+    /// Cut the file back to its first `page_count` pages.
+    pub fn truncate(&self, page_count: PageIndex) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let page_count = page_count.max(1);
+        if page_count >= self.page_count() {
+            return Ok(());
+        }
+        self.page_count.store(page_count, Ordering::SeqCst);
+
+        match &self.extents {
+            Some(extents) => extents.lock().truncate(&self.data, page_count, self.header_end())?,
+            None => {
+                self.data.truncate(self.slot(page_count).0)?;
+                self.data.sync()?;
+            },
+        }
+
+        Ok(())
+    }
+
+    /// What kind of file this is, or `None` if it doesn't have a header yet.
+    pub fn kind(&self) -> Option<FileKind> {
+        self.header.lock().map(|header| header.kind)
+    }
+
+    /// Make sure this file is a `kind` file, making it one if it doesn't have
+    /// a header yet.
+    pub fn claim(&self, kind: FileKind) -> Result<(), Error> {
+        let mut header = self.header.lock();
+
+        match &*header {
+            Some(header) if header.kind == kind => Ok(()),
+            Some(header) => Err(Error::FileKindMismatch {
+                file: self.name.clone(),
+                kind: header.kind,
+                expected: kind,
+            }),
+            None if self.read_only => Err(Error::ReadOnly),
+            None => {
+                let new = FileHeader::new(
+                    page_size(),
+                    kind,
+                    self.cipher.is_some(),
+                    self.data.segment_size(),
+                );
+                self.data.write_at(&new.encode(), 0)?;
+                self.data.sync()?;
+                *header = Some(new);
+                Ok(())
+            },
+        }
+    }
+
+    /// Remove every segment of a file, and its extent map, if it has one.
+    pub fn delete(vfs: &dyn Vfs, path: &Utf8Path) -> Result<(), Error> {
+        SegmentedFile::delete(vfs, path)?;
+
+        let extents_path = extents_path(path);
+        if vfs.exists(&extents_path)? {
+            vfs.delete(&extents_path)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn compression(&self) -> Compression {
+        match &self.extents {
+            Some(extents) => extents.lock().compression(),
+            None => Compression::None,
+        }
+    }
+
+    /// Start compressing this file's pages. This can only happen while the
+    /// file is still empty.
+    pub fn set_compression(
+        &mut self,
+        vfs: &dyn Vfs,
+        compression: Compression,
+        key: Option<&EncryptionKey>,
+    ) -> Result<(), Error> {
+        if compression == self.compression() {
+            return Ok(());
+        } else if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        if self.data.len()? > self.header_end() {
+            return Err(Error::CompressionChange {
+                file: self.name.clone(),
+            });
+        }
+
+        let extents_path = extents_path(&self.path);
+        self.extents = if compression == Compression::None {
+            vfs.delete(&extents_path)?;
+            None
+        } else {
+            let cipher = extents_cipher(key, &self.name);
+            let map = ExtentMap::create(vfs.open(&extents_path)?, compression, cipher, self.header_end())?;
+            Some(Mutex::new(map))
+        };
+
+        Ok(())
+    }
+
+    /// Where a page lives in an uncompressed file, and how big it is there.
+    fn slot(&self, page: PageIndex) -> (u64, usize) {
+        let size = slot_size(self.cipher.is_some());
+        ((page * size) as u64, size)
+    }
+
+    /// Where the header's slot ends, and pages start.
+    fn header_end(&self) -> u64 {
+        self.slot(1).0
+    }
+
+    pub fn read_pages(&self, pages: &mut [(PageIndex, &mut [u8])]) -> Result<(), Error> {
+        let stored = if let Some(extents) = &self.extents {
+            let indices: Vec<_> = pages.iter().map(|(page, _)| *page).collect();
+            extents.lock().read_extents(&self.data, &indices)?
+        } else if self.cipher.is_some() {
+            let mut stored: Vec<_> = pages
+                .iter()
+                .map(|(page, _)| vec![0; self.slot(*page).1])
+                .collect();
+            let mut ops: Vec<_> = pages
+                .iter()
+                .zip(&mut stored)
+                .map(|((page, _), bytes)| (self.slot(*page).0, &mut bytes[..]))
+                .collect();
+            self.data.read_batch(&mut ops)?;
+
+            // A slot that's still all zeroes was never written.
+            stored
+                .into_iter()
+                .map(|bytes| bytes.iter().any(|b| *b != 0).then_some(bytes))
+                .collect()
+        } else {
+            // Plain pages can be read straight into their buffers.
+            let mut ops: Vec<_> = pages
+                .iter_mut()
+                .map(|(page, buf)| (self.slot(*page).0, &mut **buf))
+                .collect();
+            self.data.read_batch(&mut ops)?;
+            return Ok(());
+        };
+
+        for ((page, buf), bytes) in pages.iter_mut().zip(stored) {
+            let Some(bytes) = bytes
+                else { buf.fill(0); continue; };
+
+            if !self.decode(*page, bytes, buf) {
+                return Err(Error::ChecksumMismatch {
+                    file: self.name.clone(),
+                    page: *page,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write pages to their home location and sync them.
+    pub fn write_pages(&self, pages: &[(PageIndex, &[u8])]) -> Result<(), Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        if self.header.lock().is_none() {
+            self.claim(FileKind::Generic)?;
+        }
+
+        if self.extents.is_none() && self.cipher.is_none() {
+            let ops: Vec<_> = pages
+                .iter()
+                .map(|(page, buf)| (self.slot(*page).0, *buf))
+                .collect();
+
+            self.data.write_batch(&ops)?;
+            self.data.sync()?;
+            return Ok(());
+        }
+
+        let stored = pages
+            .iter()
+            .map(|(page, buf)| Ok((*page, self.encode(*page, buf)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        if let Some(extents) = &self.extents {
+            extents.lock().write_extents(&self.data, &stored)?;
+        } else {
+            let ops: Vec<_> = stored
+                .iter()
+                .map(|(page, bytes)| (self.slot(*page).0, &bytes[..]))
+                .collect();
+
+            self.data.write_batch(&ops)?;
+            self.data.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Compress, then encrypt, a page into what gets stored on disk.
+    fn encode(&self, page: PageIndex, buf: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut bytes = self.compression().compress(buf)?;
+        if bytes.len() >= buf.len() {
+            bytes = buf.to_vec();
+        }
+
+        if let Some(cipher) = &self.cipher {
+            bytes = cipher.seal(page as u64, &bytes);
+        }
+
+        Ok(bytes)
+    }
+
+    /// Undo [`OpenFile::encode`]. Returns `false` if the stored bytes are torn
+    /// or otherwise aren't something we wrote for this page.
+    fn decode(&self, page: PageIndex, mut bytes: Vec<u8>, buf: &mut [u8]) -> bool {
+        if let Some(cipher) = &self.cipher {
+            let Some(opened) = cipher.open(page as u64, &bytes)
+                else { return false; };
+            bytes = opened;
+        }
+
+        if bytes.len() == buf.len() {
+            buf.copy_from_slice(&bytes);
+            true
+        } else {
+            self.compression().decompress(&bytes, buf)
+        }
+    }
+}
+
+fn slot_size(encrypted: bool) -> usize {
+    if encrypted {
+        page_size() + SEAL_OVERHEAD
+    } else {
+        page_size()
+    }
+}
+
+/// Extent maps are sealed with a cipher of their own, so that an entry can't
+/// pass for a page of the data file.
+fn extents_cipher(key: Option<&EncryptionKey>, name: &str) -> Option<Cipher> {
+    key.map(|key| Cipher::new(key, &format!("{name}.extents")))
+}
+
+fn extents_path(path: &Utf8Path) -> Utf8PathBuf {
+    let mut path = path.as_str().to_owned();
+    path.push_str(".extents");
+    path.into()
+}
@@ -0,0 +1,224 @@
+//! Page 0 of every data file is reserved for a header saying what wrote it:
+//!
+//! ```text
+//! [magic: 8 bytes][format version: u32][page size: u32][file kind: u16]
+//! [flags: u16][segment size: u64][crc32 of the rest: u32]
+//! ```
+//!
+//! The header is always stored as-is, even in compressed or encrypted files,
+//! so that we can check it before we know how to read anything else.
+
+use std::fmt;
+
+use crate::PageIndex;
+
+const MAGIC: &[u8; 8] = b"FERRODB\0";
+
+/// The newest format version that we know how to read, and the one we write.
+pub const FORMAT_VERSION: u32 = 1;
+
+pub(crate) const HEADER_SIZE: usize = 32;
+
+const FLAG_ENCRYPTED: u16 = 1 << 0;
+
+/// What a data file holds, so that one kind of file can't be opened as another.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FileKind {
+    /// A file that was only ever accessed by name, without saying what it is.
+    Generic,
+    /// Rows in slotted pages, from `ferrodb-heap`.
+    Heap,
+    /// A B+Tree index, from `ferrodb-btree`.
+    BTree,
+    /// An extendible hash index, from `ferrodb-hash`.
+    Hash,
+    /// The rows of a heap that are too big to keep in its own pages, from
+    /// `ferrodb-heap`.
+    Overflow,
+    /// The manifest of an LSM tree, from `ferrodb-lsm`.
+    Lsm,
+    /// One sorted run of an LSM tree, from `ferrodb-lsm`.
+    SsTable,
+    /// A table stored a column at a time, from `ferrodb-columnar`.
+    Columnar,
+    /// A run of sorted entries that an external sort spilled to disk, from
+    /// `ferrodb-btree`.
+    SortRun,
+    /// An R-tree spatial index, from `ferrodb-rtree`.
+    RTree,
+}
+
+impl FileKind {
+    fn to_u16(self) -> u16 {
+        match self {
+            FileKind::Generic => 0,
+            FileKind::Heap => 1,
+            FileKind::BTree => 2,
+            FileKind::Hash => 3,
+            FileKind::Overflow => 4,
+            FileKind::Lsm => 5,
+            FileKind::SsTable => 6,
+            FileKind::Columnar => 7,
+            FileKind::SortRun => 8,
+            FileKind::RTree => 9,
+        }
+    }
+
+    fn from_u16(kind: u16) -> Option<FileKind> {
+        match kind {
+            0 => Some(FileKind::Generic),
+            1 => Some(FileKind::Heap),
+            2 => Some(FileKind::BTree),
+            3 => Some(FileKind::Hash),
+            4 => Some(FileKind::Overflow),
+            5 => Some(FileKind::Lsm),
+            6 => Some(FileKind::SsTable),
+            7 => Some(FileKind::Columnar),
+            8 => Some(FileKind::SortRun),
+            9 => Some(FileKind::RTree),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileKind::Generic => f.write_str("generic"),
+            FileKind::Heap => f.write_str("heap"),
+            FileKind::BTree => f.write_str("btree"),
+            FileKind::Hash => f.write_str("hash"),
+            FileKind::Overflow => f.write_str("overflow"),
+            FileKind::Lsm => f.write_str("lsm"),
+            FileKind::SsTable => f.write_str("sstable"),
+            FileKind::Columnar => f.write_str("columnar"),
+            FileKind::SortRun => f.write_str("sort run"),
+            FileKind::RTree => f.write_str("rtree"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct FileHeader {
+    pub version: u32,
+    pub page_size: PageIndex,
+    pub kind: FileKind,
+    pub encrypted: bool,
+    /// How many bytes go in each of the file's segments.
+    pub segment_size: u64,
+}
+
+/// Why a header couldn't be decoded.
+pub(crate) enum BadHeader {
+    /// This isn't a data file at all, or its header is torn.
+    Invalid,
+    /// A newer version of ferrodb wrote this file.
+    Version(u32),
+}
+
+impl FileHeader {
+    pub fn new(
+        page_size: PageIndex,
+        kind: FileKind,
+        encrypted: bool,
+        segment_size: u64,
+    ) -> FileHeader {
+        FileHeader {
+            version: FORMAT_VERSION,
+            page_size,
+            kind,
+            encrypted,
+            segment_size,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; HEADER_SIZE] {
+        let flags = if self.encrypted { FLAG_ENCRYPTED } else { 0 };
+
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..8].copy_from_slice(MAGIC);
+        bytes[8..12].copy_from_slice(&self.version.to_le_bytes());
+        bytes[12..16].copy_from_slice(&(self.page_size as u32).to_le_bytes());
+        bytes[16..18].copy_from_slice(&self.kind.to_u16().to_le_bytes());
+        bytes[18..20].copy_from_slice(&flags.to_le_bytes());
+        bytes[20..28].copy_from_slice(&self.segment_size.to_le_bytes());
+        let crc = crc32fast::hash(&bytes[..28]);
+        bytes[28..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; HEADER_SIZE]) -> Result<FileHeader, BadHeader> {
+        if !bytes.starts_with(MAGIC) {
+            return Err(BadHeader::Invalid);
+        }
+
+        // Check the version before anything else, even the checksum, since a
+        // newer version might mean anything by the rest of the header.
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version == 0 {
+            return Err(BadHeader::Invalid);
+        } else if version > FORMAT_VERSION {
+            return Err(BadHeader::Version(version));
+        }
+
+        let crc = u32::from_le_bytes(bytes[28..].try_into().unwrap());
+        if crc != crc32fast::hash(&bytes[..28]) {
+            return Err(BadHeader::Invalid);
+        }
+
+        let page_size = u32::from_le_bytes(bytes[12..16].try_into().unwrap()) as PageIndex;
+        let kind = u16::from_le_bytes(bytes[16..18].try_into().unwrap());
+        let flags = u16::from_le_bytes(bytes[18..20].try_into().unwrap());
+        let segment_size = u64::from_le_bytes(bytes[20..28].try_into().unwrap());
+        if segment_size < HEADER_SIZE as u64 {
+            return Err(BadHeader::Invalid);
+        }
+
+        Ok(FileHeader {
+            version,
+            page_size,
+            kind: FileKind::from_u16(kind).ok_or(BadHeader::Invalid)?,
+            encrypted: flags & FLAG_ENCRYPTED != 0,
+            segment_size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> FileHeader {
+        FileHeader::new(8192, FileKind::Heap, true, 1 << 30)
+    }
+
+    #[test]
+    fn round_trips() {
+        let decoded = FileHeader::decode(&header().encode());
+        assert!(matches!(decoded, Ok(decoded) if decoded == header()));
+    }
+
+    #[test]
+    fn rejects_other_files_and_torn_headers() {
+        let mut bytes = header().encode();
+        bytes[0] = b'X';
+        assert!(matches!(FileHeader::decode(&bytes), Err(BadHeader::Invalid)));
+
+        let mut bytes = header().encode();
+        bytes[13] ^= 1;
+        assert!(matches!(FileHeader::decode(&bytes), Err(BadHeader::Invalid)));
+
+        assert!(matches!(FileHeader::decode(&[0; HEADER_SIZE]), Err(BadHeader::Invalid)));
+    }
+
+    #[test]
+    fn reports_newer_versions_whatever_the_rest_looks_like() {
+        // A newer format might lay out, and checksum, the rest differently.
+        let mut bytes = [0xff; HEADER_SIZE];
+        bytes[..8].copy_from_slice(MAGIC);
+        bytes[8..12].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+        let decoded = FileHeader::decode(&bytes);
+        assert!(matches!(decoded, Err(BadHeader::Version(version)) if version == FORMAT_VERSION + 1));
+    }
+}
@@ -0,0 +1,273 @@
+//! Batched positional I/O against a single file.
+//!
+//! With the `io-uring` feature on Linux, a whole batch is pushed onto an
+//! io_uring submission queue and reaped in one go, instead of paying one
+//! `pread`/`pwrite` syscall per page. Everywhere else (or if the kernel refuses
+//! to give us a ring) we fall back to looping over `FileExt`.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::FileExt;
+
+/// Read every `(offset, buf)` pair. Any part of a buffer that lies past the end
+/// of the file is zero-filled, same as a page that was never written.
+pub(crate) fn read_batch(file: &File, ops: &mut [(u64, &mut [u8])]) -> io::Result<()> {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if let Some(result) = uring::read_batch(file, ops) {
+        return result;
+    }
+
+    for (offset, buf) in ops.iter_mut() {
+        read_at_or_zero(file, buf, *offset)?;
+    }
+
+    Ok(())
+}
+
+/// Write every `(offset, buf)` pair in full.
+pub(crate) fn write_batch(file: &File, ops: &[(u64, &[u8])]) -> io::Result<()> {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if let Some(result) = uring::write_batch(file, ops) {
+        return result;
+    }
+
+    for (offset, buf) in ops {
+        file.write_all_at(buf, *offset)?;
+    }
+
+    Ok(())
+}
+
+fn read_at_or_zero(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        match file.read_at(buf, offset) {
+            Ok(0) => break,
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            },
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+
+    buf.fill(0);
+    Ok(())
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring {
+    use std::cell::RefCell;
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+
+    use io_uring::{opcode, types, IoUring};
+
+    const QUEUE_DEPTH: usize = 256;
+
+    /// `io_uring_enter` flag to wait for completions, which the `io-uring` crate
+    /// doesn't export.
+    const IORING_ENTER_GETEVENTS: u32 = 1;
+
+    thread_local! {
+        // `None` means we tried to set up a ring and the kernel said no, so don't
+        // bother trying again on this thread.
+        static RING: RefCell<Option<Option<IoUring>>> = const { RefCell::new(None) };
+    }
+
+    /// A single request that might need to be resubmitted after a short
+    /// read/write.
+    struct Op {
+        offset: u64,
+        ptr: *mut u8,
+        len: usize,
+        done: usize,
+    }
+
+    pub(super) fn read_batch(file: &File, ops: &mut [(u64, &mut [u8])]) -> Option<io::Result<()>> {
+        let ops = ops
+            .iter_mut()
+            .map(|(offset, buf)| Op {
+                offset: *offset,
+                ptr: buf.as_mut_ptr(),
+                len: buf.len(),
+                done: 0,
+            })
+            .collect();
+
+        with_ring(|ring| run(ring, file, ops, false))
+    }
+
+    pub(super) fn write_batch(file: &File, ops: &[(u64, &[u8])]) -> Option<io::Result<()>> {
+        let ops = ops
+            .iter()
+            .map(|(offset, buf)| Op {
+                offset: *offset,
+                // The write opcode only ever reads through this pointer.
+                ptr: buf.as_ptr() as *mut u8,
+                len: buf.len(),
+                done: 0,
+            })
+            .collect();
+
+        with_ring(|ring| run(ring, file, ops, true))
+    }
+
+    fn with_ring<T>(f: impl FnOnce(&mut IoUring) -> T) -> Option<T> {
+        RING.with(|ring| {
+            let mut ring = ring.borrow_mut();
+            let ring = ring.get_or_insert_with(|| IoUring::new(QUEUE_DEPTH as u32).ok());
+            let result = ring.as_mut().map(f);
+
+            // If submitting failed, whatever the kernel didn't take is still
+            // queued, pointing into buffers that are about to go away, so the
+            // next `io_uring_enter` mustn't ever see it.
+            let stuck = match ring {
+                Some(ring) => !ring.submission().is_empty(),
+                None => false,
+            };
+            if stuck {
+                *ring = None;
+            }
+
+            result
+        })
+    }
+
+    fn run(ring: &mut IoUring, file: &File, mut ops: Vec<Op>, write: bool) -> io::Result<()> {
+        let fd = types::Fd(file.as_raw_fd());
+        let mut pending: Vec<usize> = (0..ops.len()).collect();
+
+        while !pending.is_empty() {
+            let batch_len = pending.len().min(QUEUE_DEPTH);
+            let batch: Vec<usize> = pending.drain(..batch_len).collect();
+
+            for &idx in &batch {
+                let op = &ops[idx];
+                // SAFETY: `op.ptr` points into a caller-provided buffer that outlives this call,
+                // and `op.done < op.len`.
+                let ptr = unsafe { op.ptr.add(op.done) };
+                let len = (op.len - op.done) as u32;
+                let offset = (op.offset + op.done as u64) as i64;
+
+                let entry = if write {
+                    opcode::Write::new(fd, ptr, len).offset64(offset).build()
+                } else {
+                    opcode::Read::new(fd, ptr, len).offset64(offset).build()
+                }
+                .user_data(idx as u64);
+
+                // SAFETY: The buffer stays valid until we've reaped its completion below, and
+                // if it's never submitted, `with_ring` throws the ring away along with it.
+                unsafe { ring.submission().push(&entry) }
+                    .expect("Submission queue should have room for a full batch");
+            }
+
+            let mut error = None;
+            let mut submit_error = None;
+            let mut reaped = 0;
+
+            // Every request that the kernel took has to be reaped before we return, even if
+            // submitting fails partway, since until then it can still be reading from or
+            // writing into the buffer.
+            while reaped < batch.len() {
+                if submit_error.is_none() {
+                    match ring.submit_and_wait(batch.len() - reaped) {
+                        Ok(_) => {},
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => submit_error = Some(e),
+                    }
+                }
+
+                if submit_error.is_some() {
+                    let in_flight = batch.len() - reaped - ring.submission().len();
+                    if in_flight == 0 {
+                        break;
+                    }
+
+                    // Don't try submitting the rest again, just wait for what's in flight. There's
+                    // no giving up on that safely, so if waiting fails, it's tried again.
+                    // SAFETY: Nothing is submitted, and there are no arguments.
+                    let _ = unsafe {
+                        ring.submitter()
+                            .enter::<libc::sigset_t>(0, in_flight as u32, IORING_ENTER_GETEVENTS, None)
+                    };
+                }
+
+                for cqe in ring.completion() {
+                    reaped += 1;
+                    let idx = cqe.user_data() as usize;
+                    let op = &mut ops[idx];
+
+                    match cqe.result() {
+                        n if n < 0 => {
+                            let e = io::Error::from_raw_os_error(-n);
+                            if e.kind() == io::ErrorKind::Interrupted {
+                                pending.push(idx);
+                            } else {
+                                error.get_or_insert(e);
+                            }
+                        },
+                        0 if write => {
+                            error.get_or_insert(io::ErrorKind::WriteZero.into());
+                        },
+                        0 => {
+                            // EOF: whatever is left of this buffer was never written.
+                            // SAFETY: Same buffer as above, and the kernel is done with it.
+                            unsafe { std::ptr::write_bytes(op.ptr.add(op.done), 0, op.len - op.done) };
+                        },
+                        n => {
+                            op.done += n as usize;
+                            if op.done < op.len {
+                                pending.push(idx);
+                            }
+                        },
+                    }
+                }
+            }
+
+            if let Some(e) = submit_error.or(error) {
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+
+    use super::*;
+
+    #[test]
+    fn batches_read_back_what_they_wrote_and_zeros_past_the_end() {
+        let path = std::env::temp_dir().join(format!("ferrodb-io-{}", std::process::id()));
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&path).unwrap();
+
+        // More than fit on a ring's queue at once, in no particular order.
+        let pages: Vec<(u64, Vec<u8>)> =
+            (0..600u64).map(|n| ((n * 7 % 600) * 64, vec![(n % 251) as u8 + 1; 64])).collect();
+        let ops: Vec<_> = pages.iter().map(|(offset, page)| (*offset, &page[..])).collect();
+        write_batch(&file, &ops).unwrap();
+
+        // The last one's half past the end of the file, and the one after it
+        // all the way.
+        let mut bufs = vec![[0xff; 64]; pages.len() + 1];
+        let mut ops: Vec<_> = bufs.iter_mut().zip(0u64..).map(|(buf, n)| (n * 64, &mut buf[..])).collect();
+        let mut tail = [0xff; 64];
+        ops.push((599 * 64 + 32, &mut tail[..]));
+        read_batch(&file, &mut ops).unwrap();
+
+        for (offset, page) in &pages {
+            assert_eq!(&bufs[(offset / 64) as usize][..], &page[..]);
+        }
+        assert_eq!(bufs[600], [0; 64]);
+        assert_eq!(tail[..32], bufs[599][32..]);
+        assert_eq!(tail[32..], [0; 32]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,31 +1,144 @@
-#![feature(let_else)]
-
+mod compression;
+mod doublewrite;
+mod encryption;
 mod error;
+mod file;
+mod header;
+mod io;
+mod page;
+mod segmented;
+pub mod vfs;
 
-use std::collections::HashMap;
-use std::fs::OpenOptions;
-use std::os::unix::fs::FileExt;
-use std::os::unix::prelude::MetadataExt;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
+pub use compression::Compression;
+pub use encryption::{Cipher, EncryptionKey, KEY_ENV_VAR, SEAL_OVERHEAD};
 pub use error::Error;
-use ferrodb_page::{allocate_page, page_size, PageHandle, PageRef};
+use file::OpenFile;
+pub use header::{FileKind, FORMAT_VERSION};
+use page::{page_checksum_is_valid, set_page_checksum};
+pub use page::{page_lsn, set_page_lsn, PAGE_HEADER_SIZE};
+pub use segmented::DEFAULT_SEGMENT_SIZE;
+use segmented::SegmentedFile;
+use ferrodb_page::{
+    allocate_page, page_size, PageHandle, PageReadGuard, PageRef, PageWriteGuard,
+};
 use ferrodb_util::id_type;
 use parking_lot::Mutex;
 
+use crate::vfs::{LockMode, Vfs, VfsFile, VfsLock};
+
 id_type!(pub FileId);
 
+const DOUBLE_WRITE_FILE: &str = "doublewrite";
+/// Locked for as long as a file manager has the data directory open, and
+/// holds the PID of the process that locked it.
+const LOCK_FILE: &str = "LOCK";
+const BACKUP_CHUNK_SIZE: usize = 1 << 20;
+
 pub type PageIndex = usize;
+/// A log sequence number, i.e. the position of a record in the write-ahead log.
+pub type Lsn = u64;
 type FileHandle = Arc<Mutex<FileInner>>;
 
+#[derive(Clone, Debug)]
+pub struct FileManagerOptions {
+    /// Write every batch of pages to a scratch file, and sync it, before
+    /// writing them to their home location. A page write torn by a crash can
+    /// then be repaired with [`FileManager::repair_torn_pages`].
+    pub double_write: bool,
+    /// Encrypt every page (and the double-write file) with this key before
+    /// it's written, and authenticate it when it's read back.
+    pub encryption_key: Option<EncryptionKey>,
+    /// How many bytes go in each segment of a new data file. Existing files
+    /// keep whatever segment size they were created with.
+    pub segment_size: u64,
+    /// Only take a shared lock on the data directory, so that other read-only
+    /// users (like offline tools) can open it at the same time, and refuse to
+    /// write anything.
+    pub read_only: bool,
+}
+
+impl Default for FileManagerOptions {
+    fn default() -> FileManagerOptions {
+        FileManagerOptions {
+            double_write: false,
+            encryption_key: None,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            read_only: false,
+        }
+    }
+}
+
 pub struct FileManager {
+    vfs: Arc<dyn Vfs>,
+    options: FileManagerOptions,
+    _lock: Box<dyn VfsLock>,
+    /// Only one batch of pages is written back at a time, so that an older copy
+    /// of a page can't be written over a newer one.
+    write_back: Mutex<()>,
+    /// Only one batch at a time gets to use the double-write file.
+    double_write: Mutex<Option<Arc<dyn VfsFile>>>,
+    double_write_cipher: Option<Cipher>,
+    log: Mutex<Option<Arc<dyn WriteAheadLog>>>,
     ids: Mutex<HashMap<String, FileId>>,
+    names: Mutex<HashMap<FileId, String>>,
     paths: Mutex<HashMap<FileId, Utf8PathBuf>>,
+    handles: Mutex<HashMap<FileId, Arc<OpenFile>>>,
     files: Mutex<HashMap<(FileId, PageIndex), FileHandle>>,
 }
 
 impl FileManager {
+    pub fn new(vfs: Arc<dyn Vfs>) -> Result<FileManager, Error> {
+        FileManager::with_options(vfs, FileManagerOptions::default())
+    }
+
+    /// Open the data directory in `vfs`. Unless the file manager is read-only,
+    /// nobody else can open it until this is dropped.
+    pub fn with_options(vfs: Arc<dyn Vfs>, options: FileManagerOptions) -> Result<FileManager, Error> {
+        let mode = if options.read_only {
+            LockMode::Shared
+        } else {
+            LockMode::Exclusive
+        };
+
+        let lock_path = Utf8Path::new(LOCK_FILE);
+        let Some(lock) = vfs.lock(lock_path, mode)?
+        else { return Err(lock_error(&*vfs, lock_path, mode)?); };
+
+        // Read-only users share the lock, and don't write to it, so the PID in
+        // it belongs to whoever last had the directory open for writing.
+        if !options.read_only {
+            let pid_file = vfs.open(lock_path)?;
+            let pid = std::process::id().to_string();
+            pid_file.truncate(0)?;
+            pid_file.write_at(pid.as_bytes(), 0)?;
+            pid_file.sync()?;
+        }
+
+        let double_write_cipher = options
+            .encryption_key
+            .as_ref()
+            .map(|key| Cipher::new(key, DOUBLE_WRITE_FILE));
+
+        Ok(FileManager {
+            vfs,
+            options,
+            _lock: lock,
+            write_back: Mutex::default(),
+            double_write: Mutex::default(),
+            double_write_cipher,
+            log: Mutex::default(),
+            ids: Mutex::default(),
+            names: Mutex::default(),
+            paths: Mutex::default(),
+            handles: Mutex::default(),
+            files: Mutex::default(),
+        })
+    }
+
     pub fn id(&self, name: &str) -> FileId {
         let mut ids = self.ids.lock();
 
@@ -34,6 +147,7 @@ impl FileManager {
         } else {
             let id = FileId::new();
             ids.insert(name.to_owned(), id);
+            self.names.lock().insert(id, name.to_owned());
 
             let mut path = Utf8PathBuf::from(".");
             path.set_file_name(name);
@@ -43,91 +157,496 @@ impl FileManager {
         }
     }
 
+    /// Open the file called `name`, checking that it was written by a version of
+    /// ferrodb we understand, with the same page size, and that it's a `kind`
+    /// file. A new file is given a header saying so.
+    pub fn open(&self, name: &str, kind: FileKind) -> Result<FileId, Error> {
+        let file = self.id(name);
+        self.handle(file)?.claim(kind)?;
+        Ok(file)
+    }
+
+    /// What kind of file `file` was opened as, or `None` if it never was.
+    pub fn kind(&self, file: FileId) -> Result<Option<FileKind>, Error> {
+        Ok(self.handle(file)?.kind())
+    }
+
+    pub fn name(&self, file: FileId) -> String {
+        self.names.lock()[&file].clone()
+    }
+
+    /// Hold every page write-back to the write-ahead rule of `log`: a dirty page
+    /// is only written once `log` is flushed past its page LSN.
+    pub fn set_log(&self, log: Arc<dyn WriteAheadLog>) {
+        *self.log.lock() = Some(log);
+    }
+
     pub fn clean(&self, file: FileId, page: PageIndex) -> Result<FileRef, Error> {
         let inner = self.files.lock().entry((file, page)).or_default().clone();
         let mut inner = inner.lock();
+        self.clean_locked(&mut inner, file, page)
+    }
 
+    fn clean_locked(
+        &self,
+        inner: &mut FileInner,
+        file: FileId,
+        page: PageIndex,
+    ) -> Result<FileRef, Error> {
         if let Some(handle) = &inner.clean {
             if let Ok(page_ref) = handle.pin() {
-                return Ok(FileRef(page_ref, false));
+                return Ok(FileRef(page_ref, None));
             }
         }
 
         let (handle, page_ref) = self.read_to_page(file, page)?;
         inner.clean = Some(handle);
 
-        Ok(FileRef(page_ref, false))
+        Ok(FileRef(page_ref, None))
     }
 
     pub fn dirty(&self, file: FileId, page: PageIndex) -> Result<FileRef, Error> {
+        let entry = self.files.lock().entry((file, page)).or_default().clone();
+        let mut inner = entry.lock();
+
+        if let Some((_, page_ref)) = &inner.dirty {
+            return Ok(FileRef(page_ref.clone(), Some(entry.clone())));
+        }
+
+        self.handle(file)?.touch(page);
+
+        // Turn the clean copy into the dirty one if we still have it, rather than
+        // going back to the disk. Not copying it means that whoever is reading
+        // the page right now holds a latch on the very page that's about to be
+        // written to, which is what index latching relies on.
+        let clean = inner
+            .clean
+            .take()
+            .and_then(|handle| Some((handle.pin().ok()?, handle)));
+        let (handle, page_ref) = match clean {
+            Some((page_ref, handle)) => (handle, page_ref),
+            None => self.read_to_page(file, page)?,
+        };
+
+        inner.dirty = Some((handle, page_ref.clone()));
+        drop(inner);
+        Ok(FileRef(page_ref, Some(entry)))
+    }
+
+    /// The newest version of a page, for reading: the dirty copy if there is
+    /// one, and otherwise the clean one.
+    pub fn latest(&self, file: FileId, page: PageIndex) -> Result<FileRef, Error> {
         let inner = self.files.lock().entry((file, page)).or_default().clone();
         let mut inner = inner.lock();
 
         if let Some((_, page_ref)) = &inner.dirty {
-            return Ok(FileRef(page_ref.clone(), true));
+            return Ok(FileRef(page_ref.clone(), None));
         }
 
-        // TODO(michael): We can also read from clean to avoid fs, if it exists and is
-        // pinnable.
-        let (handle, page_ref) = self.read_to_page(file, page)?;
-        inner.dirty = Some((handle, page_ref.clone()));
-        Ok(FileRef(page_ref, true))
+        self.clean_locked(&mut inner, file, page)
+    }
+
+    /// How many pages `file` has, including its header page and any pages that
+    /// only exist in memory so far.
+    pub fn page_count(&self, file: FileId) -> Result<PageIndex, Error> {
+        Ok(self.handle(file)?.page_count())
+    }
+
+    /// Reserve a new page at the end of `file`. It reads as all zeroes until
+    /// something is written to it.
+    pub fn allocate(&self, file: FileId) -> Result<PageIndex, Error> {
+        Ok(self.handle(file)?.allocate())
+    }
+
+    /// Read a batch of pages into clean frames in one go, so that subsequent
+    /// calls to [`FileManager::clean`] for them don't have to hit the disk.
+    pub fn prefetch(&self, file: FileId, pages: &[PageIndex]) -> Result<(), Error> {
+        let mut pages = pages.to_vec();
+        pages.sort_unstable();
+        pages.dedup();
+
+        let inners: Vec<_> = {
+            let mut files = self.files.lock();
+            pages
+                .iter()
+                .map(|page| files.entry((file, *page)).or_default().clone())
+                .collect()
+        };
+        // Lock in page order so that we never deadlock with another batch.
+        let mut inners: Vec<_> = inners.iter().map(|inner| inner.lock()).collect();
+
+        let mut missing = vec![];
+        for (page, inner) in pages.iter().zip(&mut inners) {
+            if inner.dirty.is_none() && !matches!(&inner.clean, Some(handle) if handle.pin().is_ok()) {
+                let (handle, page_ref) = allocate_page()?;
+                missing.push((*page, inner, handle, page_ref));
+            }
+        }
+
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let mut bufs: Vec<_> = missing.iter().map(|(_, _, _, page_ref)| page_ref.write()).collect();
+        let mut ops: Vec<_> = missing
+            .iter()
+            .zip(&mut bufs)
+            .map(|((page, ..), buf)| (*page, &mut **buf))
+            .collect();
+
+        self.read_pages(file, &mut ops)?;
+
+        drop(ops);
+        drop(bufs);
+
+        for (_, inner, handle, _) in missing {
+            inner.clean = Some(handle);
+        }
+
+        Ok(())
     }
 
     fn read_to_page(&self, file: FileId, page: PageIndex) -> Result<(PageHandle, PageRef), Error> {
         let (page_handle, page_ref) = allocate_page()?;
         let mut buf = page_ref.write();
 
-        let file = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&self.paths.lock()[&file])?;
-        let size = file.metadata()?.size(); // TODO(mgoulet): I guess we just don't support 32-bit.
-        let offset = (page * page_size()) as u64;
-
-        if offset < size {
-            let bytes_to_read = ((size - offset) as usize).min(page_size());
-            file.read_exact_at(&mut buf[..bytes_to_read], offset)?;
-            buf[bytes_to_read..].fill(0);
-        } else {
-            buf.fill(0);
-        }
+        self.read_pages(file, &mut [(page, &mut buf)])?;
 
         drop(buf);
         Ok((page_handle, page_ref))
     }
 
+    /// Read pages from their home location, making sure none of them were torn.
+    fn read_pages(&self, file: FileId, pages: &mut [(PageIndex, &mut [u8])]) -> Result<(), Error> {
+        if pages.iter().any(|(page, _)| *page == 0) {
+            return Err(Error::HeaderPage {
+                file: self.name(file),
+            });
+        }
+
+        self.handle(file)?.read_pages(pages)?;
+
+        for (page, buf) in pages.iter() {
+            if !page_checksum_is_valid(buf) {
+                return Err(Error::ChecksumMismatch {
+                    file: self.name(file),
+                    page: *page,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write pages to their home location and sync them, going through the
+    /// double-write file first if we're doing that.
+    fn write_pages(&self, file: FileId, pages: &[(PageIndex, &[u8])]) -> Result<(), Error> {
+        if self.options.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let mut double_write = self.double_write.lock();
+
+        if self.options.double_write {
+            let scratch = match &*double_write {
+                Some(scratch) => scratch.clone(),
+                None => double_write
+                    .insert(self.vfs.open(Utf8Path::new(DOUBLE_WRITE_FILE))?.into())
+                    .clone(),
+            };
+
+            let name = self.name(file);
+            let mut bytes =
+                doublewrite::encode(pages.iter().map(|(page, buf)| (&*name, *page, *buf)));
+            if let Some(cipher) = &self.double_write_cipher {
+                bytes = cipher.seal(0, &bytes);
+            }
+
+            scratch.truncate(0)?;
+            scratch.write_at(&bytes, 0)?;
+            scratch.sync()?;
+        }
+
+        self.handle(file)?.write_pages(pages)
+    }
+
+    /// Fix up every page whose last write was torn by a crash, using the copy
+    /// in the double-write file. Has to happen before anything reads those
+    /// pages, so before recovery. Returns the pages that were repaired.
+    pub fn repair_torn_pages(&self) -> Result<Vec<(String, PageIndex)>, Error> {
+        let scratch = self.vfs.open(Utf8Path::new(DOUBLE_WRITE_FILE))?;
+        let mut bytes = vec![0; scratch.len()? as usize];
+        scratch.read_at(&mut bytes, 0)?;
+
+        if let Some(cipher) = &self.double_write_cipher {
+            let Some(opened) = cipher.open(0, &bytes)
+                else { return Ok(vec![]); };
+            bytes = opened;
+        }
+
+        // If the double-write file itself is torn, then the crash happened before
+        // any of its pages were written to their home, so none of them are torn.
+        let Some(entries) = doublewrite::decode(&bytes, page_size())
+            else { return Ok(vec![]); };
+
+        let mut repaired = vec![];
+
+        for (name, page, copy) in entries {
+            let file = self.id(name);
+            let mut home = vec![0; page_size()];
+
+            match self.read_pages(file, &mut [(page, &mut home)]) {
+                Ok(()) => {},
+                Err(Error::ChecksumMismatch { .. }) => {
+                    self.handle(file)?.write_pages(&[(page, copy)])?;
+                    repaired.push((name.to_owned(), page));
+                },
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(repaired)
+    }
+
+    fn handle(&self, file: FileId) -> Result<Arc<OpenFile>, Error> {
+        let mut handles = self.handles.lock();
+
+        if let Some(handle) = handles.get(&file) {
+            return Ok(handle.clone());
+        }
+
+        let handle = Arc::new(OpenFile::open(
+            &self.vfs,
+            &self.name(file),
+            &self.paths.lock()[&file],
+            &self.options,
+        )?);
+        handles.insert(file, handle.clone());
+        Ok(handle)
+    }
+
+    /// Copy every data file into `target`, as it's stored (so still compressed
+    /// and encrypted). Pages keep being written back while this runs, so the
+    /// copy is only consistent once the log up to the end of the copy is
+    /// replayed over it.
+    pub fn backup_to(&self, target: &dyn Vfs) -> Result<(), Error> {
+        // Holding the double-write lock for the whole copy keeps any batch of
+        // pages from being written in the middle of it. A batch can span files,
+        // and a file's extent map has to match the segments it's copied with.
+        let _double_write = self.double_write.lock();
+
+        for path in self.vfs.list(Utf8Path::new("."))? {
+            if matches!(path.file_name(), Some(DOUBLE_WRITE_FILE | LOCK_FILE)) {
+                continue;
+            }
+
+            let source = self.vfs.open(&path)?;
+            let copy = target.open(&path)?;
+            copy.truncate(0)?;
+
+            let len = source.len()?;
+            let mut buf = vec![0; BACKUP_CHUNK_SIZE];
+            let mut offset = 0;
+
+            while offset < len {
+                let n = BACKUP_CHUNK_SIZE.min((len - offset) as usize);
+                source.read_at(&mut buf[..n], offset)?;
+                copy.write_at(&buf[..n], offset)?;
+                offset += n as u64;
+            }
+
+            copy.sync()?;
+        }
+
+        Ok(())
+    }
+
+    /// Store this file's pages compressed from now on. A file can only start
+    /// (or stop) being compressed while it's still empty; after that, it's
+    /// picked up from the file's extent map whenever it's opened.
+    pub fn set_compression(&self, file: FileId, compression: Compression) -> Result<(), Error> {
+        let mut handle = OpenFile::open(
+            &self.vfs,
+            &self.name(file),
+            &self.paths.lock()[&file],
+            &self.options,
+        )?;
+        handle.set_compression(&*self.vfs, compression, self.options.encryption_key.as_ref())?;
+        self.handles.lock().insert(file, Arc::new(handle));
+        Ok(())
+    }
+
+    /// Whether there's a file called `name` in storage, without creating one.
+    pub fn exists(&self, name: &str) -> Result<bool, Error> {
+        let file = self.id(name);
+        let path = self.paths.lock()[&file].clone();
+        Ok(SegmentedFile::exists(&*self.vfs, &path)?)
+    }
+
+    /// Forget every page of `file` without writing it back, and remove it from
+    /// storage.
+    pub fn delete(&self, file: FileId) -> Result<(), Error> {
+        if self.options.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        self.files.lock().retain(|(f, _), _| *f != file);
+        self.handles.lock().remove(&file);
+        OpenFile::delete(&*self.vfs, &self.paths.lock()[&file])
+    }
+
+    /// Forget every page of `file` from `page_count` on without writing it
+    /// back, and cut them off the end of the file. Nothing may be using those
+    /// pages, and recovery mustn't have any changes to them left to redo, or
+    /// it would bring them back.
+    pub fn truncate(&self, file: FileId, page_count: PageIndex) -> Result<(), Error> {
+        if self.options.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        // Keep a batch that's being written back from writing any of the pages
+        // after they're cut off.
+        let _write_back = self.write_back.lock();
+        self.files.lock().retain(|(f, page), _| *f != file || *page < page_count);
+        self.handle(file)?.truncate(page_count)
+    }
+
     pub fn sync(&self, file: FileId, page: PageIndex) -> Result<(), Error> {
-        let Some(inner) = self.files.lock().get(&(file, page)).cloned()
-            else { return Ok(()); };
-        let mut inner = inner.lock();
+        self.sync_many([(file, page)])
+    }
+
+    /// Write back every dirty page that the file manager knows about. This is
+    /// what a checkpoint wants: a single batch of writes per file rather than
+    /// one syscall per page.
+    pub fn sync_all(&self) -> Result<(), Error> {
+        let pages: Vec<_> = self.files.lock().keys().copied().collect();
+        self.sync_many(pages)
+    }
+
+    /// Write back the given pages if they're dirty, batching the writes for
+    /// each file and syncing each touched file once at the end.
+    pub fn sync_many(
+        &self,
+        pages: impl IntoIterator<Item = (FileId, PageIndex)>,
+    ) -> Result<(), Error> {
+        let mut by_file: BTreeMap<FileId, Vec<PageIndex>> = BTreeMap::new();
+        for (file, page) in pages {
+            by_file.entry(file).or_default().push(page);
+        }
+
+        for (file, mut pages) in by_file {
+            pages.sort_unstable();
+            pages.dedup();
+
+            let _write_back = self.write_back.lock();
+            let dirty: Vec<_> = {
+                let files = self.files.lock();
+                pages
+                    .iter()
+                    .filter_map(|page| {
+                        let inner = files.get(&(file, *page))?.clone();
+                        let page_ref = inner.lock().dirty.as_ref()?.1.clone();
+                        Some((*page, inner, page_ref))
+                    })
+                    .collect()
+            };
+
+            if dirty.is_empty() {
+                continue;
+            }
 
-        // Don't drop handle immediately, so we name it `_handle` instead of `handle`
-        if let Some((_handle, page_ref)) = inner.dirty.take() {
-            let mut buf = page_ref.write();
+            // Only latch one page at a time, to copy it out, so that we can't
+            // deadlock with anyone who latches several pages in some other order
+            // than by page index, like an index going from parent to child.
+            let snapshots: Vec<Vec<u8>> =
+                dirty.iter().map(|(_, _, page_ref)| page_ref.read().to_vec()).collect();
+            let mut bufs = snapshots.clone();
+            for buf in &mut bufs {
+                set_page_checksum(buf);
+            }
 
-            let file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .open(&self.paths.lock()[&file])?;
+            // Write-ahead rule: none of these pages may reach the disk before the log
+            // records describing their changes do.
+            let max_lsn = bufs.iter().map(|buf| page_lsn(buf)).max().unwrap_or(0);
+            let dirty_refs: Vec<_> =
+                dirty.iter().map(|(page, _, page_ref)| (*page, page_ref.clone())).collect();
+            self.flush_log(file, &dirty_refs, max_lsn)?;
 
-            let offset = (page * page_size()) as u64;
+            let pages: Vec<_> = dirty
+                .iter()
+                .zip(&bufs)
+                .map(|((page, ..), buf)| (*page, &**buf))
+                .collect();
 
-            file.write_all_at(&mut buf, offset)?;
+            self.write_pages(file, &pages)?;
 
-            // Overwrite the clean buffer if we have one.
-            if let Some(clean) = &inner.clean {
-                if let Ok(page_ref) = clean.pin() {
-                    page_ref.write().copy_from_slice(&*buf);
-                } else {
-                    // Clear the clean buffer if it's invalidated.
-                    inner.clean = None;
+            for ((_, inner, page_ref), snapshot) in dirty.iter().zip(&snapshots) {
+                // If the page was changed again while it was being written, it's
+                // still dirty.
+                let buf = page_ref.read();
+                if *buf != **snapshot {
+                    continue;
+                }
+
+                // Keep the page that was written as the clean copy, so that
+                // anyone who still has it latched is looking at the same page
+                // as the next person to dirty it.
+                let mut inner = inner.lock();
+                if matches!(&inner.dirty, Some((_, dirty)) if PageRef::ptr_eq(dirty, page_ref)) {
+                    let (handle, _) = inner.dirty.take().unwrap();
+                    inner.clean = Some(handle);
                 }
             }
         }
 
         Ok(())
     }
+
+    /// Make sure the log is durable past `lsn`, if there is a log at all.
+    fn flush_log(&self, file: FileId, dirty: &[(PageIndex, PageRef)], lsn: Lsn) -> Result<(), Error> {
+        let Some(log) = self.log.lock().clone()
+            else { return Ok(()); };
+
+        let flushed_lsn = log.flush_to(lsn)?;
+
+        if flushed_lsn <= lsn {
+            return Err(Error::UnflushedLog {
+                file: self.name(file),
+                pages: dirty.iter().map(|(page, _)| *page).collect(),
+                page_lsn: lsn,
+                flushed_lsn,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Why someone else's lock on the data directory kept us from taking one in
+/// `mode`.
+fn lock_error(vfs: &dyn Vfs, path: &Utf8Path, mode: LockMode) -> Result<Error, Error> {
+    // If a shared lock can be had, only read-only users have the directory
+    // open, and the PID in the lock file is stale.
+    if mode == LockMode::Exclusive && vfs.lock(path, LockMode::Shared)?.is_some() {
+        return Ok(Error::LockedReadOnly);
+    }
+
+    let pid_file = vfs.open(path)?;
+    let mut bytes = vec![0; pid_file.len()? as usize];
+    pid_file.read_at(&mut bytes, 0)?;
+    let pid = std::str::from_utf8(&bytes)
+        .ok()
+        .and_then(|pid| pid.trim().parse().ok());
+
+    Ok(Error::Locked { pid })
+}
+
+/// A log that has to be flushed before the pages it describes can be written
+/// back. See [`FileManager::set_log`].
+pub trait WriteAheadLog: Send + Sync {
+    /// Make sure the log record at `lsn` (and everything before it) is durable,
+    /// and return the LSN that the log is now durable up to, exclusive.
+    fn flush_to(&self, lsn: Lsn) -> std::io::Result<Lsn>;
 }
 
 #[derive(Default)]
@@ -136,4 +655,39 @@ pub struct FileInner {
     dirty: Option<(PageHandle, PageRef)>,
 }
 
-pub struct FileRef(PageRef, bool /* writeable */);
+/// A page, and for a dirty page, its entry in the file manager.
+pub struct FileRef(PageRef, Option<FileHandle>);
+
+impl FileRef {
+    pub fn read(&self) -> PageReadGuard<'_> {
+        self.0.read()
+    }
+
+    pub fn write(&self) -> PageWriteGuard<'_> {
+        let entry = self.1.as_ref().expect("Expected a dirty page in order to write to it");
+        let guard = self.0.write();
+
+        // The page might have been written back, and so become the clean copy,
+        // between getting this ref and latching it. What's about to be written
+        // to it still has to be written back.
+        let mut inner = entry.lock();
+        if inner.dirty.is_none() {
+            let ours = match &inner.clean {
+                Some(handle) => match handle.pin() {
+                    Ok(clean) => PageRef::ptr_eq(&clean, &self.0),
+                    Err(_) => false,
+                },
+                None => false,
+            };
+
+            // Otherwise the page was truncated or deleted out from under us, which
+            // forgets it without writing it back, so there's nothing to re-dirty.
+            if ours {
+                let handle = inner.clean.take().unwrap();
+                inner.dirty = Some((handle, self.0.clone()));
+            }
+        }
+
+        guard
+    }
+}
@@ -0,0 +1,42 @@
+//! The few bytes at the start of every page that belong to the file manager
+//! rather than to whoever is storing things in the page.
+//!
+//! ```text
+//! [lsn: u64][checksum: u32][reserved: u32]
+//! ```
+
+use crate::Lsn;
+
+pub const PAGE_HEADER_SIZE: usize = 16;
+
+/// The LSN of the last logged change to this page. Zero means the page has
+/// never been changed under the log.
+pub fn page_lsn(page: &[u8]) -> Lsn {
+    Lsn::from_le_bytes(page[..8].try_into().unwrap())
+}
+
+pub fn set_page_lsn(page: &mut [u8], lsn: Lsn) {
+    page[..8].copy_from_slice(&lsn.to_le_bytes());
+}
+
+/// Stamp the page with a checksum of its contents, right before it's
+/// written out.
+pub(crate) fn set_page_checksum(page: &mut [u8]) {
+    let checksum = checksum(page);
+    page[8..12].copy_from_slice(&checksum.to_le_bytes());
+}
+
+/// Whether a page that was just read is the same as what was written, i.e.
+/// the write wasn't torn. Pages that were never written are all zeroes, and
+/// are fine too.
+pub(crate) fn page_checksum_is_valid(page: &[u8]) -> bool {
+    let stored = u32::from_le_bytes(page[8..12].try_into().unwrap());
+    stored == checksum(page) || page.iter().all(|b| *b == 0)
+}
+
+fn checksum(page: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(&page[..8]);
+    hasher.update(&page[12..]);
+    hasher.finalize()
+}
@@ -0,0 +1,198 @@
+//! A data file is stored as a run of fixed-size segment files, `name.0`,
+//! `name.1`, ..., so that no single file grows past what backup tools and
+//! filesystems are comfortable with. Segments are created as the file grows
+//! into them, and are otherwise just a byte range of the whole file.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::sync::Arc;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use parking_lot::Mutex;
+
+use crate::vfs::{Vfs, VfsFile};
+
+/// The default size of each segment, 1 GiB.
+pub const DEFAULT_SEGMENT_SIZE: u64 = 1 << 30;
+
+pub(crate) struct SegmentedFile {
+    vfs: Arc<dyn Vfs>,
+    path: Utf8PathBuf,
+    segment_size: u64,
+    /// Every segment that exists. There's always at least one.
+    segments: Mutex<Vec<Arc<dyn VfsFile>>>,
+    /// Segments that have been written to since they were last synced.
+    unsynced: Mutex<BTreeSet<usize>>,
+}
+
+impl SegmentedFile {
+    pub fn open(vfs: Arc<dyn Vfs>, path: &Utf8Path, segment_size: u64) -> io::Result<SegmentedFile> {
+        let mut segments: Vec<Arc<dyn VfsFile>> = vec![vfs.open(&segment_path(path, 0))?.into()];
+        while vfs.exists(&segment_path(path, segments.len()))? {
+            segments.push(vfs.open(&segment_path(path, segments.len()))?.into());
+        }
+
+        Ok(SegmentedFile {
+            vfs,
+            path: path.to_owned(),
+            segment_size,
+            segments: Mutex::new(segments),
+            unsynced: Mutex::default(),
+        })
+    }
+
+    /// Read the first bytes of the file at `path`, without knowing how big its
+    /// segments are.
+    pub fn read_start(vfs: &dyn Vfs, path: &Utf8Path, buf: &mut [u8]) -> io::Result<()> {
+        vfs.open(&segment_path(path, 0))?.read_at(buf, 0)
+    }
+
+    /// Whether there's a file at `path`, without creating it.
+    pub fn exists(vfs: &dyn Vfs, path: &Utf8Path) -> io::Result<bool> {
+        vfs.exists(&segment_path(path, 0))
+    }
+
+    /// Remove every segment of the file at `path`.
+    pub fn delete(vfs: &dyn Vfs, path: &Utf8Path) -> io::Result<()> {
+        let mut segment_no = 0;
+        while vfs.exists(&segment_path(path, segment_no))? {
+            vfs.delete(&segment_path(path, segment_no))?;
+            segment_no += 1;
+        }
+
+        Ok(())
+    }
+
+    pub fn segment_size(&self) -> u64 {
+        self.segment_size
+    }
+
+    /// Split `len` bytes at `offset` into the pieces that fall in each segment,
+    /// as `(segment, offset in the segment, length)`.
+    fn split(&self, mut offset: u64, mut len: usize) -> Vec<(usize, u64, usize)> {
+        let mut pieces = vec![];
+
+        while len > 0 {
+            let segment_offset = offset % self.segment_size;
+            let n = len.min((self.segment_size - segment_offset) as usize);
+            pieces.push(((offset / self.segment_size) as usize, segment_offset, n));
+            offset += n as u64;
+            len -= n;
+        }
+
+        pieces
+    }
+
+    /// The segment numbered `segment_no`, creating it (and any before it) if
+    /// `create` is set. Otherwise, `None` if it doesn't exist yet.
+    fn segment(&self, segment_no: usize, create: bool) -> io::Result<Option<Arc<dyn VfsFile>>> {
+        let mut segments = self.segments.lock();
+
+        while create && segments.len() <= segment_no {
+            let path = segment_path(&self.path, segments.len());
+            segments.push(self.vfs.open(&path)?.into());
+        }
+
+        Ok(segments.get(segment_no).cloned())
+    }
+}
+
+impl VfsFile for SegmentedFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.read_batch(&mut [(offset, buf)])
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.write_batch(&[(offset, buf)])
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        let unsynced = std::mem::take(&mut *self.unsynced.lock());
+
+        for segment_no in unsynced {
+            if let Some(segment) = self.segment(segment_no, false)? {
+                segment.sync()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        let segments = self.segments.lock();
+        let last = segments.last().expect("There's always a first segment");
+        Ok((segments.len() - 1) as u64 * self.segment_size + last.len()?)
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        let mut segments = self.segments.lock();
+        let keep = len.div_ceil(self.segment_size).max(1) as usize;
+
+        while segments.len() > keep {
+            segments.pop();
+            self.vfs.delete(&segment_path(&self.path, segments.len()))?;
+        }
+
+        let last = segments.last().expect("There's always a first segment");
+        last.truncate(len - (keep - 1) as u64 * self.segment_size)?;
+        self.unsynced.lock().insert(keep - 1);
+
+        Ok(())
+    }
+
+    fn read_batch(&self, ops: &mut [(u64, &mut [u8])]) -> io::Result<()> {
+        let mut by_segment: BTreeMap<usize, Vec<(u64, &mut [u8])>> = BTreeMap::new();
+
+        for (offset, buf) in ops.iter_mut() {
+            let mut rest: &mut [u8] = buf;
+
+            for (segment_no, segment_offset, n) in self.split(*offset, rest.len()) {
+                let (piece, tail) = std::mem::take(&mut rest).split_at_mut(n);
+                by_segment
+                    .entry(segment_no)
+                    .or_default()
+                    .push((segment_offset, piece));
+                rest = tail;
+            }
+        }
+
+        for (segment_no, mut ops) in by_segment {
+            match self.segment(segment_no, false)? {
+                Some(segment) => segment.read_batch(&mut ops)?,
+                // Segments that don't exist yet were never written.
+                None => ops.iter_mut().for_each(|(_, buf)| buf.fill(0)),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_batch(&self, ops: &[(u64, &[u8])]) -> io::Result<()> {
+        let mut by_segment: BTreeMap<usize, Vec<(u64, &[u8])>> = BTreeMap::new();
+
+        for (offset, buf) in ops {
+            let mut rest = *buf;
+
+            for (segment_no, segment_offset, n) in self.split(*offset, rest.len()) {
+                let (piece, tail) = rest.split_at(n);
+                by_segment
+                    .entry(segment_no)
+                    .or_default()
+                    .push((segment_offset, piece));
+                rest = tail;
+            }
+        }
+
+        for (segment_no, ops) in by_segment {
+            let segment = self.segment(segment_no, true)?.unwrap();
+            segment.write_batch(&ops)?;
+            self.unsynced.lock().insert(segment_no);
+        }
+
+        Ok(())
+    }
+}
+
+fn segment_path(path: &Utf8Path, segment_no: usize) -> Utf8PathBuf {
+    format!("{path}.{segment_no}").into()
+}
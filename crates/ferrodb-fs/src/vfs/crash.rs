@@ -0,0 +1,423 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+use super::lock::LockTable;
+use super::{LockMode, Vfs, VfsFile, VfsLock};
+
+/// An in-memory [`Vfs`] that records every write and fsync, so that tests can
+/// pretend the machine lost power at any point and see what would have made it
+/// to disk.
+///
+/// Reads always see every write, like a page cache would. Only a crash makes the
+/// difference between synced and unsynced writes observable. A new file is
+/// only durable once it's been synced, like a [`DiskVfs`](super::DiskVfs) one,
+/// while deleting a file is durable straight away.
+#[derive(Default)]
+pub struct CrashVfs {
+    inner: Arc<Mutex<CrashInner>>,
+    /// Locks don't survive a crash, since whoever held them is gone.
+    locks: LockTable,
+}
+
+/// What happens to the writes that were issued but not yet fsynced when the
+/// crash hits.
+#[derive(Copy, Clone, Debug)]
+pub enum CrashMode {
+    /// None of them made it.
+    DropUnsynced,
+    /// Some prefix of them made it, and the next one was torn at a
+    /// `sector_size`-aligned boundary.
+    TornWrite { sector_size: usize, seed: u64 },
+    /// An arbitrary subset of them made it, in an arbitrary order.
+    Reorder { seed: u64 },
+}
+
+#[derive(Clone, Debug)]
+pub enum CrashEvent {
+    /// A file that didn't exist before was opened.
+    Create { path: Utf8PathBuf },
+    Write { path: Utf8PathBuf, offset: u64, data: Vec<u8> },
+    Truncate { path: Utf8PathBuf, len: u64 },
+    Sync { path: Utf8PathBuf },
+    Delete { path: Utf8PathBuf },
+}
+
+#[derive(Default)]
+struct CrashInner {
+    /// What reads see right now.
+    files: HashMap<Utf8PathBuf, Vec<u8>>,
+    /// What was already durable before the first event, which is whatever
+    /// survived the crash this filesystem came from.
+    initial: HashMap<Utf8PathBuf, Vec<u8>>,
+    events: Vec<CrashEvent>,
+}
+
+/// A write or truncate that hasn't been fsynced yet.
+enum Pending {
+    Write { offset: u64, data: Vec<u8> },
+    Truncate { len: u64 },
+}
+
+impl CrashVfs {
+    fn with_files(files: HashMap<Utf8PathBuf, Vec<u8>>) -> CrashVfs {
+        CrashVfs {
+            inner: Arc::new(Mutex::new(CrashInner {
+                initial: files.clone(),
+                files,
+                events: vec![],
+            })),
+            locks: LockTable::default(),
+        }
+    }
+
+    /// Every file creation, write, truncate, fsync, and delete issued so far,
+    /// in order.
+    pub fn events(&self) -> Vec<CrashEvent> {
+        self.inner.lock().events.clone()
+    }
+
+    /// Crash right now. See [`CrashVfs::crash_at`].
+    pub fn crash(&self, mode: CrashMode) -> CrashVfs {
+        let len = self.inner.lock().events.len();
+        self.crash_at(len, mode)
+    }
+
+    /// Pretend the machine crashed right after the first `events` events, and
+    /// return a fresh filesystem holding whatever survived. Handles into this
+    /// filesystem are unaffected; reopen the database on the returned one.
+    pub fn crash_at(&self, events: usize, mode: CrashMode) -> CrashVfs {
+        let inner = self.inner.lock();
+
+        let mut durable = inner.initial.clone();
+        let mut pending: HashMap<Utf8PathBuf, Vec<Pending>> = HashMap::new();
+        // Files that were created, but not synced since.
+        let mut created: HashSet<Utf8PathBuf> = HashSet::new();
+
+        for event in &inner.events[..events] {
+            match event {
+                CrashEvent::Create { path } => {
+                    created.insert(path.clone());
+                    pending.entry(path.clone()).or_default();
+                },
+                CrashEvent::Write { path, offset, data } => {
+                    pending.entry(path.clone()).or_default().push(Pending::Write {
+                        offset: *offset,
+                        data: data.clone(),
+                    });
+                },
+                CrashEvent::Truncate { path, len } => {
+                    pending
+                        .entry(path.clone())
+                        .or_default()
+                        .push(Pending::Truncate { len: *len });
+                },
+                CrashEvent::Sync { path } => {
+                    created.remove(path);
+                    let contents = durable.entry(path.clone()).or_default();
+                    for op in pending.remove(path).unwrap_or_default() {
+                        op.apply(contents);
+                    }
+                },
+                CrashEvent::Delete { path } => {
+                    durable.remove(path);
+                    pending.remove(path);
+                    created.remove(path);
+                },
+            }
+        }
+
+        // Iterate in a fixed order so that a seed always produces the same crash.
+        let mut pending: Vec<_> = pending.into_iter().collect();
+        pending.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let mut rng = match mode {
+            CrashMode::DropUnsynced => StdRng::seed_from_u64(0),
+            CrashMode::TornWrite { seed, .. } | CrashMode::Reorder { seed } =>
+                StdRng::seed_from_u64(seed),
+        };
+
+        for (path, mut ops) in pending {
+            // A file that was never synced might not have made it into its
+            // directory, whatever happened to its contents.
+            if created.contains(&path) {
+                match mode {
+                    CrashMode::DropUnsynced => continue,
+                    _ if !rng.gen_bool(0.5) => continue,
+                    _ => {},
+                }
+            }
+
+            let contents = durable.entry(path).or_default();
+
+            match mode {
+                CrashMode::DropUnsynced => {},
+                CrashMode::TornWrite { sector_size, .. } => {
+                    let survivors = rng.gen_range(0..=ops.len());
+                    let mut ops = ops.drain(..);
+
+                    for op in ops.by_ref().take(survivors) {
+                        op.apply(contents);
+                    }
+
+                    if let Some(op) = ops.next() {
+                        op.tear(contents, sector_size, &mut rng);
+                    }
+                },
+                CrashMode::Reorder { .. } => {
+                    ops.shuffle(&mut rng);
+                    let survivors = rng.gen_range(0..=ops.len());
+
+                    for op in ops.drain(..).take(survivors) {
+                        op.apply(contents);
+                    }
+                },
+            }
+        }
+
+        CrashVfs::with_files(durable)
+    }
+}
+
+impl Pending {
+    fn apply(self, contents: &mut Vec<u8>) {
+        match self {
+            Pending::Write { offset, data } => write_into(contents, offset, &data),
+            Pending::Truncate { len } => contents.resize(len as usize, 0),
+        }
+    }
+
+    /// Apply only the sectors of this write up to some sector boundary inside
+    /// of it. Truncates don't tear, they either happen or they don't.
+    fn tear(self, contents: &mut Vec<u8>, sector_size: usize, rng: &mut StdRng) {
+        let Pending::Write { offset, data } = self
+            else { return; };
+
+        let start = offset as usize;
+        let end = start + data.len();
+        let first_boundary = (start / sector_size + 1) * sector_size;
+        let boundaries: Vec<_> = (first_boundary..end).step_by(sector_size).collect();
+
+        if let Some(&boundary) = boundaries.choose(rng) {
+            write_into(contents, offset, &data[..boundary - start]);
+        }
+    }
+}
+
+fn write_into(contents: &mut Vec<u8>, offset: u64, data: &[u8]) {
+    let start = offset as usize;
+    let end = start + data.len();
+
+    if contents.len() < end {
+        contents.resize(end, 0);
+    }
+    contents[start..end].copy_from_slice(data);
+}
+
+impl Vfs for CrashVfs {
+    fn open(&self, path: &Utf8Path) -> io::Result<Box<dyn VfsFile>> {
+        let mut inner = self.inner.lock();
+        if !inner.files.contains_key(path) {
+            inner.files.insert(path.to_owned(), vec![]);
+            inner.events.push(CrashEvent::Create {
+                path: path.to_owned(),
+            });
+        }
+
+        Ok(Box::new(CrashFile {
+            path: path.to_owned(),
+            inner: self.inner.clone(),
+        }))
+    }
+
+    fn exists(&self, path: &Utf8Path) -> io::Result<bool> {
+        Ok(self.inner.lock().files.contains_key(path))
+    }
+
+    fn list(&self, dir: &Utf8Path) -> io::Result<Vec<Utf8PathBuf>> {
+        let inner = self.inner.lock();
+        Ok(inner
+            .files
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn delete(&self, path: &Utf8Path) -> io::Result<()> {
+        let mut inner = self.inner.lock();
+
+        if inner.files.remove(path).is_none() {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+
+        inner.events.push(CrashEvent::Delete {
+            path: path.to_owned(),
+        });
+        Ok(())
+    }
+
+    fn lock(&self, path: &Utf8Path, mode: LockMode) -> io::Result<Option<Box<dyn VfsLock>>> {
+        self.open(path)?;
+        Ok(self.locks.lock(path, mode))
+    }
+}
+
+struct CrashFile {
+    path: Utf8PathBuf,
+    inner: Arc<Mutex<CrashInner>>,
+}
+
+impl CrashFile {
+    fn with_contents<T>(&self, f: impl FnOnce(&mut Vec<u8>) -> T) -> io::Result<T> {
+        let mut inner = self.inner.lock();
+        let contents = inner.files.get_mut(&self.path).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("`{}` was deleted while it was still open", self.path),
+            )
+        })?;
+
+        Ok(f(contents))
+    }
+}
+
+impl VfsFile for CrashFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        self.with_contents(|contents| {
+            let start = (offset as usize).min(contents.len());
+            let end = (start + buf.len()).min(contents.len());
+
+            buf[..end - start].copy_from_slice(&contents[start..end]);
+            buf[end - start..].fill(0);
+        })
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.with_contents(|contents| write_into(contents, offset, buf))?;
+        self.inner.lock().events.push(CrashEvent::Write {
+            path: self.path.clone(),
+            offset,
+            data: buf.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.inner.lock().events.push(CrashEvent::Sync {
+            path: self.path.clone(),
+        });
+
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        self.with_contents(|contents| contents.len() as u64)
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.with_contents(|contents| contents.resize(len as usize, 0))?;
+        self.inner.lock().events.push(CrashEvent::Truncate {
+            path: self.path.clone(),
+            len,
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(vfs: &CrashVfs, path: &str) -> Option<Vec<u8>> {
+        vfs.inner.lock().files.get(Utf8Path::new(path)).cloned()
+    }
+
+    #[test]
+    fn only_synced_writes_survive_dropping_unsynced_ones() {
+        let vfs = CrashVfs::default();
+        let file = vfs.open(Utf8Path::new("a")).unwrap();
+        file.write_at(b"synced", 0).unwrap();
+        file.sync().unwrap();
+        file.write_at(b"lost", 10).unwrap();
+
+        assert_eq!(file.len().unwrap(), 14, "Expected reads to see unsynced writes");
+        assert_eq!(read(&vfs.crash(CrashMode::DropUnsynced), "a").unwrap(), b"synced");
+        assert_eq!(read(&vfs.crash_at(1, CrashMode::DropUnsynced), "a"), None);
+    }
+
+    #[test]
+    fn torn_writes_tear_at_sector_boundaries() {
+        let vfs = CrashVfs::default();
+        let file = vfs.open(Utf8Path::new("a")).unwrap();
+        file.sync().unwrap();
+        file.write_at(&[1; 64], 0).unwrap();
+
+        for seed in 0..20 {
+            let contents = read(&vfs.crash(CrashMode::TornWrite { sector_size: 16, seed }), "a").unwrap();
+            assert!(contents.len().is_multiple_of(16), "Expected {contents:?} to end at a sector boundary");
+            assert!(contents.iter().all(|&byte| byte == 1));
+        }
+    }
+
+    #[test]
+    fn reordering_is_the_same_for_the_same_seed() {
+        let vfs = CrashVfs::default();
+        let file = vfs.open(Utf8Path::new("a")).unwrap();
+        for i in 0..10 {
+            file.write_at(&[i], i as u64).unwrap();
+        }
+
+        for seed in 0..5 {
+            let mode = CrashMode::Reorder { seed };
+            assert_eq!(read(&vfs.crash(mode), "a"), read(&vfs.crash(mode), "a"));
+        }
+    }
+
+    #[test]
+    fn crashing_again_keeps_what_survived_the_first_crash() {
+        let vfs = CrashVfs::default();
+        let file = vfs.open(Utf8Path::new("a")).unwrap();
+        file.write_at(b"first", 0).unwrap();
+        file.sync().unwrap();
+
+        let crashed = vfs.crash(CrashMode::DropUnsynced);
+        let file = crashed.open(Utf8Path::new("b")).unwrap();
+        file.write_at(b"second", 0).unwrap();
+
+        let again = crashed.crash_at(0, CrashMode::DropUnsynced);
+        assert_eq!(read(&again, "a").unwrap(), b"first");
+        assert_eq!(read(&again, "b"), None);
+
+        file.sync().unwrap();
+        let again = crashed.crash(CrashMode::DropUnsynced);
+        assert_eq!(read(&again, "a").unwrap(), b"first");
+        assert_eq!(read(&again, "b").unwrap(), b"second");
+    }
+
+    #[test]
+    fn new_files_only_survive_once_synced() {
+        let vfs = CrashVfs::default();
+        let file = vfs.open(Utf8Path::new("a")).unwrap();
+        file.write_at(b"data", 0).unwrap();
+        assert_eq!(read(&vfs.crash(CrashMode::DropUnsynced), "a"), None);
+
+        file.sync().unwrap();
+        vfs.delete(Utf8Path::new("a")).unwrap();
+        assert_eq!(read(&vfs.crash_at(3, CrashMode::DropUnsynced), "a").unwrap(), b"data");
+        assert_eq!(read(&vfs.crash(CrashMode::DropUnsynced), "a"), None);
+
+        // Reopening a file that's already there doesn't create it again.
+        let vfs = vfs.crash_at(3, CrashMode::DropUnsynced);
+        vfs.open(Utf8Path::new("a")).unwrap();
+        assert_eq!(read(&vfs.crash(CrashMode::DropUnsynced), "a").unwrap(), b"data");
+    }
+}
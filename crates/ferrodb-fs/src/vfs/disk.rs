@@ -0,0 +1,237 @@
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use parking_lot::Mutex;
+
+use super::{LockMode, Vfs, VfsFile, VfsLock};
+use crate::io::{read_batch, write_batch};
+
+/// Files on the real filesystem, relative to some root directory.
+pub struct DiskVfs {
+    root: Utf8PathBuf,
+}
+
+impl DiskVfs {
+    pub fn new(root: impl Into<Utf8PathBuf>) -> DiskVfs {
+        DiskVfs { root: root.into() }
+    }
+
+    fn open_file(&self, path: &Utf8Path) -> io::Result<DiskFile> {
+        let path = self.root.join(path);
+
+        // Every directory that gets a new entry here, starting with the one
+        // that holds the file itself, needs an fsync before that entry is
+        // durable.
+        let parent = path.parent().unwrap_or(Utf8Path::new(""));
+        let mut new_entries = vec![parent.to_owned()];
+        let mut dir = parent;
+        while let Some(up) = dir.parent().filter(|_| !dir.exists()) {
+            new_entries.push(up.to_owned());
+            dir = up;
+        }
+
+        std::fs::create_dir_all(parent)?;
+
+        let created = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path);
+
+        let file = match created {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                new_entries.clear();
+                OpenOptions::new().read(true).write(true).open(&path)?
+            },
+            Err(e) => return Err(e),
+        };
+
+        Ok(DiskFile {
+            file,
+            new_entries: Mutex::new(new_entries),
+        })
+    }
+}
+
+impl Vfs for DiskVfs {
+    fn open(&self, path: &Utf8Path) -> io::Result<Box<dyn VfsFile>> {
+        Ok(Box::new(self.open_file(path)?))
+    }
+
+    fn exists(&self, path: &Utf8Path) -> io::Result<bool> {
+        Ok(self.root.join(path).exists())
+    }
+
+    fn list(&self, dir: &Utf8Path) -> io::Result<Vec<Utf8PathBuf>> {
+        let entries = match std::fs::read_dir(self.root.join(dir)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut paths = vec![];
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+
+            let name = entry.file_name().into_string().map_err(|name| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{name:?} isn't UTF-8"))
+            })?;
+            paths.push(dir.join(name));
+        }
+
+        Ok(paths)
+    }
+
+    fn delete(&self, path: &Utf8Path) -> io::Result<()> {
+        let path = self.root.join(path);
+        std::fs::remove_file(&path)?;
+        sync_dir(path.parent().unwrap_or(Utf8Path::new(".")))
+    }
+
+    fn lock(&self, path: &Utf8Path, mode: LockMode) -> io::Result<Option<Box<dyn VfsLock>>> {
+        let path = self.root.join(path);
+
+        // Taking a lock doesn't need write access, so a lock file that's already
+        // there is only opened for reading, which works on a read-only mount too.
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+
+                let created = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&path);
+
+                match created {
+                    Ok(file) => file,
+                    // Nobody can open a read-only mount for writing, so a shared
+                    // lock has nothing to keep out.
+                    Err(e) if e.kind() == io::ErrorKind::ReadOnlyFilesystem && mode == LockMode::Shared =>
+                        return Ok(Some(Box::new(DiskLock { _file: None }))),
+                    Err(e) => return Err(e),
+                }
+            },
+            Err(e) => return Err(e),
+        };
+
+        let operation = match mode {
+            LockMode::Exclusive => libc::LOCK_EX,
+            LockMode::Shared => libc::LOCK_SH,
+        };
+
+        // SAFETY: `file` is an open file descriptor for as long as this call.
+        if unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            return match e.kind() {
+                io::ErrorKind::WouldBlock => Ok(None),
+                _ => Err(e),
+            };
+        }
+
+        // The lock goes away when the file is closed.
+        Ok(Some(Box::new(DiskLock { _file: Some(file) })))
+    }
+}
+
+struct DiskLock {
+    _file: Option<File>,
+}
+
+impl VfsLock for DiskLock {}
+
+struct DiskFile {
+    file: File,
+    /// Directories that got an entry when this file was created, which the
+    /// first sync makes durable along with the file's contents.
+    new_entries: Mutex<Vec<Utf8PathBuf>>,
+}
+
+fn sync_dir(dir: &Utf8Path) -> io::Result<()> {
+    let dir = if dir.as_str().is_empty() { Utf8Path::new(".") } else { dir };
+    File::open(dir)?.sync_all()
+}
+
+impl VfsFile for DiskFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        read_batch(&self.file, &mut [(offset, buf)])
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.file.write_all_at(buf, offset)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()?;
+
+        let mut new_entries = self.new_entries.lock();
+        for dir in new_entries.iter() {
+            sync_dir(dir)?;
+        }
+        new_entries.clear();
+
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.file.set_len(len)
+    }
+
+    fn read_batch(&self, ops: &mut [(u64, &mut [u8])]) -> io::Result<()> {
+        read_batch(&self.file, ops)
+    }
+
+    fn write_batch(&self, ops: &[(u64, &[u8])]) -> io::Result<()> {
+        write_batch(&self.file, ops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_files_sync_every_directory_they_were_added_to() {
+        let tmp = Utf8PathBuf::try_from(std::env::temp_dir()).unwrap();
+        let root = tmp.join(format!("ferrodb-disk-{}", std::process::id()));
+        let vfs = DiskVfs::new(root.clone());
+
+        let path = Utf8Path::new("a/b/file");
+        let file = vfs.open_file(path).unwrap();
+        let dirs = [root.join("a/b"), root.join("a"), root.clone(), tmp];
+        assert_eq!(*file.new_entries.lock(), dirs);
+
+        file.write_at(b"data", 0).unwrap();
+        file.sync().unwrap();
+        assert!(file.new_entries.lock().is_empty());
+
+        // Only a file that wasn't there yet has any directories to sync.
+        let file = vfs.open_file(Utf8Path::new("a/c")).unwrap();
+        assert_eq!(*file.new_entries.lock(), [root.join("a")]);
+        let file = vfs.open_file(path).unwrap();
+        assert!(file.new_entries.lock().is_empty());
+
+        let mut buf = [0; 4];
+        file.read_at(&mut buf, 0).unwrap();
+        assert_eq!(&buf, b"data");
+
+        vfs.delete(path).unwrap();
+        assert!(!vfs.exists(path).unwrap());
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use parking_lot::Mutex;
+
+use super::{LockMode, VfsLock};
+
+/// Advisory locks for backends that don't have a real filesystem to take
+/// them on, so they only exclude other users of the same backend.
+#[derive(Clone, Default)]
+pub(crate) struct LockTable(Arc<Mutex<HashMap<Utf8PathBuf, Held>>>);
+
+enum Held {
+    Exclusive,
+    Shared(usize),
+}
+
+impl LockTable {
+    pub fn lock(&self, path: &Utf8Path, mode: LockMode) -> Option<Box<dyn VfsLock>> {
+        let mut locks = self.0.lock();
+
+        match (locks.get_mut(path), mode) {
+            (None, LockMode::Exclusive) => {
+                locks.insert(path.to_owned(), Held::Exclusive);
+            },
+            (None, LockMode::Shared) => {
+                locks.insert(path.to_owned(), Held::Shared(1));
+            },
+            (Some(Held::Shared(count)), LockMode::Shared) => *count += 1,
+            (Some(_), _) => return None,
+        }
+
+        Some(Box::new(TableLock {
+            table: self.clone(),
+            path: path.to_owned(),
+        }))
+    }
+}
+
+struct TableLock {
+    table: LockTable,
+    path: Utf8PathBuf,
+}
+
+impl VfsLock for TableLock {}
+
+impl Drop for TableLock {
+    fn drop(&mut self) {
+        let mut locks = self.table.0.lock();
+
+        if let Some(Held::Shared(count)) = locks.get_mut(&self.path) {
+            if *count > 1 {
+                *count -= 1;
+                return;
+            }
+        }
+
+        locks.remove(&self.path);
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use parking_lot::{Mutex, RwLock};
+
+use super::lock::LockTable;
+use super::{LockMode, Vfs, VfsFile, VfsLock};
+
+/// Files that only live as long as this value (and any handles opened from
+/// it) does. Nothing ever touches the real filesystem.
+#[derive(Default)]
+pub struct MemoryVfs {
+    files: Mutex<HashMap<Utf8PathBuf, Arc<RwLock<Vec<u8>>>>>,
+    locks: LockTable,
+}
+
+impl Vfs for MemoryVfs {
+    fn open(&self, path: &Utf8Path) -> io::Result<Box<dyn VfsFile>> {
+        let contents = self.files.lock().entry(path.to_owned()).or_default().clone();
+        Ok(Box::new(MemoryFile(contents)))
+    }
+
+    fn exists(&self, path: &Utf8Path) -> io::Result<bool> {
+        Ok(self.files.lock().contains_key(path))
+    }
+
+    fn list(&self, dir: &Utf8Path) -> io::Result<Vec<Utf8PathBuf>> {
+        let files = self.files.lock();
+        Ok(files
+            .keys()
+            .filter(|path| path.parent() == Some(dir))
+            .cloned()
+            .collect())
+    }
+
+    fn delete(&self, path: &Utf8Path) -> io::Result<()> {
+        match self.files.lock().remove(path) {
+            Some(_) => Ok(()),
+            None => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn lock(&self, path: &Utf8Path, mode: LockMode) -> io::Result<Option<Box<dyn VfsLock>>> {
+        self.files.lock().entry(path.to_owned()).or_default();
+        Ok(self.locks.lock(path, mode))
+    }
+}
+
+struct MemoryFile(Arc<RwLock<Vec<u8>>>);
+
+impl VfsFile for MemoryFile {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()> {
+        let contents = self.0.read();
+        let start = (offset as usize).min(contents.len());
+        let end = (start + buf.len()).min(contents.len());
+
+        buf[..end - start].copy_from_slice(&contents[start..end]);
+        buf[end - start..].fill(0);
+
+        Ok(())
+    }
+
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let mut contents = self.0.write();
+        let start = offset as usize;
+        let end = start + buf.len();
+
+        if contents.len() < end {
+            contents.resize(end, 0);
+        }
+        contents[start..end].copy_from_slice(buf);
+
+        Ok(())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        // Memory is as durable as it's going to get.
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.0.read().len() as u64)
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()> {
+        self.0.write().resize(len as usize, 0);
+        Ok(())
+    }
+}
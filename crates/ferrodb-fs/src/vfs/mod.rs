@@ -0,0 +1,91 @@
+//! The storage underneath [`FileManager`](crate::FileManager).
+//!
+//! Everything the file manager does to durable storage goes through a [`Vfs`],
+//! so tests (and `Standalone` mode) can run entirely in memory, and embedders
+//! can plug in their own storage.
+
+mod crash;
+mod disk;
+mod lock;
+mod memory;
+
+use std::io;
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+pub use self::crash::{CrashEvent, CrashMode, CrashVfs};
+pub use self::disk::DiskVfs;
+pub use self::memory::MemoryVfs;
+
+pub trait Vfs: Send + Sync {
+    /// Open the file at `path` for reading and writing, creating it (and any
+    /// directories leading up to it) if it doesn't exist yet. A new file
+    /// only survives a crash once it's been synced.
+    fn open(&self, path: &Utf8Path) -> io::Result<Box<dyn VfsFile>>;
+
+    /// Whether there is a file at `path`, without creating one.
+    fn exists(&self, path: &Utf8Path) -> io::Result<bool>;
+
+    /// Every file directly in the directory `dir`, as paths that include `dir`.
+    fn list(&self, dir: &Utf8Path) -> io::Result<Vec<Utf8PathBuf>>;
+
+    /// Remove the file at `path`, durably. Handles that are already open keep
+    /// working.
+    fn delete(&self, path: &Utf8Path) -> io::Result<()>;
+
+    /// Take an advisory lock on the file at `path` (creating it if needed),
+    /// held until the returned guard is dropped. Returns `None` if someone
+    /// else holds a conflicting lock, rather than waiting for it.
+    fn lock(&self, path: &Utf8Path, mode: LockMode) -> io::Result<Option<Box<dyn VfsLock>>>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LockMode {
+    /// Nobody else can hold any lock on the file.
+    Exclusive,
+    /// Anybody else can hold a shared lock on the file too.
+    Shared,
+}
+
+/// Held for as long as a lock taken with [`Vfs::lock`] should be.
+pub trait VfsLock: Send + Sync {}
+
+pub trait VfsFile: Send + Sync {
+    /// Fill `buf` from `offset`. Anything past the end of the file reads as
+    /// zeroes.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<()>;
+
+    /// Write all of `buf` at `offset`, growing the file if needed.
+    fn write_at(&self, buf: &[u8], offset: u64) -> io::Result<()>;
+
+    /// Make every write so far durable.
+    fn sync(&self) -> io::Result<()>;
+
+    fn len(&self) -> io::Result<u64>;
+
+    fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    fn truncate(&self, len: u64) -> io::Result<()>;
+
+    /// Like [`VfsFile::read_at`], for many buffers at once. Backends that can
+    /// submit these together should override this.
+    fn read_batch(&self, ops: &mut [(u64, &mut [u8])]) -> io::Result<()> {
+        for (offset, buf) in ops {
+            self.read_at(buf, *offset)?;
+        }
+
+        Ok(())
+    }
+
+    /// Like [`VfsFile::write_at`], for many buffers at once. Backends that can
+    /// submit these together should override this.
+    fn write_batch(&self, ops: &[(u64, &[u8])]) -> io::Result<()> {
+        for (offset, buf) in ops {
+            self.write_at(buf, *offset)?;
+        }
+
+        Ok(())
+    }
+}
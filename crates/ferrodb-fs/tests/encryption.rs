@@ -0,0 +1,109 @@
+use std::sync::Arc;
+
+use camino::Utf8Path;
+use ferrodb_fs::vfs::{MemoryVfs, Vfs};
+use ferrodb_fs::{
+    Cipher, EncryptionKey, Error, FileKind, FileManager, FileManagerOptions, PAGE_HEADER_SIZE, SEAL_OVERHEAD,
+};
+
+const PAGE_SIZE: usize = 256;
+const SECRET: &[u8] = b"the secret ingredient";
+
+fn open(vfs: Arc<dyn Vfs>, key: Option<[u8; 32]>) -> Result<FileManager, Error> {
+    ferrodb_page::setup(PAGE_SIZE);
+    FileManager::with_options(vfs, FileManagerOptions {
+        double_write: true,
+        encryption_key: key.map(EncryptionKey::new),
+        ..FileManagerOptions::default()
+    })
+}
+
+fn read(files: &FileManager, page: usize) -> Result<Vec<u8>, Error> {
+    let file = files.open("t", FileKind::Generic)?;
+    let page = files.clean(file, page)?;
+    let body = page.read()[PAGE_HEADER_SIZE..PAGE_HEADER_SIZE + SECRET.len()].to_vec();
+    Ok(body)
+}
+
+#[test]
+fn nothing_is_written_in_the_clear_and_only_the_key_reads_it_back() {
+    let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::default());
+    let files = open(vfs.clone(), Some([1; 32])).unwrap();
+    let file = files.open("t", FileKind::Generic).unwrap();
+    for page in 1..=3 {
+        let page = files.dirty(file, page).unwrap();
+        page.write()[PAGE_HEADER_SIZE..][..SECRET.len()].copy_from_slice(SECRET);
+    }
+    files.sync_all().unwrap();
+    drop(files);
+
+    // Not in the data file, nor in the double-write file.
+    let mut paths = vfs.list(Utf8Path::new("")).unwrap();
+    paths.extend(vfs.list(Utf8Path::new(".")).unwrap());
+    assert!(paths.iter().any(|path| path == "doublewrite") && paths.iter().any(|path| path == "./t.0"));
+    for path in paths {
+        let file = vfs.open(&path).unwrap();
+        let mut bytes = vec![0; file.len().unwrap() as usize];
+        file.read_at(&mut bytes, 0).unwrap();
+        assert!(!bytes.windows(SECRET.len()).any(|window| window == SECRET), "{path}");
+    }
+
+    let files = open(vfs.clone(), Some([1; 32])).unwrap();
+    for page in 1..=3 {
+        assert_eq!(read(&files, page).unwrap(), SECRET);
+    }
+    drop(files);
+
+    let files = open(vfs.clone(), None).unwrap();
+    assert!(matches!(read(&files, 1), Err(Error::MissingKey { .. })));
+    drop(files);
+    let files = open(vfs, Some([2; 32])).unwrap();
+    assert!(read(&files, 1).is_err());
+}
+
+#[test]
+fn sealed_pages_only_open_where_they_were_sealed() {
+    let key = EncryptionKey::new([1; 32]);
+    let cipher = Cipher::new(&key, "t");
+    let sealed = cipher.seal(1, SECRET);
+    assert_eq!(sealed.len(), SECRET.len() + SEAL_OVERHEAD);
+    assert_eq!(cipher.open(1, &sealed).as_deref(), Some(SECRET));
+
+    // Sealing the same page twice never uses the same nonce.
+    assert_ne!(cipher.seal(1, SECRET), sealed);
+
+    // Moved to another page or file, sealed with another key, or changed.
+    assert_eq!(cipher.open(2, &sealed), None);
+    assert_eq!(Cipher::new(&key, "u").open(1, &sealed), None);
+    assert_eq!(Cipher::new(&EncryptionKey::new([2; 32]), "t").open(1, &sealed), None);
+    let mut tampered = sealed.clone();
+    tampered[10] ^= 1;
+    assert_eq!(cipher.open(1, &tampered), None);
+    assert_eq!(cipher.open(1, &sealed[..sealed.len() - 1]), None);
+}
+
+#[test]
+fn keys_are_read_as_hex_or_raw_bytes() {
+    let hex = "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff";
+    let key = EncryptionKey::from_hex(&format!("{hex}\n")).unwrap();
+    let cipher = Cipher::new(&key, "t");
+    let sealed = cipher.seal(1, SECRET);
+
+    let bytes: [u8; 32] = std::array::from_fn(|n| (n % 16 * 0x11) as u8);
+    assert!(Cipher::new(&EncryptionKey::new(bytes), "t").open(1, &sealed).is_some());
+
+    let dir = std::env::temp_dir();
+    let raw = Utf8Path::from_path(&dir).unwrap().join(format!("ferrodb-key-{}", std::process::id()));
+    std::fs::write(&raw, bytes).unwrap();
+    let from_raw = EncryptionKey::from_file(&raw).unwrap();
+    std::fs::write(&raw, hex).unwrap();
+    let from_hex = EncryptionKey::from_file(&raw).unwrap();
+    std::fs::remove_file(&raw).unwrap();
+    for key in [from_raw, from_hex] {
+        assert!(Cipher::new(&key, "t").open(1, &sealed).is_some());
+    }
+
+    for bad in ["", &hex[1..], &hex.replace('a', "g")] {
+        assert!(matches!(EncryptionKey::from_hex(bad), Err(Error::InvalidKey(_))));
+    }
+}
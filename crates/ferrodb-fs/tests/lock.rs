@@ -0,0 +1,62 @@
+use std::sync::Arc;
+
+use camino::Utf8PathBuf;
+use ferrodb_fs::vfs::{DiskVfs, MemoryVfs, Vfs};
+use ferrodb_fs::{Error, FileManager, FileManagerOptions};
+
+fn open(vfs: &Arc<dyn Vfs>, read_only: bool) -> Result<FileManager, Error> {
+    FileManager::with_options(vfs.clone(), FileManagerOptions {
+        read_only,
+        ..FileManagerOptions::default()
+    })
+}
+
+#[test]
+fn only_one_writer_at_a_time() {
+    let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::default());
+    let writer = open(&vfs, false).unwrap();
+
+    let pid = std::process::id();
+    assert!(matches!(open(&vfs, false), Err(Error::Locked { pid: Some(p) }) if p == pid));
+    assert!(matches!(open(&vfs, true), Err(Error::Locked { pid: Some(p) }) if p == pid));
+
+    drop(writer);
+    open(&vfs, false).unwrap();
+}
+
+#[test]
+fn readers_share_the_directory_but_keep_writers_out() {
+    let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::default());
+
+    // The first writer leaves its PID behind, which is stale once it's gone.
+    drop(open(&vfs, false).unwrap());
+
+    let first = open(&vfs, true).unwrap();
+    let second = open(&vfs, true).unwrap();
+    assert!(matches!(open(&vfs, false), Err(Error::LockedReadOnly)));
+
+    drop(first);
+    assert!(matches!(open(&vfs, false), Err(Error::LockedReadOnly)));
+    drop(second);
+    open(&vfs, false).unwrap();
+}
+
+#[test]
+fn readers_leave_the_lock_file_alone() {
+    let dir = Utf8PathBuf::try_from(std::env::temp_dir())
+        .unwrap()
+        .join(format!("ferrodb-lock-{}", std::process::id()));
+    let vfs: Arc<dyn Vfs> = Arc::new(DiskVfs::new(dir.clone()));
+
+    drop(open(&vfs, false).unwrap());
+    let before = std::fs::metadata(dir.join("LOCK")).unwrap();
+
+    let reader = open(&vfs, true).unwrap();
+    let after = std::fs::metadata(dir.join("LOCK")).unwrap();
+    assert_eq!(before.modified().unwrap(), after.modified().unwrap());
+    assert_eq!(before.len(), after.len());
+
+    assert!(matches!(open(&vfs, false), Err(Error::LockedReadOnly)));
+    drop(reader);
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use camino::Utf8Path;
+use ferrodb_fs::vfs::{MemoryVfs, Vfs};
+use ferrodb_fs::{FileKind, FileManager, FileManagerOptions, PAGE_HEADER_SIZE};
+
+const PAGE_SIZE: usize = 256;
+
+fn open(vfs: Arc<dyn Vfs>, segment_size: u64) -> FileManager {
+    ferrodb_page::setup(PAGE_SIZE);
+    FileManager::with_options(vfs, FileManagerOptions {
+        segment_size,
+        ..FileManagerOptions::default()
+    })
+    .unwrap()
+}
+
+fn segments(vfs: &dyn Vfs) -> Vec<String> {
+    let mut paths: Vec<_> = vfs.list(Utf8Path::new(".")).unwrap().into_iter().map(String::from).collect();
+    paths.sort();
+    paths
+}
+
+#[test]
+fn pages_are_spread_over_segments_and_read_back_from_them() {
+    let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::default());
+
+    // Segments that don't hold a whole number of pages, so that some pages
+    // are split between two of them.
+    let files = open(vfs.clone(), 1000);
+    let file = files.open("t", FileKind::Generic).unwrap();
+    for page in 1..=10 {
+        files.dirty(file, page).unwrap().write()[PAGE_HEADER_SIZE..].fill(page as u8);
+    }
+    files.sync_all().unwrap();
+    drop(files);
+    assert_eq!(segments(&*vfs), ["./t.0", "./t.1", "./t.2"]);
+
+    // The file keeps the segment size it was made with.
+    let files = open(vfs.clone(), 1 << 20);
+    let file = files.open("t", FileKind::Generic).unwrap();
+    assert_eq!(files.page_count(file).unwrap(), 11);
+    for page in 1..=10 {
+        let page_ref = files.clean(file, page).unwrap();
+        assert!(page_ref.read()[PAGE_HEADER_SIZE..].iter().all(|byte| *byte == page as u8));
+    }
+
+    files.dirty(file, 12).unwrap().write()[PAGE_HEADER_SIZE..].fill(12);
+    files.sync_all().unwrap();
+    assert_eq!(segments(&*vfs), ["./t.0", "./t.1", "./t.2", "./t.3"]);
+
+    files.truncate(file, 3).unwrap();
+    assert_eq!(segments(&*vfs), ["./t.0"]);
+    assert_eq!(files.page_count(file).unwrap(), 3);
+    let page_ref = files.clean(file, 2).unwrap();
+    assert!(page_ref.read()[PAGE_HEADER_SIZE..].iter().all(|byte| *byte == 2));
+
+    files.delete(file).unwrap();
+    assert!(segments(&*vfs).is_empty());
+}
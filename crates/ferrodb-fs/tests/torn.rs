@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use ferrodb_fs::vfs::{CrashMode, CrashVfs, Vfs};
+use ferrodb_fs::{Error, FileKind, FileManager, FileManagerOptions, PAGE_HEADER_SIZE};
+
+const PAGE_SIZE: usize = 256;
+const PAGES: usize = 4;
+
+fn open(vfs: Arc<dyn Vfs>, double_write: bool) -> FileManager {
+    ferrodb_page::setup(PAGE_SIZE);
+    FileManager::with_options(vfs, FileManagerOptions {
+        double_write,
+        ..FileManagerOptions::default()
+    })
+    .unwrap()
+}
+
+/// Write every page full of `old`, and then write it over with `new`,
+/// returning how many events it took for the old pages to be durable.
+fn write(vfs: &Arc<CrashVfs>, double_write: bool) -> usize {
+    let files = open(vfs.clone(), double_write);
+    let file = files.open("t", FileKind::Generic).unwrap();
+
+    let mut durable = 0;
+    for fill in [1, 2] {
+        durable = vfs.events().len();
+        for page in 1..=PAGES {
+            files.dirty(file, page).unwrap().write()[PAGE_HEADER_SIZE..].fill(fill);
+        }
+        files.sync_all().unwrap();
+    }
+
+    durable
+}
+
+/// Every crash that tears a write partway, from event `from` on.
+fn torn_crashes(vfs: &CrashVfs, from: usize) -> impl Iterator<Item = CrashVfs> + '_ {
+    (from..=vfs.events().len()).flat_map(move |events| {
+        (0..8).map(move |seed| vfs.crash_at(events, CrashMode::TornWrite { sector_size: 64, seed }))
+    })
+}
+
+#[test]
+fn torn_pages_are_repaired_from_the_double_write_file() {
+    let vfs = Arc::new(CrashVfs::default());
+    let from = write(&vfs, true);
+
+    for crashed in torn_crashes(&vfs, from) {
+        let files = open(Arc::new(crashed), true);
+        files.repair_torn_pages().unwrap();
+
+        let file = files.open("t", FileKind::Generic).unwrap();
+        for page in 1..=PAGES {
+            let page = files.clean(file, page).unwrap();
+            let body = &page.read()[PAGE_HEADER_SIZE..];
+            assert!([1, 2].iter().any(|fill| body.iter().all(|byte| byte == fill)));
+        }
+    }
+}
+
+#[test]
+fn torn_pages_are_caught_without_the_double_write_file() {
+    let vfs = Arc::new(CrashVfs::default());
+    let from = write(&vfs, false);
+
+    let mut torn = 0;
+    for crashed in torn_crashes(&vfs, from) {
+        let files = open(Arc::new(crashed), false);
+        let file = files.open("t", FileKind::Generic).unwrap();
+
+        for page in 1..=PAGES {
+            match files.clean(file, page) {
+                Ok(page) => {
+                    let body = &page.read()[PAGE_HEADER_SIZE..];
+                    assert!([1, 2].iter().any(|fill| body.iter().all(|byte| byte == fill)));
+                },
+                Err(Error::ChecksumMismatch { .. }) => torn += 1,
+                Err(e) => panic!("{e}"),
+            }
+        }
+    }
+    assert!(torn > 0);
+}
@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use ferrodb_fs::vfs::{MemoryVfs, Vfs};
+use ferrodb_fs::{FileKind, FileManager, PAGE_HEADER_SIZE};
+
+const PAGE_SIZE: usize = 256;
+
+fn open(vfs: Arc<dyn Vfs>) -> FileManager {
+    ferrodb_page::setup(PAGE_SIZE);
+    FileManager::new(vfs).unwrap()
+}
+
+#[test]
+fn a_written_back_page_is_still_the_one_readers_have() {
+    let files = open(Arc::new(MemoryVfs::default()));
+    let file = files.open("t", FileKind::Generic).unwrap();
+
+    files.dirty(file, 1).unwrap().write()[PAGE_HEADER_SIZE] = 1;
+    let reader = files.latest(file, 1).unwrap();
+    files.sync_all().unwrap();
+
+    // Dirtying the page again hands over the very page that was written back,
+    // rather than a copy of it.
+    files.dirty(file, 1).unwrap().write()[PAGE_HEADER_SIZE] = 2;
+    assert_eq!(reader.read()[PAGE_HEADER_SIZE], 2);
+}
+
+#[test]
+fn writing_to_a_written_back_page_dirties_it_again() {
+    let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::default());
+    let files = open(vfs.clone());
+    let file = files.open("t", FileKind::Generic).unwrap();
+
+    let page = files.dirty(file, 1).unwrap();
+    page.write()[PAGE_HEADER_SIZE] = 1;
+    files.sync_all().unwrap();
+
+    // `page` was written back, and became the clean copy, between being
+    // dirtied and this write, which still has to reach the disk.
+    page.write()[PAGE_HEADER_SIZE] = 2;
+    files.sync_all().unwrap();
+    drop(page);
+    drop(files);
+
+    let files = open(vfs);
+    let file = files.open("t", FileKind::Generic).unwrap();
+    assert_eq!(files.clean(file, 1).unwrap().read()[PAGE_HEADER_SIZE], 2);
+}
+
+#[test]
+fn a_page_changed_while_it_is_written_back_stays_dirty() {
+    let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::default());
+    let files = open(vfs.clone());
+    let file = files.open("t", FileKind::Generic).unwrap();
+
+    for value in 1..=10 {
+        files.dirty(file, 1).unwrap().write()[PAGE_HEADER_SIZE] = value;
+        files.sync_all().unwrap();
+    }
+    drop(files);
+
+    let files = open(vfs);
+    let file = files.open("t", FileKind::Generic).unwrap();
+    assert_eq!(files.clean(file, 1).unwrap().read()[PAGE_HEADER_SIZE], 10);
+}
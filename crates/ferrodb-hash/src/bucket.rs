@@ -0,0 +1,142 @@
+//! The body of a bucket page (everything past the page header) is:
+//!
+//! ```text
+//! [local depth: u8][flags: u8][entry count: u16][unused: u32][overflow: u64]
+//! entries, one after another
+//! ...free space...
+//! ```
+//!
+//! An entry is `[key len: u16][value len: u16][key][value]`. `overflow` is the
+//! next page of the bucket, for when its entries don't fit in one page, or 0
+//! if there isn't one. Overflow pages are laid out the same way.
+//!
+//! An all-zero page is an empty bucket. Pages on the free list have the
+//! [`FLAG_FREE`] flag, and the next free page as their `overflow`.
+
+use ferrodb_fs::PageIndex;
+
+const HEADER_SIZE: usize = 16;
+const ENTRY_HEADER_SIZE: usize = 4;
+
+const FLAG_FREE: u8 = 1 << 0;
+
+/// The longest key and value (together) that fit in a page with a
+/// `body_len` byte body.
+pub(crate) fn max_entry_len(body_len: usize) -> usize {
+    body_len - HEADER_SIZE - ENTRY_HEADER_SIZE
+}
+
+/// The next page of the bucket, read in place.
+pub(crate) fn overflow(body: &[u8]) -> PageIndex {
+    read_u64(body, 8) as PageIndex
+}
+
+/// Every entry of the page, read in place.
+pub(crate) fn entries(body: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut at = HEADER_SIZE;
+
+    (0..read_u16(body, 2)).map(move |_| {
+        let key_len = read_u16(body, at) as usize;
+        let value_len = read_u16(body, at + 2) as usize;
+        let key = &body[at + ENTRY_HEADER_SIZE..][..key_len];
+        let value = &body[at + ENTRY_HEADER_SIZE + key_len..][..value_len];
+
+        at += ENTRY_HEADER_SIZE + key_len + value_len;
+        (key, value)
+    })
+}
+
+/// One page of a bucket, read out of its page to be changed and written back.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Bucket {
+    /// How many bits of the hash every entry in the bucket has in common.
+    pub depth: u8,
+    pub overflow: PageIndex,
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl Bucket {
+    pub fn new(depth: u8) -> Bucket {
+        Bucket {
+            depth,
+            ..Bucket::default()
+        }
+    }
+
+    pub fn read(body: &[u8]) -> Bucket {
+        Bucket {
+            depth: body[0],
+            overflow: overflow(body),
+            entries: entries(body)
+                .map(|(key, value)| (key.to_vec(), value.to_vec()))
+                .collect(),
+        }
+    }
+
+    pub fn write(&self, body: &mut [u8]) {
+        assert!(self.size() <= body.len(), "Expected bucket to fit in its page");
+
+        body.fill(0);
+        body[0] = self.depth;
+        write_u16(body, 2, self.entries.len() as u16);
+        write_u64(body, 8, self.overflow as u64);
+
+        let mut at = HEADER_SIZE;
+        for (key, value) in &self.entries {
+            write_u16(body, at, key.len() as u16);
+            write_u16(body, at + 2, value.len() as u16);
+            at += ENTRY_HEADER_SIZE;
+            body[at..at + key.len()].copy_from_slice(key);
+            at += key.len();
+            body[at..at + value.len()].copy_from_slice(value);
+            at += value.len();
+        }
+    }
+
+    /// Turn the page into a free page, pointing at the `next` one.
+    pub fn write_free(body: &mut [u8], next: PageIndex) {
+        body.fill(0);
+        body[1] = FLAG_FREE;
+        write_u64(body, 8, next as u64);
+    }
+
+    /// Where the entry `(key, value)` is in this page, if it is.
+    pub fn position(&self, key: &[u8], value: &[u8]) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|(k, v)| (&**k, &**v) == (key, value))
+    }
+
+    /// Whether `(key, value)` could be added to this page, out of a `body_len`
+    /// byte body.
+    pub fn fits(&self, body_len: usize, key: &[u8], value: &[u8]) -> bool {
+        self.size() + ENTRY_HEADER_SIZE + key.len() + value.len() <= body_len
+    }
+
+    /// How many bytes of the page this takes up.
+    pub fn size(&self) -> usize {
+        let entries: usize = self
+            .entries
+            .iter()
+            .map(|(key, value)| ENTRY_HEADER_SIZE + key.len() + value.len())
+            .sum();
+
+        HEADER_SIZE + entries
+    }
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn write_u16(bytes: &mut [u8], at: usize, value: u16) {
+    bytes[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn write_u64(bytes: &mut [u8], at: usize, value: u64) {
+    bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Fs(#[from] ferrodb_fs::Error),
+    #[error(transparent)]
+    Wal(#[from] ferrodb_wal::Error),
+    #[error("Index entry is {len} bytes, but an entry can be at most {max} bytes")]
+    EntryTooLarge { len: usize, max: usize },
+}
@@ -0,0 +1,542 @@
+//! Indexes stored as extendible hash tables: entries of a key and a value, in
+//! buckets picked by the low bits of the key's hash, for equality lookups that
+//! don't need the keys in order.
+//!
+//! Page 1 of the file is the meta page:
+//!
+//! ```text
+//! [global depth: u8][unused: [u8; 7]][free list head: u64]
+//! [directory page: u64] * however many there are past the first
+//! ```
+//!
+//! The directory is an array of `2 ^ global depth` slots, a `u64` each, from
+//! the low bits of a hash to the bucket holding those entries. It starts on
+//! page 2, and carries on through the pages listed in the meta page. Slots
+//! hold bucket pages as their distance from page 3, the first bucket, so that
+//! an all-zero file is an empty index.
+//!
+//! When a bucket fills up, it's split in two on the next bit of the hash,
+//! doubling the directory first if the bucket already used every bit of it.
+//! Once the directory can't get any bigger, which is where a bucket of entries
+//! that all have the same hash ends up, a full bucket gets overflow pages
+//! instead. Buckets
+//! are never merged back together, and the directory never shrinks, but
+//! overflow pages that are emptied out are reused.
+//!
+//! Readers and writers latch the meta page, then the directory, then the
+//! bucket, letting go of the first two once they have the bucket. The latch
+//! on a bucket's first page covers its overflow pages too. A change that fits
+//! in its bucket is made under a read latch on the meta page, and one that
+//! needs to split a bucket or allocate or free a page starts over with a
+//! write latch on it. Every change goes through a [`Txn`], like for heaps.
+
+mod bucket;
+mod error;
+
+use std::ops::Range;
+use std::sync::Arc;
+
+use ferrodb_fs::{FileId, FileKind, FileManager, PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_page::page_size;
+use ferrodb_wal::Txn;
+
+use self::bucket::Bucket;
+pub use self::error::Error;
+
+const META_PAGE: PageIndex = 1;
+const DIRECTORY_PAGE: PageIndex = 2;
+const FIRST_BUCKET: PageIndex = 3;
+
+/// Where the list of directory pages starts in the meta page's body.
+const META_HEADER_SIZE: usize = 16;
+const SLOT_SIZE: usize = 8;
+
+pub struct HashIndex {
+    files: Arc<FileManager>,
+    file: FileId,
+}
+
+#[derive(Copy, Clone)]
+enum Op<'a> {
+    Insert(&'a [u8], &'a [u8]),
+    Delete(&'a [u8], &'a [u8]),
+}
+
+impl Op<'_> {
+    fn entry(&self) -> (&[u8], &[u8]) {
+        match *self {
+            Op::Insert(key, value) | Op::Delete(key, value) => (key, value),
+        }
+    }
+}
+
+/// The pages of a bucket, in order, as they were read.
+type Chain = Vec<(PageIndex, Bucket)>;
+
+impl HashIndex {
+    pub fn open(files: Arc<FileManager>, name: &str) -> Result<HashIndex, Error> {
+        let file = files.open(name, FileKind::Hash)?;
+
+        // All-zero meta, directory and bucket pages are an empty index, so a
+        // new index only needs them to exist.
+        while files.page_count(file)? <= FIRST_BUCKET {
+            files.allocate(file)?;
+        }
+
+        Ok(HashIndex { files, file })
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    /// The longest key and value, together, that an entry can have.
+    pub fn max_entry_len() -> usize {
+        bucket::max_entry_len(body_len())
+    }
+
+    /// Add an entry, returning whether it wasn't already there. Any number of
+    /// entries can have the same key, as long as their values differ.
+    pub fn insert(&self, txn: &Txn<'_>, key: &[u8], value: &[u8]) -> Result<bool, Error> {
+        let len = key.len() + value.len();
+        if len > HashIndex::max_entry_len() {
+            return Err(Error::EntryTooLarge {
+                len,
+                max: HashIndex::max_entry_len(),
+            });
+        }
+
+        self.modify(txn, Op::Insert(key, value))
+    }
+
+    /// Remove an entry, returning whether it was there.
+    pub fn delete(&self, txn: &Txn<'_>, key: &[u8], value: &[u8]) -> Result<bool, Error> {
+        self.modify(txn, Op::Delete(key, value))
+    }
+
+    /// The values of every entry with this key, in no particular order.
+    pub fn get(&self, key: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+        let meta_ref = self.files.latest(self.file, META_PAGE)?;
+        let meta = meta_ref.read();
+        let page = self.lookup(&meta, hash(key))?;
+
+        let bucket_ref = self.files.latest(self.file, page)?;
+        let buf = bucket_ref.read();
+        drop(meta);
+
+        let mut values = Vec::new();
+        let mut next = page;
+
+        while next != 0 {
+            let (overflow, found) = if next == page {
+                read_matches(&buf[PAGE_HEADER_SIZE..], key)
+            } else {
+                read_matches(&self.files.latest(self.file, next)?.read()[PAGE_HEADER_SIZE..], key)
+            };
+
+            values.extend(found);
+            next = overflow;
+        }
+
+        Ok(values)
+    }
+
+    fn modify(&self, txn: &Txn<'_>, op: Op<'_>) -> Result<bool, Error> {
+        if let Some(changed) = self.modify_bucket(txn, op)? {
+            return Ok(changed);
+        }
+
+        self.modify_locked(txn, op)
+    }
+
+    /// Apply `op` to its bucket with just a read latch on the meta page.
+    /// Returns `None` if that would take splitting the bucket, or allocating
+    /// or freeing a page, without changing anything.
+    fn modify_bucket(&self, txn: &Txn<'_>, op: Op<'_>) -> Result<Option<bool>, Error> {
+        let meta_ref = self.files.latest(self.file, META_PAGE)?;
+        let meta = meta_ref.read();
+        let page = self.lookup(&meta, hash(op.entry().0))?;
+
+        let bucket_ref = self.files.dirty(self.file, page)?;
+        let mut buf = bucket_ref.write();
+        drop(meta);
+
+        let mut chain = self.read_chain(page, &buf)?;
+        self.apply(txn, page, &mut buf, &mut chain, op)
+    }
+
+    /// Apply `op` holding a write latch on the meta page the whole time, so
+    /// that the bucket can be split or grow overflow pages.
+    fn modify_locked(&self, txn: &Txn<'_>, op: Op<'_>) -> Result<bool, Error> {
+        let meta_ref = self.files.dirty(self.file, META_PAGE)?;
+        let mut meta = meta_ref.write();
+        let hash = hash(op.entry().0);
+
+        loop {
+            let page = self.lookup(&meta, hash)?;
+            let bucket_ref = self.files.dirty(self.file, page)?;
+            let mut buf = bucket_ref.write();
+
+            let mut chain = self.read_chain(page, &buf)?;
+            if let Some(changed) = self.apply(txn, page, &mut buf, &mut chain, op)? {
+                return Ok(changed);
+            }
+
+            match op {
+                Op::Insert(key, value) => {
+                    let depth = chain[0].1.depth;
+                    if depth < max_depth() {
+                        // Splitting might not make any room in the half this
+                        // entry belongs in, so look it up again.
+                        self.split(txn, &mut meta, hash, &mut buf, chain)?;
+                        continue;
+                    }
+
+                    let overflow = self.allocate(txn, &mut meta)?;
+                    let mut bucket = Bucket::new(depth);
+                    bucket.entries.push((key.to_vec(), value.to_vec()));
+                    self.write_bucket(txn, page, &mut buf, overflow, &bucket)?;
+
+                    let (last, bucket) = chain.last_mut().unwrap();
+                    bucket.overflow = overflow;
+                    self.write_bucket(txn, page, &mut buf, *last, bucket)?;
+                },
+                Op::Delete(key, value) => {
+                    // The only thing `apply` leaves for us is an overflow page
+                    // that the entry was the last one in.
+                    let index = chain
+                        .iter()
+                        .position(|(_, bucket)| bucket.position(key, value).is_some())
+                        .expect("Expected to delete the last entry of an overflow page");
+                    let (empty, emptied) = chain.remove(index);
+
+                    let (before, bucket) = &mut chain[index - 1];
+                    bucket.overflow = emptied.overflow;
+                    self.write_bucket(txn, page, &mut buf, *before, bucket)?;
+                    self.free(txn, &mut meta, empty)?;
+                },
+            }
+
+            return Ok(true);
+        }
+    }
+
+    /// Apply `op` to a bucket, write latched through `buf`, if it doesn't take
+    /// allocating or freeing a page. Returns `None` if it does, without
+    /// changing anything.
+    fn apply(
+        &self,
+        txn: &Txn<'_>,
+        page: PageIndex,
+        buf: &mut [u8],
+        chain: &mut Chain,
+        op: Op<'_>,
+    ) -> Result<Option<bool>, Error> {
+        let (key, value) = op.entry();
+        let found = chain
+            .iter()
+            .enumerate()
+            .find_map(|(index, (_, bucket))| Some((index, bucket.position(key, value)?)));
+
+        match op {
+            Op::Insert(..) => {
+                if found.is_some() {
+                    return Ok(Some(false));
+                }
+
+                let Some((overflow, bucket)) = chain
+                    .iter_mut()
+                    .find(|(_, bucket)| bucket.fits(body_len(), key, value))
+                else {
+                    return Ok(None);
+                };
+
+                bucket.entries.push((key.to_vec(), value.to_vec()));
+                self.write_bucket(txn, page, buf, *overflow, bucket)?;
+            },
+            Op::Delete(..) => {
+                let Some((index, position)) = found
+                else {
+                    return Ok(Some(false));
+                };
+
+                let (overflow, bucket) = &mut chain[index];
+                if index > 0 && bucket.entries.len() == 1 {
+                    return Ok(None);
+                }
+
+                bucket.entries.remove(position);
+                self.write_bucket(txn, page, buf, *overflow, bucket)?;
+            },
+        }
+
+        Ok(Some(true))
+    }
+
+    /// Split a bucket, write latched through `buf`, that entries with `key_hash`
+    /// go in, moving the entries with the next bit of their hash set into a new
+    /// bucket.
+    fn split(
+        &self,
+        txn: &Txn<'_>,
+        meta: &mut [u8],
+        key_hash: u32,
+        buf: &mut [u8],
+        chain: Chain,
+    ) -> Result<(), Error> {
+        let depth = chain[0].1.depth;
+        if depth == meta[PAGE_HEADER_SIZE] {
+            self.grow(txn, meta)?;
+        }
+
+        let new_page = self.allocate(txn, meta)?;
+        let pages: Vec<PageIndex> = chain.iter().map(|&(page, _)| page).collect();
+        let (stay, moved): (Vec<_>, Vec<_>) = chain
+            .into_iter()
+            .flat_map(|(_, bucket)| bucket.entries)
+            .partition(|(key, _)| hash(key) >> depth & 1 == 0);
+
+        self.write_chain(txn, meta, &pages, Some(buf), depth + 1, stay)?;
+        self.write_chain(txn, meta, &[new_page], None, depth + 1, moved)?;
+
+        // Every slot that ends in the bits this bucket had, and then a 1.
+        let global_depth = meta[PAGE_HEADER_SIZE];
+        let low_bits = key_hash as usize & ((1 << depth) - 1);
+        let slots = (0..1usize << (global_depth - depth - 1))
+            .map(|high_bits| (high_bits << (depth + 1) | 1 << depth | low_bits, new_page));
+
+        self.set_slots(txn, meta, slots)
+    }
+
+    /// Double the directory, with each new slot pointing at the same bucket as
+    /// the one it was split off of.
+    fn grow(&self, txn: &Txn<'_>, meta: &mut [u8]) -> Result<(), Error> {
+        let global_depth = meta[PAGE_HEADER_SIZE];
+        let len = 1usize << global_depth;
+
+        for index in directory_pages(len)..directory_pages(len * 2) {
+            let page = self.allocate(txn, meta)?;
+            txn.modify_latched(&self.files, self.file, META_PAGE, meta, |body| {
+                write_u64(body, META_HEADER_SIZE + (index - 1) * SLOT_SIZE, page as u64)
+            })?;
+        }
+
+        let slots = self.read_slots(meta, 0..len)?;
+        self.set_slots(txn, meta, slots.into_iter().enumerate().map(|(slot, page)| (len + slot, page)))?;
+
+        txn.modify_latched(&self.files, self.file, META_PAGE, meta, |body| body[0] = global_depth + 1)?;
+        Ok(())
+    }
+
+    /// Pack `entries` into a bucket, reusing the pages it already has (the
+    /// first of which is write latched through `buf`, if it's given) and
+    /// allocating or freeing overflow pages as needed.
+    fn write_chain(
+        &self,
+        txn: &Txn<'_>,
+        meta: &mut [u8],
+        pages: &[PageIndex],
+        mut buf: Option<&mut [u8]>,
+        depth: u8,
+        entries: Vec<(Vec<u8>, Vec<u8>)>,
+    ) -> Result<(), Error> {
+        let mut buckets = vec![Bucket::new(depth)];
+        for (key, value) in entries {
+            if !buckets.last().unwrap().fits(body_len(), &key, &value) {
+                buckets.push(Bucket::new(depth));
+            }
+
+            buckets.last_mut().unwrap().entries.push((key, value));
+        }
+
+        let mut pages = pages.to_vec();
+        while pages.len() < buckets.len() {
+            pages.push(self.allocate(txn, meta)?);
+        }
+        for &page in &pages[buckets.len()..] {
+            self.free(txn, meta, page)?;
+        }
+        let pages = &pages[..buckets.len()];
+
+        for (index, bucket) in buckets.iter_mut().enumerate() {
+            bucket.overflow = pages.get(index + 1).copied().unwrap_or(0);
+
+            match buf.as_deref_mut() {
+                Some(buf) if index == 0 =>
+                    txn.modify_latched(&self.files, self.file, pages[0], buf, |body| bucket.write(body))?,
+                _ => txn.modify(&self.files, self.file, pages[index], |body| bucket.write(body))?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write one page of the bucket whose first page is `page`, write latched
+    /// through `buf`.
+    fn write_bucket(
+        &self,
+        txn: &Txn<'_>,
+        page: PageIndex,
+        buf: &mut [u8],
+        overflow: PageIndex,
+        bucket: &Bucket,
+    ) -> Result<(), Error> {
+        if overflow == page {
+            txn.modify_latched(&self.files, self.file, page, buf, |body| bucket.write(body))?;
+        } else {
+            txn.modify(&self.files, self.file, overflow, |body| bucket.write(body))?;
+        }
+
+        Ok(())
+    }
+
+    /// Every page of the bucket whose first page is `page`, read through `buf`.
+    /// The latch on the first page keeps the rest of them from changing.
+    fn read_chain(&self, page: PageIndex, buf: &[u8]) -> Result<Chain, Error> {
+        let mut chain = vec![(page, Bucket::read(&buf[PAGE_HEADER_SIZE..]))];
+
+        loop {
+            let next = chain.last().unwrap().1.overflow;
+            if next == 0 {
+                return Ok(chain);
+            }
+
+            let bucket = Bucket::read(&self.files.latest(self.file, next)?.read()[PAGE_HEADER_SIZE..]);
+            chain.push((next, bucket));
+        }
+    }
+
+    /// The bucket that entries with this hash go in.
+    fn lookup(&self, meta: &[u8], hash: u32) -> Result<PageIndex, Error> {
+        let mask = (1usize << meta[PAGE_HEADER_SIZE]) - 1;
+        let slot = hash as usize & mask;
+        Ok(self.read_slots(meta, slot..slot + 1)?[0])
+    }
+
+    fn read_slots(&self, meta: &[u8], slots: Range<usize>) -> Result<Vec<PageIndex>, Error> {
+        let per_page = slots_per_page();
+        let mut pages = Vec::with_capacity(slots.len());
+        let mut slot = slots.start;
+
+        while slot < slots.end {
+            let index = slot / per_page;
+            let end = slots.end.min((index + 1) * per_page);
+
+            let directory_ref = self.files.latest(self.file, directory_page(meta, index))?;
+            let directory = directory_ref.read();
+            pages.extend((slot..end).map(|slot| {
+                let at = PAGE_HEADER_SIZE + slot % per_page * SLOT_SIZE;
+                FIRST_BUCKET + read_u64(&directory, at) as PageIndex
+            }));
+
+            slot = end;
+        }
+
+        Ok(pages)
+    }
+
+    /// Point each of the slots at its page, with the slots in order.
+    fn set_slots(
+        &self,
+        txn: &Txn<'_>,
+        meta: &[u8],
+        slots: impl IntoIterator<Item = (usize, PageIndex)>,
+    ) -> Result<(), Error> {
+        let per_page = slots_per_page();
+        let mut slots = slots.into_iter().peekable();
+
+        while let Some(&(slot, _)) = slots.peek() {
+            let index = slot / per_page;
+            let mut group = Vec::new();
+            while let Some((slot, page)) = slots.next_if(|&(slot, _)| slot / per_page == index) {
+                group.push((slot, page));
+            }
+
+            txn.modify(&self.files, self.file, directory_page(meta, index), |body| {
+                for &(slot, page) in &group {
+                    write_u64(body, slot % per_page * SLOT_SIZE, (page - FIRST_BUCKET) as u64);
+                }
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Take a page off the free list, or add one to the end of the file if
+    /// there aren't any.
+    fn allocate(&self, txn: &Txn<'_>, meta: &mut [u8]) -> Result<PageIndex, Error> {
+        let head = read_u64(&meta[PAGE_HEADER_SIZE..], 8) as PageIndex;
+        if head == 0 {
+            return Ok(self.files.allocate(self.file)?);
+        }
+
+        let next = bucket::overflow(&self.files.latest(self.file, head)?.read()[PAGE_HEADER_SIZE..]);
+        txn.modify_latched(&self.files, self.file, META_PAGE, meta, |body| write_u64(body, 8, next as u64))?;
+
+        Ok(head)
+    }
+
+    /// Put an overflow page that nothing points at anymore on the free list.
+    fn free(&self, txn: &Txn<'_>, meta: &mut [u8], page: PageIndex) -> Result<(), Error> {
+        let head = read_u64(&meta[PAGE_HEADER_SIZE..], 8) as PageIndex;
+
+        txn.modify(&self.files, self.file, page, |body| Bucket::write_free(body, head))?;
+        txn.modify_latched(&self.files, self.file, META_PAGE, meta, |body| write_u64(body, 8, page as u64))?;
+
+        Ok(())
+    }
+}
+
+/// The hash that picks an entry's bucket. This is part of the file format, so
+/// it can't change.
+fn hash(key: &[u8]) -> u32 {
+    crc32fast::hash(key)
+}
+
+/// The values of the entries in one page of a bucket with this key, and the
+/// next page of the bucket.
+fn read_matches(body: &[u8], key: &[u8]) -> (PageIndex, Vec<Vec<u8>>) {
+    let values = bucket::entries(body)
+        .filter(|&(k, _)| k == key)
+        .map(|(_, value)| value.to_vec())
+        .collect();
+
+    (bucket::overflow(body), values)
+}
+
+/// The deepest the directory can get: as many slots as fit in page 2 and the
+/// pages that the meta page has room to list, or every bit of the hash.
+fn max_depth() -> u8 {
+    let directory_pages = 1 + (body_len() - META_HEADER_SIZE) / SLOT_SIZE;
+    let slots = directory_pages * slots_per_page();
+    (usize::BITS - 1 - slots.leading_zeros()).min(u32::BITS) as u8
+}
+
+/// How many directory pages `len` slots take.
+fn directory_pages(len: usize) -> usize {
+    len.div_ceil(slots_per_page())
+}
+
+/// The `index`th page of the directory.
+fn directory_page(meta: &[u8], index: usize) -> PageIndex {
+    match index {
+        0 => DIRECTORY_PAGE,
+        _ => read_u64(&meta[PAGE_HEADER_SIZE..], META_HEADER_SIZE + (index - 1) * SLOT_SIZE) as PageIndex,
+    }
+}
+
+fn slots_per_page() -> usize {
+    body_len() / SLOT_SIZE
+}
+
+fn body_len() -> usize {
+    page_size() - PAGE_HEADER_SIZE
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn write_u64(bytes: &mut [u8], at: usize, value: u64) {
+    bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
@@ -0,0 +1,125 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use ferrodb_fs::vfs::MemoryVfs;
+use ferrodb_fs::FileManager;
+use ferrodb_hash::{Error, HashIndex};
+use ferrodb_wal::{recover, Wal};
+
+const PAGE_SIZE: usize = 256;
+
+type Entries = HashMap<Vec<u8>, BTreeSet<Vec<u8>>>;
+
+fn open() -> (Arc<Wal>, Arc<FileManager>, HashIndex) {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let vfs = Arc::new(MemoryVfs::default());
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+
+    recover(&wal, &files, |_, _| {}).unwrap();
+    let index = HashIndex::open(files.clone(), "index").unwrap();
+    (wal, files, index)
+}
+
+fn check(index: &HashIndex, expected: &Entries, keys: impl Iterator<Item = Vec<u8>>) {
+    for key in keys {
+        let values: BTreeSet<_> = index.get(&key).unwrap().into_iter().collect();
+        assert_eq!(values, expected.get(&key).cloned().unwrap_or_default(), "{key:?}");
+    }
+}
+
+fn key(i: u32) -> Vec<u8> {
+    i.to_be_bytes().to_vec()
+}
+
+#[test]
+fn entries_are_found_after_splitting_buckets_and_growing_the_directory() {
+    let (wal, files, index) = open();
+    let mut expected = Entries::new();
+
+    let txn = wal.begin().unwrap();
+    for i in 0..2000 {
+        // A few keys get more than one value.
+        let count = if i % 50 == 0 { 3 } else { 1 };
+        for value in 0..count {
+            assert!(index.insert(&txn, &key(i), &[value; 10]).unwrap());
+            expected.entry(key(i)).or_default().insert(vec![value; 10]);
+        }
+    }
+    assert!(!index.insert(&txn, &key(7), &[0; 10]).unwrap());
+    txn.commit().unwrap();
+
+    // More buckets than there are slots in the directory's first page.
+    assert!(files.page_count(index.file()).unwrap() > PAGE_SIZE / 8);
+    check(&index, &expected, (0..2100).map(key));
+
+    let txn = wal.begin().unwrap();
+    for i in (0..2000).step_by(3) {
+        assert!(index.delete(&txn, &key(i), &[0; 10]).unwrap());
+        let values = expected.get_mut(&key(i)).unwrap();
+        values.remove(&vec![0; 10]);
+        if values.is_empty() {
+            expected.remove(&key(i));
+        }
+    }
+    assert!(!index.delete(&txn, &key(0), &[0; 10]).unwrap());
+    assert!(!index.delete(&txn, &key(1), &[1; 10]).unwrap());
+    txn.commit().unwrap();
+    check(&index, &expected, (0..2100).map(key));
+}
+
+#[test]
+fn a_key_with_too_many_values_for_a_bucket_gets_overflow_pages_that_are_reused() {
+    let (wal, files, index) = open();
+    let values = |key: u8| (0..300u32).map(move |n| [&[key; 4][..], &n.to_le_bytes()].concat());
+
+    let txn = wal.begin().unwrap();
+    for value in values(1) {
+        assert!(index.insert(&txn, b"same", &value).unwrap());
+    }
+    txn.commit().unwrap();
+    assert_eq!(index.get(b"same").unwrap().into_iter().collect::<BTreeSet<_>>(), values(1).collect());
+    let page_count = files.page_count(index.file()).unwrap();
+
+    let txn = wal.begin().unwrap();
+    for value in values(1) {
+        assert!(index.delete(&txn, b"same", &value).unwrap());
+    }
+    for value in values(2) {
+        assert!(index.insert(&txn, b"same", &value).unwrap());
+    }
+    txn.commit().unwrap();
+    assert_eq!(index.get(b"same").unwrap().into_iter().collect::<BTreeSet<_>>(), values(2).collect());
+    assert_eq!(files.page_count(index.file()).unwrap(), page_count);
+}
+
+#[test]
+fn aborting_takes_back_entries_along_with_the_splits_they_caused() {
+    let (wal, files, index) = open();
+    let mut expected = Entries::new();
+
+    let txn = wal.begin().unwrap();
+    for i in 0..100 {
+        index.insert(&txn, &key(i), b"kept").unwrap();
+        expected.entry(key(i)).or_default().insert(b"kept".to_vec());
+    }
+    txn.commit().unwrap();
+
+    let txn = wal.begin().unwrap();
+    for i in 0..1000 {
+        index.insert(&txn, &key(i), b"aborted").unwrap();
+    }
+    for i in 0..50 {
+        index.delete(&txn, &key(i), b"kept").unwrap();
+    }
+    txn.abort(&files).unwrap();
+    check(&index, &expected, (0..1000).map(key));
+
+    let txn = wal.begin().unwrap();
+    let too_long = vec![0; HashIndex::max_entry_len()];
+    assert!(matches!(index.insert(&txn, b"k", &too_long), Err(Error::EntryTooLarge { .. })));
+    assert!(index.insert(&txn, b"k", &too_long[1..]).unwrap());
+    txn.commit().unwrap();
+}
@@ -0,0 +1,16 @@
+use ferrodb_fs::PageIndex;
+use thiserror::Error;
+
+use crate::RecordId;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Fs(#[from] ferrodb_fs::Error),
+    #[error(transparent)]
+    Wal(#[from] ferrodb_wal::Error),
+    #[error("There's no row at {0}")]
+    NoRecord(RecordId),
+    #[error("Overflow page {0} doesn't hold what the row pointing at it expected, so it must be corrupt")]
+    CorruptOverflow(PageIndex),
+}
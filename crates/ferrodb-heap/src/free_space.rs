@@ -0,0 +1,53 @@
+//! Which pages of a heap have room for more rows, as far as the heap knows.
+//! It's only kept in memory: it starts out empty when a heap is opened, and is
+//! filled in as pages are changed, and rebuilt by a vacuum.
+
+use std::collections::BTreeMap;
+
+use ferrodb_fs::PageIndex;
+use parking_lot::Mutex;
+
+/// Pages with less than this much of their body free aren't kept track of,
+/// since they'd hardly ever have room for a row.
+const MIN_FREE_FRACTION: usize = 8;
+
+pub(crate) struct FreeSpaceMap {
+    /// How many bytes of records each page has room for.
+    pages: Mutex<BTreeMap<PageIndex, usize>>,
+    min_free: usize,
+}
+
+impl FreeSpaceMap {
+    pub fn new(body_len: usize) -> FreeSpaceMap {
+        FreeSpaceMap {
+            pages: Mutex::default(),
+            min_free: body_len / MIN_FREE_FRACTION,
+        }
+    }
+
+    /// Remember that `page` has room for `free` bytes of records.
+    pub fn set(&self, page: PageIndex, free: usize) {
+        let mut pages = self.pages.lock();
+        if free >= self.min_free {
+            pages.insert(page, free);
+        } else {
+            pages.remove(&page);
+        }
+    }
+
+    /// The first page that has room for `len` bytes of records, if there's one
+    /// that's known to.
+    pub fn find(&self, len: usize) -> Option<PageIndex> {
+        let pages = self.pages.lock();
+        pages.iter().find(|(_, free)| **free >= len).map(|(page, _)| *page)
+    }
+
+    /// Forget every page from `page_count` on.
+    pub fn truncate(&self, page_count: PageIndex) {
+        self.pages.lock().split_off(&page_count);
+    }
+
+    pub fn clear(&self) {
+        self.pages.lock().clear();
+    }
+}
@@ -0,0 +1,74 @@
+//! Room that transactions which are still running have freed up, in the heap's
+//! pages or on the overflow file's free list, and that they'd need back to roll
+//! back, along with the slots they changed. Rolling back only puts back what
+//! each change took away, rather than the whole page how it was, so nothing
+//! else may use that room, or change those slots, until they're over. It's only kept in memory, since recovery rolls back before anything
+//! else can run.
+
+use std::collections::HashMap;
+
+use ferrodb_fs::PageIndex;
+use ferrodb_wal::{TxnId, Wal};
+use parking_lot::Mutex;
+
+use crate::page::Held;
+
+struct Hold {
+    txn: TxnId,
+    slot: u16,
+    bytes: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct Holds {
+    pages: Mutex<HashMap<PageIndex, Vec<Hold>>>,
+    /// The first page of every chain of overflow pages that's on the free
+    /// list, by the transaction that put it there.
+    chains: Mutex<HashMap<PageIndex, TxnId>>,
+}
+
+impl Holds {
+    /// Keep `bytes` of `page`, and its `slot`, for `txn`.
+    pub fn hold(&self, page: PageIndex, txn: TxnId, slot: u16, bytes: usize) {
+        self.pages.lock().entry(page).or_default().push(Hold { txn, slot, bytes });
+    }
+
+    /// What's held in `page` for transactions other than `txn`, or for any
+    /// transaction if there isn't one.
+    pub fn page(&self, wal: &Wal, page: PageIndex, txn: Option<TxnId>) -> Held {
+        let mut pages = self.pages.lock();
+        let Some(holds) = pages.get_mut(&page)
+            else { return Held::default(); };
+
+        holds.retain(|hold| wal.is_running(hold.txn));
+        let mut held = Held::default();
+        for hold in holds.iter().filter(|hold| Some(hold.txn) != txn) {
+            held.bytes += hold.bytes;
+            held.slots.push(hold.slot);
+        }
+
+        if holds.is_empty() {
+            pages.remove(&page);
+        }
+        held
+    }
+
+    /// Keep the chain of overflow pages starting at `first` for `txn`.
+    pub fn hold_chain(&self, first: PageIndex, txn: TxnId) {
+        self.chains.lock().insert(first, txn);
+    }
+
+    /// Whether the chain of overflow pages starting at `first` is held for a
+    /// transaction other than `txn`.
+    pub fn chain_held(&self, wal: &Wal, first: PageIndex, txn: TxnId) -> bool {
+        let mut chains = self.chains.lock();
+        match chains.get(&first) {
+            Some(holder) if !wal.is_running(*holder) => {
+                chains.remove(&first);
+                false
+            },
+            Some(holder) => *holder != txn,
+            None => false,
+        }
+    }
+}
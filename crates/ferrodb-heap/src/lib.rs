@@ -0,0 +1,387 @@
+//! Tables stored as heap files: rows in no particular order, in slotted pages,
+//! each addressed by a [`RecordId`] that stays the same for as long as the row
+//! exists.
+//!
+//! Rows too long to keep a few of in a page go in a second file next to the
+//! heap, `<name>.overflow`, split over as many pages as they need, and
+//! optionally compressed.
+//!
+//! Every change goes through a [`Txn`], so it's logged and can be rolled back
+//! or recovered like any other, once [`HeapFile::register_undo`] has set up
+//! how. There's no locking of rows, so callers are expected to keep concurrent
+//! transactions away from the same rows, but they can share pages: rolling
+//! back only puts back the rows that the transaction changed.
+//!
+//! Deleting or updating a row frees up its space in the page for other rows
+//! once its transaction is over, but only for rows in that page, and nothing
+//! ever gives pages back. That's what [`HeapFile::vacuum`] is for, which
+//! changes wait for.
+
+mod error;
+mod free_space;
+mod hold;
+mod overflow;
+mod page;
+mod scan;
+mod undo;
+mod vacuum;
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use ferrodb_fs::{Compression, FileId, FileKind, FileManager, PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_page::page_size;
+use ferrodb_wal::Txn;
+use parking_lot::RwLock;
+
+pub use self::error::Error;
+pub use self::overflow::RowReader;
+use self::overflow::Pointer;
+use self::page::{Held, Record, Value};
+pub use self::scan::HeapScan;
+pub use self::vacuum::VacuumStats;
+use self::free_space::FreeSpaceMap;
+use self::hold::Holds;
+
+/// Where a row lives: its home page and slot.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RecordId {
+    pub page: PageIndex,
+    pub slot: u16,
+}
+
+impl RecordId {
+    /// How many bytes a record ID takes up as bytes.
+    pub const LEN: usize = 10;
+
+    /// The record ID as bytes that sort the same way it does, like for the
+    /// value of an index entry.
+    pub fn to_bytes(self) -> [u8; RecordId::LEN] {
+        let mut bytes = [0; RecordId::LEN];
+        bytes[..8].copy_from_slice(&(self.page as u64).to_be_bytes());
+        bytes[8..].copy_from_slice(&self.slot.to_be_bytes());
+        bytes
+    }
+
+    /// The record ID that [`RecordId::to_bytes`] gave `bytes`, if it did.
+    pub fn from_bytes(bytes: &[u8]) -> Option<RecordId> {
+        let bytes: &[u8; RecordId::LEN] = bytes.try_into().ok()?;
+        Some(RecordId {
+            page: u64::from_be_bytes(bytes[..8].try_into().unwrap()) as PageIndex,
+            slot: u16::from_be_bytes(bytes[8..].try_into().unwrap()),
+        })
+    }
+}
+
+impl fmt::Display for RecordId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({}, {})", self.page, self.slot)
+    }
+}
+
+/// Where an existing row is kept.
+struct Found {
+    /// The slot that the row was moved to, if it's not in its home slot.
+    forwarded: Option<RecordId>,
+    /// Where the row is in the overflow file, if it's there.
+    overflow: Option<Pointer>,
+}
+
+pub struct HeapFile {
+    files: Arc<FileManager>,
+    file: FileId,
+    overflow: FileId,
+    /// What rows in the overflow file are compressed with.
+    compression: Compression,
+    /// The page that the last row was inserted into, which is where the next
+    /// insert tries first.
+    insert_hint: AtomicUsize,
+    /// Where inserts go once the `insert_hint` page is full.
+    free_space: FreeSpaceMap,
+    /// Room that transactions which are still running freed up, and might
+    /// need back.
+    holds: Holds,
+    /// How many rows have been updated or deleted since the heap was opened,
+    /// or last vacuumed.
+    changes: AtomicUsize,
+    /// Held for reading by every change to the heap, and for writing by
+    /// vacuuming and truncating it, which move rows and pages out from under
+    /// them.
+    vacuuming: RwLock<()>,
+}
+
+impl HeapFile {
+    pub fn open(files: Arc<FileManager>, name: &str) -> Result<HeapFile, Error> {
+        let file = files.open(name, FileKind::Heap)?;
+        let last_page = files.page_count(file)? - 1;
+
+        // Page 1 of the overflow file is where its free list starts.
+        let overflow = files.open(&overflow_name(name), FileKind::Overflow)?;
+        while files.page_count(overflow)? <= 1 {
+            files.allocate(overflow)?;
+        }
+
+        Ok(HeapFile {
+            files,
+            file,
+            overflow,
+            compression: Compression::None,
+            // Page 0 is the file header, so rows start on page 1.
+            insert_hint: AtomicUsize::new(last_page.max(1)),
+            free_space: FreeSpaceMap::new(page_size() - PAGE_HEADER_SIZE),
+            holds: Holds::default(),
+            changes: AtomicUsize::new(0),
+            vacuuming: RwLock::new(()),
+        })
+    }
+
+    /// Delete the files of the heap called `name`, which mustn't be open.
+    pub fn delete_files(files: &FileManager, name: &str) -> Result<(), Error> {
+        files.delete(files.id(name))?;
+        files.delete(files.id(&overflow_name(name)))?;
+        Ok(())
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    /// The file that rows too long to keep in the heap's pages go in.
+    pub fn overflow_file(&self) -> FileId {
+        self.overflow
+    }
+
+    /// Compress rows that go in the overflow file from now on. Rows that are
+    /// already there stay how they are.
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// How many rows have been updated or deleted since the heap was opened,
+    /// or last vacuumed, which is roughly how much a vacuum has to do.
+    pub fn changes_since_vacuum(&self) -> usize {
+        self.changes.load(Ordering::Relaxed)
+    }
+
+    /// The longest row that's kept in the heap's own pages, rather than in the
+    /// overflow file.
+    pub fn max_inline_len() -> usize {
+        page::max_inline_len(page_size() - PAGE_HEADER_SIZE)
+    }
+
+    pub fn insert(&self, txn: &Txn<'_>, row: &[u8]) -> Result<RecordId, Error> {
+        let _vacuuming = self.vacuuming.read();
+        let value = self.store(txn, row)?;
+        self.insert_record(txn, &Record::Row(value))
+    }
+
+    pub fn get(&self, rid: RecordId) -> Result<Option<Vec<u8>>, Error> {
+        self.open_row(rid)?.map(RowReader::read_all).transpose()
+    }
+
+    /// Start reading the row at `rid`, without reading all of it up front.
+    pub fn open_row(&self, rid: RecordId) -> Result<Option<RowReader<'_>>, Error> {
+        match self.read(rid, |record| match record {
+            Some(Record::Row(value)) => Ok(Some(RowReader::new(self, value))),
+            Some(Record::Forward(target)) => Err(target),
+            Some(Record::Moved(_)) | None => Ok(None),
+        })? {
+            Ok(row) => Ok(row),
+            Err(target) => self.open_moved(target),
+        }
+    }
+
+    /// Replace the row at `rid`. If it doesn't fit in its page anymore, it's
+    /// moved somewhere else and left a forwarding pointer, so `rid` stays valid.
+    pub fn update(&self, txn: &Txn<'_>, rid: RecordId, row: &[u8]) -> Result<(), Error> {
+        let _vacuuming = self.vacuuming.read();
+        let Found { forwarded, overflow } = self.find(rid)?.ok_or(Error::NoRecord(rid))?;
+        let value = self.store(txn, row)?;
+        self.changes.fetch_add(1, Ordering::Relaxed);
+
+        let replaced = match forwarded {
+            None => self.modify(txn, rid.page, |body, held| {
+                (page::replace(body, rid.slot, &Record::Row(value), held), Some(rid.slot))
+            })?,
+            Some(target) => self.modify(txn, target.page, |body, held| {
+                (page::replace(body, target.slot, &Record::Moved(value), held), Some(target.slot))
+            })?,
+        };
+        if replaced {
+            return self.free_value(txn, overflow);
+        }
+
+        let target = self.insert_record(txn, &Record::Moved(value))?;
+        let replaced = self.modify(txn, rid.page, |body, held| {
+            (page::replace(body, rid.slot, &Record::Forward(target), held), Some(rid.slot))
+        })?;
+        assert!(replaced, "A forwarding pointer always fits in place of a row");
+
+        if let Some(old_target) = forwarded {
+            self.remove(txn, old_target)?;
+        }
+
+        self.free_value(txn, overflow)
+    }
+
+    /// Delete the row at `rid`, returning whether there was one.
+    pub fn delete(&self, txn: &Txn<'_>, rid: RecordId) -> Result<bool, Error> {
+        let _vacuuming = self.vacuuming.read();
+        let Some(Found { forwarded, overflow }) = self.find(rid)?
+            else { return Ok(false); };
+        self.changes.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(target) = forwarded {
+            self.remove(txn, target)?;
+        }
+        self.remove(txn, rid)?;
+
+        self.free_value(txn, overflow)?;
+        Ok(true)
+    }
+
+    /// Every row in the heap, in storage order.
+    pub fn scan(&self) -> Result<HeapScan<'_>, Error> {
+        HeapScan::new(self)
+    }
+
+    /// Insert a record into the `insert_hint` page if it fits, and otherwise
+    /// into the first page that the free space map says it fits in, or a new
+    /// page if there isn't one.
+    fn insert_record(&self, txn: &Txn<'_>, record: &Record<'_>) -> Result<RecordId, Error> {
+        let len = page::reserved(record.encoded_len());
+        let mut page = self.insert_hint.load(Ordering::Relaxed);
+
+        loop {
+            let slot = self.modify(txn, page, |body, held| {
+                let slot = page::insert(body, record, held);
+                (slot, slot)
+            })?;
+
+            if let Some(slot) = slot {
+                self.insert_hint.store(page, Ordering::Relaxed);
+                return Ok(RecordId { page, slot });
+            }
+
+            // The page's free space was just brought up to date, so it can't
+            // come up again.
+            page = match self.free_space.find(len) {
+                Some(page) => page,
+                None => self.files.allocate(self.file)?,
+            };
+        }
+    }
+
+    fn open_moved(&self, rid: RecordId) -> Result<Option<RowReader<'_>>, Error> {
+        self.read(rid, |record| match record {
+            Some(Record::Moved(value)) => Some(RowReader::new(self, value)),
+            _ => None,
+        })
+    }
+
+    /// Where the row at `rid` is, or `None` if there isn't one.
+    fn find(&self, rid: RecordId) -> Result<Option<Found>, Error> {
+        let forwarded = self.read(rid, |record| match record {
+            Some(Record::Row(value)) => Some(Err(value.overflow())),
+            Some(Record::Forward(target)) => Some(Ok(target)),
+            Some(Record::Moved(_)) | None => None,
+        })?;
+
+        match forwarded {
+            None => Ok(None),
+            Some(Err(overflow)) => Ok(Some(Found {
+                forwarded: None,
+                overflow,
+            })),
+            Some(Ok(target)) => {
+                let overflow = self.read(target, |record| match record {
+                    Some(Record::Moved(value)) => value.overflow(),
+                    _ => None,
+                })?;
+
+                Ok(Some(Found {
+                    forwarded: Some(target),
+                    overflow,
+                }))
+            },
+        }
+    }
+
+    /// How `row` should be kept in its record, writing it to the overflow file
+    /// if it's too long to be kept in the record itself.
+    fn store<'r>(&self, txn: &Txn<'_>, row: &'r [u8]) -> Result<Value<'r>, Error> {
+        if row.len() <= HeapFile::max_inline_len() {
+            return Ok(Value::Inline(row));
+        }
+
+        Ok(Value::Overflow(self.write_overflow(txn, row)?))
+    }
+
+    /// Let go of the overflow pages of a row that was replaced or deleted.
+    fn free_value(&self, txn: &Txn<'_>, pointer: Option<Pointer>) -> Result<(), Error> {
+        match pointer {
+            Some(pointer) => self.free_overflow(txn, pointer),
+            None => Ok(()),
+        }
+    }
+
+    /// Look at the record at `rid`, if there's one.
+    fn read<T>(&self, rid: RecordId, f: impl FnOnce(Option<Record<'_>>) -> T) -> Result<T, Error> {
+        if rid.page == 0 || rid.page >= self.files.page_count(self.file)? {
+            return Ok(f(None));
+        }
+
+        self.read_page(rid.page, |body| f(page::get(body, rid.slot)))
+    }
+
+    fn read_page<T>(&self, page: PageIndex, f: impl FnOnce(&[u8]) -> T) -> Result<T, Error> {
+        let page_ref = self.files.latest(self.file, page)?;
+        let buf = page_ref.read();
+        Ok(f(&buf[PAGE_HEADER_SIZE..]))
+    }
+
+    /// Change one slot of a page, which `f` returns along with its result,
+    /// keeping the free space map up to date with it. `f` has to leave alone
+    /// what's held in the page for other transactions. Rolling back only puts
+    /// back what was in the slot, so whatever room that took up is held for
+    /// this transaction until it's over, along with the slot.
+    fn modify<T>(
+        &self,
+        txn: &Txn<'_>,
+        page: PageIndex,
+        f: impl FnOnce(&mut [u8], &Held) -> (T, Option<u16>),
+    ) -> Result<T, Error> {
+        let page_ref = self.files.dirty(self.file, page)?;
+        let mut buf = page_ref.write();
+
+        let held = self.holds.page(txn.wal(), page, Some(txn.id()));
+        let (result, freed) = txn.modify_logical(&self.files, self.file, page, &mut buf, |body| {
+            let before = body.to_vec();
+            let (result, slot) = f(body, &held);
+            let Some(slot) = slot
+                else { return ((result, None), vec![]); };
+
+            let freed = page::taken(&before, slot).saturating_sub(page::taken(body, slot));
+            ((result, Some((slot, freed))), undo::put(slot, page::raw(&before, slot)))
+        })?;
+
+        // Still under the page latch, so nothing else can take it first. The
+        // slot is held even if no room was freed, so that vacuuming leaves it be.
+        if let Some((slot, freed)) = freed {
+            self.holds.hold(page, txn.id(), slot, freed);
+        }
+
+        let held = self.holds.page(txn.wal(), page, None);
+        self.free_space.set(page, page::free_space(&buf[PAGE_HEADER_SIZE..], &held));
+        Ok(result)
+    }
+
+    fn remove(&self, txn: &Txn<'_>, rid: RecordId) -> Result<(), Error> {
+        self.modify(txn, rid.page, |body, _| (page::remove(body, rid.slot), Some(rid.slot)))
+    }
+}
+
+fn overflow_name(name: &str) -> String {
+    format!("{name}.overflow")
+}
@@ -0,0 +1,325 @@
+//! Rows too long to keep in a heap page go in the heap's overflow file, as a
+//! chain of pages, each with a body of:
+//!
+//! ```text
+//! [next page: u64][len: u16][unused: [u8; 6]][len bytes of the row]
+//! ```
+//!
+//! The bytes in a chain are the row in chunks of up to [`CHUNK_LEN`] bytes,
+//! each of them `[stored len: u32][len: u32][stored bytes]`, so that a row can
+//! be read back a chunk at a time. A chunk is compressed with the heap's
+//! compression, unless that doesn't make it any shorter, in which case it's
+//! stored as it is and its stored len is the same as its len.
+//!
+//! Page 1 of the overflow file holds the first free page as a `u64`, or 0 if
+//! there aren't any, and free pages point at the next one as their `next`.
+//! Rows are freed a chain at a time, onto the front of the list, and pages are
+//! only taken off the front of it, but not past a chain that a transaction
+//! that's still running freed, which it might want back.
+
+use std::io::{self, Read};
+
+use ferrodb_fs::{Compression, PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_page::page_size;
+use ferrodb_wal::Txn;
+
+use crate::page::Value;
+use crate::{undo, Error, HeapFile};
+
+pub(crate) const META_PAGE: PageIndex = 1;
+const HEADER_SIZE: usize = 16;
+
+const CHUNK_LEN: usize = 64 * 1024;
+const CHUNK_HEADER_SIZE: usize = 8;
+
+/// Where a row in the overflow file is, as kept in its record.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Pointer {
+    pub first: PageIndex,
+    pub len: u64,
+    /// The [`Compression::codec`] that its chunks were compressed with.
+    pub codec: u8,
+}
+
+impl Pointer {
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&(self.first as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.len.to_le_bytes());
+        bytes.push(self.codec);
+    }
+
+    pub fn decode(bytes: &[u8]) -> Pointer {
+        Pointer {
+            first: read_u64(bytes, 0) as PageIndex,
+            len: read_u64(bytes, 8),
+            codec: bytes[16],
+        }
+    }
+}
+
+impl HeapFile {
+    /// Write a row out to a new chain of pages of the overflow file.
+    pub(crate) fn write_overflow(&self, txn: &Txn<'_>, row: &[u8]) -> Result<Pointer, Error> {
+        let mut bytes = Vec::with_capacity(row.len() + CHUNK_HEADER_SIZE);
+        for chunk in row.chunks(CHUNK_LEN) {
+            let compressed = match self.compression {
+                Compression::None => None,
+                compression => Some(compression.compress(chunk).map_err(ferrodb_fs::Error::from)?),
+            };
+            let stored = match &compressed {
+                Some(compressed) if compressed.len() < chunk.len() => compressed,
+                _ => chunk,
+            };
+
+            bytes.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(stored);
+        }
+
+        let parts: Vec<&[u8]> = bytes.chunks(body_len() - HEADER_SIZE).collect();
+        let pages = self.allocate_overflow(txn, parts.len())?;
+
+        for (index, part) in parts.iter().enumerate() {
+            let next = pages.get(index + 1).copied().unwrap_or(0);
+
+            txn.modify(&self.files, self.overflow, pages[index], |body| {
+                body.fill(0);
+                write_u64(body, 0, next as u64);
+                body[8..10].copy_from_slice(&(part.len() as u16).to_le_bytes());
+                body[HEADER_SIZE..HEADER_SIZE + part.len()].copy_from_slice(part);
+            })?;
+        }
+
+        Ok(Pointer {
+            first: pages[0],
+            len: row.len() as u64,
+            codec: self.compression.codec(),
+        })
+    }
+
+    /// Put every page of a row in the overflow file on the free list.
+    pub(crate) fn free_overflow(&self, txn: &Txn<'_>, pointer: Pointer) -> Result<(), Error> {
+        let mut last = pointer.first;
+        loop {
+            let next = read_u64(&self.files.latest(self.overflow, last)?.read()[PAGE_HEADER_SIZE..], 0);
+            if next == 0 {
+                break;
+            }
+
+            last = next as PageIndex;
+        }
+
+        let meta_ref = self.files.dirty(self.overflow, META_PAGE)?;
+        let mut meta = meta_ref.write();
+        let head = read_u64(&meta[PAGE_HEADER_SIZE..], 0);
+
+        let last_ref = self.files.dirty(self.overflow, last)?;
+        txn.modify_logical(&self.files, self.overflow, last, &mut last_ref.write(), |body| {
+            write_u64(body, 0, head);
+            ((), undo::end_chain(last))
+        })?;
+        txn.modify_logical(&self.files, self.overflow, META_PAGE, &mut meta, |body| {
+            write_u64(body, 0, pointer.first as u64);
+            ((), undo::unlink(pointer.first, last))
+        })?;
+
+        // The row comes back in these pages if the transaction rolls back.
+        self.holds.hold_chain(pointer.first, txn.id());
+        Ok(())
+    }
+
+    /// Take `count` pages off the free list, adding pages to the end of the
+    /// file once there aren't any more.
+    fn allocate_overflow(&self, txn: &Txn<'_>, count: usize) -> Result<Vec<PageIndex>, Error> {
+        let meta_ref = self.files.dirty(self.overflow, META_PAGE)?;
+        let mut meta = meta_ref.write();
+
+        let mut pages = Vec::with_capacity(count);
+        let mut head = read_u64(&meta[PAGE_HEADER_SIZE..], 0) as PageIndex;
+
+        // Chains that were freed by transactions that are still running stay
+        // where they are, along with every page after them.
+        while pages.len() < count && head != 0 && !self.holds.chain_held(txn.wal(), head, txn.id()) {
+            pages.push(head);
+            head = read_u64(&self.files.latest(self.overflow, head)?.read()[PAGE_HEADER_SIZE..], 0) as PageIndex;
+        }
+
+        if let (Some(first), Some(last)) = (pages.first(), pages.last()) {
+            txn.modify_logical(&self.files, self.overflow, META_PAGE, &mut meta, |body| {
+                write_u64(body, 0, head as u64);
+                ((), undo::push(*first, *last))
+            })?;
+        }
+
+        while pages.len() < count {
+            pages.push(self.files.allocate(self.overflow)?);
+        }
+
+        Ok(pages)
+    }
+}
+
+/// Reads a row back a chunk at a time, so that a row in the overflow file
+/// never has to be in memory all at once.
+pub struct RowReader<'h> {
+    heap: &'h HeapFile,
+    len: u64,
+    codec: u8,
+    /// The overflow page that `stored` came from, and the one after it, which
+    /// is 0 at the end of the chain.
+    page: PageIndex,
+    next_page: PageIndex,
+    /// What's left of the current overflow page.
+    stored: Vec<u8>,
+    stored_pos: usize,
+    /// What's left of the current chunk of the row.
+    chunk: Vec<u8>,
+    chunk_pos: usize,
+    /// How much of the row is in chunks that haven't been read yet.
+    remaining: u64,
+}
+
+impl<'h> RowReader<'h> {
+    pub(crate) fn new(heap: &'h HeapFile, value: Value<'_>) -> RowReader<'h> {
+        let reader = RowReader {
+            heap,
+            len: 0,
+            codec: 0,
+            page: 0,
+            next_page: 0,
+            stored: vec![],
+            stored_pos: 0,
+            chunk: vec![],
+            chunk_pos: 0,
+            remaining: 0,
+        };
+
+        match value {
+            Value::Inline(row) => RowReader {
+                len: row.len() as u64,
+                chunk: row.to_vec(),
+                ..reader
+            },
+            Value::Overflow(pointer) => RowReader {
+                len: pointer.len,
+                codec: pointer.codec,
+                next_page: pointer.first,
+                remaining: pointer.len,
+                ..reader
+            },
+        }
+    }
+
+    /// How long the whole row is.
+    pub fn row_len(&self) -> u64 {
+        self.len
+    }
+
+    /// Read the rest of the row.
+    pub fn read_all(mut self) -> Result<Vec<u8>, Error> {
+        let mut row = Vec::with_capacity(self.len as usize);
+
+        loop {
+            row.extend_from_slice(&self.chunk[self.chunk_pos..]);
+            if self.remaining == 0 {
+                return Ok(row);
+            }
+
+            self.next_chunk()?;
+        }
+    }
+
+    fn next_chunk(&mut self) -> Result<(), Error> {
+        let header = self.take(CHUNK_HEADER_SIZE)?;
+        let stored_len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let len = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+
+        if len as u64 > self.remaining || stored_len > len {
+            return Err(Error::CorruptOverflow(self.page));
+        }
+
+        let stored = self.take(stored_len)?;
+        self.chunk = if stored_len == len {
+            stored
+        } else {
+            let mut chunk = vec![0; len];
+            let decompressed = match Compression::from_codec(self.codec, 0) {
+                Some(compression) => compression.decompress(&stored, &mut chunk),
+                None => false,
+            };
+            if !decompressed {
+                return Err(Error::CorruptOverflow(self.page));
+            }
+
+            chunk
+        };
+
+        self.chunk_pos = 0;
+        self.remaining -= len as u64;
+        Ok(())
+    }
+
+    /// Read the next `len` bytes of the chain.
+    fn take(&mut self, len: usize) -> Result<Vec<u8>, Error> {
+        let mut bytes = Vec::with_capacity(len);
+
+        while bytes.len() < len {
+            if self.stored_pos == self.stored.len() {
+                self.next_page()?;
+            }
+
+            let n = (len - bytes.len()).min(self.stored.len() - self.stored_pos);
+            bytes.extend_from_slice(&self.stored[self.stored_pos..self.stored_pos + n]);
+            self.stored_pos += n;
+        }
+
+        Ok(bytes)
+    }
+
+    fn next_page(&mut self) -> Result<(), Error> {
+        if self.next_page == 0 {
+            return Err(Error::CorruptOverflow(self.page));
+        }
+
+        let page_ref = self.heap.files.latest(self.heap.overflow, self.next_page)?;
+        let buf = page_ref.read();
+        let body = &buf[PAGE_HEADER_SIZE..];
+
+        let len = u16::from_le_bytes(body[8..10].try_into().unwrap()) as usize;
+        if len == 0 || HEADER_SIZE + len > body.len() {
+            return Err(Error::CorruptOverflow(self.next_page));
+        }
+
+        self.page = self.next_page;
+        self.next_page = read_u64(body, 0) as PageIndex;
+        self.stored = body[HEADER_SIZE..HEADER_SIZE + len].to_vec();
+        self.stored_pos = 0;
+        Ok(())
+    }
+}
+
+impl Read for RowReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.chunk_pos == self.chunk.len() && self.remaining > 0 {
+            self.next_chunk()
+                .map_err(io::Error::other)?;
+        }
+
+        let n = buf.len().min(self.chunk.len() - self.chunk_pos);
+        buf[..n].copy_from_slice(&self.chunk[self.chunk_pos..self.chunk_pos + n]);
+        self.chunk_pos += n;
+        Ok(n)
+    }
+}
+
+fn body_len() -> usize {
+    page_size() - PAGE_HEADER_SIZE
+}
+
+pub(crate) fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+pub(crate) fn write_u64(bytes: &mut [u8], at: usize, value: u64) {
+    bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
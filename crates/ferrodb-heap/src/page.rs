@@ -0,0 +1,358 @@
+//! The body of a heap page (everything past the page header) is a slotted
+//! page:
+//!
+//! ```text
+//! [slot count: u16][free space end: u16]([offset: u16][len: u16]) * slot count
+//! ...free space...
+//! records, packed against the end of the page
+//! ```
+//!
+//! A slot with offset 0 is unused, and a free space end of 0 means the end of
+//! the page, so an all-zero page is an empty page. Every record starts with a
+//! tag byte saying what it is. A row that's too long to keep in the page is
+//! in the heap's overflow file, and its record only has a pointer to it.
+//!
+//! Every record takes up at least [`MIN_RECORD_SIZE`] bytes of the page, even
+//! if it's shorter, so that a row can always be replaced in place by a
+//! forwarding pointer when it grows too big for its page.
+
+use ferrodb_fs::PageIndex;
+
+use crate::overflow::Pointer;
+use crate::RecordId;
+
+const HEADER_SIZE: usize = 4;
+const SLOT_SIZE: usize = 4;
+
+const TAG_ROW: u8 = 0;
+const TAG_FORWARD: u8 = 1;
+const TAG_MOVED: u8 = 2;
+const TAG_ROW_OVERFLOW: u8 = 3;
+const TAG_MOVED_OVERFLOW: u8 = 4;
+
+const FORWARD_SIZE: usize = 1 + 8 + 2;
+const MIN_RECORD_SIZE: usize = FORWARD_SIZE;
+
+pub(crate) enum Record<'p> {
+    /// A row that lives in its home slot.
+    Row(Value<'p>),
+    /// The row at this slot has moved to another slot.
+    Forward(RecordId),
+    /// A row that was moved here from its home slot, which has a [`Record::Forward`]
+    /// pointing at this one.
+    Moved(Value<'p>),
+}
+
+/// A row, as it's kept in its record.
+#[derive(Copy, Clone)]
+pub(crate) enum Value<'p> {
+    Inline(&'p [u8]),
+    Overflow(Pointer),
+}
+
+impl Value<'_> {
+    pub fn overflow(&self) -> Option<Pointer> {
+        match *self {
+            Value::Inline(_) => None,
+            Value::Overflow(pointer) => Some(pointer),
+        }
+    }
+
+    fn encode(&self, inline_tag: u8, overflow_tag: u8) -> Vec<u8> {
+        match self {
+            Value::Inline(row) => [&[inline_tag][..], row].concat(),
+            Value::Overflow(pointer) => {
+                let mut bytes = vec![overflow_tag];
+                pointer.encode(&mut bytes);
+                bytes
+            },
+        }
+    }
+}
+
+impl Record<'_> {
+    pub fn encoded_len(&self) -> usize {
+        self.encode().len()
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            Record::Row(value) => value.encode(TAG_ROW, TAG_ROW_OVERFLOW),
+            Record::Forward(rid) => {
+                let mut bytes = vec![TAG_FORWARD];
+                bytes.extend_from_slice(&(rid.page as u64).to_le_bytes());
+                bytes.extend_from_slice(&rid.slot.to_le_bytes());
+                bytes
+            },
+            Record::Moved(value) => value.encode(TAG_MOVED, TAG_MOVED_OVERFLOW),
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Record<'_> {
+        match bytes[0] {
+            TAG_ROW => Record::Row(Value::Inline(&bytes[1..])),
+            TAG_FORWARD => Record::Forward(RecordId {
+                page: u64::from_le_bytes(bytes[1..9].try_into().unwrap()) as PageIndex,
+                slot: u16::from_le_bytes(bytes[9..11].try_into().unwrap()),
+            }),
+            TAG_MOVED => Record::Moved(Value::Inline(&bytes[1..])),
+            TAG_ROW_OVERFLOW => Record::Row(Value::Overflow(Pointer::decode(&bytes[1..]))),
+            TAG_MOVED_OVERFLOW => Record::Moved(Value::Overflow(Pointer::decode(&bytes[1..]))),
+            tag => panic!("Unknown heap record tag {tag}"),
+        }
+    }
+}
+
+/// The longest row that's kept in a page with a `body_len` byte body, rather
+/// than in the overflow file: a quarter of what would fit in an otherwise
+/// empty page, so that a page always has room for a few rows.
+pub(crate) fn max_inline_len(body_len: usize) -> usize {
+    (body_len - HEADER_SIZE) / 4 - SLOT_SIZE - 1
+}
+
+pub(crate) fn slot_count(body: &[u8]) -> u16 {
+    read_u16(body, 0)
+}
+
+pub(crate) fn get(body: &[u8], slot: u16) -> Option<Record<'_>> {
+    if slot >= slot_count(body) {
+        return None;
+    }
+
+    let (offset, len) = read_slot(body, slot);
+    if offset == 0 {
+        return None;
+    }
+
+    Some(Record::decode(&body[offset..offset + len]))
+}
+
+/// Room in a page that's held for transactions that are still running, which
+/// other changes to the page have to leave alone: bytes of records, and slots
+/// that mustn't be reused.
+#[derive(Default)]
+pub(crate) struct Held {
+    pub bytes: usize,
+    pub slots: Vec<u16>,
+}
+
+/// Add a record in a new (or unused) slot, if there's room for it.
+pub(crate) fn insert(body: &mut [u8], record: &Record<'_>, held: &Held) -> Option<u16> {
+    let bytes = record.encode();
+    if reserved(bytes.len()) > free_space(body, held) {
+        return None;
+    }
+
+    let slot = free_slot(body, held);
+    let made_room = make_room(body, reserved(bytes.len()) + new_slots_len(body, slot), 0);
+    assert!(made_room, "A record that fits in the free space has room once the page is compacted");
+
+    add_slots(body, slot);
+    place(body, slot, &bytes);
+    Some(slot)
+}
+
+/// Swap the record at `slot` for another, if there's room for it. If there
+/// isn't, the page is left how it was.
+pub(crate) fn replace(body: &mut [u8], slot: u16, record: &Record<'_>, held: &Held) -> bool {
+    let bytes = record.encode();
+    let (offset, len) = read_slot(body, slot);
+
+    if reserved(bytes.len()) <= reserved(len) {
+        body[offset..offset + bytes.len()].copy_from_slice(&bytes);
+        write_slot(body, slot, offset, bytes.len());
+        return true;
+    }
+
+    // Free up the old record first, so that its space counts towards the new
+    // one, but put it back if the new one still doesn't fit.
+    write_slot(body, slot, 0, 0);
+
+    if !make_room(body, reserved(bytes.len()), held.bytes) {
+        write_slot(body, slot, offset, len);
+        return false;
+    }
+
+    place(body, slot, &bytes);
+    true
+}
+
+pub(crate) fn remove(body: &mut [u8], slot: u16) {
+    write_slot(body, slot, 0, 0);
+
+    // Unused slots at the end of the slot array can go away completely, since
+    // nothing can be pointing at them.
+    let mut count = slot_count(body);
+    while count > 0 && read_slot(body, count - 1).0 == 0 {
+        count -= 1;
+    }
+    write_u16(body, 0, count);
+}
+
+/// How many bytes of records fit in the page on top of what's there and what's
+/// `held`, which is what [`insert`] has room for, once it's compacted. A record
+/// of `len` bytes fits if [`reserved`]`(len)` is no more than this.
+pub(crate) fn free_space(body: &[u8], held: &Held) -> usize {
+    let slot_space = new_slots_len(body, free_slot(body, held));
+    (body.len() - slots_end(body) - used(body)).saturating_sub(slot_space + held.bytes)
+}
+
+/// The bytes of the record at `slot`, if there's one.
+pub(crate) fn raw(body: &[u8], slot: u16) -> Option<&[u8]> {
+    if slot >= slot_count(body) {
+        return None;
+    }
+
+    match read_slot(body, slot) {
+        (0, _) => None,
+        (offset, len) => Some(&body[offset..offset + len]),
+    }
+}
+
+/// How much of the page the record at `slot` takes up, along with its slot.
+pub(crate) fn taken(body: &[u8], slot: u16) -> usize {
+    raw(body, slot).map_or(0, |bytes| reserved(bytes.len()) + SLOT_SIZE)
+}
+
+/// Make `slot` hold exactly `bytes`, or nothing, like it did before a change
+/// that's being rolled back, returning whether there was room to.
+pub(crate) fn put(body: &mut [u8], slot: u16, bytes: Option<&[u8]>) -> bool {
+    if raw(body, slot) == bytes {
+        return true;
+    }
+    let Some(bytes) = bytes
+        else {
+            remove(body, slot);
+            return true;
+        };
+
+    let old = (slot < slot_count(body)).then(|| read_slot(body, slot));
+    if old.is_some() {
+        write_slot(body, slot, 0, 0);
+    }
+
+    if !make_room(body, reserved(bytes.len()) + new_slots_len(body, slot), 0) {
+        if let Some((offset, len)) = old {
+            write_slot(body, slot, offset, len);
+        }
+        return false;
+    }
+
+    add_slots(body, slot);
+    place(body, slot, bytes);
+    true
+}
+
+/// Whether compacting the page would make more of its free space contiguous.
+pub(crate) fn has_holes(body: &[u8]) -> bool {
+    free_end(body) - slots_end(body) < body.len() - slots_end(body) - used(body)
+}
+
+/// How much of the page a record of `len` bytes takes up.
+pub(crate) fn reserved(len: usize) -> usize {
+    len.max(MIN_RECORD_SIZE)
+}
+
+fn free_end(body: &[u8]) -> usize {
+    match read_u16(body, 2) as usize {
+        0 => body.len(),
+        end => end,
+    }
+}
+
+fn slots_end(body: &[u8]) -> usize {
+    HEADER_SIZE + slot_count(body) as usize * SLOT_SIZE
+}
+
+/// Make sure there are `needed` contiguous free bytes between the slot array
+/// and the records, and `held` more free bytes somewhere in the page,
+/// compacting the records if that helps.
+fn make_room(body: &mut [u8], needed: usize, held: usize) -> bool {
+    if body.len() - slots_end(body) - used(body) < needed + held {
+        return false;
+    }
+
+    if free_end(body) - slots_end(body) < needed {
+        compact(body);
+    }
+    true
+}
+
+/// The first slot that's unused, or past the end of the slot array, and that
+/// isn't `held`.
+fn free_slot(body: &[u8], held: &Held) -> u16 {
+    let count = slot_count(body);
+    (0..)
+        .find(|slot| (*slot >= count || read_slot(body, *slot).0 == 0) && !held.slots.contains(slot))
+        .unwrap()
+}
+
+/// How many bytes the slot array has to grow by to have `slot` in it.
+fn new_slots_len(body: &[u8], slot: u16) -> usize {
+    (slot + 1).saturating_sub(slot_count(body)) as usize * SLOT_SIZE
+}
+
+/// Grow the slot array to have `slot` in it, with every new slot unused.
+fn add_slots(body: &mut [u8], slot: u16) {
+    let count = slot_count(body);
+    if slot < count {
+        return;
+    }
+
+    for unused in count..=slot {
+        write_slot(body, unused, 0, 0);
+    }
+    write_u16(body, 0, slot + 1);
+}
+
+/// How much of the page the records take up.
+fn used(body: &[u8]) -> usize {
+    (0..slot_count(body))
+        .map(|slot| read_slot(body, slot))
+        .filter(|(offset, _)| *offset != 0)
+        .map(|(_, len)| reserved(len))
+        .sum()
+}
+
+/// Pack every record against the end of the page, getting rid of the holes
+/// left behind by removed and shrunk records.
+pub(crate) fn compact(body: &mut [u8]) {
+    let records: Vec<_> = (0..slot_count(body))
+        .filter_map(|slot| {
+            let (offset, len) = read_slot(body, slot);
+            (offset != 0).then(|| (slot, body[offset..offset + len].to_vec()))
+        })
+        .collect();
+
+    write_u16(body, 2, 0);
+    for (slot, bytes) in records {
+        place(body, slot, &bytes);
+    }
+}
+
+/// Put a record at the start of the records, which is known to have room.
+fn place(body: &mut [u8], slot: u16, bytes: &[u8]) {
+    let offset = free_end(body) - reserved(bytes.len());
+    body[offset..offset + bytes.len()].copy_from_slice(bytes);
+    write_slot(body, slot, offset, bytes.len());
+    write_u16(body, 2, offset as u16);
+}
+
+fn read_slot(body: &[u8], slot: u16) -> (usize, usize) {
+    let at = HEADER_SIZE + slot as usize * SLOT_SIZE;
+    (read_u16(body, at) as usize, read_u16(body, at + 2) as usize)
+}
+
+fn write_slot(body: &mut [u8], slot: u16, offset: usize, len: usize) {
+    let at = HEADER_SIZE + slot as usize * SLOT_SIZE;
+    write_u16(body, at, offset as u16);
+    write_u16(body, at + 2, len as u16);
+}
+
+fn read_u16(body: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(body[at..at + 2].try_into().unwrap())
+}
+
+fn write_u16(body: &mut [u8], at: usize, value: u16) {
+    body[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
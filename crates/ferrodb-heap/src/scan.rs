@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+
+use ferrodb_fs::{PageIndex, PAGE_HEADER_SIZE};
+
+use crate::page::{self, Record};
+use crate::{Error, HeapFile, RecordId, RowReader};
+
+/// Iterates over every row of a heap, a page at a time. Rows that moved away
+/// from their home page are returned with their home record ID, in the place
+/// of their home slot.
+pub struct HeapScan<'h> {
+    heap: &'h HeapFile,
+    next_page: PageIndex,
+    /// Pages added after the scan started aren't scanned.
+    page_count: PageIndex,
+    rows: VecDeque<(RecordId, Vec<u8>)>,
+}
+
+impl<'h> HeapScan<'h> {
+    pub(crate) fn new(heap: &'h HeapFile) -> Result<HeapScan<'h>, Error> {
+        Ok(HeapScan {
+            heap,
+            next_page: 1,
+            page_count: heap.files.page_count(heap.file)?,
+            rows: VecDeque::new(),
+        })
+    }
+
+    fn read_page(&mut self, page: PageIndex) -> Result<(), Error> {
+        let mut rows = vec![];
+
+        {
+            let page_ref = self.heap.files.latest(self.heap.file, page)?;
+            let buf = page_ref.read();
+            let body = &buf[PAGE_HEADER_SIZE..];
+
+            for slot in 0..page::slot_count(body) {
+                let rid = RecordId { page, slot };
+
+                match page::get(body, slot) {
+                    Some(Record::Row(value)) => rows.push((rid, Ok(RowReader::new(self.heap, value)))),
+                    Some(Record::Forward(target)) => rows.push((rid, Err(target))),
+                    Some(Record::Moved(_)) | None => {},
+                }
+            }
+        }
+
+        // Only follow forwarding pointers and read the overflow file once this
+        // page isn't latched anymore, since the rows might be on pages that
+        // come before it.
+        for (rid, row) in rows {
+            let reader = match row {
+                Ok(reader) => Some(reader),
+                Err(target) => self.heap.open_moved(target)?,
+            };
+
+            if let Some(reader) = reader {
+                self.rows.push_back((rid, reader.read_all()?));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for HeapScan<'_> {
+    type Item = Result<(RecordId, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.rows.is_empty() {
+            if self.next_page >= self.page_count {
+                return None;
+            }
+
+            let page = self.next_page;
+            self.next_page += 1;
+
+            if let Err(e) = self.read_page(page) {
+                return Some(Err(e));
+            }
+        }
+
+        self.rows.pop_front().map(Ok)
+    }
+}
@@ -0,0 +1,128 @@
+//! How changes to a heap's pages are rolled back. Other transactions can go on
+//! changing a page after this one has, so rolling back only undoes what the
+//! change did to its own slot, or to the overflow file's free list:
+//!
+//! * Heap pages: `[slot: u16]`, for a slot that was empty, or
+//!   `[slot: u16][record bytes]` for what the slot held. Compacting a page is
+//!   logged with no undo at all, since it changes nothing that anyone sees.
+//! * Overflow pages: `[op: u8][first page: u64][last page: u64]`, for taking
+//!   a chain of pages back off the free list, putting them back on, or making
+//!   `last` the end of its chain again.
+
+use std::sync::{Arc, Weak};
+
+use ferrodb_fs::{FileId, FileKind, FileManager, PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_wal::{Error, Txn, Undo, Wal};
+
+use crate::overflow::{read_u64, write_u64, META_PAGE};
+use crate::{page, HeapFile};
+
+const OP_UNLINK: u8 = 0;
+const OP_PUSH: u8 = 1;
+const OP_END_CHAIN: u8 = 2;
+
+impl HeapFile {
+    /// Set up `wal` to roll back changes to heaps that are in `files`, which
+    /// has to happen before it recovers or anything changes a heap.
+    pub fn register_undo(wal: &Wal, files: &Arc<FileManager>) {
+        wal.set_undo(FileKind::Heap, Arc::new(HeapUndo(Arc::downgrade(files))));
+        wal.set_undo(FileKind::Overflow, Arc::new(OverflowUndo(Arc::downgrade(files))));
+    }
+}
+
+/// The undo for a change to `slot`, which held `before`.
+pub(crate) fn put(slot: u16, before: Option<&[u8]>) -> Vec<u8> {
+    [&slot.to_le_bytes()[..], before.unwrap_or_default()].concat()
+}
+
+/// The undo for putting the chain of overflow pages from `first` to `last` on
+/// the free list.
+pub(crate) fn unlink(first: PageIndex, last: PageIndex) -> Vec<u8> {
+    chain(OP_UNLINK, first, last)
+}
+
+/// The undo for taking the chain of overflow pages from `first` to `last` off
+/// the free list.
+pub(crate) fn push(first: PageIndex, last: PageIndex) -> Vec<u8> {
+    chain(OP_PUSH, first, last)
+}
+
+/// The undo for pointing `last`, the end of a row's chain, at another page.
+pub(crate) fn end_chain(last: PageIndex) -> Vec<u8> {
+    chain(OP_END_CHAIN, last, last)
+}
+
+fn chain(op: u8, first: PageIndex, last: PageIndex) -> Vec<u8> {
+    let mut undo = vec![op];
+    undo.extend_from_slice(&(first as u64).to_le_bytes());
+    undo.extend_from_slice(&(last as u64).to_le_bytes());
+    undo
+}
+
+/// `files` keeps the log, so this only points back at it weakly, or neither
+/// would ever be dropped. Changes are only rolled back while `files` is open.
+struct HeapUndo(Weak<FileManager>);
+
+impl Undo for HeapUndo {
+    fn undo(&self, txn: &Txn<'_>, file: FileId, page: PageIndex, undo: &[u8]) -> Result<(), Error> {
+        let Some((slot, before)) = undo.split_first_chunk::<2>()
+            else { return Ok(()); };
+        let slot = u16::from_le_bytes(*slot);
+        let before = (!before.is_empty()).then_some(before);
+
+        let files = self.0.upgrade().expect("Changes are only rolled back while their files are open");
+        let put = txn.modify(&files, file, page, |body| page::put(body, slot, before))?;
+        assert!(put, "The room a record took up is held until its transaction is over");
+        Ok(())
+    }
+}
+
+struct OverflowUndo(Weak<FileManager>);
+
+impl Undo for OverflowUndo {
+    fn undo(&self, txn: &Txn<'_>, file: FileId, _page: PageIndex, undo: &[u8]) -> Result<(), Error> {
+        let files = &*self.0.upgrade().expect("Changes are only rolled back while their files are open");
+        let first = read_u64(undo, 1) as PageIndex;
+        let last = read_u64(undo, 9) as PageIndex;
+
+        if undo[0] == OP_END_CHAIN {
+            return txn.modify(files, file, last, |body| write_u64(body, 0, 0));
+        }
+
+        let meta_ref = files.dirty(file, META_PAGE)?;
+        let mut meta = meta_ref.write();
+        let head = read_u64(&meta[PAGE_HEADER_SIZE..], 0) as PageIndex;
+
+        if undo[0] == OP_PUSH {
+            // Already back on the list if a crash stopped this after it was.
+            if head == first {
+                return Ok(());
+            }
+
+            txn.modify(files, file, last, |body| write_u64(body, 0, head as u64))?;
+            return txn.modify_latched(files, file, META_PAGE, &mut meta, |body| {
+                write_u64(body, 0, first as u64)
+            });
+        }
+
+        // Other chains might have been put on the list in front of this one
+        // since, so it's taken out from wherever it is now. It's not there
+        // anymore if a crash stopped this after it was taken out.
+        let mut prev = META_PAGE;
+        let mut page = head;
+        while page != first {
+            if page == 0 {
+                return Ok(());
+            }
+
+            prev = page;
+            page = read_u64(&files.latest(file, page)?.read()[PAGE_HEADER_SIZE..], 0) as PageIndex;
+        }
+
+        let next = read_u64(&files.latest(file, last)?.read()[PAGE_HEADER_SIZE..], 0);
+        match prev {
+            META_PAGE => txn.modify_latched(files, file, META_PAGE, &mut meta, |body| write_u64(body, 0, next)),
+            prev => txn.modify(files, file, prev, |body| write_u64(body, 0, next)),
+        }
+    }
+}
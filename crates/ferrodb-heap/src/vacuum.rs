@@ -0,0 +1,156 @@
+use std::sync::atomic::Ordering;
+
+use ferrodb_fs::PageIndex;
+use ferrodb_wal::{Txn, Wal};
+
+use crate::page::{self, Record, Value};
+use crate::{Error, HeapFile, RecordId};
+
+/// What a [`HeapFile::vacuum`] did.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VacuumStats {
+    /// How many pages the heap has, past its header.
+    pub pages: usize,
+    /// How many pages had their records packed together.
+    pub compacted: usize,
+    /// How many rows that had been moved away from their home page fit back
+    /// in it, and were moved back.
+    pub moved_home: usize,
+    /// How many bytes of records the heap's pages have room for between them.
+    pub free_bytes: usize,
+    /// How many pages at the end of the heap are empty, which
+    /// [`HeapFile::truncate`] can cut off.
+    pub empty_tail: usize,
+}
+
+impl HeapFile {
+    /// Reclaim the space that deleted and shrunk rows left behind in the
+    /// heap's pages: move rows that were moved away from their home page back
+    /// to it wherever they fit now, pack every page's records together, and
+    /// rebuild the free space map. Record IDs stay the same.
+    ///
+    /// Changes to the heap wait for this to finish, and it waits for the ones
+    /// that are being made. Room that transactions which are still running
+    /// freed up stays theirs, and rows they changed aren't moved. Pages that end up empty at the end of the heap
+    /// are left for [`HeapFile::truncate`].
+    pub fn vacuum(&self, txn: &Txn<'_>) -> Result<VacuumStats, Error> {
+        let _vacuuming = self.vacuuming.write();
+        let page_count = self.files.page_count(self.file)?;
+        let mut stats = VacuumStats {
+            pages: page_count - 1,
+            ..VacuumStats::default()
+        };
+
+        // Moving a row home leaves a hole in the page it was moved to, which
+        // might come before its home page, so every row is moved before any
+        // page is compacted. That hole might be what another row needs to fit
+        // back in its own home, so this goes on until no more rows move.
+        loop {
+            let moved_home = stats.moved_home;
+            for page in 1..page_count {
+                let forwards: Vec<_> = self.read_page(page, |body| {
+                    (0..page::slot_count(body))
+                        .filter_map(|slot| match page::get(body, slot) {
+                            Some(Record::Forward(target)) => Some((RecordId { page, slot }, target)),
+                            _ => None,
+                        })
+                        .collect()
+                })?;
+
+                for (rid, target) in forwards {
+                    if self.move_home(txn, rid, target)? {
+                        stats.moved_home += 1;
+                    }
+                }
+            }
+
+            if stats.moved_home == moved_home {
+                break;
+            }
+        }
+
+        self.free_space.clear();
+        for page in 1..page_count {
+            if self.read_page(page, page::has_holes)? {
+                self.modify(txn, page, |body, _| (page::compact(body), None))?;
+                stats.compacted += 1;
+            }
+
+            let held = self.holds.page(txn.wal(), page, None);
+            let free = self.read_page(page, |body| page::free_space(body, &held))?;
+            self.free_space.set(page, free);
+            stats.free_bytes += free;
+        }
+
+        stats.empty_tail = page_count - self.used_page_count(txn.wal())?;
+        self.changes.store(0, Ordering::Relaxed);
+        Ok(stats)
+    }
+
+    /// Cut off the empty pages at the end of the heap, returning how many
+    /// there were. The changes that emptied them have to be committed, and a
+    /// checkpoint taken since, so that recovery doesn't bring them back.
+    /// Pages emptied by transactions in `wal` that are still running stay, and
+    /// changes to the heap wait for this to finish.
+    pub fn truncate(&self, wal: &Wal) -> Result<usize, Error> {
+        let _vacuuming = self.vacuuming.write();
+        let page_count = self.files.page_count(self.file)?;
+        let end = self.used_page_count(wal)?;
+        if end == page_count {
+            return Ok(0);
+        }
+
+        self.files.truncate(self.file, end)?;
+        self.free_space.truncate(end);
+        self.insert_hint.fetch_min((end - 1).max(1), Ordering::Relaxed);
+        Ok(page_count - end)
+    }
+
+    /// How many pages the heap would have without the empty ones at its end,
+    /// apart from ones that transactions in `wal` that are still running might
+    /// need back.
+    fn used_page_count(&self, wal: &Wal) -> Result<PageIndex, Error> {
+        let mut end = self.files.page_count(self.file)?;
+        while end > 1 && self.read_page(end - 1, |body| page::slot_count(body) == 0)? {
+            if !self.holds.page(wal, end - 1, None).slots.is_empty() {
+                break;
+            }
+            end -= 1;
+        }
+
+        Ok(end)
+    }
+
+    /// Move the row that `home` forwards to at `target` back into its home
+    /// slot, if it fits there, returning whether it did. A row that a
+    /// transaction which is still running changed stays where it is, since
+    /// rolling that back puts back what was in both slots.
+    fn move_home(&self, txn: &Txn<'_>, home: RecordId, target: RecordId) -> Result<bool, Error> {
+        for rid in [home, target] {
+            if self.holds.page(txn.wal(), rid.page, Some(txn.id())).slots.contains(&rid.slot) {
+                return Ok(false);
+            }
+        }
+
+        let moved = self.read(target, |record| match record {
+            Some(Record::Moved(Value::Inline(row))) => Some(Ok(row.to_vec())),
+            Some(Record::Moved(Value::Overflow(pointer))) => Some(Err(pointer)),
+            _ => None,
+        })?;
+        let value = match &moved {
+            Some(Ok(row)) => Value::Inline(row),
+            Some(Err(pointer)) => Value::Overflow(*pointer),
+            None => return Ok(false),
+        };
+
+        let moved = self.modify(txn, home.page, |body, held| {
+            (page::replace(body, home.slot, &Record::Row(value), held), Some(home.slot))
+        })?;
+        if !moved {
+            return Ok(false);
+        }
+        self.remove(txn, target)?;
+
+        Ok(true)
+    }
+}
@@ -0,0 +1,128 @@
+use std::io::Read;
+use std::sync::Arc;
+
+use ferrodb_fs::vfs::MemoryVfs;
+use ferrodb_fs::{Compression, FileManager};
+use ferrodb_heap::HeapFile;
+use ferrodb_wal::{recover, Wal};
+
+const PAGE_SIZE: usize = 512;
+
+fn open() -> (Arc<Wal>, Arc<FileManager>, HeapFile) {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let vfs = Arc::new(MemoryVfs::default());
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+    HeapFile::register_undo(&wal, &files);
+
+    recover(&wal, &files, |_, _| {}).unwrap();
+    let heap = HeapFile::open(files.clone(), "heap").unwrap();
+    (wal, files, heap)
+}
+
+/// A row that doesn't compress.
+fn noise(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(2654435761) | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+#[test]
+fn long_rows_read_back_whole_or_a_bit_at_a_time() {
+    let (wal, _files, heap) = open();
+    let max = HeapFile::max_inline_len();
+    let rows: Vec<_> = [max, max + 1, 1000, 70_000, 200_000]
+        .into_iter()
+        .enumerate()
+        .map(|(seed, len)| noise(len, seed as u32))
+        .collect();
+
+    let txn = wal.begin().unwrap();
+    let rids: Vec<_> = rows.iter().map(|row| heap.insert(&txn, row).unwrap()).collect();
+    txn.commit().unwrap();
+
+    for (rid, row) in rids.iter().zip(&rows) {
+        assert_eq!(heap.get(*rid).unwrap().as_ref(), Some(row));
+
+        let mut reader = heap.open_row(*rid).unwrap().unwrap();
+        assert_eq!(reader.row_len(), row.len() as u64);
+        let mut read = vec![];
+        let mut buf = [0; 1000];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                n => read.extend_from_slice(&buf[..n]),
+            }
+        }
+        assert_eq!(&read, row);
+    }
+
+    let scanned: Vec<_> = heap.scan().unwrap().map(|row| row.unwrap().1).collect();
+    assert_eq!(scanned.len(), rows.len());
+    assert!(rows.iter().all(|row| scanned.contains(row)));
+}
+
+#[test]
+fn compressed_rows_take_fewer_pages_and_read_back_the_same() {
+    let (wal, files, mut heap) = open();
+    let repetitive: Vec<u8> = (0..100_000u32).map(|n| (n % 7) as u8).collect();
+    let random = noise(20_000, 1);
+
+    let txn = wal.begin().unwrap();
+    let plain = heap.insert(&txn, &repetitive).unwrap();
+    let uncompressed_pages = files.page_count(heap.overflow_file()).unwrap();
+
+    let mut compressed = vec![];
+    for compression in [Compression::Lz4, Compression::Zstd { level: 3 }] {
+        heap.set_compression(compression);
+        let before = files.page_count(heap.overflow_file()).unwrap();
+        compressed.push((heap.insert(&txn, &repetitive).unwrap(), &repetitive));
+        assert!(files.page_count(heap.overflow_file()).unwrap() - before < uncompressed_pages / 10);
+
+        // What doesn't compress is kept as it is.
+        compressed.push((heap.insert(&txn, &random).unwrap(), &random));
+    }
+    txn.commit().unwrap();
+
+    heap.set_compression(Compression::None);
+    assert_eq!(heap.get(plain).unwrap(), Some(repetitive.clone()));
+    for (rid, row) in compressed {
+        assert_eq!(heap.get(rid).unwrap().as_ref(), Some(row));
+    }
+}
+
+#[test]
+fn pages_of_replaced_and_deleted_rows_are_reused() {
+    let (wal, files, heap) = open();
+
+    let txn = wal.begin().unwrap();
+    let updated = heap.insert(&txn, &noise(10_000, 1)).unwrap();
+    let deleted = heap.insert(&txn, &noise(10_000, 2)).unwrap();
+    txn.commit().unwrap();
+    let page_count = files.page_count(heap.overflow_file()).unwrap();
+
+    let txn = wal.begin().unwrap();
+    heap.update(&txn, updated, b"short").unwrap();
+    assert!(heap.delete(&txn, deleted).unwrap());
+    txn.commit().unwrap();
+
+    let txn = wal.begin().unwrap();
+    let rows: Vec<_> = (3..5).map(|seed| (heap.insert(&txn, &noise(10_000, seed)).unwrap(), seed)).collect();
+    heap.update(&txn, updated, &noise(1000, 5)).unwrap();
+    txn.commit().unwrap();
+    // The update needed a few pages more than the deleted rows gave back.
+    assert!(files.page_count(heap.overflow_file()).unwrap() <= page_count + 3);
+
+    assert_eq!(heap.get(updated).unwrap(), Some(noise(1000, 5)));
+    for (rid, seed) in rows {
+        assert_eq!(heap.get(rid).unwrap(), Some(noise(10_000, seed)));
+    }
+}
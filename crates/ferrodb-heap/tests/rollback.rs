@@ -0,0 +1,125 @@
+use std::sync::Arc;
+
+use ferrodb_fs::vfs::{CrashMode, CrashVfs, Vfs};
+use ferrodb_fs::FileManager;
+use ferrodb_heap::{HeapFile, RecordId};
+use ferrodb_wal::{recover, Wal};
+
+const PAGE_SIZE: usize = 512;
+
+fn open(vfs: Arc<dyn Vfs>) -> (Arc<Wal>, Arc<FileManager>, HeapFile) {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+    HeapFile::register_undo(&wal, &files);
+
+    recover(&wal, &files, |_, _| {}).unwrap();
+    let heap = HeapFile::open(files.clone(), "heap").unwrap();
+    (wal, files, heap)
+}
+
+fn row(n: u8, len: usize) -> Vec<u8> {
+    vec![n; len]
+}
+
+#[test]
+fn rolling_back_leaves_rows_other_transactions_put_in_the_same_page() {
+    let (wal, files, heap) = open(Arc::new(CrashVfs::default()));
+
+    let aborted = wal.begin().unwrap();
+    let committed = wal.begin().unwrap();
+    let a = heap.insert(&aborted, &row(1, 20)).unwrap();
+    let b = heap.insert(&committed, &row(2, 20)).unwrap();
+    let c = heap.insert(&aborted, &row(3, 20)).unwrap();
+    heap.update(&committed, b, &row(4, 30)).unwrap();
+    assert_eq!([a.page, c.page], [b.page, b.page]);
+    committed.commit().unwrap();
+
+    aborted.abort(&files).unwrap();
+    assert_eq!(heap.get(a).unwrap(), None);
+    assert_eq!(heap.get(b).unwrap(), Some(row(4, 30)));
+    assert_eq!(heap.get(c).unwrap(), None);
+}
+
+#[test]
+fn room_that_a_running_transaction_freed_up_is_kept_for_it() {
+    let (wal, files, heap) = open(Arc::new(CrashVfs::default()));
+
+    let setup = wal.begin().unwrap();
+    let rows: Vec<RecordId> = (0..4).map(|n| heap.insert(&setup, &row(n, 100)).unwrap()).collect();
+    let long = heap.insert(&setup, &row(9, 2000)).unwrap();
+    setup.commit().unwrap();
+
+    // Fill the page back up after a delete and a shrink, and take overflow
+    // pages after a long row's freed, all of which must go somewhere else.
+    let aborted = wal.begin().unwrap();
+    assert!(heap.delete(&aborted, rows[1]).unwrap());
+    heap.update(&aborted, rows[2], &row(5, 10)).unwrap();
+    assert!(heap.delete(&aborted, long).unwrap());
+
+    let committed = wal.begin().unwrap();
+    let others: Vec<RecordId> = (0..4).map(|n| heap.insert(&committed, &row(10 + n, 100)).unwrap()).collect();
+    let other_long = heap.insert(&committed, &row(20, 2000)).unwrap();
+    committed.commit().unwrap();
+
+    aborted.abort(&files).unwrap();
+    for (n, rid) in rows.iter().enumerate() {
+        assert_eq!(heap.get(*rid).unwrap(), Some(row(n as u8, 100)));
+    }
+    assert_eq!(heap.get(long).unwrap(), Some(row(9, 2000)));
+    for (n, rid) in others.iter().enumerate() {
+        assert_eq!(heap.get(*rid).unwrap(), Some(row(10 + n as u8, 100)));
+    }
+    assert_eq!(heap.get(other_long).unwrap(), Some(row(20, 2000)));
+
+    // Once it's over, the room's anyone's.
+    let txn = wal.begin().unwrap();
+    assert!(heap.delete(&txn, long).unwrap());
+    txn.commit().unwrap();
+    let txn = wal.begin().unwrap();
+    heap.insert(&txn, &row(30, 2000)).unwrap();
+    txn.commit().unwrap();
+    assert_eq!(files.page_count(heap.overflow_file()).unwrap(), 2 + 2 * 5);
+}
+
+#[test]
+fn recovery_rolls_back_without_touching_committed_rows_after_any_crash() {
+    let vfs = Arc::new(CrashVfs::default());
+    let (wal, files, heap) = open(vfs.clone());
+
+    let setup = wal.begin().unwrap();
+    let kept = heap.insert(&setup, &row(1, 50)).unwrap();
+    let long = heap.insert(&setup, &row(2, 1000)).unwrap();
+    setup.commit().unwrap();
+
+    let running = wal.begin().unwrap();
+    let committed = wal.begin().unwrap();
+    let inserted = heap.insert(&running, &row(3, 1000)).unwrap();
+    assert!(heap.delete(&running, kept).unwrap());
+    let theirs = heap.insert(&committed, &row(4, 50)).unwrap();
+    assert!(heap.delete(&running, long).unwrap());
+    let their_long = heap.insert(&committed, &row(5, 1000)).unwrap();
+    committed.commit().unwrap();
+    files.sync_all().unwrap();
+
+    let check = |heap: &HeapFile| {
+        assert_eq!(heap.get(kept).unwrap(), Some(row(1, 50)));
+        assert_eq!(heap.get(long).unwrap(), Some(row(2, 1000)));
+        assert_eq!(heap.get(inserted).unwrap(), None);
+        assert_eq!(heap.get(theirs).unwrap(), Some(row(4, 50)));
+        assert_eq!(heap.get(their_long).unwrap(), Some(row(5, 1000)));
+    };
+
+    // Recovery can crash partway through rolling back too.
+    let crashed = Arc::new(vfs.crash(CrashMode::DropUnsynced));
+    let (_wal, files, heap) = open(crashed.clone());
+    check(&heap);
+    drop((heap, files));
+
+    for events in 0..=crashed.events().len() {
+        let (_wal, _files, heap) = open(Arc::new(crashed.crash_at(events, CrashMode::DropUnsynced)));
+        check(&heap);
+    }
+}
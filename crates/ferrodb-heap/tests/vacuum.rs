@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use ferrodb_fs::vfs::MemoryVfs;
+use ferrodb_fs::FileManager;
+use ferrodb_heap::{HeapFile, RecordId};
+use ferrodb_wal::{recover, Wal};
+
+const PAGE_SIZE: usize = 512;
+
+fn open() -> (Arc<Wal>, Arc<FileManager>, Arc<HeapFile>) {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let vfs = Arc::new(MemoryVfs::default());
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+    HeapFile::register_undo(&wal, &files);
+
+    recover(&wal, &files, |_, _| {}).unwrap();
+    let heap = Arc::new(HeapFile::open(files.clone(), "heap").unwrap());
+    (wal, files, heap)
+}
+
+#[test]
+fn vacuuming_while_rows_change_loses_none_of_them() {
+    let (wal, files, heap) = open();
+    let stop = Arc::new(AtomicBool::new(false));
+
+    let vacuum = thread::spawn({
+        let (wal, files, heap, stop) = (wal.clone(), files.clone(), heap.clone(), stop.clone());
+        move || {
+            while !stop.load(Ordering::Relaxed) {
+                let txn = wal.begin().unwrap();
+                heap.vacuum(&txn).unwrap();
+                txn.commit().unwrap();
+
+                wal.checkpoint(&files).unwrap();
+                heap.truncate(&wal).unwrap();
+            }
+        }
+    });
+
+    // Rows grow out of their pages and shrink back, so that vacuuming has rows
+    // to move home, and some of the changes are rolled back.
+    let writers: Vec<_> = (0..4u8)
+        .map(|writer| {
+            let (wal, files, heap) = (wal.clone(), files.clone(), heap.clone());
+            thread::spawn(move || {
+                let mut rows: HashMap<RecordId, Vec<u8>> = HashMap::new();
+                for i in 0..300usize {
+                    let txn = wal.begin().unwrap();
+                    let mut changed = rows.clone();
+
+                    let row = vec![writer; 10 + i % 7 * 15];
+                    changed.insert(heap.insert(&txn, &row).unwrap(), row);
+                    if let Some(&rid) = rows.keys().nth(i % 5) {
+                        let row = vec![writer; 10 + i % 11 * 10];
+                        heap.update(&txn, rid, &row).unwrap();
+                        changed.insert(rid, row);
+                    }
+                    if let Some(&rid) = rows.keys().nth(i % 3).filter(|_| i % 4 == 0) {
+                        assert!(heap.delete(&txn, rid).unwrap());
+                        changed.remove(&rid);
+                    }
+
+                    if i % 6 == 0 {
+                        txn.abort(&files).unwrap();
+                    } else {
+                        txn.commit().unwrap();
+                        rows = changed;
+                    }
+                }
+                rows
+            })
+        })
+        .collect();
+
+    let rows: Vec<_> = writers.into_iter().map(|writer| writer.join().unwrap()).collect();
+    stop.store(true, Ordering::Relaxed);
+    vacuum.join().unwrap();
+
+    let expected: HashMap<_, _> = rows.into_iter().flatten().collect();
+    let scanned: HashMap<_, _> = heap.scan().unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(scanned, expected);
+}
+
+#[test]
+fn truncating_keeps_pages_that_running_transactions_emptied() {
+    let (wal, files, heap) = open();
+
+    let setup = wal.begin().unwrap();
+    let rids: Vec<_> = (0..40u8).map(|n| (heap.insert(&setup, &[n; 60]).unwrap(), n)).collect();
+    setup.commit().unwrap();
+    let page_count = files.page_count(heap.file()).unwrap();
+
+    let running = wal.begin().unwrap();
+    for (rid, _) in rids.iter().filter(|(rid, _)| rid.page == page_count - 1) {
+        assert!(heap.delete(&running, *rid).unwrap());
+    }
+
+    let txn = wal.begin().unwrap();
+    let stats = heap.vacuum(&txn).unwrap();
+    txn.commit().unwrap();
+    wal.checkpoint(&files).unwrap();
+    assert_eq!(stats.empty_tail, 0);
+    assert_eq!(heap.truncate(&wal).unwrap(), 0);
+
+    running.abort(&files).unwrap();
+    for (rid, n) in rids {
+        assert_eq!(heap.get(rid).unwrap(), Some(vec![n; 60]));
+    }
+}
+
+#[test]
+fn vacuuming_leaves_rows_that_running_transactions_changed_where_they_are() {
+    let (wal, files, heap) = open();
+    let long = HeapFile::max_inline_len();
+
+    // Page 1 fills up, so the first row is moved out when it grows.
+    let setup = wal.begin().unwrap();
+    let mut rids = vec![];
+    while rids.last().is_none_or(|rid: &RecordId| rid.page == 1) {
+        rids.push(heap.insert(&setup, &[rids.len() as u8; 20]).unwrap());
+    }
+    heap.update(&setup, rids[0], &vec![10; long]).unwrap();
+    setup.commit().unwrap();
+
+    // Now there's room for it back home.
+    let txn = wal.begin().unwrap();
+    for rid in &rids[1..9] {
+        assert!(heap.delete(&txn, *rid).unwrap());
+    }
+    txn.commit().unwrap();
+
+    let running = wal.begin().unwrap();
+    heap.update(&running, rids[0], &vec![20; long]).unwrap();
+    let txn = wal.begin().unwrap();
+    assert_eq!(heap.vacuum(&txn).unwrap().moved_home, 0);
+    txn.commit().unwrap();
+    running.abort(&files).unwrap();
+    assert_eq!(heap.get(rids[0]).unwrap(), Some(vec![10; long]));
+
+    let txn = wal.begin().unwrap();
+    assert_eq!(heap.vacuum(&txn).unwrap().moved_home, 1);
+    txn.commit().unwrap();
+    assert_eq!(heap.get(rids[0]).unwrap(), Some(vec![10; long]));
+}
@@ -0,0 +1,76 @@
+//! Bloom filters over the keys of an SSTable, so that looking up a key that
+//! isn't in it usually doesn't have to read any of its pages.
+//!
+//! Filters are stored as `[hashes: u8][len: u32][bits]`, little endian.
+
+/// The two hashes of a key that all of its bits in a filter come from.
+pub(crate) type KeyHash = (u32, u32);
+
+pub(crate) struct BloomFilter {
+    bits: Vec<u8>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// A filter holding the keys with these hashes, with about `bits_per_key`
+    /// bits for each of them.
+    pub fn build(keys: &[KeyHash], bits_per_key: usize) -> BloomFilter {
+        let len = (keys.len() * bits_per_key).max(64);
+        // This many hashes gives the fewest false positives for the size.
+        let hashes = ((bits_per_key as f64 * std::f64::consts::LN_2) as u32).clamp(1, 30);
+
+        let mut filter = BloomFilter {
+            bits: vec![0; len.div_ceil(8)],
+            hashes,
+        };
+        for &key in keys {
+            for bit in filter.bits_of(key) {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+
+        filter
+    }
+
+    /// Whether a key with this hash might have been added. False positives
+    /// are possible, false negatives aren't.
+    pub fn may_contain(&self, key: KeyHash) -> bool {
+        self.bits_of(key)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Double hashing: the `i`th bit is picked by `h1 + i * h2`.
+    fn bits_of(&self, (h1, h2): KeyHash) -> impl Iterator<Item = usize> {
+        let len = self.bits.len() * 8;
+        (0..self.hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) as usize % len)
+    }
+
+    pub fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.push(self.hashes as u8);
+        bytes.extend_from_slice(&(self.bits.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.bits);
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<BloomFilter> {
+        let hashes = *bytes.first()? as u32;
+        let len = u32::from_le_bytes(bytes.get(1..5)?.try_into().unwrap()) as usize;
+        let bits = bytes.get(5..5 + len)?.to_vec();
+
+        if hashes == 0 || bits.is_empty() {
+            return None;
+        }
+
+        Some(BloomFilter { bits, hashes })
+    }
+}
+
+pub(crate) fn key_hash(key: &[u8]) -> KeyHash {
+    let h1 = crc32fast::hash(key);
+
+    let mut hasher = crc32fast::Hasher::new_with_initial(h1);
+    hasher.update(key);
+    // An odd step never lands back on the same bit before it has to.
+    let h2 = hasher.finalize() | 1;
+
+    (h1, h2)
+}
@@ -0,0 +1,290 @@
+//! The worker that runs in the background for each tree: it writes memtables
+//! out to level 0, merges levels into the ones below them, and deletes
+//! SSTables once nothing's reading them anymore.
+
+use std::ops::Bound;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ferrodb_fs::Lsn;
+
+use crate::manifest::Manifest;
+use crate::memtable::MemTable;
+use crate::merge::{MergeIter, Source};
+use crate::sstable::{SsTable, SsTableIter, SsTableWriter};
+use crate::{write_manifest, Error, Shared, Version, LEVELS};
+
+/// How often the worker checks whether the SSTables that were replaced are
+/// still being read.
+const OBSOLETE_POLL: Duration = Duration::from_millis(100);
+
+/// What the worker owns: nothing else changes the version, so it only takes
+/// the state to swap in a new one.
+pub(crate) struct Worker {
+    shared: Arc<Shared>,
+    log_start: Lsn,
+    next_table: u64,
+    /// Replaced SSTables that scans might still be reading.
+    obsolete: Vec<Arc<SsTable>>,
+    /// For each level, the last key of the SSTable that was last merged out of
+    /// it, so the next one to go is the one after it.
+    compact_from: Vec<Vec<u8>>,
+}
+
+enum Job {
+    Flush(Arc<MemTable>, Lsn),
+    Compact(Compaction),
+    DeleteObsolete,
+}
+
+/// SSTables of `level` to merge into the ones of the next level that they
+/// overlap.
+struct Compaction {
+    level: usize,
+    inputs: Vec<Arc<SsTable>>,
+    overlapping: Vec<Arc<SsTable>>,
+}
+
+impl Worker {
+    pub fn new(shared: Arc<Shared>, manifest: &Manifest) -> Worker {
+        Worker {
+            shared,
+            log_start: manifest.log_start,
+            next_table: manifest.next_table,
+            obsolete: vec![],
+            compact_from: vec![vec![]; LEVELS],
+        }
+    }
+
+    /// Work until the tree is closed. If something goes wrong, the tree fails,
+    /// and whatever's waiting on the worker is told why.
+    pub fn run(&mut self) {
+        while let Some(job) = self.next_job() {
+            let result = match job {
+                Job::Flush(memtable, log_end) => self.flush(&memtable, log_end),
+                Job::Compact(compaction) => self.compact(compaction),
+                Job::DeleteObsolete => self.delete_obsolete(),
+            };
+
+            if let Err(e) = result {
+                self.shared.state.lock().failed = Some(e.to_string());
+                self.shared.changed.notify_all();
+                return;
+            }
+        }
+    }
+
+    /// Wait for something to do, or `None` once the tree is closing.
+    fn next_job(&mut self) -> Option<Job> {
+        let shared = self.shared.clone();
+        let mut state = shared.state.lock();
+
+        loop {
+            if state.closing {
+                return None;
+            }
+
+            if let Some((memtable, log_end)) = state.immutable.front() {
+                return Some(Job::Flush(memtable.clone(), *log_end));
+            }
+
+            if let Some(compaction) = self.pick_compaction(&state.version) {
+                return Some(Job::Compact(compaction));
+            }
+
+            if self.obsolete.iter().any(|table| Arc::strong_count(table) == 1) {
+                return Some(Job::DeleteObsolete);
+            }
+
+            // Nothing says when a scan lets go of an SSTable.
+            if self.obsolete.is_empty() {
+                shared.changed.wait(&mut state);
+            } else {
+                shared.changed.wait_for(&mut state, OBSOLETE_POLL);
+            }
+        }
+    }
+
+    /// Write the oldest memtable out as a new SSTable at the front of level 0.
+    fn flush(&mut self, memtable: &MemTable, log_end: Lsn) -> Result<(), Error> {
+        let mut writer = self.writer()?;
+        for (key, value) in memtable.iter() {
+            writer.add(key, value)?;
+        }
+        let table = Arc::new(writer.finish()?);
+
+        let mut version = Version::clone(&self.shared.state.lock().version);
+        version.levels[0].insert(0, table);
+        self.log_start = log_end;
+        self.write_manifest(&version)?;
+
+        let mut state = self.shared.state.lock();
+        state.version = Arc::new(version);
+        state.immutable.pop_front();
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    fn pick_compaction(&self, version: &Version) -> Option<Compaction> {
+        let options = &self.shared.options;
+
+        // Level 0's SSTables overlap, so they all go at once.
+        if version.levels[0].len() >= options.level0_tables {
+            return Some(self.compaction(version, 0, version.levels[0].clone()));
+        }
+
+        // The last level can get as big as it likes.
+        let mut max_size = options.table_size;
+        for level in 1..LEVELS - 1 {
+            max_size = max_size.saturating_mul(options.level_ratio);
+
+            let tables = &version.levels[level];
+            let size: u64 = tables.iter().map(|table| table.size()).sum();
+            if size <= max_size {
+                continue;
+            }
+
+            // Go round the level, so every part of it gets its turn.
+            let from = &self.compact_from[level];
+            let table = tables
+                .iter()
+                .find(|table| table.first_key() > from.as_slice())
+                .unwrap_or(&tables[0]);
+
+            return Some(self.compaction(version, level, vec![table.clone()]));
+        }
+
+        None
+    }
+
+    fn compaction(&self, version: &Version, level: usize, inputs: Vec<Arc<SsTable>>) -> Compaction {
+        let first = inputs.iter().map(|table| table.first_key()).min().unwrap();
+        let last = inputs.iter().map(|table| table.last_key()).max().unwrap();
+
+        let overlapping = version.levels[level + 1]
+            .iter()
+            .filter(|table| table.overlaps(first, last))
+            .cloned()
+            .collect();
+
+        Compaction {
+            level,
+            inputs,
+            overlapping,
+        }
+    }
+
+    fn compact(&mut self, compaction: Compaction) -> Result<(), Error> {
+        let Compaction {
+            level,
+            inputs,
+            overlapping,
+        } = compaction;
+
+        let version = self.shared.state.lock().version.clone();
+        let mut next = Version::clone(&version);
+        next.levels[level].retain(|table| !inputs.iter().any(|input| Arc::ptr_eq(input, table)));
+
+        if level > 0 && overlapping.is_empty() {
+            // Nothing to merge with, so the table can just move down.
+            next.levels[level + 1].extend(inputs.iter().cloned());
+        } else {
+            let outputs = self.merge(&version, level, inputs.iter().chain(&overlapping))?;
+            next.levels[level + 1]
+                .retain(|table| !overlapping.iter().any(|old| Arc::ptr_eq(old, table)));
+            next.levels[level + 1].extend(outputs);
+            self.obsolete.extend(inputs.iter().chain(&overlapping).cloned());
+        }
+        next.levels[level + 1].sort_by(|a, b| a.first_key().cmp(b.first_key()));
+
+        if level > 0 {
+            self.compact_from[level] = inputs.last().unwrap().last_key().to_vec();
+        }
+
+        self.write_manifest(&next)?;
+
+        self.shared.state.lock().version = Arc::new(next);
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    /// Merge SSTables, newest first, into new ones for `level + 1`.
+    fn merge<'a>(
+        &mut self,
+        version: &Version,
+        level: usize,
+        tables: impl Iterator<Item = &'a Arc<SsTable>>,
+    ) -> Result<Vec<Arc<SsTable>>, Error> {
+        let sources = tables
+            .map(|table| Ok(Box::new(SsTableIter::new(table.clone(), Bound::Unbounded)?) as Source))
+            .collect::<Result<_, Error>>()?;
+
+        // A delete only has to be kept while there's something older under it.
+        let bottom = version.levels[level + 2..].iter().all(Vec::is_empty);
+
+        let mut outputs = vec![];
+        let mut writer = None;
+        for entry in MergeIter::new(sources) {
+            let (key, value) = entry?;
+            if bottom && value.is_none() {
+                continue;
+            }
+
+            let current = match &mut writer {
+                Some(current) => current,
+                None => writer.insert(self.writer()?),
+            };
+            current.add(&key, value.as_deref())?;
+
+            if current.size() >= self.shared.options.table_size {
+                outputs.push(Arc::new(writer.take().unwrap().finish()?));
+            }
+        }
+
+        if let Some(writer) = writer {
+            outputs.push(Arc::new(writer.finish()?));
+        }
+
+        Ok(outputs)
+    }
+
+    /// Delete the replaced SSTables that nothing's reading anymore: the last
+    /// reference to each is ours.
+    fn delete_obsolete(&mut self) -> Result<(), Error> {
+        let (unused, used) = std::mem::take(&mut self.obsolete)
+            .into_iter()
+            .partition::<Vec<_>, _>(|table| Arc::strong_count(table) == 1);
+        self.obsolete = used;
+
+        for table in unused {
+            SsTable::delete_file(&self.shared.files, &self.shared.name, table.number())?;
+        }
+
+        Ok(())
+    }
+
+    fn writer(&mut self) -> Result<SsTableWriter, Error> {
+        let number = self.next_table;
+        self.next_table += 1;
+
+        SsTableWriter::new(
+            self.shared.files.clone(),
+            &self.shared.name,
+            number,
+            self.shared.options.bloom_bits_per_key,
+        )
+    }
+
+    fn write_manifest(&self, version: &Version) -> Result<(), Error> {
+        let numbers = |tables: &[Arc<SsTable>]| tables.iter().map(|table| table.number()).collect();
+
+        let manifest = Manifest {
+            log_start: self.log_start,
+            next_table: self.next_table,
+            levels: version.levels.iter().map(|tables| numbers(tables)).collect(),
+            obsolete: numbers(&self.obsolete),
+        };
+
+        write_manifest(&self.shared.files, &self.shared.wal, self.shared.manifest_file, &manifest)
+    }
+}
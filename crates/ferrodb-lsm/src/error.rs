@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Fs(#[from] ferrodb_fs::Error),
+    #[error(transparent)]
+    Wal(#[from] ferrodb_wal::Error),
+    #[error("Entry is {len} bytes, but an entry can be at most {max} bytes")]
+    EntryTooLarge { len: usize, max: usize },
+    #[error("LSM tree `{tree}` is corrupt: {reason}")]
+    Corrupt { tree: String, reason: &'static str },
+    #[error("LSM tree `{tree}` can't be written to, since writing out its SSTables failed: {reason}")]
+    Failed { tree: String, reason: String },
+}
@@ -0,0 +1,564 @@
+//! Tables stored as LSM trees, for write-heavy tables. Writes go to a sorted
+//! memtable in memory, which is written out as a sorted, immutable SSTable
+//! once it fills up. Reads merge the memtable with the SSTables, newest
+//! first.
+//!
+//! The SSTables are kept in levels. Level 0 is where memtables are written out
+//! to, so its SSTables can overlap each other, and once there are
+//! [`LsmOptions::level0_tables`] of them, they're all merged into level 1. From
+//! level 1 on, the SSTables of a level don't overlap, and each level holds
+//! [`LsmOptions::level_ratio`] times as much as the one before it. When one
+//! holds more than that, one of its SSTables is merged into the ones of the
+//! next level that it overlaps. All of that is done by a background thread,
+//! and writes wait for it if it falls too far behind.
+//!
+//! Writes aren't part of any transaction: each one is logged as an
+//! [`LogRecord::LsmWrite`], and is durable once it returns. The manifest says
+//! where in the log the memtable starts, and the log is replayed from there
+//! when the tree is opened. Changes to the manifest go through a [`Txn`], like
+//! everything else, and only once the SSTables that they mention are synced.
+//!
+//! A tree is the file named after it, which holds its manifest, and its
+//! SSTables are the files named `<tree>.sst.<number>`.
+//!
+//! [`Txn`]: ferrodb_wal::Txn
+
+mod bloom;
+mod compaction;
+mod error;
+mod manifest;
+mod memtable;
+mod merge;
+mod sstable;
+
+use std::collections::VecDeque;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use ferrodb_fs::{FileId, FileKind, FileManager, Lsn, PAGE_HEADER_SIZE};
+use ferrodb_page::page_size;
+use ferrodb_wal::{LogRecord, Wal};
+use parking_lot::{Condvar, Mutex};
+
+use self::compaction::Worker;
+pub use self::error::Error;
+use self::manifest::Manifest;
+use self::memtable::{MemTable, MemTableIter};
+use self::merge::{MergeIter, Source};
+use self::sstable::{SsTable, SsTableIter};
+
+/// A key and what it was last set to, or `None` if it was deleted.
+type Entry = (Vec<u8>, Option<Vec<u8>>);
+
+/// How many levels there are, counting level 0.
+const LEVELS: usize = 7;
+/// How many memtables can be waiting to be written out before writes wait.
+const MAX_IMMUTABLE: usize = 2;
+/// How many times [`LsmOptions::level0_tables`] level 0 can get to before
+/// writes wait.
+const LEVEL0_STOP_FACTOR: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct LsmOptions {
+    /// About how many bytes of writes the memtable holds before it's written
+    /// out.
+    pub memtable_size: usize,
+    /// How many SSTables level 0 gets to before they're merged into level 1.
+    pub level0_tables: usize,
+    /// How big the SSTables that compaction writes get.
+    pub table_size: u64,
+    /// How many times more each level from 1 on holds than the one before it,
+    /// where level 1 holds this many SSTables' worth.
+    pub level_ratio: u64,
+    /// How many bits of bloom filter each key gets. The default of 10 gives
+    /// about 1% false positives.
+    pub bloom_bits_per_key: usize,
+}
+
+impl Default for LsmOptions {
+    fn default() -> LsmOptions {
+        LsmOptions {
+            memtable_size: 4 << 20,
+            level0_tables: 4,
+            table_size: 2 << 20,
+            level_ratio: 10,
+            bloom_bits_per_key: 10,
+        }
+    }
+}
+
+pub struct LsmTree {
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// Everything a tree and its worker share.
+struct Shared {
+    files: Arc<FileManager>,
+    wal: Arc<Wal>,
+    name: String,
+    manifest_file: FileId,
+    options: LsmOptions,
+    state: Mutex<State>,
+    /// Notified whenever anything in `state` changes.
+    changed: Condvar,
+}
+
+struct State {
+    memtable: MemTable,
+    /// Memtables waiting to be written out, oldest first, each with the LSN
+    /// that the log carries on from after it.
+    immutable: VecDeque<(Arc<MemTable>, Lsn)>,
+    version: Arc<Version>,
+    /// Why the worker stopped, if writing something out failed.
+    failed: Option<String>,
+    closing: bool,
+}
+
+/// The SSTables of each level, as of some point.
+#[derive(Clone)]
+struct Version {
+    levels: Vec<Vec<Arc<SsTable>>>,
+}
+
+impl LsmTree {
+    /// Open the tree called `name`, setting it up if it's new, and start its
+    /// worker. The log has to have been recovered already.
+    pub fn open(
+        files: Arc<FileManager>,
+        wal: Arc<Wal>,
+        name: &str,
+        options: LsmOptions,
+    ) -> Result<LsmTree, Error> {
+        let manifest_file = files.open(name, FileKind::Lsm)?;
+        let manifest = match Manifest::read(&files, manifest_file, name)? {
+            Some(manifest) => manifest,
+            None => {
+                // Nothing in the log so far is this tree's, even if it's there
+                // for another tree that had the same name.
+                let manifest = Manifest {
+                    log_start: wal.next_lsn(),
+                    levels: vec![vec![]; LEVELS],
+                    ..Manifest::default()
+                };
+                write_manifest(&files, &wal, manifest_file, &manifest)?;
+                manifest
+            },
+        };
+        if manifest.levels.len() != LEVELS {
+            return Err(Error::Corrupt {
+                tree: name.to_owned(),
+                reason: "its manifest has the wrong number of levels",
+            });
+        }
+
+        delete_leftovers(&files, name, &manifest)?;
+
+        let mut levels = vec![];
+        for tables in &manifest.levels {
+            let tables = tables
+                .iter()
+                .map(|&number| Ok(Arc::new(SsTable::open(files.clone(), name, number)?)))
+                .collect::<Result<_, Error>>()?;
+            levels.push(tables);
+        }
+
+        // Whatever was written since the last memtable was written out.
+        let mut memtable = MemTable::default();
+        for record in wal.reader(manifest.log_start) {
+            if let (_, LogRecord::LsmWrite { tree, key, value }) = record? {
+                if tree == name {
+                    memtable.insert(key, value);
+                }
+            }
+        }
+
+        let shared = Arc::new(Shared {
+            files,
+            wal,
+            name: name.to_owned(),
+            manifest_file,
+            state: Mutex::new(State {
+                memtable,
+                immutable: VecDeque::new(),
+                version: Arc::new(Version { levels }),
+                failed: None,
+                closing: false,
+            }),
+            changed: Condvar::new(),
+            options,
+        });
+
+        {
+            let mut state = shared.state.lock();
+            if state.memtable.size() >= shared.options.memtable_size {
+                shared.freeze(&mut state);
+            }
+        }
+
+        let mut worker = Worker::new(shared.clone(), &manifest);
+        let worker = std::thread::spawn(move || worker.run());
+
+        Ok(LsmTree {
+            shared,
+            worker: Some(worker),
+        })
+    }
+
+    /// Delete every file of the tree called `name`, which mustn't be open. A
+    /// file at `name` that isn't a tree is just deleted.
+    pub fn delete_files(files: &FileManager, name: &str) -> Result<(), Error> {
+        if !files.exists(name)? {
+            return Ok(());
+        }
+
+        match files.open(name, FileKind::Lsm) {
+            Ok(file) =>
+                if let Some(manifest) = Manifest::read(files, file, name)? {
+                    for &number in manifest.levels.iter().flatten() {
+                        SsTable::delete_file(files, name, number)?;
+                    }
+                    delete_leftovers(files, name, &manifest)?;
+                },
+            Err(ferrodb_fs::Error::FileKindMismatch { .. }) => {},
+            Err(e) => return Err(e.into()),
+        }
+
+        files.delete(files.id(name))?;
+        Ok(())
+    }
+
+    /// The file that holds the tree's manifest.
+    pub fn file(&self) -> FileId {
+        self.shared.manifest_file
+    }
+
+    /// The longest key and value, together, that an entry can have.
+    pub fn max_entry_len() -> usize {
+        sstable::max_entry_len(body_len()).min(u16::MAX as usize)
+    }
+
+    /// Set `key` to `value`, replacing whatever it was set to before.
+    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        self.write(&[(key, Some(value))])
+    }
+
+    pub fn delete(&self, key: &[u8]) -> Result<(), Error> {
+        self.write(&[(key, None)])
+    }
+
+    /// Set each key to its value, or delete it for `None`, in order. They're
+    /// all logged together, and are durable once this returns.
+    pub fn write(&self, batch: &[(&[u8], Option<&[u8]>)]) -> Result<(), Error> {
+        for (key, value) in batch {
+            let len = key.len() + value.map_or(0, <[u8]>::len);
+            if len > LsmTree::max_entry_len() {
+                return Err(Error::EntryTooLarge {
+                    len,
+                    max: LsmTree::max_entry_len(),
+                });
+            }
+        }
+
+        let records: Vec<_> = batch
+            .iter()
+            .map(|(key, value)| LogRecord::LsmWrite {
+                tree: self.shared.name.clone(),
+                key: key.to_vec(),
+                value: value.map(<[u8]>::to_vec),
+            })
+            .collect();
+
+        let mut state = self.shared.state.lock();
+
+        // Let the worker catch up if it's fallen too far behind.
+        let level0_stop = self.shared.options.level0_tables * LEVEL0_STOP_FACTOR;
+        while state.failed.is_none()
+            && (state.immutable.len() >= MAX_IMMUTABLE
+                || state.version.levels[0].len() >= level0_stop)
+        {
+            self.shared.changed.wait(&mut state);
+        }
+        self.shared.check(&state)?;
+
+        // Appending while holding the state keeps the memtable in log order.
+        let lsns = self.shared.wal.append_batch(&records)?;
+        for record in records {
+            let LogRecord::LsmWrite { key, value, .. } = record
+                else { unreachable!() };
+            state.memtable.insert(key, value);
+        }
+
+        if state.memtable.size() >= self.shared.options.memtable_size {
+            self.shared.freeze(&mut state);
+        }
+        drop(state);
+
+        if let Some(&lsn) = lsns.last() {
+            self.shared.wal.flush_to(lsn)?;
+        }
+
+        Ok(())
+    }
+
+    /// What `key` is set to.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        let version = {
+            let state = self.shared.state.lock();
+
+            let memtables = [&state.memtable]
+                .into_iter()
+                .chain(state.immutable.iter().rev().map(|(memtable, _)| &**memtable));
+            for memtable in memtables {
+                if let Some(value) = memtable.get(key) {
+                    return Ok(value.map(<[u8]>::to_vec));
+                }
+            }
+
+            state.version.clone()
+        };
+
+        let hash = bloom::key_hash(key);
+
+        for table in &version.levels[0] {
+            if let Some(value) = table.get(key, hash)? {
+                return Ok(value);
+            }
+        }
+
+        for level in &version.levels[1..] {
+            let index = level.partition_point(|table| table.last_key() < key);
+            if let Some(table) = level.get(index) {
+                if let Some(value) = table.get(key, hash)? {
+                    return Ok(value);
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Every key in `range` and what it's set to, in order, as of now.
+    pub fn range(&self, range: impl RangeBounds<[u8]>) -> Result<Scan, Error> {
+        let start = range.start_bound();
+        let end = range.end_bound();
+
+        let (mut sources, version) = {
+            let state = self.shared.state.lock();
+
+            // The memtable keeps changing, so it's copied, but the others don't.
+            let memtable = state.memtable.range(start, end);
+            let mut sources: Vec<Source> = vec![Box::new(memtable.into_iter().map(Ok))];
+            for (memtable, _) in state.immutable.iter().rev() {
+                sources.push(Box::new(MemTableIter::new(memtable.clone(), start)));
+            }
+
+            (sources, state.version.clone())
+        };
+
+        for table in &version.levels[0] {
+            if in_range(table, start, end) {
+                sources.push(Box::new(SsTableIter::new(table.clone(), start)?));
+            }
+        }
+
+        for level in &version.levels[1..] {
+            let tables = level
+                .iter()
+                .filter(|table| in_range(table, start, end))
+                .cloned()
+                .collect();
+            sources.push(Box::new(LevelIter::new(tables, start)));
+        }
+
+        Ok(Scan {
+            merge: MergeIter::new(sources),
+            end: end.map(<[u8]>::to_vec),
+            done: false,
+        })
+    }
+
+    /// Every key and what it's set to, in order, as of now.
+    pub fn iter(&self) -> Result<Scan, Error> {
+        self.range(..)
+    }
+
+    /// Write the memtable out now, rather than once it fills up, and wait
+    /// until every memtable has been written out.
+    pub fn flush(&self) -> Result<(), Error> {
+        let mut state = self.shared.state.lock();
+
+        if !state.memtable.is_empty() {
+            self.shared.freeze(&mut state);
+        }
+        while state.failed.is_none() && !state.immutable.is_empty() {
+            self.shared.changed.wait(&mut state);
+        }
+
+        self.shared.check(&state)
+    }
+
+    /// How many SSTables each level has, from level 0 on.
+    pub fn level_tables(&self) -> Vec<usize> {
+        let version = self.shared.state.lock().version.clone();
+        version.levels.iter().map(Vec::len).collect()
+    }
+}
+
+impl Drop for LsmTree {
+    /// Stop the worker once it's done with what it's doing. Anything that's
+    /// still in a memtable is in the log.
+    fn drop(&mut self) {
+        self.shared.state.lock().closing = true;
+        self.shared.changed.notify_all();
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Shared {
+    /// Hand the memtable over to the worker to write out, and start a new one.
+    fn freeze(&self, state: &mut State) {
+        let memtable = std::mem::take(&mut state.memtable);
+        state
+            .immutable
+            .push_back((Arc::new(memtable), self.wal.next_lsn()));
+        self.changed.notify_all();
+    }
+
+    fn check(&self, state: &State) -> Result<(), Error> {
+        match &state.failed {
+            Some(reason) => Err(Error::Failed {
+                tree: self.name.clone(),
+                reason: reason.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Some of the keys of a tree and what they're set to, in order, as of when
+/// the scan started.
+pub struct Scan {
+    merge: MergeIter,
+    end: Bound<Vec<u8>>,
+    done: bool,
+}
+
+impl Iterator for Scan {
+    type Item = Result<(Vec<u8>, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            let (key, value) = match self.merge.next()? {
+                Ok(entry) => entry,
+                Err(e) => return Some(Err(e)),
+            };
+
+            self.done = match &self.end {
+                Bound::Included(end) => key > *end,
+                Bound::Excluded(end) => key >= *end,
+                Bound::Unbounded => false,
+            };
+
+            // Deletes only hide what's under them.
+            if let (false, Some(value)) = (self.done, value) {
+                return Some(Ok((key, value)));
+            }
+        }
+
+        None
+    }
+}
+
+/// Walks over the SSTables of a level from 1 on, which are in order and don't
+/// overlap, opening one at a time.
+struct LevelIter {
+    tables: VecDeque<Arc<SsTable>>,
+    start: Bound<Vec<u8>>,
+    current: Option<SsTableIter>,
+}
+
+impl LevelIter {
+    fn new(tables: VecDeque<Arc<SsTable>>, start: Bound<&[u8]>) -> LevelIter {
+        LevelIter {
+            tables,
+            start: start.map(<[u8]>::to_vec),
+            current: None,
+        }
+    }
+}
+
+impl Iterator for LevelIter {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.current.as_mut().and_then(Iterator::next) {
+                return Some(entry);
+            }
+
+            let table = self.tables.pop_front()?;
+            match SsTableIter::new(table, self.start.as_ref().map(Vec::as_slice)) {
+                Ok(iter) => self.current = Some(iter),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// Whether a table could have any keys from `start` to `end`.
+fn in_range(table: &SsTable, start: Bound<&[u8]>, end: Bound<&[u8]>) -> bool {
+    let after_start = match start {
+        Bound::Included(start) => table.last_key() >= start,
+        Bound::Excluded(start) => table.last_key() > start,
+        Bound::Unbounded => true,
+    };
+    let before_end = match end {
+        Bound::Included(end) => table.first_key() <= end,
+        Bound::Excluded(end) => table.first_key() < end,
+        Bound::Unbounded => true,
+    };
+
+    after_start && before_end
+}
+
+fn write_manifest(
+    files: &FileManager,
+    wal: &Wal,
+    file: FileId,
+    manifest: &Manifest,
+) -> Result<(), Error> {
+    let txn = wal.begin()?;
+
+    if let Err(e) = manifest.write(&txn, files, file) {
+        txn.abort(files)?;
+        return Err(e);
+    }
+
+    txn.commit()?;
+    Ok(())
+}
+
+/// Delete the SSTables that a crash left behind: ones that were replaced, but
+/// not deleted yet, and ones that were being written.
+fn delete_leftovers(files: &FileManager, name: &str, manifest: &Manifest) -> Result<(), Error> {
+    for &number in &manifest.obsolete {
+        SsTable::delete_file(files, name, number)?;
+    }
+
+    let mut number = manifest.next_table;
+    while files.exists(&sstable::file_name(name, number))? {
+        SsTable::delete_file(files, name, number)?;
+        number += 1;
+    }
+
+    Ok(())
+}
+
+fn body_len() -> usize {
+    page_size() - PAGE_HEADER_SIZE
+}
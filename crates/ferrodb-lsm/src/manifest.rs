@@ -0,0 +1,123 @@
+//! The manifest says which SSTables make up a tree, and where in the log its
+//! memtable starts. It's the tree's own file, starting on page 1 and carrying
+//! on over as many pages as it takes:
+//!
+//! ```text
+//! [len: u32][log start: u64][next table: u64]
+//! [levels: u8] ([tables: u32][table: u64] * tables) * levels
+//! [obsolete: u32][table: u64] * obsolete
+//! ```
+//!
+//! An all-zero manifest is a tree that hasn't been set up yet. Changes to it
+//! go through a [`Txn`], so it's always in line with the log.
+
+use ferrodb_fs::{FileId, FileManager, Lsn, PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_wal::Txn;
+
+use crate::{body_len, Error};
+
+const FIRST_PAGE: PageIndex = 1;
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Manifest {
+    /// Where to start replaying the log into the memtable from.
+    pub log_start: Lsn,
+    /// The number the next SSTable will get. Any file from here on is left
+    /// over from writing a table that never made it into the manifest.
+    pub next_table: u64,
+    /// The SSTables of each level: newest first in level 0, and in key order
+    /// after that.
+    pub levels: Vec<Vec<u64>>,
+    /// SSTables that have been replaced, but might not have been deleted yet.
+    pub obsolete: Vec<u64>,
+}
+
+impl Manifest {
+    /// Read the manifest, or `None` if it's never been written.
+    pub fn read(files: &FileManager, file: FileId, tree: &str) -> Result<Option<Manifest>, Error> {
+        let mut bytes = vec![];
+        let mut page = FIRST_PAGE;
+        let mut len = None;
+
+        while bytes.len() < len.unwrap_or(4) {
+            if page >= files.page_count(file)? {
+                break;
+            }
+
+            bytes.extend_from_slice(&files.latest(file, page)?.read()[PAGE_HEADER_SIZE..]);
+            page += 1;
+
+            if len.is_none() {
+                len = Some(u32::from_le_bytes(bytes[..4].try_into().unwrap()) as usize);
+            }
+        }
+
+        match len {
+            None | Some(0) => Ok(None),
+            Some(len) => match Manifest::decode(bytes.get(4..len)) {
+                Some(manifest) => Ok(Some(manifest)),
+                None => Err(Error::Corrupt {
+                    tree: tree.to_owned(),
+                    reason: "its manifest can't be read",
+                }),
+            },
+        }
+    }
+
+    pub fn write(&self, txn: &Txn<'_>, files: &FileManager, file: FileId) -> Result<(), Error> {
+        let mut bytes = vec![0; 4];
+        bytes.extend_from_slice(&self.log_start.to_le_bytes());
+        bytes.extend_from_slice(&self.next_table.to_le_bytes());
+        bytes.push(self.levels.len() as u8);
+        for level in self.levels.iter().chain([&self.obsolete]) {
+            bytes.extend_from_slice(&(level.len() as u32).to_le_bytes());
+            for table in level {
+                bytes.extend_from_slice(&table.to_le_bytes());
+            }
+        }
+
+        let len = bytes.len() as u32;
+        bytes[..4].copy_from_slice(&len.to_le_bytes());
+
+        for (i, chunk) in bytes.chunks(body_len()).enumerate() {
+            let page = FIRST_PAGE + i;
+            while files.page_count(file)? <= page {
+                files.allocate(file)?;
+            }
+
+            txn.modify(files, file, page, |body| body[..chunk.len()].copy_from_slice(chunk))?;
+        }
+
+        Ok(())
+    }
+
+    fn decode(bytes: Option<&[u8]>) -> Option<Manifest> {
+        let bytes = bytes?;
+        let mut at = 0;
+        let read_u64 = |at: &mut usize| {
+            let value = u64::from_le_bytes(bytes.get(*at..*at + 8)?.try_into().unwrap());
+            *at += 8;
+            Some(value)
+        };
+
+        let log_start = read_u64(&mut at)?;
+        let next_table = read_u64(&mut at)?;
+        let level_count = *bytes.get(at)? as usize;
+        at += 1;
+
+        let mut lists = Vec::with_capacity(level_count + 1);
+        for _ in 0..=level_count {
+            let len = u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().unwrap());
+            at += 4;
+            lists.push((0..len).map(|_| read_u64(&mut at)).collect::<Option<Vec<_>>>()?);
+        }
+
+        let obsolete = lists.pop()?;
+        Some(Manifest {
+            log_start,
+            next_table,
+            levels: lists,
+            obsolete,
+        })
+    }
+}
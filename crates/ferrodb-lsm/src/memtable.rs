@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use crate::{Entry, Error};
+
+/// Roughly what each entry costs on top of its key and value.
+const ENTRY_OVERHEAD: usize = 32;
+
+/// The latest writes to a tree, in memory and in order, with `None` for a
+/// delete.
+#[derive(Default)]
+pub(crate) struct MemTable {
+    entries: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    /// About how many bytes the entries take up.
+    size: usize,
+}
+
+impl MemTable {
+    pub fn insert(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) {
+        let key_len = key.len();
+        let value_len = value.as_ref().map_or(0, Vec::len);
+
+        match self.entries.insert(key, value) {
+            Some(old) => self.size = self.size - old.map_or(0, |old| old.len()) + value_len,
+            None => self.size += key_len + value_len + ENTRY_OVERHEAD,
+        }
+    }
+
+    /// The latest write to `key`, if there's been one.
+    pub fn get(&self, key: &[u8]) -> Option<Option<&[u8]>> {
+        self.entries.get(key).map(Option::as_deref)
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], Option<&[u8]>)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (&**key, value.as_deref()))
+    }
+
+    /// Copies of the entries from `start` up to `end`.
+    pub fn range(&self, start: Bound<&[u8]>, end: Bound<&[u8]>) -> Vec<Entry> {
+        self.entries
+            .range::<[u8], _>((start, end))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// Walks over a memtable that's waiting to be written out, which nothing can
+/// change anymore, an entry at a time.
+pub(crate) struct MemTableIter {
+    table: Arc<MemTable>,
+    next: Bound<Vec<u8>>,
+}
+
+impl MemTableIter {
+    pub fn new(table: Arc<MemTable>, start: Bound<&[u8]>) -> MemTableIter {
+        MemTableIter {
+            table,
+            next: start.map(<[u8]>::to_vec),
+        }
+    }
+}
+
+impl Iterator for MemTableIter {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bound = self.next.as_ref().map(Vec::as_slice);
+        let (key, value) = self
+            .table
+            .entries
+            .range::<[u8], _>((bound, Bound::Unbounded))
+            .next()?;
+
+        self.next = Bound::Excluded(key.clone());
+        Some(Ok((key.clone(), value.clone())))
+    }
+}
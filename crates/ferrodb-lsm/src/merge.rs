@@ -0,0 +1,89 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+use crate::{Entry, Error};
+
+pub(crate) type Source = Box<dyn Iterator<Item = Result<Entry, Error>>>;
+
+/// Merges sources that are each in key order into one, in key order. A key
+/// that's in more than one source comes from the first of them, which is
+/// the newest, and the older ones are skipped.
+pub(crate) struct MergeIter {
+    sources: Vec<Source>,
+    /// The next entry of each source, until it runs out.
+    heads: Vec<Option<Entry>>,
+    /// The key of every head, with its source, smallest first, and the newest
+    /// source first for the same key.
+    queue: BinaryHeap<Reverse<(Vec<u8>, usize)>>,
+    /// What went wrong reading the first entries of the sources.
+    error: Option<Error>,
+    /// Nothing more is returned after an error.
+    failed: bool,
+}
+
+impl MergeIter {
+    pub fn new(sources: Vec<Source>) -> MergeIter {
+        let mut merge = MergeIter {
+            heads: sources.iter().map(|_| None).collect(),
+            sources,
+            queue: BinaryHeap::new(),
+            error: None,
+            failed: false,
+        };
+
+        for source in 0..merge.sources.len() {
+            if let Err(e) = merge.advance(source) {
+                merge.error.get_or_insert(e);
+            }
+        }
+
+        merge
+    }
+
+    /// Move a source on to its next entry.
+    fn advance(&mut self, source: usize) -> Result<(), Error> {
+        let head = self.sources[source].next().transpose()?;
+        if let Some((key, _)) = &head {
+            self.queue.push(Reverse((key.clone(), source)));
+        }
+
+        self.heads[source] = head;
+        Ok(())
+    }
+
+    fn next_entry(&mut self) -> Option<Result<Entry, Error>> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+
+        let Reverse((key, source)) = self.queue.pop()?;
+        let entry = self.heads[source].take().unwrap();
+        if let Err(e) = self.advance(source) {
+            return Some(Err(e));
+        }
+
+        // Older versions of the same key.
+        while matches!(self.queue.peek(), Some(Reverse((next, _))) if *next == key) {
+            let Reverse((_, older)) = self.queue.pop().unwrap();
+            if let Err(e) = self.advance(older) {
+                return Some(Err(e));
+            }
+        }
+
+        Some(Ok(entry))
+    }
+}
+
+impl Iterator for MergeIter {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+
+        let next = self.next_entry();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
@@ -0,0 +1,410 @@
+//! SSTables: sorted runs of entries that are written once, in order, and
+//! never changed after that. Each one is a file of its own:
+//!
+//! ```text
+//! page 1:          [data pages: u64][entries: u64][footer len: u64]
+//! pages 2..:       data pages, each [count: u16][entry] * count
+//! after the data:  the footer, over as many pages as it takes
+//! ```
+//!
+//! An entry is `[key len: u16][value len: u32][key][value]`, where a value
+//! length of `u32::MAX` is a delete, with no value. The footer is the first
+//! key of every data page and then the last key of the table, each as
+//! `[len: u16][key]`, followed by the table's bloom filter. It's read into
+//! memory when the table is opened, so a lookup reads at most one page.
+//!
+//! Nothing about an SSTable is logged. It's synced as soon as it's written,
+//! and only after that does the manifest say it's there.
+
+use std::collections::VecDeque;
+use std::ops::Bound;
+use std::sync::Arc;
+
+use ferrodb_fs::{FileId, FileKind, FileManager, PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_page::page_size;
+
+use crate::bloom::{self, BloomFilter, KeyHash};
+use crate::{body_len, Entry, Error};
+
+const META_PAGE: PageIndex = 1;
+const FIRST_DATA_PAGE: PageIndex = 2;
+
+const COUNT_SIZE: usize = 2;
+const ENTRY_HEADER_SIZE: usize = 6;
+/// The value length of a delete.
+const DELETED: u32 = u32::MAX;
+
+/// The longest key and value, together, that fit in a data page.
+pub(crate) fn max_entry_len(body_len: usize) -> usize {
+    body_len - COUNT_SIZE - ENTRY_HEADER_SIZE
+}
+
+pub(crate) fn file_name(tree: &str, number: u64) -> String {
+    format!("{tree}.sst.{number}")
+}
+
+pub(crate) struct SsTable {
+    files: Arc<FileManager>,
+    number: u64,
+    file: FileId,
+    /// The first key of each data page.
+    first_keys: Vec<Vec<u8>>,
+    last_key: Vec<u8>,
+    bloom: BloomFilter,
+    /// How many pages the file has, including its header.
+    pages: PageIndex,
+}
+
+impl SsTable {
+    pub fn open(files: Arc<FileManager>, tree: &str, number: u64) -> Result<SsTable, Error> {
+        let file = files.open(&file_name(tree, number), FileKind::SsTable)?;
+        let corrupt = |reason| Error::Corrupt {
+            tree: tree.to_owned(),
+            reason,
+        };
+
+        let (data_pages, footer_len) = {
+            let meta_ref = files.latest(file, META_PAGE)?;
+            let meta = meta_ref.read();
+            let body = &meta[PAGE_HEADER_SIZE..];
+            (read_u64(body, 0) as usize, read_u64(body, 16) as usize)
+        };
+        if data_pages == 0 {
+            return Err(corrupt("an SSTable in the manifest was never finished"));
+        }
+
+        let mut footer = Vec::with_capacity(footer_len);
+        let mut page = FIRST_DATA_PAGE + data_pages;
+        while footer.len() < footer_len {
+            let len = (footer_len - footer.len()).min(body_len());
+            footer.extend_from_slice(&files.latest(file, page)?.read()[PAGE_HEADER_SIZE..][..len]);
+            page += 1;
+        }
+
+        let Some((first_keys, last_key, bloom)) = decode_footer(&footer, data_pages)
+            else { return Err(corrupt("an SSTable's footer can't be read")); };
+
+        Ok(SsTable {
+            files,
+            number,
+            file,
+            first_keys,
+            last_key,
+            bloom,
+            pages: page,
+        })
+    }
+
+    /// Delete the file of a table, which nobody can be reading anymore.
+    pub fn delete_file(files: &FileManager, tree: &str, number: u64) -> Result<(), Error> {
+        files.delete(files.id(&file_name(tree, number)))?;
+        Ok(())
+    }
+
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    pub fn first_key(&self) -> &[u8] {
+        &self.first_keys[0]
+    }
+
+    pub fn last_key(&self) -> &[u8] {
+        &self.last_key
+    }
+
+    /// How many bytes the table takes up.
+    pub fn size(&self) -> u64 {
+        (self.pages * page_size()) as u64
+    }
+
+    /// Whether any of the keys from `first` to `last` could be in the table.
+    pub fn overlaps(&self, first: &[u8], last: &[u8]) -> bool {
+        self.first_key() <= last && first <= self.last_key()
+    }
+
+    /// The write to `key` in this table, if there is one, with `None` for a
+    /// delete.
+    pub fn get(&self, key: &[u8], hash: KeyHash) -> Result<Option<Option<Vec<u8>>>, Error> {
+        if key < self.first_key() || key > self.last_key() || !self.bloom.may_contain(hash) {
+            return Ok(None);
+        }
+
+        let index = self.page_for(key);
+        let page_ref = self.files.latest(self.file, FIRST_DATA_PAGE + index)?;
+        let buf = page_ref.read();
+
+        for (k, value) in entries(&buf[PAGE_HEADER_SIZE..]) {
+            if k == key {
+                return Ok(Some(value.map(<[u8]>::to_vec)));
+            }
+            if k > key {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The data page that `key` would be in, as an index into `first_keys`.
+    fn page_for(&self, key: &[u8]) -> usize {
+        self.first_keys
+            .partition_point(|first| first.as_slice() <= key)
+            .saturating_sub(1)
+    }
+}
+
+/// Walks over a table's entries in order, a page at a time.
+pub(crate) struct SsTableIter {
+    table: Arc<SsTable>,
+    /// The next data page to read, as an index into `first_keys`.
+    next_page: usize,
+    buf: VecDeque<Entry>,
+}
+
+impl SsTableIter {
+    /// Start at the first entry in `start`.
+    pub fn new(table: Arc<SsTable>, start: Bound<&[u8]>) -> Result<SsTableIter, Error> {
+        let next_page = match start {
+            Bound::Included(key) | Bound::Excluded(key) => table.page_for(key),
+            Bound::Unbounded => 0,
+        };
+        let mut iter = SsTableIter {
+            table,
+            next_page,
+            buf: VecDeque::new(),
+        };
+
+        iter.read_page()?;
+        while let Some((key, _)) = iter.buf.front() {
+            let before = match start {
+                Bound::Included(start) => key.as_slice() < start,
+                Bound::Excluded(start) => key.as_slice() <= start,
+                Bound::Unbounded => false,
+            };
+            if !before {
+                break;
+            }
+
+            iter.buf.pop_front();
+        }
+
+        Ok(iter)
+    }
+
+    fn read_page(&mut self) -> Result<(), Error> {
+        if self.next_page == self.table.first_keys.len() {
+            return Ok(());
+        }
+
+        let page = FIRST_DATA_PAGE + self.next_page;
+        let page_ref = self.table.files.latest(self.table.file, page)?;
+        let buf = page_ref.read();
+
+        self.buf.extend(
+            entries(&buf[PAGE_HEADER_SIZE..])
+                .map(|(key, value)| (key.to_vec(), value.map(<[u8]>::to_vec))),
+        );
+        self.next_page += 1;
+        Ok(())
+    }
+}
+
+impl Iterator for SsTableIter {
+    type Item = Result<Entry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            if let Err(e) = self.read_page() {
+                return Some(Err(e));
+            }
+        }
+
+        self.buf.pop_front().map(Ok)
+    }
+}
+
+/// Writes a new table, an entry at a time, in order.
+pub(crate) struct SsTableWriter {
+    files: Arc<FileManager>,
+    number: u64,
+    file: FileId,
+    bits_per_key: usize,
+    /// The body of the data page being filled in.
+    page: Vec<u8>,
+    count: u16,
+    first_keys: Vec<Vec<u8>>,
+    last_key: Vec<u8>,
+    hashes: Vec<KeyHash>,
+    /// The page that `page` will be written to.
+    next_page: PageIndex,
+}
+
+impl SsTableWriter {
+    pub fn new(
+        files: Arc<FileManager>,
+        tree: &str,
+        number: u64,
+        bits_per_key: usize,
+    ) -> Result<SsTableWriter, Error> {
+        // Anything that's already there was being written when we crashed.
+        let name = file_name(tree, number);
+        files.delete(files.id(&name))?;
+        let file = files.open(&name, FileKind::SsTable)?;
+
+        Ok(SsTableWriter {
+            files,
+            number,
+            file,
+            bits_per_key,
+            page: vec![0; COUNT_SIZE],
+            count: 0,
+            first_keys: vec![],
+            last_key: vec![],
+            hashes: vec![],
+            next_page: FIRST_DATA_PAGE,
+        })
+    }
+
+    /// Add an entry, which has to come after every one added so far. Its key
+    /// and value have to fit in [`max_entry_len`].
+    pub fn add(&mut self, key: &[u8], value: Option<&[u8]>) -> Result<(), Error> {
+        let len = ENTRY_HEADER_SIZE + key.len() + value.map_or(0, <[u8]>::len);
+        if self.page.len() + len > body_len() {
+            self.write_page()?;
+        }
+
+        if self.count == 0 {
+            self.first_keys.push(key.to_vec());
+        }
+
+        let value_len = value.map_or(DELETED, |value| value.len() as u32);
+        self.page.extend_from_slice(&(key.len() as u16).to_le_bytes());
+        self.page.extend_from_slice(&value_len.to_le_bytes());
+        self.page.extend_from_slice(key);
+        self.page.extend_from_slice(value.unwrap_or_default());
+
+        self.count += 1;
+        self.last_key = key.to_vec();
+        self.hashes.push(bloom::key_hash(key));
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// About how many bytes the table takes up so far.
+    pub fn size(&self) -> u64 {
+        ((self.next_page + 1) * page_size()) as u64
+    }
+
+    /// Write out the rest of the table, and sync all of it. There has to be
+    /// at least one entry.
+    pub fn finish(mut self) -> Result<SsTable, Error> {
+        assert!(!self.is_empty(), "Expected an SSTable to have entries");
+
+        if self.count > 0 {
+            self.write_page()?;
+        }
+
+        let data_pages = self.next_page - FIRST_DATA_PAGE;
+        let bloom = BloomFilter::build(&self.hashes, self.bits_per_key);
+
+        let mut footer = vec![];
+        for key in self.first_keys.iter().chain([&self.last_key]) {
+            footer.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            footer.extend_from_slice(key);
+        }
+        bloom.encode(&mut footer);
+
+        for chunk in footer.chunks(body_len()) {
+            self.write(self.next_page, chunk)?;
+            self.next_page += 1;
+        }
+
+        let mut meta = vec![];
+        meta.extend_from_slice(&(data_pages as u64).to_le_bytes());
+        meta.extend_from_slice(&(self.hashes.len() as u64).to_le_bytes());
+        meta.extend_from_slice(&(footer.len() as u64).to_le_bytes());
+        self.write(META_PAGE, &meta)?;
+
+        self.files
+            .sync_many((META_PAGE..self.next_page).map(|page| (self.file, page)))?;
+
+        Ok(SsTable {
+            files: self.files,
+            number: self.number,
+            file: self.file,
+            first_keys: self.first_keys,
+            last_key: self.last_key,
+            bloom,
+            pages: self.next_page,
+        })
+    }
+
+    fn write_page(&mut self) -> Result<(), Error> {
+        let mut page = std::mem::replace(&mut self.page, vec![0; COUNT_SIZE]);
+        page[..COUNT_SIZE].copy_from_slice(&self.count.to_le_bytes());
+
+        self.write(self.next_page, &page)?;
+        self.next_page += 1;
+        self.count = 0;
+        Ok(())
+    }
+
+    fn write(&self, page: PageIndex, body: &[u8]) -> Result<(), Error> {
+        while self.files.page_count(self.file)? <= page {
+            self.files.allocate(self.file)?;
+        }
+
+        let page_ref = self.files.dirty(self.file, page)?;
+        page_ref.write()[PAGE_HEADER_SIZE..][..body.len()].copy_from_slice(body);
+        Ok(())
+    }
+}
+
+/// The entries of a data page, in order. A page that's been corrupted just
+/// ends early.
+fn entries(body: &[u8]) -> impl Iterator<Item = (&[u8], Option<&[u8]>)> {
+    let count = u16::from_le_bytes(body[..COUNT_SIZE].try_into().unwrap());
+    let mut at = COUNT_SIZE;
+
+    (0..count).map_while(move |_| {
+        let header = body.get(at..at + ENTRY_HEADER_SIZE)?;
+        let key_len = u16::from_le_bytes(header[..2].try_into().unwrap()) as usize;
+        let value_len = u32::from_le_bytes(header[2..].try_into().unwrap());
+
+        let key_start = at + ENTRY_HEADER_SIZE;
+        let key = body.get(key_start..key_start + key_len)?;
+        let (value, value_len) = match value_len {
+            DELETED => (None, 0),
+            len => {
+                let value_start = key_start + key_len;
+                (Some(body.get(value_start..value_start + len as usize)?), len as usize)
+            },
+        };
+
+        at = key_start + key_len + value_len;
+        Some((key, value))
+    })
+}
+
+fn decode_footer(footer: &[u8], data_pages: usize) -> Option<(Vec<Vec<u8>>, Vec<u8>, BloomFilter)> {
+    let mut at = 0;
+    let mut keys = Vec::with_capacity(data_pages + 1);
+
+    for _ in 0..=data_pages {
+        let len = u16::from_le_bytes(footer.get(at..at + 2)?.try_into().unwrap()) as usize;
+        keys.push(footer.get(at + 2..at + 2 + len)?.to_vec());
+        at += 2 + len;
+    }
+
+    let last_key = keys.pop()?;
+    let bloom = BloomFilter::decode(&footer[at..])?;
+    Some((keys, last_key, bloom))
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
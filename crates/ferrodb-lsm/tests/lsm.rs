@@ -0,0 +1,149 @@
+use std::collections::BTreeMap;
+use std::ops::{Bound, Range};
+use std::sync::Arc;
+
+use ferrodb_fs::vfs::{CrashMode, CrashVfs, Vfs};
+use ferrodb_fs::FileManager;
+use ferrodb_lsm::{Error, LsmOptions, LsmTree};
+use ferrodb_wal::{recover, Wal};
+
+const PAGE_SIZE: usize = 512;
+
+/// Small enough that a few thousand writes go through every part of the tree.
+fn options() -> LsmOptions {
+    LsmOptions {
+        memtable_size: 2048,
+        level0_tables: 2,
+        table_size: 4096,
+        level_ratio: 2,
+        bloom_bits_per_key: 10,
+    }
+}
+
+fn open(vfs: Arc<dyn Vfs>) -> (Arc<FileManager>, Arc<Wal>, LsmTree) {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+
+    recover(&wal, &files, |_, _| {}).unwrap();
+    let tree = LsmTree::open(files.clone(), wal.clone(), "tree", options()).unwrap();
+    (files, wal, tree)
+}
+
+/// Writes, overwrites and deletes keys all over the place, keeping track of
+/// what the tree should end up with.
+fn write(tree: &LsmTree, expected: &mut BTreeMap<Vec<u8>, Vec<u8>>, writes: Range<usize>) {
+    for i in writes {
+        let key = format!("key{:05}", i * 7919 % 1000).into_bytes();
+        if i % 5 == 0 {
+            tree.delete(&key).unwrap();
+            expected.remove(&key);
+        } else {
+            let value = vec![i as u8; i % 40];
+            tree.put(&key, &value).unwrap();
+            expected.insert(key, value);
+        }
+    }
+}
+
+fn check(tree: &LsmTree, expected: &BTreeMap<Vec<u8>, Vec<u8>>) {
+    for i in 0..1000 {
+        let key = format!("key{i:05}").into_bytes();
+        assert_eq!(tree.get(&key).unwrap().as_ref(), expected.get(&key), "{i}");
+    }
+
+    let scanned: Vec<_> = tree.iter().unwrap().collect::<Result<_, _>>().unwrap();
+    assert_eq!(scanned, expected.clone().into_iter().collect::<Vec<_>>());
+
+    let (start, end) = (b"key00100".to_vec(), b"key00500".to_vec());
+    let scanned: Vec<_> = tree
+        .range((Bound::Included(&start[..]), Bound::Excluded(&end[..])))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    let in_range: Vec<_> = expected.range(start..end).map(|(k, v)| (k.clone(), v.clone())).collect();
+    assert_eq!(scanned, in_range);
+}
+
+#[test]
+fn reads_see_the_newest_write_wherever_it_is() {
+    let (_, _, tree) = open(Arc::new(CrashVfs::default()));
+    let mut expected = BTreeMap::new();
+
+    write(&tree, &mut expected, 0..5000);
+    check(&tree, &expected);
+
+    tree.flush().unwrap();
+    let levels = tree.level_tables();
+    assert!(levels[1..].iter().sum::<usize>() > 0, "Nothing was compacted: {levels:?}");
+    check(&tree, &expected);
+
+    // A key that's been deleted stays deleted, however far down what it was
+    // set to before has been compacted.
+    write(&tree, &mut expected, 5000..5200);
+    tree.delete(b"key00001").unwrap();
+    expected.remove(&b"key00001"[..]);
+    check(&tree, &expected);
+}
+
+#[test]
+fn writes_survive_a_restart_and_a_crash() {
+    let vfs = Arc::new(CrashVfs::default());
+    let (files, wal, tree) = open(vfs.clone());
+    let mut expected = BTreeMap::new();
+
+    write(&tree, &mut expected, 0..3000);
+    drop((files, wal, tree));
+    let (files, wal, tree) = open(vfs.clone());
+    check(&tree, &expected);
+
+    // Every write is durable once it returns, whether or not the tree has
+    // written it out yet.
+    write(&tree, &mut expected, 3000..4500);
+    let crashed = Arc::new(vfs.crash(CrashMode::DropUnsynced));
+    drop((files, wal, tree));
+
+    let (_, _, tree) = open(crashed);
+    check(&tree, &expected);
+    write(&tree, &mut expected, 4500..5000);
+    check(&tree, &expected);
+}
+
+#[test]
+fn entries_that_dont_fit_in_a_page_are_refused() {
+    let (_, _, tree) = open(Arc::new(CrashVfs::default()));
+    let max = LsmTree::max_entry_len();
+
+    tree.put(b"key", &vec![1; max - 3]).unwrap();
+    assert!(matches!(
+        tree.put(b"key", &vec![2; max - 2]),
+        Err(Error::EntryTooLarge { len, .. }) if len == max + 1
+    ));
+    // Nothing in a batch is written if any of it is refused.
+    let batch: [(&[u8], Option<&[u8]>); 2] = [(b"a", Some(b"1")), (b"key", Some(&vec![2; max]))];
+    assert!(tree.write(&batch).is_err());
+    assert_eq!(tree.get(b"a").unwrap(), None);
+
+    tree.flush().unwrap();
+    assert_eq!(tree.get(b"key").unwrap(), Some(vec![1; max - 3]));
+}
+
+#[test]
+fn deleting_a_tree_deletes_its_sstables() {
+    let vfs: Arc<dyn Vfs> = Arc::new(CrashVfs::default());
+    let (files, wal, tree) = open(vfs.clone());
+    write(&tree, &mut BTreeMap::new(), 0..2000);
+    tree.flush().unwrap();
+    drop(tree);
+
+    let names = || -> Vec<String> { vfs.list(".".into()).unwrap().into_iter().map(String::from).collect() };
+    assert!(names().iter().any(|name| name.starts_with("./tree.sst.")));
+    LsmTree::delete_files(&files, "tree").unwrap();
+    assert!(!names().iter().any(|name| name.starts_with("./tree")), "{:?}", names());
+
+    // A tree by the same name starts out empty.
+    let tree = LsmTree::open(files.clone(), wal, "tree", options()).unwrap();
+    assert_eq!(tree.iter().unwrap().count(), 0);
+}
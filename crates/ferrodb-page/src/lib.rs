@@ -1,25 +1,43 @@
-#![feature(once_cell)]
-#![feature(let_else)]
-
+// Not picked by `setup` yet, along with the replacement strategies that only
+// it uses.
+#[allow(dead_code)]
 mod buffered;
 mod page;
+#[allow(dead_code, unused_imports)]
 mod replacement_strategy;
 mod unlimited;
 
-use std::lazy::SyncOnceCell;
+use std::sync::OnceLock;
 
-pub use self::page::{PageHandle, PageId, PageRef};
+pub use self::page::{PageHandle, PageId, PageReadGuard, PageRef, PageWriteGuard};
 pub use self::replacement_strategy::NoPages;
+use self::unlimited::UnlimitedPageManager;
 
-static PAGE_MANAGER: SyncOnceCell<Box<dyn PageManager + Send + Sync + 'static>> =
-    SyncOnceCell::new();
+static PAGE_MANAGER: OnceLock<Box<dyn PageManager + Send + Sync + 'static>> = OnceLock::new();
 
-static PAGE_SIZE: SyncOnceCell<usize> = SyncOnceCell::new();
+static PAGE_SIZE: OnceLock<usize> = OnceLock::new();
 
 trait PageManager {
     fn allocate(&'static self) -> Result<(PageHandle, PageRef), NoPages>;
 }
 
+/// Set up pages of `page_size` bytes for the whole process. Called once during
+/// database startup, before anything allocates a page.
+pub fn setup(page_size: usize) {
+    if PAGE_SIZE.set(page_size).is_err() {
+        assert_eq!(
+            self::page_size(),
+            page_size,
+            "Expected Page size to only ever be setup with one size"
+        );
+        return;
+    }
+
+    // Pages are freed as soon as nothing holds onto them, so there's no limit
+    // to keep to.
+    let _ = PAGE_MANAGER.set(Box::new(UnlimitedPageManager));
+}
+
 pub fn allocate_page() -> Result<(PageHandle, PageRef), NoPages> {
     PAGE_MANAGER
         .get()
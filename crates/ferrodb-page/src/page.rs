@@ -37,6 +37,7 @@ impl Page {
         contents: Box<[u8]>,
         strat: &'static dyn ReplacementStrategy,
     ) -> (Page, PageHandle, PageRef) {
+        // Counting the Page, the PageHandle, and the PageRef.
         let inner: NonNull<PageInner> = Box::leak(Box::new(PageInner {
             handle_count: AtomicUsize::new(3),
             ref_count: AtomicUsize::new(1),
@@ -82,20 +83,8 @@ impl Page {
 
 impl Drop for Page {
     fn drop(&mut self) {
-        let count = self.inner().handle_count.fetch_sub(1, Ordering::SeqCst);
-
-        if count == 1 {
-            if self.inner().ref_count.load(Ordering::SeqCst) > 0 {
-                panic!(
-                    "Either we let a stray PageRef escape, or PageHandle has been dropped without \
-                     first invalidating the page. This is not good."
-                );
-            }
-
-            // SAFETY: This pointer was allocated from Box::leak. We're the last ones to
-            // have a reference to this pointer.
-            let _ = unsafe { Box::from_raw(self.inner.as_ptr()) };
-        }
+        // SAFETY: This is the last use of `inner` by this Page.
+        unsafe { release(self.inner) };
     }
 }
 
@@ -124,6 +113,7 @@ impl PageHandle {
                 .compare_exchange(ref_count, ref_count + 1, Ordering::SeqCst, Ordering::SeqCst)
                 .is_ok()
             {
+                self.inner().handle_count.fetch_add(1, Ordering::SeqCst);
                 return Ok(PageRef {
                     id: self.id,
                     inner: self.inner,
@@ -141,20 +131,8 @@ impl PageHandle {
 
 impl Drop for PageHandle {
     fn drop(&mut self) {
-        let count = self.inner().handle_count.fetch_sub(1, Ordering::SeqCst);
-
-        if count == 1 {
-            if self.inner().ref_count.load(Ordering::SeqCst) > 0 {
-                panic!(
-                    "Either we let a stray PageRef escape, or both Page and PageHandle have been \
-                     dropped without first invalidating the page. This is not good."
-                );
-            }
-
-            // SAFETY: This pointer was allocated from Box::leak. We're the last ones to
-            // have a reference to this pointer.
-            let _ = unsafe { Box::from_raw(self.inner.as_ptr()) };
-        }
+        // SAFETY: This is the last use of `inner` by this PageHandle.
+        unsafe { release(self.inner) };
     }
 }
 
@@ -191,6 +169,12 @@ impl PageRef {
             )
         }))
     }
+
+    /// Whether two refs point at the same page, rather than just pages with
+    /// the same contents.
+    pub fn ptr_eq(a: &PageRef, b: &PageRef) -> bool {
+        a.inner == b.inner
+    }
 }
 
 pub struct PageReadGuard<'a>(MappedRwLockReadGuard<'a, [u8]>);
@@ -219,9 +203,25 @@ impl DerefMut for PageWriteGuard<'_> {
     }
 }
 
+// SAFETY: A PageHandle only uses `inner` to pin the page, which only touches
+// the atomic counts, and `inner` lives until the last Page, PageHandle, and
+// PageRef of it is dropped, on whichever thread that is.
+unsafe impl Send for PageHandle {}
+// SAFETY: Pinning takes `&self` and only does atomic updates, so it can race
+// with itself on other threads.
+unsafe impl Sync for PageHandle {}
+// SAFETY: A PageRef only touches `inner` through the atomic counts and the
+// payload's RwLock, which are all Send and Sync, and it keeps `inner` alive for
+// as long as it's around. The replacement strategy is Send + Sync itself.
+unsafe impl Send for PageRef {}
+// SAFETY: Reading and writing through `&PageRef` goes through the payload's
+// RwLock, so refs on different threads are latched against each other.
+unsafe impl Sync for PageRef {}
+
 impl Clone for PageRef {
     fn clone(&self) -> PageRef {
         self.inner().ref_count.fetch_add(1, Ordering::SeqCst);
+        self.inner().handle_count.fetch_add(1, Ordering::SeqCst);
 
         PageRef {
             id: self.id,
@@ -233,12 +233,74 @@ impl Clone for PageRef {
 
 impl Drop for PageRef {
     fn drop(&mut self) {
-        self.inner().ref_count.fetch_sub(1, Ordering::SeqCst);
+        // Once there are no refs, the page can't be pinned again, so nothing
+        // can read its contents any more.
+        if self.inner().ref_count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner().payload.write().take();
+        }
+
+        // SAFETY: This is the last use of `inner` by this PageRef.
+        unsafe { release(self.inner) };
     }
 }
 
 struct PageInner {
+    /// How many Pages, PageHandles, and PageRefs there are of this page, which
+    /// is freed along with the last of them.
     handle_count: AtomicUsize,
+    /// How many PageRefs there are. A page can only be pinned while this is
+    /// above zero.
     ref_count: AtomicUsize,
     payload: RwLock<Option<Box<[u8]>>>,
 }
+
+/// Give up one of the counts in `handle_count`, freeing the page if it was the
+/// last one.
+///
+/// # Safety
+///
+/// `inner` has to be from [`Page::allocate`], and whoever calls this has to
+/// have held one of its counts, and not use it after this.
+unsafe fn release(inner: NonNull<PageInner>) {
+    // SAFETY: The caller still holds a count, so `inner` hasn't been freed yet.
+    let count = unsafe { inner.as_ref() }.handle_count.fetch_sub(1, Ordering::SeqCst);
+
+    if count == 1 {
+        // SAFETY: This pointer was allocated from Box::leak, and that was its last
+        // count, so nobody else can have a reference to it.
+        drop(unsafe { Box::from_raw(inner.as_ptr()) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replacement_strategy::NoOpReplacementStrategy;
+
+    #[test]
+    fn a_page_can_be_pinned_while_it_has_refs() {
+        let (page, handle, page_ref) = Page::allocate_with_size(16, &NoOpReplacementStrategy);
+        drop(page);
+        page_ref.write()[0] = 1;
+
+        let pinned = handle.pin().unwrap();
+        assert!(PageRef::ptr_eq(&pinned, &page_ref));
+        drop(page_ref);
+        assert_eq!(pinned.read()[0], 1);
+
+        drop(pinned);
+        assert!(handle.pin().is_err());
+    }
+
+    #[test]
+    fn refs_outlive_the_handle() {
+        let (page, handle, page_ref) = Page::allocate_with_size(16, &NoOpReplacementStrategy);
+        let clone = page_ref.clone();
+        drop(page);
+        drop(handle);
+
+        clone.write()[0] = 1;
+        drop(clone);
+        assert_eq!(page_ref.read()[0], 1);
+    }
+}
@@ -11,7 +11,7 @@ pub use self::noop::NoOpReplacementStrategy;
 pub use self::random::RandomReplacementStrategy;
 pub use crate::PageId;
 
-pub trait ReplacementStrategy {
+pub trait ReplacementStrategy: Send + Sync {
     fn new(limit: usize) -> Self
     where
         Self: Sized;
@@ -1,16 +1,12 @@
-/**!
- * The connection protocol introduction as follows:
- * 
- * 1. Client, over plaintext sends `HELLO! FERRODB {transport}\n`
- * 2. Client, serializes Ping message over {transport} serialization method.
- * 3. Server reads this plaintext message, and then reads serialized Ping 
- *     message using the serialization specified in the first plaintext string.
- * 4. Server verifies the protocol version, and serializes a Pong message.
- * 5. This concludes the introduction, and the server will wait for serialized 
- *     Commands from the client.
- */
-
-#![feature(derive_default_enum)]
+//! The connection protocol introduction as follows:
+//!
+//! 1. Client, over plaintext sends `HELLO! FERRODB {transport}\n`
+//! 2. Client, serializes Ping message over {transport} serialization method.
+//! 3. Server reads this plaintext message, and then reads serialized Ping
+//!    message using the serialization specified in the first plaintext string.
+//! 4. Server verifies the protocol version, and serializes a Pong message.
+//! 5. This concludes the introduction, and the server will wait for serialized
+//!    Commands from the client.
 
 mod transport;
 
@@ -18,9 +14,9 @@ use serde::{Deserialize, Serialize};
 
 pub use self::transport::{Stream, Transport};
 
-pub const DEFAULT_PORT: &'static str = "1337";
-pub const PROTOCOL_VERSION: usize = 0;
-pub const PREAMBLE: &'static str = "HELLO! FERRODB ";
+pub const DEFAULT_PORT: &str = "1337";
+pub const PROTOCOL_VERSION: usize = 1;
+pub const PREAMBLE: &str = "HELLO! FERRODB ";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Ping {
@@ -33,12 +29,16 @@ pub enum Pong {
     WrongProtocol,
 }
 
-enum Command {
-    Select(String),
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Command {
+    /// Run a statement. The server answers with any number of `SomeRows`,
+    /// followed by either `Done` or `Error`.
+    Query(String),
     Goodbye,
 }
 
-enum SelectResponse {
+#[derive(Debug, Serialize, Deserialize)]
+pub enum QueryResponse {
     SomeRows(String),
     Error(String),
     Done,
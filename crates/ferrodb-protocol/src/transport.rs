@@ -15,7 +15,7 @@ pub enum Transport {
 }
 
 impl Transport {
-    pub fn stream<'c, C>(self, conn: C) -> Stream<C> {
+    pub fn stream<C>(self, conn: C) -> Stream<C> {
         Stream {
             transport: self,
             conn,
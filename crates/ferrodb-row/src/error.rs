@@ -0,0 +1,29 @@
+use thiserror::Error;
+
+use crate::DataType;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("Expected {expected} values for the row, got {got}")]
+    WrongValueCount { expected: usize, got: usize },
+    #[error("Column `{column}` is {expected}, but got a {got} value")]
+    TypeMismatch {
+        column: String,
+        expected: DataType,
+        got: DataType,
+    },
+    #[error("Column `{column}` can't be NULL")]
+    NotNullable { column: String },
+    #[error("A table can have at most {max} columns")]
+    TooManyColumns { max: usize },
+    #[error("There's already a column named `{column}`")]
+    DuplicateColumn { column: String },
+    #[error("Column `{column}` can't be added to existing rows, since it isn't nullable")]
+    AddedColumnNotNullable { column: String },
+    #[error("There's no type called `{0}`")]
+    UnknownDataType(String),
+    #[error("Row is more than 4 GiB once it's encoded")]
+    RowTooLong,
+    #[error("Row is corrupt: {0}")]
+    Corrupt(&'static str),
+}
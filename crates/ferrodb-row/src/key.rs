@@ -0,0 +1,62 @@
+//! Index keys are values encoded so that comparing the bytes of two keys
+//! orders them the same way as comparing their values, column by column. Each
+//! value is a tag byte, 0 for NULL (so NULLs come first) or 1 for anything
+//! else, followed by:
+//!
+//! - a bool as a byte that's 0 or 1;
+//! - an integer as big-endian, with its sign bit flipped;
+//! - a double as big-endian, with its sign bit flipped if it's positive, and
+//!   every bit flipped if it's negative, so that negative numbers come first
+//!   and the bigger their magnitude the earlier they come;
+//! - text and blobs as their bytes, with every 0 byte written as `[0, 0xFF]`,
+//!   followed by `[0, 1]`, so that a value comes before anything it's a prefix
+//!   of.
+//!
+//! Keys can't be decoded back into their values: an index only ever looks them
+//! up.
+
+use crate::ValueRef;
+
+const NULL: u8 = 0;
+const NOT_NULL: u8 = 1;
+
+/// Encode the values of an index's columns, in key order, as its key.
+pub fn encode_key<'v>(values: impl IntoIterator<Item = ValueRef<'v>>) -> Vec<u8> {
+    let mut key = vec![];
+
+    for value in values {
+        if value.is_null() {
+            key.push(NULL);
+            continue;
+        }
+
+        key.push(NOT_NULL);
+        match value {
+            ValueRef::Null => unreachable!(),
+            ValueRef::Bool(value) => key.push(value as u8),
+            ValueRef::Int(value) => key.extend_from_slice(&(value as u32 ^ 1 << 31).to_be_bytes()),
+            ValueRef::BigInt(value) => key.extend_from_slice(&(value as u64 ^ 1 << 63).to_be_bytes()),
+            ValueRef::Double(value) => {
+                // -0.0 and 0.0 are equal, so they get the same key.
+                let bits = if value == 0.0 { 0 } else { value.to_bits() };
+                let bits = if bits >> 63 == 0 { bits ^ 1 << 63 } else { !bits };
+                key.extend_from_slice(&bits.to_be_bytes());
+            },
+            ValueRef::Text(value) => encode_bytes(&mut key, value.as_bytes()),
+            ValueRef::Blob(value) => encode_bytes(&mut key, value),
+        }
+    }
+
+    key
+}
+
+fn encode_bytes(key: &mut Vec<u8>, bytes: &[u8]) {
+    for &byte in bytes {
+        key.push(byte);
+        if byte == 0 {
+            key.push(0xFF);
+        }
+    }
+
+    key.extend_from_slice(&[0, 1]);
+}
@@ -0,0 +1,20 @@
+//! The format that rows of a table are encoded in, going by its [`Schema`].
+//!
+//! Values are read straight out of the encoded bytes by a [`RowRef`], without
+//! copying anything, so a row can be read while it's still in its page, e.g.
+//! through the `PageReadGuard` of a latched page.
+//!
+//! The values of an index's columns are encoded differently, as a key whose
+//! bytes sort the same way as the values do: see [`encode_key`].
+
+mod error;
+mod key;
+mod row;
+mod schema;
+mod value;
+
+pub use self::error::Error;
+pub use self::key::encode_key;
+pub use self::row::RowRef;
+pub use self::schema::{Column, DataType, Schema, MAX_COLUMNS};
+pub use self::value::{Value, ValueRef};
@@ -0,0 +1,229 @@
+//! An encoded row is:
+//!
+//! ```text
+//! [column count: u16][null bitmap: (column count + 7) / 8 bytes]
+//! [end offset: u32] * variable-length columns
+//! fixed-width fields, in column order
+//! variable-length fields, in column order
+//! ```
+//!
+//! A fixed-width column takes up its width even when it's NULL, so where it
+//! is only depends on the schema. A variable-length field ends at its end
+//! offset (from the start of the row), and starts where the one before it
+//! ended, or after the fixed-width fields for the first one.
+//!
+//! The column count is how many columns the schema had when the row was
+//! encoded. Columns are only ever added to the end of a schema, and columns
+//! past the count read as NULL, so a nullable column can be added without
+//! rewriting the rows that are already there.
+//!
+//! Numbers are little-endian, and a bool is a byte that's 0 or 1.
+
+use std::str;
+
+use crate::schema::Slot;
+use crate::{DataType, Error, Schema, Value, ValueRef};
+
+const COUNT_SIZE: usize = 2;
+const END_SIZE: usize = 4;
+
+impl Schema {
+    /// Encode a row with a value for every column.
+    pub fn encode(&self, values: &[Value]) -> Result<Vec<u8>, Error> {
+        if values.len() != self.len() {
+            return Err(Error::WrongValueCount {
+                expected: self.len(),
+                got: values.len(),
+            });
+        }
+
+        let layout = Layout::new(self, self.len());
+        let mut row = vec![0; layout.var_at];
+        row[..COUNT_SIZE].copy_from_slice(&(self.len() as u16).to_le_bytes());
+
+        for (index, (column, value)) in self.columns().iter().zip(values).enumerate() {
+            let value = value.as_ref();
+
+            match value.data_type() {
+                None if !column.nullable =>
+                    return Err(Error::NotNullable {
+                        column: column.name.clone(),
+                    }),
+                None => row[COUNT_SIZE + index / 8] |= 1 << (index % 8),
+                Some(data_type) if data_type != column.data_type =>
+                    return Err(Error::TypeMismatch {
+                        column: column.name.clone(),
+                        expected: column.data_type,
+                        got: data_type,
+                    }),
+                Some(_) => {},
+            }
+
+            match self.slot(index) {
+                Slot::Fixed { offset, width } => {
+                    let at = layout.fixed_at + offset;
+                    write_fixed(value, &mut row[at..at + width]);
+                },
+                Slot::Var(n) => {
+                    match value {
+                        ValueRef::Text(text) => row.extend_from_slice(text.as_bytes()),
+                        ValueRef::Blob(blob) => row.extend_from_slice(blob),
+                        _ => {},
+                    }
+
+                    let end = u32::try_from(row.len()).map_err(|_| Error::RowTooLong)?;
+                    let at = layout.ends_at + n * END_SIZE;
+                    row[at..at + END_SIZE].copy_from_slice(&end.to_le_bytes());
+                },
+            }
+        }
+
+        Ok(row)
+    }
+
+    pub fn decode(&self, row: &[u8]) -> Result<Vec<Value>, Error> {
+        Ok(RowRef::new(self, row)?.to_values())
+    }
+}
+
+/// Where the parts of a row with some number of columns start.
+#[derive(Copy, Clone)]
+struct Layout {
+    ends_at: usize,
+    fixed_at: usize,
+    var_at: usize,
+}
+
+impl Layout {
+    fn new(schema: &Schema, count: usize) -> Layout {
+        let ends_at = COUNT_SIZE + count.div_ceil(8);
+        let fixed_at = ends_at + schema.var_count(count) * END_SIZE;
+        let var_at = fixed_at + schema.fixed_len(count);
+
+        Layout {
+            ends_at,
+            fixed_at,
+            var_at,
+        }
+    }
+}
+
+/// An encoded row, read in place, wherever its bytes are. Everything about the
+/// row is checked up front, so getting at its values can't fail.
+#[derive(Copy, Clone)]
+pub struct RowRef<'r> {
+    schema: &'r Schema,
+    row: &'r [u8],
+    /// How many columns the row was encoded with.
+    count: usize,
+    layout: Layout,
+}
+
+impl<'r> RowRef<'r> {
+    pub fn new(schema: &'r Schema, row: &'r [u8]) -> Result<RowRef<'r>, Error> {
+        if row.len() < COUNT_SIZE {
+            return Err(Error::Corrupt("it's too short to have a column count"));
+        }
+
+        let count = u16::from_le_bytes(row[..COUNT_SIZE].try_into().unwrap()) as usize;
+        if count > schema.len() {
+            return Err(Error::Corrupt("it has more columns than its schema"));
+        }
+
+        let layout = Layout::new(schema, count);
+        if layout.var_at > row.len() {
+            return Err(Error::Corrupt("it's too short for its columns"));
+        }
+
+        let row = RowRef {
+            schema,
+            row,
+            count,
+            layout,
+        };
+
+        let mut start = layout.var_at;
+        for n in 0..schema.var_count(count) {
+            let end = row.end(n);
+            if end < start || end > row.row.len() {
+                return Err(Error::Corrupt("its variable-length fields are out of bounds"));
+            }
+
+            start = end;
+        }
+
+        for (index, column) in schema.columns()[..count].iter().enumerate() {
+            if let (DataType::Text, Some(field)) = (column.data_type, row.field(index)) {
+                if str::from_utf8(field).is_err() {
+                    return Err(Error::Corrupt("it has TEXT that isn't UTF-8"));
+                }
+            }
+        }
+
+        Ok(row)
+    }
+
+    pub fn schema(&self) -> &'r Schema {
+        self.schema
+    }
+
+    /// The value of column `index` of the schema, which has to be one of its
+    /// columns.
+    pub fn get(&self, index: usize) -> ValueRef<'r> {
+        let data_type = self.schema.columns()[index].data_type;
+        let Some(field) = self.field(index)
+            else { return ValueRef::Null; };
+
+        match data_type {
+            DataType::Bool => ValueRef::Bool(field[0] != 0),
+            DataType::Int => ValueRef::Int(i32::from_le_bytes(field.try_into().unwrap())),
+            DataType::BigInt => ValueRef::BigInt(i64::from_le_bytes(field.try_into().unwrap())),
+            DataType::Double => ValueRef::Double(f64::from_le_bytes(field.try_into().unwrap())),
+            // SAFETY: Every TEXT field was checked to be UTF-8 in `RowRef::new`.
+            DataType::Text => ValueRef::Text(unsafe { str::from_utf8_unchecked(field) }),
+            DataType::Blob => ValueRef::Blob(field),
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = ValueRef<'r>> + '_ {
+        (0..self.schema.len()).map(|index| self.get(index))
+    }
+
+    pub fn to_values(&self) -> Vec<Value> {
+        self.values().map(|value| value.to_owned()).collect()
+    }
+
+    /// The bytes of column `index`, or `None` if it's NULL.
+    fn field(&self, index: usize) -> Option<&'r [u8]> {
+        if index >= self.count || self.row[COUNT_SIZE + index / 8] & (1 << (index % 8)) != 0 {
+            return None;
+        }
+
+        match self.schema.slot(index) {
+            Slot::Fixed { offset, width } => {
+                let at = self.layout.fixed_at + offset;
+                Some(&self.row[at..at + width])
+            },
+            Slot::Var(n) => {
+                let start = if n == 0 { self.layout.var_at } else { self.end(n - 1) };
+                Some(&self.row[start..self.end(n)])
+            },
+        }
+    }
+
+    /// Where the `n`th variable-length field ends.
+    fn end(&self, n: usize) -> usize {
+        let at = self.layout.ends_at + n * END_SIZE;
+        u32::from_le_bytes(self.row[at..at + END_SIZE].try_into().unwrap()) as usize
+    }
+}
+
+fn write_fixed(value: ValueRef<'_>, field: &mut [u8]) {
+    match value {
+        ValueRef::Bool(value) => field[0] = value as u8,
+        ValueRef::Int(value) => field.copy_from_slice(&value.to_le_bytes()),
+        ValueRef::BigInt(value) => field.copy_from_slice(&value.to_le_bytes()),
+        ValueRef::Double(value) => field.copy_from_slice(&value.to_le_bytes()),
+        ValueRef::Null | ValueRef::Text(_) | ValueRef::Blob(_) => {},
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::Error;
+
+/// The most columns a table can have, since a row keeps its column count in a
+/// `u16`.
+pub const MAX_COLUMNS: usize = u16::MAX as usize;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DataType {
+    Bool,
+    /// A 32-bit signed integer.
+    Int,
+    /// A 64-bit signed integer.
+    BigInt,
+    /// A 64-bit float.
+    Double,
+    /// A UTF-8 string, of any length.
+    Text,
+    /// Bytes, of any length.
+    Blob,
+}
+
+impl DataType {
+    /// How many bytes a value of this type always takes up, or `None` if its
+    /// values can be any length.
+    pub fn fixed_width(self) -> Option<usize> {
+        match self {
+            DataType::Bool => Some(1),
+            DataType::Int => Some(4),
+            DataType::BigInt | DataType::Double => Some(8),
+            DataType::Text | DataType::Blob => None,
+        }
+    }
+}
+
+impl fmt::Display for DataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataType::Bool => f.write_str("BOOLEAN"),
+            DataType::Int => f.write_str("INT"),
+            DataType::BigInt => f.write_str("BIGINT"),
+            DataType::Double => f.write_str("DOUBLE"),
+            DataType::Text => f.write_str("TEXT"),
+            DataType::Blob => f.write_str("BLOB"),
+        }
+    }
+}
+
+impl FromStr for DataType {
+    type Err = Error;
+
+    /// Parse the name of a type, as SQL spells it, ignoring case.
+    fn from_str(name: &str) -> Result<DataType, Error> {
+        let data_type = match &*name.to_ascii_uppercase() {
+            "BOOLEAN" | "BOOL" => DataType::Bool,
+            "INT" | "INTEGER" => DataType::Int,
+            "BIGINT" => DataType::BigInt,
+            "DOUBLE" | "REAL" | "FLOAT" => DataType::Double,
+            "TEXT" | "VARCHAR" => DataType::Text,
+            "BLOB" | "BYTEA" => DataType::Blob,
+            _ => return Err(Error::UnknownDataType(name.to_owned())),
+        };
+
+        Ok(data_type)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Column {
+    pub name: String,
+    pub data_type: DataType,
+    pub nullable: bool,
+}
+
+/// The columns of a table, which say how its rows are encoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Schema {
+    columns: Vec<Column>,
+    /// For every column (and one past the last), how many bytes the
+    /// fixed-width columns before it take up.
+    fixed_before: Vec<usize>,
+    /// For every column (and one past the last), how many variable-length
+    /// columns come before it.
+    vars_before: Vec<usize>,
+}
+
+impl Schema {
+    pub fn new(columns: Vec<Column>) -> Result<Schema, Error> {
+        let mut schema = Schema {
+            columns: vec![],
+            fixed_before: vec![0],
+            vars_before: vec![0],
+        };
+
+        for column in columns {
+            schema.push(column)?;
+        }
+
+        Ok(schema)
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    pub fn len(&self) -> usize {
+        self.columns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.columns.is_empty()
+    }
+
+    /// The index of the column with this name, ignoring case.
+    pub fn position(&self, name: &str) -> Option<usize> {
+        self.columns
+            .iter()
+            .position(|column| column.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Add a column to the end, which rows encoded before it was added read as
+    /// NULL, so it has to be nullable.
+    pub fn add_column(&mut self, column: Column) -> Result<(), Error> {
+        if !column.nullable {
+            return Err(Error::AddedColumnNotNullable { column: column.name });
+        }
+
+        self.push(column)
+    }
+
+    fn push(&mut self, column: Column) -> Result<(), Error> {
+        if self.columns.len() == MAX_COLUMNS {
+            return Err(Error::TooManyColumns { max: MAX_COLUMNS });
+        }
+        if self.position(&column.name).is_some() {
+            return Err(Error::DuplicateColumn { column: column.name });
+        }
+
+        let (fixed, vars) = match column.data_type.fixed_width() {
+            Some(width) => (width, 0),
+            None => (0, 1),
+        };
+        self.fixed_before.push(self.fixed_before.last().unwrap() + fixed);
+        self.vars_before.push(self.vars_before.last().unwrap() + vars);
+        self.columns.push(column);
+
+        Ok(())
+    }
+
+    /// How many bytes the fixed-width columns of the first `count` columns
+    /// take up.
+    pub(crate) fn fixed_len(&self, count: usize) -> usize {
+        self.fixed_before[count]
+    }
+
+    /// Where column `index` is among the fixed-width or variable-length columns.
+    pub(crate) fn slot(&self, index: usize) -> Slot {
+        match self.columns[index].data_type.fixed_width() {
+            Some(width) => Slot::Fixed {
+                offset: self.fixed_before[index],
+                width,
+            },
+            None => Slot::Var(self.vars_before[index]),
+        }
+    }
+
+    /// How many of the first `count` columns are variable-length.
+    pub(crate) fn var_count(&self, count: usize) -> usize {
+        self.vars_before[count]
+    }
+}
+
+#[derive(Copy, Clone)]
+pub(crate) enum Slot {
+    /// At `offset` into the fixed-width fields.
+    Fixed { offset: usize, width: usize },
+    /// The `n`th variable-length field.
+    Var(usize),
+}
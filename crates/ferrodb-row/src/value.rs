@@ -0,0 +1,103 @@
+use std::fmt;
+
+use crate::DataType;
+
+/// A value of a column, or NULL.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i32),
+    BigInt(i64),
+    Double(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// A value borrowed from an encoded row.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ValueRef<'r> {
+    Null,
+    Bool(bool),
+    Int(i32),
+    BigInt(i64),
+    Double(f64),
+    Text(&'r str),
+    Blob(&'r [u8]),
+}
+
+impl Value {
+    pub fn as_ref(&self) -> ValueRef<'_> {
+        match self {
+            Value::Null => ValueRef::Null,
+            Value::Bool(value) => ValueRef::Bool(*value),
+            Value::Int(value) => ValueRef::Int(*value),
+            Value::BigInt(value) => ValueRef::BigInt(*value),
+            Value::Double(value) => ValueRef::Double(*value),
+            Value::Text(value) => ValueRef::Text(value),
+            Value::Blob(value) => ValueRef::Blob(value),
+        }
+    }
+
+    /// The type of the value, or `None` for NULL, which goes in a column of any
+    /// type.
+    pub fn data_type(&self) -> Option<DataType> {
+        self.as_ref().data_type()
+    }
+}
+
+impl ValueRef<'_> {
+    pub fn to_owned(&self) -> Value {
+        match *self {
+            ValueRef::Null => Value::Null,
+            ValueRef::Bool(value) => Value::Bool(value),
+            ValueRef::Int(value) => Value::Int(value),
+            ValueRef::BigInt(value) => Value::BigInt(value),
+            ValueRef::Double(value) => Value::Double(value),
+            ValueRef::Text(value) => Value::Text(value.to_owned()),
+            ValueRef::Blob(value) => Value::Blob(value.to_vec()),
+        }
+    }
+
+    pub fn data_type(&self) -> Option<DataType> {
+        match self {
+            ValueRef::Null => None,
+            ValueRef::Bool(_) => Some(DataType::Bool),
+            ValueRef::Int(_) => Some(DataType::Int),
+            ValueRef::BigInt(_) => Some(DataType::BigInt),
+            ValueRef::Double(_) => Some(DataType::Double),
+            ValueRef::Text(_) => Some(DataType::Text),
+            ValueRef::Blob(_) => Some(DataType::Blob),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, ValueRef::Null)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_ref().fmt(f)
+    }
+}
+
+impl fmt::Display for ValueRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueRef::Null => f.write_str("NULL"),
+            ValueRef::Bool(value) => write!(f, "{value}"),
+            ValueRef::Int(value) => write!(f, "{value}"),
+            ValueRef::BigInt(value) => write!(f, "{value}"),
+            ValueRef::Double(value) => write!(f, "{value}"),
+            ValueRef::Text(value) => write!(f, "'{}'", value.replace('\'', "''")),
+            ValueRef::Blob(value) => {
+                f.write_str("X'")?;
+                for byte in value.iter() {
+                    write!(f, "{byte:02X}")?;
+                }
+                f.write_str("'")
+            },
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use ferrodb_row::{Column, DataType, Error, RowRef, Schema, Value, ValueRef};
+
+fn column(name: &str, data_type: DataType, nullable: bool) -> Column {
+    Column {
+        name: name.to_owned(),
+        data_type,
+        nullable,
+    }
+}
+
+fn schema() -> Schema {
+    Schema::new(vec![
+        column("id", DataType::BigInt, false),
+        column("name", DataType::Text, true),
+        column("active", DataType::Bool, false),
+        column("score", DataType::Double, true),
+        column("data", DataType::Blob, true),
+        column("age", DataType::Int, true),
+        column("notes", DataType::Text, true),
+        column("extra", DataType::Int, true),
+        column("more", DataType::Text, true),
+    ])
+    .unwrap()
+}
+
+fn rows() -> Vec<Vec<Value>> {
+    vec![
+        vec![
+            Value::BigInt(i64::MIN),
+            Value::Text("héllo".to_owned()),
+            Value::Bool(true),
+            Value::Double(-1.5),
+            Value::Blob(vec![0, 1, 2, 255]),
+            Value::Int(-7),
+            Value::Text(String::new()),
+            Value::Int(i32::MAX),
+            Value::Text("last".to_owned()),
+        ],
+        // Every nullable column NULL, including past the first byte of the
+        // null bitmap.
+        vec![
+            Value::BigInt(1),
+            Value::Null,
+            Value::Bool(false),
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::Null,
+            Value::Null,
+        ],
+        vec![
+            Value::BigInt(2),
+            Value::Null,
+            Value::Bool(true),
+            Value::Double(f64::INFINITY),
+            Value::Blob(vec![]),
+            Value::Null,
+            Value::Text("x".repeat(1000)),
+            Value::Null,
+            Value::Text("y".to_owned()),
+        ],
+    ]
+}
+
+#[test]
+fn rows_read_back_the_values_they_were_encoded_with() {
+    let schema = schema();
+
+    for values in rows() {
+        let row = schema.encode(&values).unwrap();
+        assert_eq!(schema.decode(&row).unwrap(), values);
+
+        // Values are read straight out of the row's bytes.
+        let row_ref = RowRef::new(&schema, &row).unwrap();
+        for (index, value) in values.iter().enumerate() {
+            let got = row_ref.get(index);
+            assert_eq!(got, value.as_ref());
+            if let ValueRef::Text(text) = got {
+                assert!(row.as_ptr_range().contains(&text.as_ptr()) || text.is_empty());
+            }
+        }
+    }
+}
+
+#[test]
+fn rows_from_before_a_column_was_added_read_it_as_null() {
+    let mut schema = schema();
+    let old = schema.encode(&rows()[0]).unwrap();
+
+    assert!(matches!(
+        schema.add_column(column("required", DataType::Int, false)),
+        Err(Error::AddedColumnNotNullable { .. })
+    ));
+    schema.add_column(column("added", DataType::Text, true)).unwrap();
+    schema.add_column(column("also_added", DataType::BigInt, true)).unwrap();
+
+    let mut expected = rows()[0].clone();
+    expected.extend([Value::Null, Value::Null]);
+    assert_eq!(schema.decode(&old).unwrap(), expected);
+
+    expected[9] = Value::Text("new".to_owned());
+    expected[10] = Value::BigInt(3);
+    let new = schema.encode(&expected).unwrap();
+    assert_eq!(schema.decode(&new).unwrap(), expected);
+}
+
+#[test]
+fn values_that_dont_fit_the_schema_are_refused() {
+    let schema = schema();
+    let mut values = rows()[0].clone();
+
+    values.pop();
+    assert!(matches!(schema.encode(&values), Err(Error::WrongValueCount { expected: 9, got: 8 })));
+    values.push(Value::Int(1));
+    assert!(matches!(schema.encode(&values), Err(Error::TypeMismatch { .. })));
+    values[8] = Value::Null;
+    values[0] = Value::Null;
+    assert!(matches!(schema.encode(&values), Err(Error::NotNullable { .. })));
+
+    let duplicate = Schema::new(vec![column("a", DataType::Int, false), column("A", DataType::Text, true)]);
+    assert!(matches!(duplicate, Err(Error::DuplicateColumn { .. })));
+    assert_eq!(schema.position("NAME"), Some(1));
+    assert_eq!("varchar".parse::<DataType>().unwrap(), DataType::Text);
+    assert!(matches!("money".parse::<DataType>(), Err(Error::UnknownDataType(_))));
+}
+
+#[test]
+fn corrupt_rows_are_caught_up_front() {
+    let schema = schema();
+    let row = schema.encode(&rows()[0]).unwrap();
+
+    for len in 0..row.len() {
+        assert!(matches!(RowRef::new(&schema, &row[..len]), Err(Error::Corrupt(_))), "{len}");
+    }
+
+    // More columns than the schema has.
+    let mut corrupt = row.clone();
+    corrupt[..2].copy_from_slice(&100u16.to_le_bytes());
+    assert!(matches!(RowRef::new(&schema, &corrupt), Err(Error::Corrupt(_))));
+
+    // TEXT that isn't UTF-8.
+    let mut corrupt = row;
+    let at = corrupt.windows(6).position(|window| window == "héllo".as_bytes()).unwrap();
+    corrupt[at + 1] = 0xff;
+    assert!(matches!(RowRef::new(&schema, &corrupt), Err(Error::Corrupt(_))));
+}
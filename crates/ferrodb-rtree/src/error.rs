@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+use crate::Rect;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Fs(#[from] ferrodb_fs::Error),
+    #[error(transparent)]
+    Wal(#[from] ferrodb_wal::Error),
+    #[error("Index entry's value is {len} bytes, but it can be at most {max} bytes")]
+    EntryTooLarge { len: usize, max: usize },
+    #[error("{0} isn't a box that can be indexed, since its corners have to be finite numbers")]
+    InvalidRect(Rect),
+}
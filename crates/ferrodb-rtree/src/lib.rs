@@ -0,0 +1,416 @@
+//! Spatial indexes stored as R-trees: entries of a two-dimensional box and a
+//! value, with internal nodes above them holding the smallest box around each
+//! of their children, so that a search only has to go down the children whose
+//! boxes could have what it's looking for. Points are boxes with no area.
+//!
+//! Page 1 of the file holds the head of the list of free pages, and page 2 is
+//! always the root, like in a B+Tree. Every change goes through a [`Txn`], like
+//! for heaps.
+//!
+//! A new entry goes under whichever child's box would have to grow the least
+//! to hold it, and a node that fills up is split the way an R*-tree splits
+//! them. A node that a delete leaves too empty is taken out of the tree
+//! altogether, and the entries under it are inserted again, which tends to
+//! find them better places than merging it with a sibling would.
+//!
+//! Readers and writers latch the root for as long as they use the tree: any
+//! number of readers at once, or one writer. Searches can go down any number
+//! of paths, and changes can make boxes grow or shrink all the way back up to
+//! the root, so latching one node at a time wouldn't buy much.
+
+mod error;
+mod node;
+mod rect;
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use ferrodb_fs::{FileId, FileKind, FileManager, PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_page::page_size;
+use ferrodb_wal::Txn;
+
+pub use self::error::Error;
+use self::node::{Entry, Node};
+pub use self::rect::{Query, Rect};
+
+/// Holds the first page of the free list, as a `u64` at the start of its body,
+/// or 0 if there are no free pages.
+const META_PAGE: PageIndex = 1;
+/// The root never moves, so that nothing has to point at it.
+const ROOT_PAGE: PageIndex = 2;
+
+pub struct RTree {
+    files: Arc<FileManager>,
+    file: FileId,
+}
+
+impl RTree {
+    pub fn open(files: Arc<FileManager>, name: &str) -> Result<RTree, Error> {
+        let file = files.open(name, FileKind::RTree)?;
+
+        // An all-zero meta page and root are an empty tree, so a new tree only
+        // needs them to exist.
+        while files.page_count(file)? <= ROOT_PAGE {
+            files.allocate(file)?;
+        }
+
+        Ok(RTree { files, file })
+    }
+
+    pub fn file(&self) -> FileId {
+        self.file
+    }
+
+    /// The longest value that an entry can have.
+    pub fn max_value_len() -> usize {
+        node::max_value_len(body_len())
+    }
+
+    /// Add an entry, returning whether it wasn't already there. Any number of
+    /// entries can have the same box, as long as their values differ.
+    pub fn insert(&self, txn: &Txn<'_>, rect: Rect, value: &[u8]) -> Result<bool, Error> {
+        if !rect.is_valid() {
+            return Err(Error::InvalidRect(rect));
+        }
+        if value.len() > RTree::max_value_len() {
+            return Err(Error::EntryTooLarge {
+                len: value.len(),
+                max: RTree::max_value_len(),
+            });
+        }
+
+        let root_ref = self.files.dirty(self.file, ROOT_PAGE)?;
+        let mut root = root_ref.write();
+        if self.find(&root, ROOT_PAGE, &rect, value, &mut vec![])? {
+            return Ok(false);
+        }
+
+        self.insert_entry(txn, &mut root, Entry::leaf(rect, value))?;
+        Ok(true)
+    }
+
+    /// Remove an entry, returning whether it was there.
+    pub fn delete(&self, txn: &Txn<'_>, rect: Rect, value: &[u8]) -> Result<bool, Error> {
+        let root_ref = self.files.dirty(self.file, ROOT_PAGE)?;
+        let mut root = root_ref.write();
+
+        let mut path = vec![];
+        if !self.find(&root, ROOT_PAGE, &rect, value, &mut path)? {
+            return Ok(false);
+        }
+
+        let mut nodes = path
+            .iter()
+            .map(|&(page, _)| Ok((page, self.read_node(&root, page)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let (_, leaf) = nodes.last_mut().unwrap();
+        leaf.entries.remove(path.last().unwrap().1);
+
+        // Going back up, every node that's too empty now comes out of its
+        // parent, and every other one's box in its parent shrinks to fit it.
+        let mut orphans = vec![];
+        while nodes.len() > 1 {
+            let (page, node) = nodes.pop().unwrap();
+            let index = path[nodes.len() - 1].1;
+            let (_, parent) = nodes.last_mut().unwrap();
+
+            if node.content() < node::min_content(body_len()) {
+                parent.entries.remove(index);
+                self.take_entries(txn, page, node, &mut orphans)?;
+            } else {
+                parent.entries[index].rect = node.bounds();
+                self.write_node(txn, &mut root, page, &node)?;
+            }
+        }
+
+        // The root takes over its only child for as long as it has just the
+        // one, and becomes an empty leaf if it's lost every one.
+        let (_, mut root_node) = nodes.pop().unwrap();
+        while !root_node.is_leaf() && root_node.entries.len() <= 1 {
+            let Some(entry) = root_node.entries.pop()
+                else {
+                    root_node = Node::default();
+                    break;
+                };
+
+            root_node = self.read_node(&root, entry.child)?;
+            self.free(txn, entry.child)?;
+        }
+        self.write_node(txn, &mut root, ROOT_PAGE, &root_node)?;
+
+        for entry in orphans {
+            self.insert_entry(txn, &mut root, entry)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Every entry whose box is one that `query` is looking for, in no
+    /// particular order.
+    pub fn search(&self, query: Query) -> Result<Vec<(Rect, Vec<u8>)>, Error> {
+        let root_ref = self.files.latest(self.file, ROOT_PAGE)?;
+        let root = root_ref.read();
+
+        let mut found = vec![];
+        let mut pages = vec![ROOT_PAGE];
+        while let Some(page) = pages.pop() {
+            let node = self.read_node(&root, page)?;
+
+            for entry in node.entries {
+                if node.level > 0 {
+                    if query.might_match(&entry.rect) {
+                        pages.push(entry.child);
+                    }
+                } else if query.matches(&entry.rect) {
+                    found.push((entry.rect, entry.value));
+                }
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// The `k` entries whose boxes are the nearest to the point `(x, y)`,
+    /// nearest first, as measured by [`Rect::distance`]. Entries that are as
+    /// near as each other come in no particular order.
+    ///
+    /// Nodes and entries are visited nearest first, so the search only ever
+    /// reads nodes that are nearer than the `k`th entry.
+    pub fn nearest(&self, x: f64, y: f64, k: usize) -> Result<Vec<(Rect, Vec<u8>)>, Error> {
+        let point = Rect::point(x, y);
+        if !point.is_valid() {
+            return Err(Error::InvalidRect(point));
+        }
+
+        let root_ref = self.files.latest(self.file, ROOT_PAGE)?;
+        let root = root_ref.read();
+
+        // Whatever's left to visit, nearest first, as indexes into `candidates`.
+        let mut candidates = vec![Candidate::Node(ROOT_PAGE)];
+        let mut queue = BinaryHeap::from([Reverse((Distance(0.0), 0))]);
+        let mut found = vec![];
+
+        while found.len() < k {
+            let Some(Reverse((_, index))) = queue.pop()
+                else { break; };
+
+            match std::mem::replace(&mut candidates[index], Candidate::Visited) {
+                Candidate::Node(page) => {
+                    let node = self.read_node(&root, page)?;
+
+                    for entry in node.entries {
+                        let distance = Distance(entry.rect.distance(x, y));
+                        queue.push(Reverse((distance, candidates.len())));
+                        candidates.push(match node.level {
+                            0 => Candidate::Entry(entry.rect, entry.value),
+                            _ => Candidate::Node(entry.child),
+                        });
+                    }
+                },
+                Candidate::Entry(rect, value) => found.push((rect, value)),
+                Candidate::Visited => unreachable!(),
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Whether there's an entry of `rect` and `value` under `page`, and if
+    /// there is, add the page and index of every entry on the way down to it
+    /// to `path`, the entry's own included.
+    fn find(
+        &self,
+        root: &[u8],
+        page: PageIndex,
+        rect: &Rect,
+        value: &[u8],
+        path: &mut Vec<(PageIndex, usize)>,
+    ) -> Result<bool, Error> {
+        let node = self.read_node(root, page)?;
+
+        for (index, entry) in node.entries.iter().enumerate() {
+            if node.is_leaf() {
+                if entry.rect == *rect && entry.value == value {
+                    path.push((page, index));
+                    return Ok(true);
+                }
+            } else if entry.rect.contains(rect) {
+                path.push((page, index));
+                if self.find(root, entry.child, rect, value, path)? {
+                    return Ok(true);
+                }
+                path.pop();
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Insert an entry into a leaf, with the root write latched through `root`.
+    fn insert_entry(&self, txn: &Txn<'_>, root: &mut [u8], entry: Entry) -> Result<(), Error> {
+        self.insert_under(txn, root, ROOT_PAGE, entry)?;
+        Ok(())
+    }
+
+    /// Insert an entry into a leaf under `page`, splitting whatever nodes fill
+    /// up on the way back up. Returns the node's new box, and if it was split,
+    /// the entry that its parent needs for the new node.
+    fn insert_under(
+        &self,
+        txn: &Txn<'_>,
+        root: &mut [u8],
+        page: PageIndex,
+        entry: Entry,
+    ) -> Result<(Rect, Option<Entry>), Error> {
+        let mut node = self.read_node(root, page)?;
+
+        if node.is_leaf() {
+            node.entries.push(entry);
+        } else {
+            let index = node.choose(&entry.rect);
+            let (bounds, split) = self.insert_under(txn, root, node.entries[index].child, entry)?;
+            node.entries[index].rect = bounds;
+            node.entries.extend(split);
+        }
+
+        if node.size() <= body_len() {
+            self.write_node(txn, root, page, &node)?;
+            return Ok((node.bounds(), None));
+        }
+
+        let right = node.split(body_len());
+
+        // The root grows by moving both of its halves out to new pages.
+        if page == ROOT_PAGE {
+            let left_page = self.allocate(txn)?;
+            let right_page = self.allocate(txn)?;
+            self.write_node(txn, root, left_page, &node)?;
+            self.write_node(txn, root, right_page, &right)?;
+
+            let root_node = Node {
+                level: node.level + 1,
+                entries: vec![
+                    Entry::internal(node.bounds(), left_page),
+                    Entry::internal(right.bounds(), right_page),
+                ],
+            };
+            self.write_node(txn, root, ROOT_PAGE, &root_node)?;
+            return Ok((root_node.bounds(), None));
+        }
+
+        let right_page = self.allocate(txn)?;
+        self.write_node(txn, root, right_page, &right)?;
+        self.write_node(txn, root, page, &node)?;
+
+        Ok((node.bounds(), Some(Entry::internal(right.bounds(), right_page))))
+    }
+
+    /// Take every leaf entry under a node that's been taken out of the tree,
+    /// at `page`, freeing its page and every one under it.
+    fn take_entries(&self, txn: &Txn<'_>, page: PageIndex, node: Node, entries: &mut Vec<Entry>) -> Result<(), Error> {
+        if node.is_leaf() {
+            entries.extend(node.entries);
+        } else {
+            for entry in node.entries {
+                let child = Node::read(&self.files.latest(self.file, entry.child)?.read()[PAGE_HEADER_SIZE..]);
+                self.take_entries(txn, entry.child, child, entries)?;
+            }
+        }
+
+        self.free(txn, page)
+    }
+
+    /// Read the node at `page`, where the root is read through `root`, which
+    /// the caller has latched.
+    fn read_node(&self, root: &[u8], page: PageIndex) -> Result<Node, Error> {
+        if page == ROOT_PAGE {
+            return Ok(Node::read(&root[PAGE_HEADER_SIZE..]));
+        }
+
+        Ok(Node::read(&self.files.latest(self.file, page)?.read()[PAGE_HEADER_SIZE..]))
+    }
+
+    /// Write a node to `page`, where the root is written through `root`, which
+    /// the caller has write latched. Nobody else gets past the root's latch, so
+    /// no other page needs latching for longer than it takes to write it.
+    fn write_node(&self, txn: &Txn<'_>, root: &mut [u8], page: PageIndex, node: &Node) -> Result<(), Error> {
+        if page == ROOT_PAGE {
+            txn.modify_latched(&self.files, self.file, page, root, |body| node.write(body))?;
+        } else {
+            txn.modify(&self.files, self.file, page, |body| node.write(body))?;
+        }
+
+        Ok(())
+    }
+
+    /// Take a page off the free list, or add one to the end of the file if
+    /// there aren't any.
+    fn allocate(&self, txn: &Txn<'_>) -> Result<PageIndex, Error> {
+        let meta_ref = self.files.dirty(self.file, META_PAGE)?;
+        let mut meta = meta_ref.write();
+
+        let head = read_page_index(&meta[PAGE_HEADER_SIZE..]);
+        if head == 0 {
+            return Ok(self.files.allocate(self.file)?);
+        }
+
+        let next = node::next_free(&self.files.latest(self.file, head)?.read()[PAGE_HEADER_SIZE..]);
+        txn.modify_latched(&self.files, self.file, META_PAGE, &mut meta, |body| {
+            write_page_index(body, next)
+        })?;
+
+        Ok(head)
+    }
+
+    /// Put a page that nothing points at anymore on the free list.
+    fn free(&self, txn: &Txn<'_>, page: PageIndex) -> Result<(), Error> {
+        let meta_ref = self.files.dirty(self.file, META_PAGE)?;
+        let mut meta = meta_ref.write();
+
+        let head = read_page_index(&meta[PAGE_HEADER_SIZE..]);
+        txn.modify(&self.files, self.file, page, |body| Node::write_free(body, head))?;
+        txn.modify_latched(&self.files, self.file, META_PAGE, &mut meta, |body| {
+            write_page_index(body, page)
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Something that [`RTree::nearest`] has yet to visit.
+enum Candidate {
+    Node(PageIndex),
+    Entry(Rect, Vec<u8>),
+    Visited,
+}
+
+/// A distance that can be ordered, which it can since it's never NaN.
+#[derive(Copy, Clone, PartialEq)]
+struct Distance(f64);
+
+impl Eq for Distance {}
+
+impl PartialOrd for Distance {
+    fn partial_cmp(&self, other: &Distance) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Distance {
+    fn cmp(&self, other: &Distance) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+fn body_len() -> usize {
+    page_size() - PAGE_HEADER_SIZE
+}
+
+fn read_page_index(body: &[u8]) -> PageIndex {
+    u64::from_le_bytes(body[..8].try_into().unwrap()) as PageIndex
+}
+
+fn write_page_index(body: &mut [u8], page: PageIndex) {
+    body[..8].copy_from_slice(&(page as u64).to_le_bytes());
+}
@@ -0,0 +1,336 @@
+//! The body of a tree page (everything past the page header) is a node:
+//!
+//! ```text
+//! [level: u8][flags: u8][entry count: u16][unused: u32][next free page: u64]
+//! entries, one after another
+//! ...free space...
+//! ```
+//!
+//! Every entry starts with a box, as `[min x: f64][min y: f64][max x: f64]
+//! [max y: f64]`. In a leaf (level 0), that's followed by
+//! `[value len: u16][value]`, and in an internal node, by `[child: u64]`,
+//! where the box is the smallest one that holds every box under the child.
+//!
+//! An all-zero page is an empty leaf. Pages on the free list have the
+//! [`FLAG_FREE`] flag, and the next free page after the header.
+
+use std::cmp::Ordering;
+
+use ferrodb_fs::PageIndex;
+
+use crate::Rect;
+
+const HEADER_SIZE: usize = 16;
+const RECT_SIZE: usize = 32;
+const VALUE_LEN_SIZE: usize = 2;
+const CHILD_SIZE: usize = 8;
+
+const FLAG_FREE: u8 = 1 << 0;
+
+/// The longest value that can go in a node with a `body_len` byte body, which
+/// makes sure that at least four of the biggest entries fit in a node.
+pub(crate) fn max_value_len(body_len: usize) -> usize {
+    (body_len - HEADER_SIZE) / 4 - RECT_SIZE - VALUE_LEN_SIZE
+}
+
+/// A node other than the root with fewer bytes of entries than this (out of a
+/// `body_len` byte body) is taken out of the tree, and its entries are put
+/// back in elsewhere.
+pub(crate) fn min_content(body_len: usize) -> usize {
+    (body_len - HEADER_SIZE) / 3
+}
+
+/// The next page on the free list after a free page.
+pub(crate) fn next_free(body: &[u8]) -> PageIndex {
+    read_u64(body, 8) as PageIndex
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Entry {
+    pub rect: Rect,
+    /// Only used in leaves.
+    pub value: Vec<u8>,
+    /// Only used in internal nodes.
+    pub child: PageIndex,
+}
+
+impl Entry {
+    pub fn leaf(rect: Rect, value: &[u8]) -> Entry {
+        Entry {
+            rect,
+            value: value.to_vec(),
+            child: 0,
+        }
+    }
+
+    pub fn internal(rect: Rect, child: PageIndex) -> Entry {
+        Entry {
+            rect,
+            value: vec![],
+            child,
+        }
+    }
+}
+
+/// A node, read out of its page to be changed and written back.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Node {
+    pub level: u8,
+    pub entries: Vec<Entry>,
+}
+
+impl Node {
+    pub fn read(body: &[u8]) -> Node {
+        let level = body[0];
+        let mut at = HEADER_SIZE;
+
+        let entries = (0..read_u16(body, 2))
+            .map(|_| {
+                let rect = Rect {
+                    min_x: read_f64(body, at),
+                    min_y: read_f64(body, at + 8),
+                    max_x: read_f64(body, at + 16),
+                    max_y: read_f64(body, at + 24),
+                };
+                at += RECT_SIZE;
+
+                if level == 0 {
+                    let len = read_u16(body, at) as usize;
+                    let value = &body[at + VALUE_LEN_SIZE..][..len];
+                    at += VALUE_LEN_SIZE + len;
+                    Entry::leaf(rect, value)
+                } else {
+                    let child = read_u64(body, at) as PageIndex;
+                    at += CHILD_SIZE;
+                    Entry::internal(rect, child)
+                }
+            })
+            .collect();
+
+        Node { level, entries }
+    }
+
+    pub fn write(&self, body: &mut [u8]) {
+        assert!(self.size() <= body.len(), "Expected node to fit in its page");
+
+        body.fill(0);
+        body[0] = self.level;
+        write_u16(body, 2, self.entries.len() as u16);
+
+        let mut at = HEADER_SIZE;
+        for entry in &self.entries {
+            let rect = entry.rect;
+            for (offset, coordinate) in [rect.min_x, rect.min_y, rect.max_x, rect.max_y].into_iter().enumerate() {
+                write_f64(body, at + offset * 8, coordinate);
+            }
+            at += RECT_SIZE;
+
+            if self.is_leaf() {
+                write_u16(body, at, entry.value.len() as u16);
+                body[at + VALUE_LEN_SIZE..][..entry.value.len()].copy_from_slice(&entry.value);
+                at += VALUE_LEN_SIZE + entry.value.len();
+            } else {
+                write_u64(body, at, entry.child as u64);
+                at += CHILD_SIZE;
+            }
+        }
+    }
+
+    /// Turn the page into a free page, pointing at the `next` one.
+    pub fn write_free(body: &mut [u8], next: PageIndex) {
+        body.fill(0);
+        body[1] = FLAG_FREE;
+        write_u64(body, 8, next as u64);
+    }
+
+    pub fn is_leaf(&self) -> bool {
+        self.level == 0
+    }
+
+    /// The smallest box that holds every entry's, which there has to be at
+    /// least one of.
+    pub fn bounds(&self) -> Rect {
+        let first = self.entries[0].rect;
+        self.entries[1..].iter().fold(first, |bounds, entry| bounds.union(&entry.rect))
+    }
+
+    /// How many bytes of the page this node takes up.
+    pub fn size(&self) -> usize {
+        HEADER_SIZE + self.content()
+    }
+
+    /// How many bytes of the page this node's entries take up.
+    pub fn content(&self) -> usize {
+        self.entries.iter().map(|entry| self.entry_size(entry)).sum()
+    }
+
+    fn entry_size(&self, entry: &Entry) -> usize {
+        match self.is_leaf() {
+            true => RECT_SIZE + VALUE_LEN_SIZE + entry.value.len(),
+            false => RECT_SIZE + CHILD_SIZE,
+        }
+    }
+
+    /// The index of the child that a new entry with this box should go under:
+    /// the one whose box would have to grow the least to hold it, or if
+    /// there's a tie, the smallest one.
+    pub fn choose(&self, rect: &Rect) -> usize {
+        let cost = |entry: &Entry| {
+            let area = entry.rect.area();
+            (entry.rect.union(rect).area() - area, area)
+        };
+
+        (0..self.entries.len())
+            .min_by(|&a, &b| {
+                let (a, b) = (cost(&self.entries[a]), cost(&self.entries[b]));
+                a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1))
+            })
+            .unwrap()
+    }
+
+    /// Move some of this node's entries, which are too big for a page with a
+    /// `body_len` byte body, into a new node, leaving each of them at least
+    /// [`min_content`] full.
+    ///
+    /// This is the R*-tree's split. The entries are sorted along each axis, by
+    /// their lower edges and by their upper ones, and every way of cutting one
+    /// of those in two is a candidate. The axis is the one whose candidates
+    /// have the least margin between them, since square boxes are the ones
+    /// that searches can skip the most often, and the cut along it is the one
+    /// where the two halves overlap the least, or if there's a tie, take up
+    /// the least area.
+    pub fn split(&mut self, body_len: usize) -> Node {
+        let capacity = body_len - HEADER_SIZE;
+        let fits = |content: usize| (min_content(body_len)..=capacity).contains(&content);
+        let total = self.content();
+
+        // The total margin of each axis's candidates, and its best one.
+        let mut best: Option<(f64, Candidate)> = None;
+        for axis in [Axis::X, Axis::Y] {
+            let mut margin = 0.0;
+            let mut axis_best: Option<Candidate> = None;
+
+            for by_upper in [false, true] {
+                let mut order: Vec<usize> = (0..self.entries.len()).collect();
+                order.sort_by(|&a, &b| axis.cmp(&self.entries[a].rect, &self.entries[b].rect, by_upper));
+                let (lower, upper) = self.running_bounds(&order);
+
+                let mut left = 0;
+                for cut in 1..order.len() {
+                    left += self.entry_size(&self.entries[order[cut - 1]]);
+                    if !fits(left) || !fits(total - left) {
+                        continue;
+                    }
+
+                    let (a, b) = (lower[cut - 1], upper[cut]);
+                    margin += a.margin() + b.margin();
+
+                    let cost = (a.overlap(&b), a.area() + b.area());
+                    if axis_best.as_ref().is_none_or(|best| best.cmp_cost(cost) == Ordering::Greater) {
+                        axis_best = Some(Candidate {
+                            cost,
+                            order: order.clone(),
+                            cut,
+                        });
+                    }
+                }
+            }
+
+            let axis_best = axis_best.expect("Expected a node that's too big to have some way to split it");
+            if best.as_ref().is_none_or(|(best_margin, _)| margin < *best_margin) {
+                best = Some((margin, axis_best));
+            }
+        }
+
+        let (_, Candidate { order, cut, .. }) = best.unwrap();
+        let mut entries: Vec<Option<Entry>> = std::mem::take(&mut self.entries).into_iter().map(Some).collect();
+        let mut sorted = order.into_iter().map(|index| entries[index].take().unwrap());
+
+        self.entries = sorted.by_ref().take(cut).collect();
+        Node {
+            level: self.level,
+            entries: sorted.collect(),
+        }
+    }
+
+    /// For entries in `order`, the bounds of the first `i + 1` of them at
+    /// index `i` of the first list, and of all of them from index `i` on at
+    /// index `i` of the second.
+    fn running_bounds(&self, order: &[usize]) -> (Vec<Rect>, Vec<Rect>) {
+        let rect = |index: usize| self.entries[order[index]].rect;
+
+        let mut lower = vec![rect(0)];
+        for index in 1..order.len() {
+            lower.push(lower[index - 1].union(&rect(index)));
+        }
+
+        let mut upper = vec![rect(order.len() - 1)];
+        for index in (0..order.len() - 1).rev() {
+            upper.push(upper.last().unwrap().union(&rect(index)));
+        }
+        upper.reverse();
+
+        (lower, upper)
+    }
+}
+
+/// One way that [`Node::split`] could split a node: its entries in `order`,
+/// cut in two before the one at `cut`.
+struct Candidate {
+    /// How much the two halves overlap, and how much area they take up.
+    cost: (f64, f64),
+    order: Vec<usize>,
+    cut: usize,
+}
+
+impl Candidate {
+    fn cmp_cost(&self, cost: (f64, f64)) -> Ordering {
+        self.cost.0.total_cmp(&cost.0).then(self.cost.1.total_cmp(&cost.1))
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Axis {
+    X,
+    Y,
+}
+
+impl Axis {
+    /// How two boxes compare by their lower edges along this axis, or by their
+    /// upper ones, with the other edge breaking ties.
+    fn cmp(self, a: &Rect, b: &Rect, by_upper: bool) -> Ordering {
+        let edges = |rect: &Rect| match (self, by_upper) {
+            (Axis::X, false) => (rect.min_x, rect.max_x),
+            (Axis::X, true) => (rect.max_x, rect.min_x),
+            (Axis::Y, false) => (rect.min_y, rect.max_y),
+            (Axis::Y, true) => (rect.max_y, rect.min_y),
+        };
+
+        let (a, b) = (edges(a), edges(b));
+        a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1))
+    }
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap())
+}
+
+fn write_u16(bytes: &mut [u8], at: usize, value: u16) {
+    bytes[at..at + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn write_u64(bytes: &mut [u8], at: usize, value: u64) {
+    bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn read_f64(bytes: &[u8], at: usize) -> f64 {
+    f64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn write_f64(bytes: &mut [u8], at: usize, value: f64) {
+    bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
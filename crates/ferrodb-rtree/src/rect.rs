@@ -0,0 +1,130 @@
+use std::fmt;
+
+/// An axis-aligned box in two dimensions, which is a point if its minimums
+/// and maximums are the same.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+impl Rect {
+    /// The box with opposite corners `(x1, y1)` and `(x2, y2)`, which can be
+    /// any two opposite corners.
+    pub fn new(x1: f64, y1: f64, x2: f64, y2: f64) -> Rect {
+        Rect {
+            min_x: x1.min(x2),
+            min_y: y1.min(y2),
+            max_x: x1.max(x2),
+            max_y: y1.max(y2),
+        }
+    }
+
+    pub fn point(x: f64, y: f64) -> Rect {
+        Rect {
+            min_x: x,
+            min_y: y,
+            max_x: x,
+            max_y: y,
+        }
+    }
+
+    /// Whether every coordinate is a finite number, and no minimum is past its
+    /// maximum, which every box in a tree has to be.
+    pub fn is_valid(&self) -> bool {
+        [self.min_x, self.min_y, self.max_x, self.max_y]
+            .iter()
+            .all(|coordinate| coordinate.is_finite())
+            && self.min_x <= self.max_x
+            && self.min_y <= self.max_y
+    }
+
+    /// Whether the two boxes have any point in common, their edges included.
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.min_x <= other.max_x
+            && other.min_x <= self.max_x
+            && self.min_y <= other.max_y
+            && other.min_y <= self.max_y
+    }
+
+    /// Whether every point of `other` is in this box, its edges included.
+    pub fn contains(&self, other: &Rect) -> bool {
+        self.min_x <= other.min_x
+            && other.max_x <= self.max_x
+            && self.min_y <= other.min_y
+            && other.max_y <= self.max_y
+    }
+
+    /// How far the point `(x, y)` is from the nearest point of this box, which
+    /// is 0 if it's in it.
+    pub fn distance(&self, x: f64, y: f64) -> f64 {
+        let dx = (self.min_x - x).max(x - self.max_x).max(0.0);
+        let dy = (self.min_y - y).max(y - self.max_y).max(0.0);
+        dx.hypot(dy)
+    }
+
+    /// The smallest box that holds both of these.
+    pub fn union(&self, other: &Rect) -> Rect {
+        Rect {
+            min_x: self.min_x.min(other.min_x),
+            min_y: self.min_y.min(other.min_y),
+            max_x: self.max_x.max(other.max_x),
+            max_y: self.max_y.max(other.max_y),
+        }
+    }
+
+    pub(crate) fn area(&self) -> f64 {
+        (self.max_x - self.min_x) * (self.max_y - self.min_y)
+    }
+
+    /// Half the perimeter, which is smaller the more square a box is.
+    pub(crate) fn margin(&self) -> f64 {
+        (self.max_x - self.min_x) + (self.max_y - self.min_y)
+    }
+
+    /// How much area the two boxes have in common.
+    pub(crate) fn overlap(&self, other: &Rect) -> f64 {
+        let width = self.max_x.min(other.max_x) - self.min_x.max(other.min_x);
+        let height = self.max_y.min(other.max_y) - self.min_y.max(other.min_y);
+        width.max(0.0) * height.max(0.0)
+    }
+}
+
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BOX({}, {}, {}, {})", self.min_x, self.min_y, self.max_x, self.max_y)
+    }
+}
+
+/// What the boxes that a search finds have to have to do with its box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Query {
+    /// Boxes that have any point in common with this one, like SQL's `&&`.
+    Intersects(Rect),
+    /// Boxes that this one is inside of, like SQL's `@>`.
+    Contains(Rect),
+    /// Boxes that are inside of this one, like SQL's `<@`.
+    Within(Rect),
+}
+
+impl Query {
+    /// Whether a box is one that the search is looking for.
+    pub fn matches(&self, rect: &Rect) -> bool {
+        match self {
+            Query::Intersects(query) => rect.intersects(query),
+            Query::Contains(query) => rect.contains(query),
+            Query::Within(query) => query.contains(rect),
+        }
+    }
+
+    /// Whether a node whose entries are all inside of `bounds` could have any
+    /// that the search is looking for.
+    pub(crate) fn might_match(&self, bounds: &Rect) -> bool {
+        match self {
+            Query::Intersects(query) | Query::Within(query) => bounds.intersects(query),
+            Query::Contains(query) => bounds.contains(query),
+        }
+    }
+}
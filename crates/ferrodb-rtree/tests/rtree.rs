@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use ferrodb_fs::vfs::MemoryVfs;
+use ferrodb_fs::FileManager;
+use ferrodb_rtree::{Error, Query, RTree, Rect};
+use ferrodb_wal::Wal;
+
+const PAGE_SIZE: usize = 512;
+
+type Entry = (Rect, Vec<u8>);
+
+fn open() -> (Arc<Wal>, RTree) {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let vfs = Arc::new(MemoryVfs::default());
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+
+    let tree = RTree::open(files, "tree").unwrap();
+    (wal, tree)
+}
+
+/// A xorshift generator, so that every run sees the same entries.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: u64) -> f64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n) as f64
+    }
+
+    /// A point or a small box somewhere in a 1000 by 1000 square, on whole
+    /// coordinates, so that there are plenty of ties and shared edges.
+    fn rect(&mut self) -> Rect {
+        let (x, y) = (self.below(1000), self.below(1000));
+        if self.below(3) == 0.0 {
+            Rect::point(x, y)
+        } else {
+            Rect::new(x, y, x + self.below(50), y + self.below(50))
+        }
+    }
+}
+
+fn sorted(mut entries: Vec<Entry>) -> Vec<Entry> {
+    entries.sort_by(|(a, a_value), (b, b_value)| {
+        let a = [a.min_x, a.min_y, a.max_x, a.max_y];
+        let b = [b.min_x, b.min_y, b.max_x, b.max_y];
+        a.partial_cmp(&b).unwrap().then_with(|| a_value.cmp(b_value))
+    });
+    entries
+}
+
+/// Check every kind of search, and nearest-neighbor searches, against
+/// looking through every entry.
+fn check(tree: &RTree, model: &[Entry], rng: &mut Rng) {
+    for _ in 0..50 {
+        let rect = rng.rect();
+        let wide = Rect::new(rect.min_x, rect.min_y, rect.max_x + 100.0, rect.max_y + 100.0);
+
+        for query in [Query::Intersects(wide), Query::Contains(rect), Query::Within(wide)] {
+            let expected = model.iter().filter(|(rect, _)| query.matches(rect)).cloned().collect();
+            assert_eq!(sorted(tree.search(query).unwrap()), sorted(expected), "{query:?}");
+        }
+    }
+
+    for _ in 0..50 {
+        let (x, y, k) = (rng.below(1100) - 50.0, rng.below(1100) - 50.0, rng.below(20) as usize);
+        let found = tree.nearest(x, y, k).unwrap();
+
+        // Ties can come in any order, so only the distances have to match.
+        let mut expected: Vec<_> = model.iter().map(|(rect, _)| rect.distance(x, y)).collect();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        expected.truncate(k);
+
+        let distances: Vec<_> = found.iter().map(|(rect, _)| rect.distance(x, y)).collect();
+        assert_eq!(distances, expected, "nearest {k} to ({x}, {y})");
+        assert!(found.iter().all(|entry| model.contains(entry)));
+    }
+}
+
+#[test]
+fn matches_a_list_of_entries() {
+    let (wal, tree) = open();
+    let mut rng = Rng(7);
+    let mut model = vec![];
+
+    let txn = wal.begin().unwrap();
+    for i in 0..3000u32 {
+        let entry = (rng.rect(), i.to_be_bytes().to_vec());
+        assert!(tree.insert(&txn, entry.0, &entry.1).unwrap());
+        model.push(entry);
+    }
+    // The same box can have several values, but not the same one twice.
+    for i in 0..100 {
+        let (rect, value) = model[i * 7].clone();
+        assert!(!tree.insert(&txn, rect, &value).unwrap());
+        assert!(tree.insert(&txn, rect, b"again").unwrap());
+        model.push((rect, b"again".to_vec()));
+    }
+    txn.commit().unwrap();
+    check(&tree, &model, &mut rng);
+
+    // Deleting most of them empties out enough nodes that their entries have
+    // to be inserted again.
+    let txn = wal.begin().unwrap();
+    let mut kept = vec![];
+    for (i, (rect, value)) in model.into_iter().enumerate() {
+        if i % 4 == 0 {
+            kept.push((rect, value));
+        } else {
+            assert!(tree.delete(&txn, rect, &value).unwrap());
+            assert!(!tree.delete(&txn, rect, &value).unwrap());
+        }
+    }
+    txn.commit().unwrap();
+    check(&tree, &kept, &mut rng);
+
+    let txn = wal.begin().unwrap();
+    for (rect, value) in kept.drain(..) {
+        assert!(tree.delete(&txn, rect, &value).unwrap());
+    }
+    txn.commit().unwrap();
+    check(&tree, &kept, &mut rng);
+    assert!(tree.nearest(0.0, 0.0, 10).unwrap().is_empty());
+
+    // An emptied tree is as good as a new one.
+    let txn = wal.begin().unwrap();
+    for i in 0..500u32 {
+        let entry = (rng.rect(), i.to_be_bytes().to_vec());
+        assert!(tree.insert(&txn, entry.0, &entry.1).unwrap());
+        kept.push(entry);
+    }
+    txn.commit().unwrap();
+    check(&tree, &kept, &mut rng);
+}
+
+#[test]
+fn rejects_invalid_boxes() {
+    let (wal, tree) = open();
+    let txn = wal.begin().unwrap();
+
+    let nan = Rect::point(f64::NAN, 0.0);
+    assert!(matches!(tree.insert(&txn, nan, b"value"), Err(Error::InvalidRect(_))));
+    let infinite = Rect::new(0.0, 0.0, f64::INFINITY, 1.0);
+    assert!(matches!(tree.insert(&txn, infinite, b"value"), Err(Error::InvalidRect(_))));
+    assert!(matches!(tree.nearest(f64::NAN, 0.0, 1), Err(Error::InvalidRect(_))));
+    txn.commit().unwrap();
+}
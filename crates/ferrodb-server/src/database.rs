@@ -0,0 +1,159 @@
+use std::io;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use camino::Utf8PathBuf;
+use ferrodb_catalog::{Catalog, Ddl};
+use ferrodb_fs::vfs::{DiskVfs, Vfs};
+use ferrodb_fs::{EncryptionKey, FileManager, FileManagerOptions, Lsn};
+use ferrodb_wal::{needs_recovery, recover, RecoveryPhase, Wal, WalOptions};
+
+pub const PAGE_SIZE: usize = 8192;
+
+/// Everything the server keeps on disk.
+pub struct Database {
+    files: Arc<FileManager>,
+    wal: Arc<Wal>,
+    catalog: Catalog,
+}
+
+impl Database {
+    /// Open the database stored in `vfs`, recovering from a crash if the last
+    /// run didn't shut down cleanly. If there's an `encryption_key`, every
+    /// data file, and the log, is encrypted at rest with it.
+    pub fn open(vfs: Arc<dyn Vfs>, encryption_key: Option<EncryptionKey>) -> Result<Database> {
+        ferrodb_page::setup(PAGE_SIZE);
+
+        // The file manager locks the data directory, so it has to be opened
+        // before anything (like the log) can be touched.
+        let files = Arc::new(FileManager::with_options(vfs.clone(), FileManagerOptions {
+            double_write: true,
+            encryption_key: encryption_key.clone(),
+            ..FileManagerOptions::default()
+        })?);
+        let wal = Arc::new(Wal::with_options(vfs, "wal", WalOptions {
+            encryption_key,
+            ..WalOptions::default()
+        })?);
+        files.set_log(wal.clone());
+
+        for (file, page) in files.repair_torn_pages()? {
+            println!("Repaired torn page {page} of `{file}`");
+        }
+
+        Catalog::register_undo(&wal, &files);
+        let report = recover(&wal, &files, |phase, lsn| match phase {
+            RecoveryPhase::Analysis => println!("Recovery: analyzing log at LSN {lsn}"),
+            RecoveryPhase::Redo => println!("Recovery: redoing log at LSN {lsn}"),
+            RecoveryPhase::Undo => println!("Recovery: undoing log at LSN {lsn}"),
+        })?;
+        println!(
+            "Recovered LSNs {}..{}: redid {} changes, rolled back {} transactions",
+            report.start_lsn,
+            report.end_lsn,
+            report.redone,
+            report.rolled_back.len()
+        );
+
+        let catalog = Catalog::open(files.clone(), wal.clone())?;
+        println!("Loaded {} tables from the catalog", catalog.tables().len());
+
+        Ok(Database {
+            files,
+            wal,
+            catalog,
+        })
+    }
+
+    /// Open the database stored in `vfs` without writing anything to it, so
+    /// that other read-only users can have it open at the same time. That
+    /// only works if it doesn't need recovering: after a crash, it has to be
+    /// opened with [`Database::open`] once first.
+    pub fn open_read_only(vfs: Arc<dyn Vfs>, encryption_key: Option<EncryptionKey>) -> Result<Database> {
+        ferrodb_page::setup(PAGE_SIZE);
+
+        let files = Arc::new(FileManager::with_options(vfs.clone(), FileManagerOptions {
+            double_write: true,
+            encryption_key: encryption_key.clone(),
+            read_only: true,
+            ..FileManagerOptions::default()
+        })?);
+        let wal = Arc::new(Wal::with_options(vfs, "wal", WalOptions {
+            encryption_key,
+            read_only: true,
+        })?);
+        files.set_log(wal.clone());
+
+        if needs_recovery(&wal, &files)? {
+            bail!("The database didn't shut down cleanly, so it has to be opened read-write once to recover");
+        }
+
+        let catalog = Catalog::open(files.clone(), wal.clone())?;
+        println!("Loaded {} tables from the catalog", catalog.tables().len());
+
+        Ok(Database {
+            files,
+            wal,
+            catalog,
+        })
+    }
+
+    /// Copy the whole database into `dir` while it keeps taking writes. The
+    /// copy is restored by opening it like any other database directory:
+    /// recovery replays the log that was copied along with the data files,
+    /// which makes them consistent. Returns the LSN the copy is as of.
+    pub fn backup(&self, dir: impl Into<Utf8PathBuf>) -> Result<Lsn> {
+        let dir = dir.into();
+
+        // The log goes in a directory of its own, so it isn't enough that there
+        // are no files directly in `dir`.
+        match std::fs::read_dir(&dir) {
+            Ok(mut entries) =>
+                if entries.next().is_some() {
+                    bail!("Backup directory isn't empty");
+                },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+            Err(e) => return Err(e.into()),
+        }
+
+        let target = DiskVfs::new(dir);
+
+        // Nothing before the checkpoint has to be redone, and everything that's
+        // written back while the data files are copied will be in the log tail
+        // that's copied after them.
+        let checkpoint = self.wal.checkpoint(&self.files)?;
+        self.files.backup_to(&target)?;
+        let end = self.wal.backup_to(&target, checkpoint)?;
+
+        Ok(end)
+    }
+
+    /// Change the catalog with `f`, committing the changes if it succeeds and
+    /// rolling them back if it fails.
+    pub fn ddl<T>(&self, f: impl FnOnce(&mut Ddl<'_>) -> Result<T>) -> Result<T> {
+        let mut ddl = self.catalog.begin()?;
+
+        match f(&mut ddl) {
+            Ok(result) => {
+                ddl.commit()?;
+                Ok(result)
+            },
+            Err(e) => {
+                ddl.abort()?;
+                Err(e)
+            },
+        }
+    }
+
+    pub fn files(&self) -> &FileManager {
+        &self.files
+    }
+
+    pub fn wal(&self) -> &Wal {
+        &self.wal
+    }
+
+    pub fn catalog(&self) -> &Catalog {
+        &self.catalog
+    }
+}
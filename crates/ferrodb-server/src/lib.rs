@@ -1,24 +1,74 @@
-#![feature(let_else)]
+mod database;
+mod statement;
 
+use std::collections::HashMap;
 use std::io::{Read, Write};
+use std::net::TcpListener;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::thread::JoinHandle;
+use std::time::Duration;
 
 use anyhow::{anyhow, bail, Result};
-use ferrodb_protocol::{Ping, Pong, Transport, PROTOCOL_VERSION, PREAMBLE};
+use ferrodb_catalog::{
+    BuildOptions, ConstraintKind, Ddl, IndexKind, ObjectId, SequenceOptions, StorageEngine,
+};
+use ferrodb_heap::VacuumStats;
+use ferrodb_protocol::{
+    Command, Ping, Pong, QueryResponse, Transport, PROTOCOL_VERSION, PREAMBLE,
+};
+use ferrodb_row::{Column, DataType, Schema, Value};
 
-pub fn spawn_server_loop(port: u16) -> JoinHandle<Result<()>> {
-    todo!()
+pub use self::database::Database;
+use self::statement::{ColumnDef, ConstraintDef, Statement};
+
+pub fn spawn_server_loop(port: u16, db: Arc<Database>) -> JoinHandle<Result<()>> {
+    std::thread::spawn(move || {
+        let listener = TcpListener::bind(("::", port))?;
+
+        for conn in listener.incoming() {
+            let conn = conn?;
+            let db = db.clone();
+
+            std::thread::spawn(move || {
+                if let Err(e) = server_standalone(conn, db) {
+                    println!("Connection closed: {e}");
+                }
+            });
+        }
+
+        Ok(())
+    })
+}
+
+/// How many rows of a heap have to have been updated or deleted since it was
+/// last vacuumed for autovacuum to vacuum it.
+const AUTOVACUUM_MIN_CHANGES: usize = 50;
+
+/// Vacuum every heap that needs it, every `interval`, for as long as the
+/// process runs.
+pub fn spawn_autovacuum(db: Arc<Database>, interval: Duration) -> JoinHandle<()> {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(interval);
+
+        match db.catalog().autovacuum(AUTOVACUUM_MIN_CHANGES) {
+            Ok(vacuumed) =>
+                for (name, stats) in vacuumed {
+                    println!("Autovacuum: {}", describe_vacuum(&name, &stats));
+                },
+            Err(e) => println!("Autovacuum failed: {e}"),
+        }
+    })
 }
 
-pub fn spawn_server_standalone<C>(conn: C) -> JoinHandle<Result<()>>
+pub fn spawn_server_standalone<C>(conn: C, db: Arc<Database>) -> JoinHandle<Result<()>>
 where
     C: Read + Write + Send + 'static,
 {
-    std::thread::spawn(|| server_standalone(conn))
+    std::thread::spawn(|| server_standalone(conn, db))
 }
 
-fn server_standalone<C>(mut conn: C) -> Result<()>
+fn server_standalone<C>(mut conn: C, db: Arc<Database>) -> Result<()>
 where
     C: Read + Write,
 {
@@ -46,6 +96,251 @@ where
     }
 
     println!("ok.");
+
+    let mut session = Session::default();
+
+    // Serve queries until the client says `Goodbye`.
+    while let Command::Query(query) = stream.read()? {
+        match execute(&db, &mut session, &query) {
+            Ok(rows) => {
+                if let Some(rows) = rows {
+                    stream.write(QueryResponse::SomeRows(rows))?;
+                }
+                stream.write(QueryResponse::Done)?;
+            },
+            Err(e) => stream.write(QueryResponse::Error(format!("{e:#}")))?,
+        }
+    }
+
+    Ok(())
+}
+
+/// What a connection remembers between its queries.
+#[derive(Default)]
+struct Session {
+    /// The last value of each sequence that was handed out to the connection.
+    current_values: HashMap<ObjectId, i64>,
+}
+
+fn execute(db: &Database, session: &mut Session, query: &str) -> Result<Option<String>> {
+    match Statement::parse(query)? {
+        Statement::Backup { dir } => {
+            let lsn = db.backup(&dir)?;
+            Ok(Some(format!("Backed up to `{dir}` as of LSN {lsn}")))
+        },
+        Statement::CreateTable {
+            name,
+            columns,
+            constraints,
+            engine,
+        } => {
+            db.ddl(|ddl| create_table(ddl, &name, &columns, &constraints, engine))?;
+            Ok(Some(format!("Created table `{name}`")))
+        },
+        Statement::DropTable { name } => {
+            db.ddl(|ddl| Ok(ddl.drop_table(&name)?))?;
+            Ok(Some(format!("Dropped table `{name}`")))
+        },
+        Statement::AddColumn { table, column } => {
+            if column.default.is_some() || column.serial {
+                bail!("A column that's added to a table can't have a default");
+            }
+
+            db.ddl(|ddl| {
+                Ok(ddl.add_column(&table, Column {
+                    name: column.name.clone(),
+                    data_type: column.data_type,
+                    nullable: column.nullable,
+                })?)
+            })?;
+            Ok(Some(format!("Added column `{}` to `{table}`", column.name)))
+        },
+        Statement::CreateIndex {
+            name,
+            table,
+            kind,
+            columns,
+            unique,
+            fill_factor,
+        } => {
+            let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+            let mut options = BuildOptions::default();
+            options.fill_factor = fill_factor.unwrap_or(options.fill_factor);
+
+            let entries = db.ddl(|ddl| {
+                ddl.create_index(&table, &name, kind, &columns, unique)?;
+                Ok(ddl.build_index(&name, &options)?)
+            })?;
+
+            Ok(Some(format!("Created index `{name}` on `{table}` with {entries} entries")))
+        },
+        Statement::DropIndex { name } => {
+            db.ddl(|ddl| Ok(ddl.drop_index(&name)?))?;
+            Ok(Some(format!("Dropped index `{name}`")))
+        },
+        Statement::CreateSequence { name, options } => {
+            db.ddl(|ddl| {
+                ddl.create_sequence(&name, &options, None)?;
+                Ok(())
+            })?;
+            Ok(Some(format!("Created sequence `{name}`")))
+        },
+        Statement::DropSequence { name } => {
+            db.ddl(|ddl| Ok(ddl.drop_sequence(&name)?))?;
+            Ok(Some(format!("Dropped sequence `{name}`")))
+        },
+        Statement::NextValue { sequence } => {
+            let id = sequence_id(db, &sequence)?;
+            let value = db.catalog().next_value(id)?;
+            session.current_values.insert(id, value);
+            Ok(Some(value.to_string()))
+        },
+        Statement::CurrentValue { sequence } => {
+            let id = sequence_id(db, &sequence)?;
+            let Some(value) = session.current_values.get(&id)
+                else { bail!("`nextval` hasn't been called for sequence `{sequence}` yet"); };
+
+            Ok(Some(value.to_string()))
+        },
+        Statement::SetValue {
+            sequence,
+            value,
+            is_called,
+        } => {
+            let id = sequence_id(db, &sequence)?;
+            db.catalog().set_value(id, value, is_called)?;
+            session.current_values.insert(id, value);
+            Ok(Some(value.to_string()))
+        },
+        Statement::Vacuum { table } => {
+            let vacuumed = db.catalog().vacuum(table.as_deref())?;
+            let lines: Vec<_> = vacuumed
+                .iter()
+                .map(|(name, stats)| describe_vacuum(name, stats))
+                .collect();
+            Ok(Some(lines.join("\n")))
+        },
+        Statement::SearchBoxes {
+            table,
+            columns,
+            query,
+        } => {
+            let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+            let rows = db.catalog().search_boxes(&table, &columns, query)?;
+            Ok(Some(describe_rows(&rows)))
+        },
+        Statement::Nearest {
+            table,
+            columns,
+            x,
+            y,
+            k,
+        } => {
+            let columns: Vec<&str> = columns.iter().map(String::as_str).collect();
+            let rows = db.catalog().nearest(&table, &columns, x, y, k)?;
+            Ok(Some(describe_rows(&rows)))
+        },
+    }
+}
+
+/// A row per line, with its values separated by commas.
+fn describe_rows(rows: &[Vec<Value>]) -> String {
+    let lines: Vec<_> = rows
+        .iter()
+        .map(|row| row.iter().map(Value::to_string).collect::<Vec<_>>().join(", "))
+        .collect();
+    lines.join("\n")
+}
+
+fn describe_vacuum(name: &str, stats: &VacuumStats) -> String {
+    format!(
+        "Vacuumed `{name}`: compacted {} of {} pages, moved {} rows home, truncated {} pages, {} bytes free",
+        stats.compacted, stats.pages, stats.moved_home, stats.empty_tail, stats.free_bytes
+    )
+}
+
+fn sequence_id(db: &Database, name: &str) -> Result<ObjectId> {
+    match db.catalog().sequence(name) {
+        Some(def) => Ok(def.id),
+        None => Err(ferrodb_catalog::Error::NoSuchSequence(name.to_owned()).into()),
+    }
+}
+
+fn create_table(
+    ddl: &mut Ddl<'_>,
+    name: &str,
+    columns: &[ColumnDef],
+    constraints: &[ConstraintDef],
+    engine: StorageEngine,
+) -> Result<()> {
+    let primary_keys = constraints
+        .iter()
+        .filter(|constraint| constraint.kind == ConstraintKind::PrimaryKey)
+        .count();
+    if primary_keys > 1 {
+        return Err(ferrodb_catalog::Error::MultiplePrimaryKeys(name.to_owned()).into());
+    }
+
+    let mut schema = vec![];
+    let mut defaults = vec![];
+
+    for column in columns {
+        // Columns of the primary key are `NOT NULL` whether they say so or not.
+        let primary_key = constraints.iter().any(|constraint| {
+            constraint.kind == ConstraintKind::PrimaryKey
+                && constraint
+                    .columns
+                    .iter()
+                    .any(|name| name.eq_ignore_ascii_case(&column.name))
+        });
+
+        if column.serial {
+            if column.default.is_some() {
+                bail!("Column `{}` can't have a default, since it's a serial column", column.name);
+            }
+            if !matches!(column.data_type, DataType::Int | DataType::BigInt) {
+                bail!("Column `{}` has to be an INT or BIGINT to be a serial column", column.name);
+            }
+        }
+
+        schema.push(Column {
+            name: column.name.clone(),
+            data_type: column.data_type,
+            nullable: column.nullable && !primary_key && !column.serial,
+        });
+        defaults.push(match &column.default {
+            Some(default) => default.to_value(column.data_type)?,
+            None => Value::Null,
+        });
+    }
+
+    ddl.create_table(name, Schema::new(schema)?, defaults, engine)?;
+
+    // Each serial column gets a sequence of its own, which its values come
+    // from by default.
+    for column in columns.iter().filter(|column| column.serial) {
+        let options = SequenceOptions {
+            max: (column.data_type == DataType::Int).then(|| i32::MAX.into()),
+            ..SequenceOptions::default()
+        };
+        let sequence = format!("{name}_{}_seq", column.name);
+        ddl.create_sequence(&sequence, &options, Some((name, &column.name)))?;
+    }
+
+    // Every constraint is checked with a unique index on its columns, which is
+    // named after it.
+    for constraint in constraints {
+        let columns: Vec<&str> = constraint.columns.iter().map(String::as_str).collect();
+        let constraint_name = match (&constraint.name, constraint.kind) {
+            (Some(constraint_name), _) => constraint_name.clone(),
+            (None, ConstraintKind::PrimaryKey) => format!("{name}_pkey"),
+            (None, ConstraintKind::Unique) => format!("{name}_{}_key", columns.join("_")),
+        };
+
+        ddl.create_index(name, &constraint_name, IndexKind::BTree, &columns, true)?;
+        ddl.add_constraint(name, &constraint_name, constraint.kind, &columns, Some(&constraint_name))?;
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,788 @@
+use std::fmt;
+
+use anyhow::{bail, Result};
+use ferrodb_catalog::{ConstraintKind, IndexKind, SequenceOptions, StorageEngine};
+use ferrodb_row::{DataType, Value};
+use ferrodb_rtree::{Query, Rect};
+
+/// A statement that the server knows how to run.
+#[derive(Debug, PartialEq)]
+pub enum Statement {
+    /// `BACKUP TO '<dir>'`
+    Backup { dir: String },
+    /// `CREATE TABLE <name> (<column or constraint>, ...) [USING <engine>]`, where a
+    /// column is
+    /// `<name> <type> [NOT NULL | NULL | DEFAULT <literal> | PRIMARY KEY | UNIQUE | AUTO_INCREMENT]...`
+    /// and a constraint is `[CONSTRAINT <name>] {PRIMARY KEY | UNIQUE} (<column>, ...)`.
+    /// A column's type can also be `SERIAL` or `BIGSERIAL`, which are `INT` or
+    /// `BIGINT` with `AUTO_INCREMENT`. The engine is `heap`, which is the
+    /// default, `lsm` or `columnar`.
+    CreateTable {
+        name: String,
+        columns: Vec<ColumnDef>,
+        /// The constraints of the table, including the ones given with a
+        /// column.
+        constraints: Vec<ConstraintDef>,
+        engine: StorageEngine,
+    },
+    /// `DROP TABLE <name>`
+    DropTable { name: String },
+    /// `ALTER TABLE <table> ADD [COLUMN] <column>`
+    AddColumn { table: String, column: ColumnDef },
+    /// `CREATE [UNIQUE] INDEX <name> ON <table> [USING <kind>] (<column>, ...)
+    /// [WITH (FILLFACTOR = <percent>)]`
+    CreateIndex {
+        name: String,
+        table: String,
+        kind: IndexKind,
+        columns: Vec<String>,
+        unique: bool,
+        /// How full to fill a B+Tree's nodes when it's built, if not the
+        /// default.
+        fill_factor: Option<u8>,
+    },
+    /// `DROP INDEX <name>`
+    DropIndex { name: String },
+    /// `CREATE SEQUENCE <name> [INCREMENT [BY] <n> | MINVALUE <n> | NO MINVALUE
+    /// | MAXVALUE <n> | NO MAXVALUE | START [WITH] <n> | CACHE <n> | [NO] CYCLE]...`
+    CreateSequence {
+        name: String,
+        options: SequenceOptions,
+    },
+    /// `DROP SEQUENCE <name>`
+    DropSequence { name: String },
+    /// `SELECT nextval('<sequence>')`
+    NextValue { sequence: String },
+    /// `SELECT currval('<sequence>')`
+    CurrentValue { sequence: String },
+    /// `SELECT setval('<sequence>', <value> [, <is called>])`
+    SetValue {
+        sequence: String,
+        value: i64,
+        is_called: bool,
+    },
+    /// `VACUUM [<table>]`, which vacuums every heap, the catalog's included, if
+    /// there's no table.
+    Vacuum { table: Option<String> },
+    /// `SELECT * FROM <table> WHERE (<column>, ...) <operator> BOX(<x1>, <y1>, <x2>, <y2>)`,
+    /// where the columns make up a point or a box, and the operator is `&&`
+    /// (they overlap), `@>` (the row's box contains the other one) or `<@`
+    /// (the row's box is inside the other one).
+    SearchBoxes {
+        table: String,
+        columns: Vec<String>,
+        query: Query,
+    },
+    /// `SELECT * FROM <table> ORDER BY (<column>, ...) <-> POINT(<x>, <y>) LIMIT <k>`,
+    /// the `k` rows whose points or boxes are the nearest to the point.
+    Nearest {
+        table: String,
+        columns: Vec<String>,
+        x: f64,
+        y: f64,
+        k: usize,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ColumnDef {
+    pub name: String,
+    pub data_type: DataType,
+    pub nullable: bool,
+    pub default: Option<Literal>,
+    /// Whether the column defaults to the next value of a sequence of its own.
+    pub serial: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConstraintDef {
+    /// The name given with `CONSTRAINT`, if there was one.
+    pub name: Option<String>,
+    pub kind: ConstraintKind,
+    pub columns: Vec<String>,
+}
+
+/// A constant, whose type depends on where it's used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Literal {
+    Null,
+    Bool(bool),
+    /// A number, as it was written, which is only parsed once there's a type
+    /// to parse it as.
+    Number(String),
+    String(String),
+    /// `X'<hex digits>'`
+    Blob(Vec<u8>),
+}
+
+impl Literal {
+    pub fn to_value(&self, data_type: DataType) -> Result<Value> {
+        let value = match (self, data_type) {
+            (Literal::Null, _) => Value::Null,
+            (Literal::Bool(value), DataType::Bool) => Value::Bool(*value),
+            (Literal::Number(number), DataType::Int) => match number.parse() {
+                Ok(value) => Value::Int(value),
+                Err(_) => bail!("{number} is out of range for an INT"),
+            },
+            (Literal::Number(number), DataType::BigInt) => match number.parse() {
+                Ok(value) => Value::BigInt(value),
+                Err(_) => bail!("{number} is out of range for a BIGINT"),
+            },
+            (Literal::Number(number), DataType::Double) => match number.parse() {
+                Ok(value) => Value::Double(value),
+                Err(_) => bail!("{number} isn't a DOUBLE"),
+            },
+            (Literal::String(string), DataType::Text) => Value::Text(string.clone()),
+            (Literal::Blob(blob), DataType::Blob) => Value::Blob(blob.clone()),
+            _ => bail!("Expected a value of type {data_type}, got {self}"),
+        };
+
+        Ok(value)
+    }
+}
+
+impl fmt::Display for Literal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Null => f.write_str("NULL"),
+            Literal::Bool(true) => f.write_str("TRUE"),
+            Literal::Bool(false) => f.write_str("FALSE"),
+            Literal::Number(number) => f.write_str(number),
+            Literal::String(string) => Value::Text(string.clone()).fmt(f),
+            Literal::Blob(blob) => Value::Blob(blob.clone()).fmt(f),
+        }
+    }
+}
+
+impl Statement {
+    pub fn parse(input: &str) -> Result<Statement> {
+        let mut parser = Parser::new(input)?;
+
+        let statement = if parser.eat_keyword("BACKUP") {
+            parser.expect_keyword("TO")?;
+            Statement::Backup {
+                dir: parser.expect_string()?,
+            }
+        } else if parser.eat_keyword("CREATE") {
+            if parser.eat_keyword("TABLE") {
+                parse_create_table(&mut parser)?
+            } else if parser.eat_keyword("SEQUENCE") {
+                parse_create_sequence(&mut parser)?
+            } else {
+                let unique = parser.eat_keyword("UNIQUE");
+                parser.expect_keyword("INDEX")?;
+                parse_create_index(&mut parser, unique)?
+            }
+        } else if parser.eat_keyword("DROP") {
+            if parser.eat_keyword("TABLE") {
+                Statement::DropTable {
+                    name: parser.expect_identifier()?,
+                }
+            } else if parser.eat_keyword("SEQUENCE") {
+                Statement::DropSequence {
+                    name: parser.expect_identifier()?,
+                }
+            } else {
+                parser.expect_keyword("INDEX")?;
+                Statement::DropIndex {
+                    name: parser.expect_identifier()?,
+                }
+            }
+        } else if parser.eat_keyword("ALTER") {
+            parser.expect_keyword("TABLE")?;
+            let table = parser.expect_identifier()?;
+            parser.expect_keyword("ADD")?;
+            parser.eat_keyword("COLUMN");
+
+            let mut constraints = vec![];
+            let column = parse_column(&mut parser, &mut constraints)?;
+            if !constraints.is_empty() {
+                bail!("A column that's added to a table can't be a primary key or unique");
+            }
+
+            Statement::AddColumn { table, column }
+        } else if parser.eat_keyword("SELECT") {
+            parse_select(&mut parser)?
+        } else if parser.eat_keyword("VACUUM") {
+            let table = match parser.peek() {
+                Some(Token::Word(_)) => Some(parser.expect_identifier()?),
+                _ => None,
+            };
+
+            Statement::Vacuum { table }
+        } else {
+            bail!("Expected a statement, got {}", parser.describe_next());
+        };
+
+        parser.eat(&Token::Semicolon);
+        parser.expect_end()?;
+        Ok(statement)
+    }
+}
+
+fn parse_create_table(parser: &mut Parser) -> Result<Statement> {
+    let name = parser.expect_identifier()?;
+    let mut columns = vec![];
+    let mut constraints = vec![];
+
+    parser.expect(&Token::LeftParen)?;
+    loop {
+        let constraint_name = if parser.eat_keyword("CONSTRAINT") {
+            Some(parser.expect_identifier()?)
+        } else {
+            None
+        };
+
+        if let Some(kind) = parse_constraint_kind(parser)? {
+            constraints.push(ConstraintDef {
+                name: constraint_name,
+                kind,
+                columns: parse_column_list(parser)?,
+            });
+        } else if constraint_name.is_some() {
+            bail!("Expected `PRIMARY KEY` or `UNIQUE`, got {}", parser.describe_next());
+        } else {
+            columns.push(parse_column(parser, &mut constraints)?);
+        }
+
+        if !parser.eat(&Token::Comma) {
+            break;
+        }
+    }
+    parser.expect(&Token::RightParen)?;
+
+    let engine = if parser.eat_keyword("USING") {
+        parser.expect_identifier()?.parse()?
+    } else {
+        StorageEngine::Heap
+    };
+
+    Ok(Statement::CreateTable {
+        name,
+        columns,
+        constraints,
+        engine,
+    })
+}
+
+/// Parse a column's definition, adding any constraint on it to `constraints`.
+fn parse_column(parser: &mut Parser, constraints: &mut Vec<ConstraintDef>) -> Result<ColumnDef> {
+    let name = parser.expect_identifier()?;
+    let (data_type, serial) = match &*parser.expect_identifier()?.to_ascii_uppercase() {
+        "SERIAL" => (DataType::Int, true),
+        "BIGSERIAL" => (DataType::BigInt, true),
+        data_type => (data_type.parse()?, false),
+    };
+    let mut column = ColumnDef {
+        name,
+        data_type,
+        nullable: true,
+        default: None,
+        serial,
+    };
+
+    loop {
+        if parser.eat_keyword("NOT") {
+            parser.expect_keyword("NULL")?;
+            column.nullable = false;
+        } else if parser.eat_keyword("NULL") {
+            column.nullable = true;
+        } else if parser.eat_keyword("DEFAULT") {
+            column.default = Some(parser.expect_literal()?);
+        } else if parser.eat_keyword("AUTO_INCREMENT") {
+            column.serial = true;
+        } else if let Some(kind) = parse_constraint_kind(parser)? {
+            constraints.push(ConstraintDef {
+                name: None,
+                kind,
+                columns: vec![column.name.clone()],
+            });
+        } else {
+            break;
+        }
+    }
+
+    Ok(column)
+}
+
+fn parse_constraint_kind(parser: &mut Parser) -> Result<Option<ConstraintKind>> {
+    if parser.eat_keyword("PRIMARY") {
+        parser.expect_keyword("KEY")?;
+        Ok(Some(ConstraintKind::PrimaryKey))
+    } else if parser.eat_keyword("UNIQUE") {
+        Ok(Some(ConstraintKind::Unique))
+    } else {
+        Ok(None)
+    }
+}
+
+fn parse_create_index(parser: &mut Parser, unique: bool) -> Result<Statement> {
+    let name = parser.expect_identifier()?;
+    parser.expect_keyword("ON")?;
+    let table = parser.expect_identifier()?;
+
+    let kind = if parser.eat_keyword("USING") {
+        parser.expect_identifier()?.parse()?
+    } else {
+        IndexKind::BTree
+    };
+
+    let columns = parse_column_list(parser)?;
+
+    let mut fill_factor = None;
+    if parser.eat_keyword("WITH") {
+        parser.expect(&Token::LeftParen)?;
+        loop {
+            let option = parser.expect_identifier()?;
+            if !option.eq_ignore_ascii_case("FILLFACTOR") {
+                bail!("There's no index option called `{option}`");
+            }
+
+            parser.expect(&Token::Equals)?;
+            let value = parser.expect_integer()?;
+            let Ok(value) = u8::try_from(value)
+                else { bail!("Fill factor has to be a percentage, got {value}"); };
+            fill_factor = Some(value);
+
+            if !parser.eat(&Token::Comma) {
+                break;
+            }
+        }
+        parser.expect(&Token::RightParen)?;
+    }
+
+    Ok(Statement::CreateIndex {
+        name,
+        table,
+        kind,
+        columns,
+        unique,
+        fill_factor,
+    })
+}
+
+fn parse_create_sequence(parser: &mut Parser) -> Result<Statement> {
+    let name = parser.expect_identifier()?;
+    let mut options = SequenceOptions::default();
+
+    loop {
+        if parser.eat_keyword("INCREMENT") {
+            parser.eat_keyword("BY");
+            options.increment = Some(parser.expect_integer()?);
+        } else if parser.eat_keyword("MINVALUE") {
+            options.min = Some(parser.expect_integer()?);
+        } else if parser.eat_keyword("MAXVALUE") {
+            options.max = Some(parser.expect_integer()?);
+        } else if parser.eat_keyword("START") {
+            parser.eat_keyword("WITH");
+            options.start = Some(parser.expect_integer()?);
+        } else if parser.eat_keyword("CACHE") {
+            options.cache = Some(parser.expect_integer()?);
+        } else if parser.eat_keyword("CYCLE") {
+            options.cycle = true;
+        } else if parser.eat_keyword("NO") {
+            if parser.eat_keyword("MINVALUE") {
+                options.min = None;
+            } else if parser.eat_keyword("MAXVALUE") {
+                options.max = None;
+            } else {
+                parser.expect_keyword("CYCLE")?;
+                options.cycle = false;
+            }
+        } else {
+            break;
+        }
+    }
+
+    Ok(Statement::CreateSequence { name, options })
+}
+
+/// `SELECT <function>(<argument>, ...)`, for the functions of sequences, or
+/// `SELECT * FROM ...`, for spatial searches.
+fn parse_select(parser: &mut Parser) -> Result<Statement> {
+    if parser.eat(&Token::Star) {
+        return parse_spatial_search(parser);
+    }
+
+    let function = parser.expect_identifier()?;
+
+    let mut arguments = vec![];
+    parser.expect(&Token::LeftParen)?;
+    if !parser.eat(&Token::RightParen) {
+        loop {
+            arguments.push(parser.expect_literal()?);
+
+            if !parser.eat(&Token::Comma) {
+                break;
+            }
+        }
+        parser.expect(&Token::RightParen)?;
+    }
+
+    let statement = match (&*function.to_ascii_lowercase(), &arguments[..]) {
+        ("nextval", [Literal::String(sequence)]) => Statement::NextValue {
+            sequence: sequence.clone(),
+        },
+        ("currval", [Literal::String(sequence)]) => Statement::CurrentValue {
+            sequence: sequence.clone(),
+        },
+        ("setval", [Literal::String(sequence), value, rest @ ..]) if rest.len() <= 1 => {
+            let Value::BigInt(value) = value.to_value(DataType::BigInt)?
+                else { bail!("Expected a value for the sequence, got NULL"); };
+            let is_called = match rest {
+                [] => true,
+                [Literal::Bool(is_called)] => *is_called,
+                [literal] => bail!("Expected TRUE or FALSE, got {literal}"),
+                _ => unreachable!(),
+            };
+
+            Statement::SetValue {
+                sequence: sequence.clone(),
+                value,
+                is_called,
+            }
+        },
+        ("nextval" | "currval", _) => bail!("`{function}` takes the name of a sequence"),
+        ("setval", _) => bail!("`{function}` takes the name of a sequence, a value, and whether it's been used"),
+        _ => bail!("There's no function called `{function}`"),
+    };
+
+    Ok(statement)
+}
+
+/// What comes after `SELECT *`, which is either a search for boxes or one for
+/// the nearest ones to a point.
+fn parse_spatial_search(parser: &mut Parser) -> Result<Statement> {
+    parser.expect_keyword("FROM")?;
+    let table = parser.expect_identifier()?;
+
+    if parser.eat_keyword("WHERE") {
+        let columns = parse_column_list(parser)?;
+        let operator = parser.expect_operator()?;
+        parser.expect_keyword("BOX")?;
+        let [x1, y1, x2, y2] = parse_numbers(parser)?[..]
+            else { bail!("`BOX` takes the x and y of two opposite corners"); };
+
+        let rect = Rect::new(x1, y1, x2, y2);
+        let query = match &*operator {
+            "&&" => Query::Intersects(rect),
+            "@>" => Query::Contains(rect),
+            "<@" => Query::Within(rect),
+            _ => bail!("Expected `&&`, `@>` or `<@`, got `{operator}`"),
+        };
+
+        Ok(Statement::SearchBoxes {
+            table,
+            columns,
+            query,
+        })
+    } else if parser.eat_keyword("ORDER") {
+        parser.expect_keyword("BY")?;
+        let columns = parse_column_list(parser)?;
+        parser.expect(&Token::Operator("<->".to_owned()))?;
+        parser.expect_keyword("POINT")?;
+        let [x, y] = parse_numbers(parser)?[..]
+            else { bail!("`POINT` takes an x and a y"); };
+
+        parser.expect_keyword("LIMIT")?;
+        let limit = parser.expect_integer()?;
+        let Ok(k) = usize::try_from(limit)
+            else { bail!("`LIMIT` has to be a number of rows, got {limit}"); };
+
+        Ok(Statement::Nearest {
+            table,
+            columns,
+            x,
+            y,
+            k,
+        })
+    } else {
+        bail!("Expected `WHERE` or `ORDER BY`, got {}", parser.describe_next());
+    }
+}
+
+/// `(<number>, ...)`
+fn parse_numbers(parser: &mut Parser) -> Result<Vec<f64>> {
+    let mut numbers = vec![];
+
+    parser.expect(&Token::LeftParen)?;
+    loop {
+        numbers.push(parser.expect_double()?);
+
+        if !parser.eat(&Token::Comma) {
+            break;
+        }
+    }
+    parser.expect(&Token::RightParen)?;
+
+    Ok(numbers)
+}
+
+/// `(<column>, ...)`
+fn parse_column_list(parser: &mut Parser) -> Result<Vec<String>> {
+    let mut columns = vec![];
+
+    parser.expect(&Token::LeftParen)?;
+    loop {
+        columns.push(parser.expect_identifier()?);
+
+        if !parser.eat(&Token::Comma) {
+            break;
+        }
+    }
+    parser.expect(&Token::RightParen)?;
+
+    Ok(columns)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    /// A keyword or identifier, which are told apart by the parser.
+    Word(String),
+    String(String),
+    /// A number, with any fraction or exponent, but not its sign.
+    Number(String),
+    LeftParen,
+    RightParen,
+    Comma,
+    Minus,
+    Equals,
+    Star,
+    /// An operator made up of more than one character, like `&&`, which starts
+    /// with one of `&@<>`, and can have `-` in it after that.
+    Operator(String),
+    Semicolon,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+}
+
+impl Parser {
+    fn new(input: &str) -> Result<Parser> {
+        let mut tokens = vec![];
+        let mut chars = input.chars().peekable();
+
+        while let Some(&c) = chars.peek() {
+            match c {
+                c if c.is_whitespace() => {
+                    chars.next();
+                },
+                ';' | '(' | ')' | ',' | '-' | '=' | '*' => {
+                    chars.next();
+                    tokens.push(match c {
+                        ';' => Token::Semicolon,
+                        '(' => Token::LeftParen,
+                        ')' => Token::RightParen,
+                        ',' => Token::Comma,
+                        '-' => Token::Minus,
+                        '*' => Token::Star,
+                        _ => Token::Equals,
+                    });
+                },
+                '&' | '@' | '<' | '>' => {
+                    let mut operator = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if !matches!(c, '&' | '@' | '<' | '>' | '-') {
+                            break;
+                        }
+                        operator.push(c);
+                        chars.next();
+                    }
+                    tokens.push(Token::Operator(operator));
+                },
+                '\'' => {
+                    chars.next();
+                    let mut string = String::new();
+
+                    loop {
+                        match chars.next() {
+                            // `''` is an escaped quote.
+                            Some('\'') if chars.peek() == Some(&'\'') => {
+                                chars.next();
+                                string.push('\'');
+                            },
+                            Some('\'') => break,
+                            Some(c) => string.push(c),
+                            None => bail!("Unterminated string literal"),
+                        }
+                    }
+
+                    tokens.push(Token::String(string));
+                },
+                c if c.is_ascii_digit() => {
+                    let mut number = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if !(c.is_ascii_alphanumeric() || c == '.') {
+                            break;
+                        }
+                        number.push(c);
+                        chars.next();
+                    }
+                    tokens.push(Token::Number(number));
+                },
+                c if c.is_alphanumeric() || c == '_' => {
+                    let mut word = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if !(c.is_alphanumeric() || c == '_') {
+                            break;
+                        }
+                        word.push(c);
+                        chars.next();
+                    }
+                    tokens.push(Token::Word(word));
+                },
+                c => bail!("Unexpected character `{c}`"),
+            }
+        }
+
+        Ok(Parser { tokens, next: 0 })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn describe_next(&self) -> String {
+        describe(self.peek())
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<()> {
+        if !self.eat(token) {
+            bail!("Expected {}, got {}", describe(Some(token)), self.describe_next());
+        }
+
+        Ok(())
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword) => {
+                self.next += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if !self.eat_keyword(keyword) {
+            bail!("Expected `{keyword}`, got {}", self.describe_next());
+        }
+
+        Ok(())
+    }
+
+    fn expect_string(&mut self) -> Result<String> {
+        let Some(Token::String(string)) = self.peek().cloned()
+            else { bail!("Expected a string, got {}", self.describe_next()); };
+
+        self.next += 1;
+        Ok(string)
+    }
+
+    /// The name of a table, column, or whatever else.
+    fn expect_identifier(&mut self) -> Result<String> {
+        let Some(Token::Word(word)) = self.peek().cloned()
+            else { bail!("Expected a name, got {}", self.describe_next()); };
+
+        self.next += 1;
+        Ok(word)
+    }
+
+    fn expect_literal(&mut self) -> Result<Literal> {
+        let literal = match self.peek().cloned() {
+            Some(Token::String(string)) => Literal::String(string),
+            Some(Token::Number(number)) => Literal::Number(number),
+            Some(Token::Minus) => {
+                self.next += 1;
+                let Some(Token::Number(number)) = self.peek().cloned()
+                    else { bail!("Expected a number, got {}", self.describe_next()); };
+
+                Literal::Number(format!("-{number}"))
+            },
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("NULL") => Literal::Null,
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("TRUE") => Literal::Bool(true),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("FALSE") => Literal::Bool(false),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("X") => {
+                self.next += 1;
+                let Some(Token::String(hex)) = self.peek().cloned()
+                    else { bail!("Expected a string of hex digits, got {}", self.describe_next()); };
+
+                Literal::Blob(parse_hex(&hex)?)
+            },
+            _ => bail!("Expected a value, got {}", self.describe_next()),
+        };
+
+        self.next += 1;
+        Ok(literal)
+    }
+
+    fn expect_integer(&mut self) -> Result<i64> {
+        match self.expect_literal()?.to_value(DataType::BigInt)? {
+            Value::BigInt(value) => Ok(value),
+            _ => bail!("Expected a number, got NULL"),
+        }
+    }
+
+    fn expect_double(&mut self) -> Result<f64> {
+        match self.expect_literal()?.to_value(DataType::Double)? {
+            Value::Double(value) => Ok(value),
+            _ => bail!("Expected a number, got NULL"),
+        }
+    }
+
+    fn expect_operator(&mut self) -> Result<String> {
+        let Some(Token::Operator(operator)) = self.peek().cloned()
+            else { bail!("Expected an operator, got {}", self.describe_next()); };
+
+        self.next += 1;
+        Ok(operator)
+    }
+
+    fn expect_end(&self) -> Result<()> {
+        if self.peek().is_some() {
+            bail!("Expected the end of the statement, got {}", self.describe_next());
+        }
+
+        Ok(())
+    }
+}
+
+fn describe(token: Option<&Token>) -> String {
+    match token {
+        Some(Token::Word(word)) => format!("`{word}`"),
+        Some(Token::String(string)) => format!("'{string}'"),
+        Some(Token::Number(number)) => number.clone(),
+        Some(Token::LeftParen) => "`(`".to_owned(),
+        Some(Token::RightParen) => "`)`".to_owned(),
+        Some(Token::Comma) => "`,`".to_owned(),
+        Some(Token::Minus) => "`-`".to_owned(),
+        Some(Token::Equals) => "`=`".to_owned(),
+        Some(Token::Star) => "`*`".to_owned(),
+        Some(Token::Operator(operator)) => format!("`{operator}`"),
+        Some(Token::Semicolon) => "`;`".to_owned(),
+        None => "the end of the statement".to_owned(),
+    }
+}
+
+fn parse_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("'{hex}' isn't an even number of hex digits");
+    }
+
+    Ok((0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect())
+}
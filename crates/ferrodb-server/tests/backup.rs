@@ -0,0 +1,99 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use camino::Utf8PathBuf;
+use ferrodb_fs::vfs::DiskVfs;
+use ferrodb_fs::PAGE_HEADER_SIZE;
+use ferrodb_server::Database;
+
+fn temp_dir(name: &str) -> Utf8PathBuf {
+    let dir = Utf8PathBuf::try_from(std::env::temp_dir())
+        .unwrap()
+        .join(format!("ferrodb-backup-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn backups_taken_during_writes_recover_to_a_consistent_state() {
+    let dir = temp_dir("consistent");
+    let db = Arc::new(Database::open(Arc::new(DiskVfs::new(dir.join("db"))), None).unwrap());
+    let stop = Arc::new(AtomicBool::new(false));
+
+    // Every transaction writes the same counter to two pages of its own, so
+    // the two pages only differ if the copy is torn.
+    let writers: Vec<_> = (0..4)
+        .map(|writer| {
+            let db = db.clone();
+            let stop = stop.clone();
+
+            thread::spawn(move || {
+                let file = db.files().id("t");
+
+                for i in 0u64.. {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+
+                    let txn = db.wal().begin().unwrap();
+                    for page in [1 + writer * 2, 2 + writer * 2] {
+                        let counter = i.to_le_bytes();
+                        txn.modify(db.files(), file, page, |body| body[..8].copy_from_slice(&counter))
+                            .unwrap();
+                    }
+
+                    if i % 3 == 0 {
+                        txn.abort(db.files()).unwrap();
+                    } else {
+                        txn.commit().unwrap();
+                    }
+
+                    if i % 50 == 0 {
+                        db.files().sync_all().unwrap();
+                    }
+                }
+            })
+        })
+        .collect();
+
+    thread::sleep(Duration::from_millis(100));
+    db.backup(dir.join("copy")).unwrap();
+    stop.store(true, Ordering::SeqCst);
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    drop(db);
+
+    let copy = Database::open(Arc::new(DiskVfs::new(dir.join("copy"))), None).unwrap();
+    let file = copy.files().id("t");
+    for writer in 0..4 {
+        let [a, b] = [1 + writer * 2, 2 + writer * 2].map(|page| {
+            let page = copy.files().clean(file, page).unwrap();
+            let counter = page.read()[PAGE_HEADER_SIZE..][..8].to_vec();
+            counter
+        });
+        assert_eq!(a, b, "Expected writer {writer}'s pages to match");
+    }
+
+    drop(copy);
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn backups_only_go_into_empty_directories() {
+    let dir = temp_dir("empty");
+    let db = Database::open(Arc::new(DiskVfs::new(dir.join("db"))), None).unwrap();
+
+    // Only a directory in the way, like the log of an earlier backup.
+    std::fs::create_dir_all(dir.join("copy/wal")).unwrap();
+    assert!(db.backup(dir.join("copy")).is_err());
+
+    std::fs::remove_dir(dir.join("copy/wal")).unwrap();
+    db.backup(dir.join("copy")).unwrap();
+    assert!(db.backup(dir.join("copy")).is_err());
+
+    drop(db);
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,30 @@
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Fs(#[from] ferrodb_fs::Error),
+    #[error(transparent)]
+    Encoding(#[from] bincode::Error),
+    #[error("Log record is {0} bytes, which doesn't fit in a log segment")]
+    RecordTooLarge(usize),
+    #[error("There's no intact log record at LSN {0}")]
+    NoRecord(ferrodb_fs::Lsn),
+    #[error("Log segment `{0}` doesn't start with the log segment magic bytes")]
+    NotASegment(String),
+    #[error("Log segment `{0}` is encrypted, but no encryption key was given")]
+    MissingKey(String),
+    #[error("Log segment `{0}` isn't encrypted, but an encryption key was given")]
+    UnexpectedKey(String),
+    #[error(
+        "Log record at LSN {0} can't be decrypted, so it was either tampered with or encrypted \
+         with another key"
+    )]
+    Undecryptable(ferrodb_fs::Lsn),
+    #[error("The log was opened read-only")]
+    ReadOnly,
+    #[error("Nothing was set up to undo changes to {0} files")]
+    NoUndo(ferrodb_fs::FileKind),
+}
@@ -0,0 +1,15 @@
+mod error;
+mod reader;
+mod record;
+mod recovery;
+mod txn;
+mod undo;
+mod wal;
+
+pub use self::error::Error;
+pub use self::reader::LogReader;
+pub use self::record::{LogRecord, TxnId};
+pub use self::recovery::{needs_recovery, recover, RecoveryPhase, RecoveryReport};
+pub use self::txn::Txn;
+pub use self::undo::Undo;
+pub use self::wal::{Wal, WalOptions, SEGMENT_SIZE};
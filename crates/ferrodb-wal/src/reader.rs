@@ -0,0 +1,63 @@
+use ferrodb_fs::Lsn;
+
+use crate::wal::SEGMENT_MAGIC;
+use crate::{Error, LogRecord, Wal, SEGMENT_SIZE};
+
+/// Reads records out of the durable part of the log, in LSN order.
+pub struct LogReader<'w> {
+    wal: &'w Wal,
+    segment_no: u64,
+    segment: Option<Vec<u8>>,
+    offset: usize,
+    done: bool,
+}
+
+impl<'w> LogReader<'w> {
+    pub(crate) fn new(wal: &'w Wal, lsn: Lsn) -> LogReader<'w> {
+        LogReader {
+            wal,
+            segment_no: lsn / SEGMENT_SIZE,
+            segment: None,
+            offset: ((lsn % SEGMENT_SIZE) as usize).max(SEGMENT_MAGIC.len()),
+            done: false,
+        }
+    }
+
+    fn next_record(&mut self) -> Result<Option<(Lsn, LogRecord)>, Error> {
+        loop {
+            if self.segment.is_none() {
+                let Some(segment) = self.wal.segment(self.segment_no)?
+                    else { return Ok(None); };
+                self.segment = Some(segment);
+            }
+
+            let segment = self.segment.as_ref().unwrap();
+
+            let lsn = self.segment_no * SEGMENT_SIZE + self.offset as u64;
+            if let Some((record, len)) = self.wal.decode(segment, self.offset, lsn)? {
+                self.offset += len;
+                return Ok(Some((lsn, record)));
+            }
+
+            // Either this segment ran out of room, or the log ends here. Only the
+            // next segment knows which.
+            self.segment_no += 1;
+            self.segment = None;
+            self.offset = SEGMENT_MAGIC.len();
+        }
+    }
+}
+
+impl Iterator for LogReader<'_> {
+    type Item = Result<(Lsn, LogRecord), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let next = self.next_record().transpose();
+        self.done = !matches!(next, Some(Ok(_)));
+        next
+    }
+}
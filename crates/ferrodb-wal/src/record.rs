@@ -0,0 +1,126 @@
+use ferrodb_fs::{Lsn, PageIndex};
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// A transaction is identified by the LSN of its `Begin` record, so ids are
+/// unique even across restarts.
+pub type TxnId = Lsn;
+
+/// Every record is framed as `[len: u32][crc32: u32][payload]`, little endian.
+/// In an encrypted log, the payload is sealed.
+pub(crate) const RECORD_HEADER_SIZE: usize = 8;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum LogRecord {
+    Begin,
+    /// The bytes `offset..offset + after.len()` of a page changed from
+    /// `before` to `after`.
+    Update {
+        txn: TxnId,
+        prev_lsn: Lsn,
+        file: String,
+        page: PageIndex,
+        offset: usize,
+        before: Vec<u8>,
+        after: Vec<u8>,
+    },
+    Commit {
+        txn: TxnId,
+        prev_lsn: Lsn,
+    },
+    Abort {
+        txn: TxnId,
+        prev_lsn: Lsn,
+    },
+    /// Undoes the `Update` before `undo_next` by writing `after` (that
+    /// update's `before`), or is part of undoing the `LogicalUpdate` at
+    /// `undo_next`. Only ever redone, never undone.
+    Compensation {
+        txn: TxnId,
+        prev_lsn: Lsn,
+        undo_next: Lsn,
+        file: String,
+        page: PageIndex,
+        offset: usize,
+        after: Vec<u8>,
+    },
+    /// A write to the memtable of the LSM tree in file `tree`, which isn't
+    /// part of any transaction. Recovery leaves these alone: the tree replays
+    /// the ones it hasn't written out yet when it's opened.
+    LsmWrite {
+        tree: String,
+        key: Vec<u8>,
+        /// `None` for a delete.
+        value: Option<Vec<u8>>,
+    },
+    /// Every page that was dirty when this was logged has been written back
+    /// by the time the matching `CheckpointEnd` is logged.
+    CheckpointBegin,
+    CheckpointEnd {
+        begin: Lsn,
+        /// Every transaction that was running, with its last LSN.
+        active: Vec<(TxnId, Lsn)>,
+    },
+    /// Like an `Update`, but undone by handing `undo` to the [`Undo`](crate::Undo)
+    /// for the file's kind, rather than by writing back what was there before,
+    /// since other transactions may have changed the page since.
+    LogicalUpdate {
+        txn: TxnId,
+        prev_lsn: Lsn,
+        file: String,
+        page: PageIndex,
+        offset: usize,
+        after: Vec<u8>,
+        undo: Vec<u8>,
+    },
+    /// Undo carries on from `undo_next`, leaving every change in between as
+    /// it is: the ones in a nested top action, or the ones that undid a
+    /// `LogicalUpdate`. Never redone, since it doesn't change anything.
+    Skip {
+        txn: TxnId,
+        prev_lsn: Lsn,
+        undo_next: Lsn,
+    },
+}
+
+impl LogRecord {
+    /// The record's payload, before it's framed.
+    pub(crate) fn encode(&self) -> Result<Vec<u8>, Error> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub(crate) fn decode(payload: &[u8]) -> Result<LogRecord, Error> {
+        Ok(bincode::deserialize(payload)?)
+    }
+}
+
+pub(crate) fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(RECORD_HEADER_SIZE + payload.len());
+    bytes.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+/// The payload of the record framed at `offset` of a segment, along with its
+/// framed size. `None` means there's no (intact) record there, either because
+/// we've hit the end of the log or because the last write was torn.
+pub(crate) fn unframe(segment: &[u8], offset: usize) -> Option<(&[u8], usize)> {
+    let header = segment.get(offset..offset + RECORD_HEADER_SIZE)?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+    if len == 0 {
+        return None;
+    }
+
+    let start = offset + RECORD_HEADER_SIZE;
+    let payload = segment.get(start..start + len)?;
+
+    if crc32fast::hash(payload) != crc {
+        return None;
+    }
+
+    Some((payload, RECORD_HEADER_SIZE + len))
+}
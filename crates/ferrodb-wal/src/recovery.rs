@@ -0,0 +1,315 @@
+//! ARIES-style restart recovery: analysis, redo, and undo.
+//!
+//! Redo is "repeating history": every logged change since the last checkpoint
+//! is reapplied to any page whose LSN shows it hasn't seen that change yet.
+//! Undo then rolls back whatever transactions were still running at the
+//! crash, logging a `Compensation` record for every change it undoes. Both
+//! of those make recovery idempotent: crashing during recovery and running
+//! it again ends up in the same place.
+//!
+//! An `Update` is undone by writing back the bytes it changed. A
+//! `LogicalUpdate` is undone by the [`Undo`](crate::Undo) for its file's kind,
+//! whose changes are compensations for it, and a `Skip` once it's done.
+
+use std::collections::{BTreeMap, HashMap};
+
+use ferrodb_fs::{page_lsn, set_page_lsn, FileKind, FileManager, Lsn, PageIndex};
+
+use crate::{Error, LogRecord, Txn, TxnId, Wal};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RecoveryPhase {
+    Analysis,
+    Redo,
+    Undo,
+}
+
+#[derive(Debug, Default)]
+pub struct RecoveryReport {
+    /// Where analysis and redo started from.
+    pub start_lsn: Lsn,
+    /// The first LSN after the end of the log.
+    pub end_lsn: Lsn,
+    /// How many logged changes actually had to be reapplied.
+    pub redone: usize,
+    /// Transactions that were rolled back because they never committed.
+    pub rolled_back: Vec<TxnId>,
+}
+
+/// Bring every page back in line with the log after a crash (or a clean
+/// shutdown, in which case there's nothing to do), then take a checkpoint.
+/// `progress` is called at the start of each phase and then every so often
+/// with the LSN that phase has gotten to.
+pub fn recover(
+    wal: &Wal,
+    files: &FileManager,
+    mut progress: impl FnMut(RecoveryPhase, Lsn),
+) -> Result<RecoveryReport, Error> {
+    const PROGRESS_EVERY: usize = 10_000;
+
+    let start_lsn = wal.last_checkpoint()?.unwrap_or(0);
+    let mut report = RecoveryReport {
+        start_lsn,
+        end_lsn: wal.next_lsn(),
+        ..RecoveryReport::default()
+    };
+
+    // Analysis: figure out which transactions never finished, and where each of
+    // them left off.
+    let losers = analyze(wal, start_lsn, &mut progress)?;
+
+    // Redo: repeat history, including the changes of transactions that we're
+    // about to roll back.
+    progress(RecoveryPhase::Redo, start_lsn);
+
+    for (i, record) in wal.reader(start_lsn).enumerate() {
+        let (lsn, record) = record?;
+
+        let (file, page, offset, bytes) = match &record {
+            LogRecord::Update {
+                file,
+                page,
+                offset,
+                after,
+                ..
+            }
+            | LogRecord::LogicalUpdate {
+                file,
+                page,
+                offset,
+                after,
+                ..
+            }
+            | LogRecord::Compensation {
+                file,
+                page,
+                offset,
+                after,
+                ..
+            } => (file, *page, *offset, after),
+            _ => continue,
+        };
+
+        if redo(files, file, page, offset, bytes, lsn)? {
+            report.redone += 1;
+        }
+
+        if i % PROGRESS_EVERY == 0 {
+            progress(RecoveryPhase::Redo, lsn);
+        }
+    }
+
+    // Undo: roll back the losers, all together, newest change first.
+    progress(RecoveryPhase::Undo, report.end_lsn);
+    report.rolled_back = losers.keys().copied().collect();
+    report.rolled_back.sort_unstable();
+    undo(wal, files, losers, &mut progress)?;
+
+    wal.checkpoint(files)?;
+    Ok(report)
+}
+
+/// Whether [`recover`] would have anything to do: a transaction that never
+/// finished, or a logged change that a page hasn't seen. Unlike `recover`,
+/// this doesn't write anything, so it works on a read-only database.
+pub fn needs_recovery(wal: &Wal, files: &FileManager) -> Result<bool, Error> {
+    let start_lsn = wal.last_checkpoint()?.unwrap_or(0);
+
+    if !analyze(wal, start_lsn, &mut |_, _| {})?.is_empty() {
+        return Ok(true);
+    }
+
+    for record in wal.reader(start_lsn) {
+        let (lsn, record) = record?;
+
+        let (file, page) = match &record {
+            LogRecord::Update { file, page, .. }
+            | LogRecord::LogicalUpdate { file, page, .. }
+            | LogRecord::Compensation { file, page, .. } => (file, *page),
+            _ => continue,
+        };
+
+        let page_ref = match files.clean(files.id(file), page) {
+            Ok(page_ref) => page_ref,
+            // Only repairing it from the double-write file can fix a torn page.
+            Err(ferrodb_fs::Error::ChecksumMismatch { .. }) => return Ok(true),
+            Err(e) => return Err(e.into()),
+        };
+
+        if page_lsn(&page_ref.read()) < lsn {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Find the transactions that never finished, along with the last LSN each of
+/// them logged.
+fn analyze(
+    wal: &Wal,
+    start_lsn: Lsn,
+    progress: &mut dyn FnMut(RecoveryPhase, Lsn),
+) -> Result<HashMap<TxnId, Lsn>, Error> {
+    const PROGRESS_EVERY: usize = 10_000;
+
+    progress(RecoveryPhase::Analysis, start_lsn);
+    let mut losers: HashMap<TxnId, Lsn> = HashMap::new();
+
+    for (i, record) in wal.reader(start_lsn).enumerate() {
+        let (lsn, record) = record?;
+
+        match record {
+            LogRecord::Begin => {
+                losers.insert(lsn, lsn);
+            },
+            LogRecord::Update { txn, .. }
+            | LogRecord::LogicalUpdate { txn, .. }
+            | LogRecord::Compensation { txn, .. }
+            | LogRecord::Skip { txn, .. } => {
+                losers.insert(txn, lsn);
+            },
+            LogRecord::Commit { txn, .. } | LogRecord::Abort { txn, .. } => {
+                losers.remove(&txn);
+            },
+            LogRecord::LsmWrite { .. } | LogRecord::CheckpointBegin => {},
+            LogRecord::CheckpointEnd { active, .. } =>
+                for (txn, last_lsn) in active {
+                    let entry = losers.entry(txn).or_insert(last_lsn);
+                    *entry = (*entry).max(last_lsn);
+                },
+        }
+
+        if i % PROGRESS_EVERY == 0 {
+            progress(RecoveryPhase::Analysis, lsn);
+        }
+    }
+
+    Ok(losers)
+}
+
+/// Apply a logged change if the page hasn't seen it yet.
+fn redo(
+    files: &FileManager,
+    file: &str,
+    page: PageIndex,
+    offset: usize,
+    bytes: &[u8],
+    lsn: Lsn,
+) -> Result<bool, Error> {
+    let file = files.id(file);
+
+    if page_lsn(&files.clean(file, page)?.read()) >= lsn {
+        return Ok(false);
+    }
+
+    let page_ref = files.dirty(file, page)?;
+    let mut buf = page_ref.write();
+
+    // The dirty copy might be ahead of what's on disk.
+    if page_lsn(&buf) >= lsn {
+        return Ok(false);
+    }
+
+    buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+    set_page_lsn(&mut buf, lsn);
+    Ok(true)
+}
+
+/// Roll back a single transaction that's still running.
+pub(crate) fn rollback(
+    wal: &Wal,
+    files: &FileManager,
+    txn: TxnId,
+    last_lsn: Lsn,
+) -> Result<Lsn, Error> {
+    undo(wal, files, HashMap::from([(txn, last_lsn)]), &mut |_, _| {})?;
+    wal.flush()
+}
+
+fn undo(
+    wal: &Wal,
+    files: &FileManager,
+    txns: HashMap<TxnId, Lsn>,
+    progress: &mut dyn FnMut(RecoveryPhase, Lsn),
+) -> Result<(), Error> {
+    // The next LSN to undo for each transaction, with the last LSN it logged.
+    let mut to_undo: BTreeMap<Lsn, (TxnId, Lsn)> = txns
+        .into_iter()
+        .map(|(txn, last_lsn)| (last_lsn, (txn, last_lsn)))
+        .collect();
+
+    while let Some(&lsn) = to_undo.keys().next_back() {
+        let (txn, last_lsn) = to_undo.remove(&lsn).unwrap();
+        progress(RecoveryPhase::Undo, lsn);
+
+        let (next_lsn, last_lsn) = match wal.read(lsn)? {
+            LogRecord::Update {
+                prev_lsn,
+                file,
+                page,
+                offset,
+                before,
+                ..
+            } => {
+                let file_id = files.id(&file);
+                let page_ref = files.dirty(file_id, page)?;
+                let mut buf = page_ref.write();
+
+                buf[offset..offset + before.len()].copy_from_slice(&before);
+
+                let clr = wal.append_for(txn, &LogRecord::Compensation {
+                    txn,
+                    prev_lsn: last_lsn,
+                    undo_next: prev_lsn,
+                    file,
+                    page,
+                    offset,
+                    after: before,
+                })?;
+                set_page_lsn(&mut buf, clr);
+
+                (prev_lsn, clr)
+            },
+            LogRecord::LogicalUpdate {
+                prev_lsn,
+                file,
+                page,
+                undo,
+                ..
+            } => {
+                let file_id = files.id(&file);
+                let kind = files.kind(file_id)?.unwrap_or(FileKind::Generic);
+
+                let undoing = Txn::undoing(wal, txn, last_lsn, lsn);
+                wal.undo_for(kind)?.undo(&undoing, file_id, page, &undo)?;
+
+                let skip = wal.append_for(txn, &LogRecord::Skip {
+                    txn,
+                    prev_lsn: undoing.last_lsn(),
+                    undo_next: prev_lsn,
+                })?;
+
+                (prev_lsn, skip)
+            },
+            // Whatever this compensated for is already undone, or is being undone
+            // again from the start, so skip over it.
+            LogRecord::Compensation { undo_next, .. } | LogRecord::Skip { undo_next, .. } =>
+                (undo_next, last_lsn),
+            LogRecord::Begin => (lsn, last_lsn),
+            record => unreachable!("Didn't expect to undo {record:?} of transaction {txn}"),
+        };
+
+        // The transaction's `Begin` is its id, so that's where we stop.
+        if next_lsn == txn {
+            wal.append_for(txn, &LogRecord::Abort {
+                txn,
+                prev_lsn: last_lsn,
+            })?;
+        } else {
+            to_undo.insert(next_lsn, (txn, last_lsn));
+        }
+    }
+
+    Ok(())
+}
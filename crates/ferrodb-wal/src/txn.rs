@@ -0,0 +1,220 @@
+use ferrodb_fs::{set_page_lsn, FileId, FileManager, Lsn, PageIndex, PAGE_HEADER_SIZE};
+use parking_lot::Mutex;
+
+use crate::recovery::rollback;
+use crate::{Error, LogRecord, TxnId, Wal};
+
+/// A transaction that logs every change it makes to a page before making it.
+///
+/// A transaction that is dropped without being committed or aborted stays in
+/// the log as running, and gets rolled back by recovery.
+pub struct Txn<'w> {
+    wal: &'w Wal,
+    id: TxnId,
+    /// The LSN of the last record this transaction logged.
+    last_lsn: Mutex<Lsn>,
+    /// While the transaction rolls back, the `LogicalUpdate` being undone,
+    /// which is where undo starts over if a crash stops it partway.
+    undoing: Option<Lsn>,
+    /// How many nested top actions are running, none of whose changes are
+    /// compensations even while rolling back.
+    nested: Mutex<usize>,
+}
+
+impl<'w> Txn<'w> {
+    pub(crate) fn new(wal: &'w Wal, id: TxnId) -> Txn<'w> {
+        Txn {
+            wal,
+            id,
+            last_lsn: Mutex::new(id),
+            undoing: None,
+            nested: Mutex::new(0),
+        }
+    }
+
+    /// The transaction `id` while it rolls back the `LogicalUpdate` at
+    /// `undoing`: every change it makes compensates for that one.
+    pub(crate) fn undoing(wal: &'w Wal, id: TxnId, last_lsn: Lsn, undoing: Lsn) -> Txn<'w> {
+        Txn {
+            wal,
+            id,
+            last_lsn: Mutex::new(last_lsn),
+            undoing: Some(undoing),
+            nested: Mutex::new(0),
+        }
+    }
+
+    pub fn id(&self) -> TxnId {
+        self.id
+    }
+
+    pub fn wal(&self) -> &'w Wal {
+        self.wal
+    }
+
+    pub(crate) fn last_lsn(&self) -> Lsn {
+        *self.last_lsn.lock()
+    }
+
+    /// Change a page, logging the changed bytes and stamping the page with the
+    /// LSN of that log record. `f` only gets to see the part of the page past
+    /// its header.
+    ///
+    /// Rolling back writes the bytes back how they were, which is only right
+    /// for a page that no other transaction changes until this one is over.
+    /// Pages that are shared should be changed with [`Txn::modify_logical`].
+    pub fn modify<T>(
+        &self,
+        files: &FileManager,
+        file: FileId,
+        page: PageIndex,
+        f: impl FnOnce(&mut [u8]) -> T,
+    ) -> Result<T, Error> {
+        let page_ref = files.dirty(file, page)?;
+        let mut buf = page_ref.write();
+        self.modify_latched(files, file, page, &mut buf, f)
+    }
+
+    /// Like [`Txn::modify`], for a page that the caller already holds the write
+    /// latch of, i.e. `buf` is the whole of a page from [`FileManager::dirty`].
+    pub fn modify_latched<T>(
+        &self,
+        files: &FileManager,
+        file: FileId,
+        page: PageIndex,
+        buf: &mut [u8],
+        f: impl FnOnce(&mut [u8]) -> T,
+    ) -> Result<T, Error> {
+        let before = buf[PAGE_HEADER_SIZE..].to_vec();
+        let result = f(&mut buf[PAGE_HEADER_SIZE..]);
+        self.log(files, file, page, buf, &before, None)?;
+        Ok(result)
+    }
+
+    /// Like [`Txn::modify_latched`], for a change that's undone by whatever
+    /// [`Undo`](crate::Undo) is registered for the file's kind, with the
+    /// bytes that `f` returns along with its result. Other transactions can
+    /// go on changing the page in the meantime, which is what undoing it has
+    /// to allow for.
+    pub fn modify_logical<T>(
+        &self,
+        files: &FileManager,
+        file: FileId,
+        page: PageIndex,
+        buf: &mut [u8],
+        f: impl FnOnce(&mut [u8]) -> (T, Vec<u8>),
+    ) -> Result<T, Error> {
+        let before = buf[PAGE_HEADER_SIZE..].to_vec();
+        let (result, undo) = f(&mut buf[PAGE_HEADER_SIZE..]);
+        self.log(files, file, page, buf, &before, Some(undo))?;
+        Ok(result)
+    }
+
+    /// Run `f` as a nested top action: once it's done, its changes stay even
+    /// if the transaction rolls back, which is what a change to the structure
+    /// of an index needs once other transactions might depend on it. If it
+    /// fails, or a crash stops it partway, what it did so far is undone.
+    ///
+    /// Every page that `f` changes has to stay latched until this returns,
+    /// so that nothing else can change them before the action is done.
+    pub fn nested_top_action<T, E: From<Error>>(&self, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        let undo_next = self.last_lsn();
+
+        *self.nested.lock() += 1;
+        let result = f();
+        *self.nested.lock() -= 1;
+
+        let result = result?;
+        if self.last_lsn() != undo_next {
+            let mut last_lsn = self.last_lsn.lock();
+            *last_lsn = self.wal.append_for(self.id, &LogRecord::Skip {
+                txn: self.id,
+                prev_lsn: *last_lsn,
+                undo_next,
+            })?;
+        }
+
+        Ok(result)
+    }
+
+    /// Log the bytes of `buf` that changed from `before`, if any.
+    fn log(
+        &self,
+        files: &FileManager,
+        file: FileId,
+        page: PageIndex,
+        buf: &mut [u8],
+        before: &[u8],
+        undo: Option<Vec<u8>>,
+    ) -> Result<(), Error> {
+        let after = &buf[PAGE_HEADER_SIZE..];
+
+        let Some(start) = before.iter().zip(after).position(|(b, a)| b != a)
+            else { return Ok(()); };
+        let end = before.len()
+            - before
+                .iter()
+                .rev()
+                .zip(after.iter().rev())
+                .position(|(b, a)| b != a)
+                .unwrap();
+
+        let mut last_lsn = self.last_lsn.lock();
+        let (txn, prev_lsn, file) = (self.id, *last_lsn, files.name(file));
+        let offset = PAGE_HEADER_SIZE + start;
+        let after = after[start..end].to_vec();
+
+        let record = match (self.undoing, undo) {
+            (Some(undo_next), _) if *self.nested.lock() == 0 => LogRecord::Compensation {
+                txn,
+                prev_lsn,
+                undo_next,
+                file,
+                page,
+                offset,
+                after,
+            },
+            (_, Some(undo)) => LogRecord::LogicalUpdate {
+                txn,
+                prev_lsn,
+                file,
+                page,
+                offset,
+                after,
+                undo,
+            },
+            (_, None) => LogRecord::Update {
+                txn,
+                prev_lsn,
+                file,
+                page,
+                offset,
+                before: before[start..end].to_vec(),
+                after,
+            },
+        };
+        let lsn = self.wal.append_for(self.id, &record)?;
+        *last_lsn = lsn;
+
+        // Still under the page latch, so page LSNs only ever go up.
+        set_page_lsn(buf, lsn);
+        Ok(())
+    }
+
+    /// Log the commit and wait for it to be durable.
+    pub fn commit(self) -> Result<Lsn, Error> {
+        let lsn = self.wal.append_for(self.id, &LogRecord::Commit {
+            txn: self.id,
+            prev_lsn: *self.last_lsn.lock(),
+        })?;
+
+        self.wal.flush_to(lsn)?;
+        Ok(lsn)
+    }
+
+    /// Undo every change this transaction made, newest first.
+    pub fn abort(self, files: &FileManager) -> Result<Lsn, Error> {
+        let last_lsn = *self.last_lsn.lock();
+        rollback(self.wal, files, self.id, last_lsn)
+    }
+}
@@ -0,0 +1,13 @@
+use ferrodb_fs::{FileId, PageIndex};
+
+use crate::{Error, Txn};
+
+/// Undoes the changes that were logged with [`Txn::modify_logical`] to files of
+/// one kind, registered with [`Wal::set_undo`](crate::Wal::set_undo).
+pub trait Undo: Send + Sync {
+    /// Undo the change to `page` of `file` that was logged with `undo`, making
+    /// the changes that do so through `txn`. If a crash stops this partway, it's
+    /// called again for the same change once the log has been replayed, so it
+    /// has to cope with finding some of its work already done.
+    fn undo(&self, txn: &Txn<'_>, file: FileId, page: PageIndex, undo: &[u8]) -> Result<(), Error>;
+}
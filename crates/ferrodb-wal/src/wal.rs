@@ -0,0 +1,541 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use ferrodb_fs::vfs::{Vfs, VfsFile};
+use ferrodb_fs::{Cipher, EncryptionKey, FileKind, FileManager, Lsn, WriteAheadLog, SEAL_OVERHEAD};
+use parking_lot::Mutex;
+
+use crate::record::{self, RECORD_HEADER_SIZE};
+use crate::{Error, LogReader, LogRecord, Txn, TxnId, Undo};
+
+/// The log is split into segment files of this many bytes. An LSN is a byte
+/// offset into the whole log, so segment `n` holds LSNs
+/// `n * SEGMENT_SIZE..(n + 1) * SEGMENT_SIZE`.
+pub const SEGMENT_SIZE: u64 = 16 << 20;
+
+pub(crate) const SEGMENT_MAGIC: &[u8; 8] = b"FERROWAL";
+/// What the segments of an encrypted log start with instead.
+const SEALED_SEGMENT_MAGIC: &[u8; 8] = b"FERROWAS";
+
+/// What the log's cipher is named, which no data file can be.
+const CIPHER_NAME: &str = "write-ahead log";
+
+/// Holds the LSN of the last complete checkpoint's `CheckpointBegin`.
+const MASTER_RECORD: &str = "master";
+
+#[derive(Clone, Debug, Default)]
+pub struct WalOptions {
+    /// Seal every record with this key before it's written, with its LSN as
+    /// its position, and authenticate it when it's read back.
+    pub encryption_key: Option<EncryptionKey>,
+    /// Leave the log exactly as it is, even a torn record at its end, and
+    /// refuse to append anything to it.
+    pub read_only: bool,
+}
+
+pub struct Wal {
+    vfs: Arc<dyn Vfs>,
+    dir: Utf8PathBuf,
+    cipher: Option<Cipher>,
+    read_only: bool,
+    inner: Mutex<WalInner>,
+    /// Everything before this LSN is durable.
+    flushed: AtomicU64,
+    /// Every running transaction, with the LSN of the last record it logged.
+    active: Mutex<HashMap<TxnId, Lsn>>,
+    /// What undoes logical changes to each kind of file.
+    undo: Mutex<HashMap<FileKind, Arc<dyn Undo>>>,
+}
+
+struct WalInner {
+    /// The segment that `buffer` will be written to.
+    segment: Arc<dyn VfsFile>,
+    /// Older segments that have been written to, but not synced yet.
+    unsynced: Vec<Arc<dyn VfsFile>>,
+    /// Appended records that haven't been written out yet.
+    buffer: Vec<u8>,
+    buffer_start: Lsn,
+}
+
+impl Wal {
+    /// Open the log in `dir`, picking up after the last intact record.
+    pub fn open(vfs: Arc<dyn Vfs>, dir: impl Into<Utf8PathBuf>) -> Result<Wal, Error> {
+        Wal::with_options(vfs, dir, WalOptions::default())
+    }
+
+    pub fn with_options(
+        vfs: Arc<dyn Vfs>,
+        dir: impl Into<Utf8PathBuf>,
+        options: WalOptions,
+    ) -> Result<Wal, Error> {
+        let dir = dir.into();
+        let cipher = options
+            .encryption_key
+            .map(|key| Cipher::new(&key, CIPHER_NAME));
+        let magic = segment_magic(cipher.is_some());
+
+        let mut segment_no = 0;
+        while let Some(next) = open_existing(&*vfs, &segment_path(&dir, segment_no + 1))? {
+            if next.is_empty()? {
+                break;
+            }
+            segment_no += 1;
+        }
+
+        let path = segment_path(&dir, segment_no);
+        let segment: Arc<dyn VfsFile> = if options.read_only {
+            // There's no log to read, and starting one would be a write.
+            let Some(segment) = open_existing(&*vfs, &path)?
+                else { return Err(Error::ReadOnly); };
+            segment.into()
+        } else {
+            vfs.open(&path)?.into()
+        };
+        let segment_start = segment_no * SEGMENT_SIZE;
+        let mut buffer = vec![];
+
+        let buffer_start = if segment.is_empty()? {
+            buffer.extend_from_slice(magic);
+            segment_start
+        } else {
+            let bytes = read_segment(&*segment, &path, magic)?;
+
+            let mut offset = SEGMENT_MAGIC.len();
+            while let Some((_, len)) = record::unframe(&bytes, offset) {
+                offset += len;
+            }
+
+            // Anything after the last intact record is a torn write, and we don't want
+            // to ever mistake it for a real record again. Nothing is appended to a
+            // read-only log, so there it can just be ignored.
+            if !options.read_only {
+                segment.truncate(offset as u64)?;
+                segment.sync()?;
+            }
+            segment_start + offset as u64
+        };
+
+        Ok(Wal {
+            vfs,
+            dir,
+            cipher,
+            read_only: options.read_only,
+            inner: Mutex::new(WalInner {
+                segment,
+                unsynced: vec![],
+                buffer,
+                buffer_start,
+            }),
+            flushed: AtomicU64::new(buffer_start),
+            active: Mutex::default(),
+            undo: Mutex::default(),
+        })
+    }
+
+    /// Buffer `record` to be written with the next flush, returning its LSN.
+    pub fn append(&self, record: &LogRecord) -> Result<Lsn, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let payload = record.encode()?;
+        let len = self.framed_len(&payload)?;
+
+        let mut inner = self.inner.lock();
+
+        if (inner.next_lsn() % SEGMENT_SIZE) + len as u64 > SEGMENT_SIZE {
+            self.next_segment(&mut inner)?;
+        }
+
+        let lsn = inner.next_lsn();
+        let bytes = self.frame(lsn, &payload);
+        inner.buffer.extend_from_slice(&bytes);
+        Ok(lsn)
+    }
+
+    /// Buffer many records at once, so that they end up next to each other in
+    /// the log.
+    pub fn append_batch<'r>(
+        &self,
+        records: impl IntoIterator<Item = &'r LogRecord>,
+    ) -> Result<Vec<Lsn>, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+
+        let payloads: Vec<_> = records
+            .into_iter()
+            .map(|record| record.encode())
+            .collect::<Result<_, _>>()?;
+
+        let mut inner = self.inner.lock();
+        let mut lsns = Vec::with_capacity(payloads.len());
+
+        for payload in payloads {
+            let len = self.framed_len(&payload)?;
+
+            if (inner.next_lsn() % SEGMENT_SIZE) + len as u64 > SEGMENT_SIZE {
+                self.next_segment(&mut inner)?;
+            }
+
+            let lsn = inner.next_lsn();
+            let bytes = self.frame(lsn, &payload);
+            inner.buffer.extend_from_slice(&bytes);
+            lsns.push(lsn);
+        }
+
+        Ok(lsns)
+    }
+
+    /// How many bytes a record with this payload takes up in the log, which
+    /// has to fit in a segment.
+    fn framed_len(&self, payload: &[u8]) -> Result<usize, Error> {
+        let overhead = if self.cipher.is_some() { SEAL_OVERHEAD } else { 0 };
+        let len = RECORD_HEADER_SIZE + payload.len() + overhead;
+
+        if (len + SEGMENT_MAGIC.len()) as u64 > SEGMENT_SIZE {
+            return Err(Error::RecordTooLarge(len));
+        }
+
+        Ok(len)
+    }
+
+    /// Frame a record's payload to go at `lsn`, sealing it first if the log
+    /// is encrypted.
+    fn frame(&self, lsn: Lsn, payload: &[u8]) -> Vec<u8> {
+        match &self.cipher {
+            Some(cipher) => record::frame(&cipher.seal(lsn, payload)),
+            None => record::frame(payload),
+        }
+    }
+
+    /// The record framed at `offset` of `bytes`, which is at `lsn`, along with
+    /// its framed size, or `None` if there's no intact record there.
+    pub(crate) fn decode(
+        &self,
+        bytes: &[u8],
+        offset: usize,
+        lsn: Lsn,
+    ) -> Result<Option<(LogRecord, usize)>, Error> {
+        let Some((payload, len)) = record::unframe(bytes, offset)
+            else { return Ok(None); };
+
+        let record = match &self.cipher {
+            Some(cipher) => {
+                let Some(payload) = cipher.open(lsn, payload)
+                    else { return Err(Error::Undecryptable(lsn)); };
+                LogRecord::decode(&payload)?
+            },
+            None => LogRecord::decode(payload)?,
+        };
+
+        Ok(Some((record, len)))
+    }
+
+    /// Write out the current segment's buffer and start on the next segment.
+    fn next_segment(&self, inner: &mut WalInner) -> Result<(), Error> {
+        inner
+            .segment
+            .write_at(&inner.buffer, inner.buffer_start % SEGMENT_SIZE)?;
+
+        let next_segment_no = inner.buffer_start / SEGMENT_SIZE + 1;
+        let next: Arc<dyn VfsFile> = self
+            .vfs
+            .open(&segment_path(&self.dir, next_segment_no))?
+            .into();
+
+        let previous = std::mem::replace(&mut inner.segment, next);
+        inner.unsynced.push(previous);
+        inner.buffer.clear();
+        inner
+            .buffer
+            .extend_from_slice(segment_magic(self.cipher.is_some()));
+        inner.buffer_start = next_segment_no * SEGMENT_SIZE;
+
+        Ok(())
+    }
+
+    /// Make sure the record at `lsn` is durable. Everything else that has been
+    /// appended so far gets flushed along with it, so concurrent committers
+    /// share a single fsync.
+    pub fn flush_to(&self, lsn: Lsn) -> Result<Lsn, Error> {
+        let flushed = self.flushed_lsn();
+        if flushed > lsn {
+            return Ok(flushed);
+        }
+
+        let mut inner = self.inner.lock();
+
+        // Someone might have flushed for us while we were waiting for the lock.
+        let flushed = self.flushed_lsn();
+        if flushed > lsn {
+            return Ok(flushed);
+        }
+
+        // Older segments have to be durable first, so that a crash can never
+        // leave a hole in the middle of the log.
+        for segment in inner.unsynced.drain(..) {
+            segment.sync()?;
+        }
+
+        inner
+            .segment
+            .write_at(&inner.buffer, inner.buffer_start % SEGMENT_SIZE)?;
+        inner.segment.sync()?;
+
+        inner.buffer_start = inner.next_lsn();
+        inner.buffer.clear();
+        self.flushed.store(inner.buffer_start, Ordering::SeqCst);
+
+        Ok(inner.buffer_start)
+    }
+
+    /// Flush everything appended so far.
+    pub fn flush(&self) -> Result<Lsn, Error> {
+        let lsn = self.inner.lock().next_lsn();
+        self.flush_to(lsn.saturating_sub(1))
+    }
+
+    /// Everything before this LSN is durable.
+    pub fn flushed_lsn(&self) -> Lsn {
+        self.flushed.load(Ordering::SeqCst)
+    }
+
+    /// The LSN that the next appended record will get, unless it has to go in
+    /// a new segment.
+    pub fn next_lsn(&self) -> Lsn {
+        self.inner.lock().next_lsn()
+    }
+
+    pub fn begin(&self) -> Result<Txn<'_>, Error> {
+        let lsn = self.append(&LogRecord::Begin)?;
+        self.active.lock().insert(lsn, lsn);
+        Ok(Txn::new(self, lsn))
+    }
+
+    /// Whether `txn` hasn't committed or rolled back yet.
+    pub fn is_running(&self, txn: TxnId) -> bool {
+        self.active.lock().contains_key(&txn)
+    }
+
+    /// Have `undo` undo the changes to `kind` files that were logged with
+    /// [`Txn::modify_logical`]. Recovery needs this too, so it has to be set
+    /// up before that runs.
+    pub fn set_undo(&self, kind: FileKind, undo: Arc<dyn Undo>) {
+        self.undo.lock().insert(kind, undo);
+    }
+
+    pub(crate) fn undo_for(&self, kind: FileKind) -> Result<Arc<dyn Undo>, Error> {
+        self.undo.lock().get(&kind).cloned().ok_or(Error::NoUndo(kind))
+    }
+
+    /// Append a record on behalf of a running transaction.
+    pub(crate) fn append_for(&self, txn: TxnId, record: &LogRecord) -> Result<Lsn, Error> {
+        let mut active = self.active.lock();
+        let lsn = self.append(record)?;
+
+        match record {
+            LogRecord::Commit { .. } | LogRecord::Abort { .. } => {
+                active.remove(&txn);
+            },
+            _ => {
+                active.insert(txn, lsn);
+            },
+        }
+
+        Ok(lsn)
+    }
+
+    /// Read the durable log, starting at `lsn`.
+    pub fn reader(&self, lsn: Lsn) -> LogReader<'_> {
+        LogReader::new(self, lsn)
+    }
+
+    /// Read the single record at `lsn`, which may not have been flushed yet.
+    pub fn read(&self, lsn: Lsn) -> Result<LogRecord, Error> {
+        let inner = self.inner.lock();
+
+        if lsn >= inner.buffer_start {
+            let offset = (lsn - inner.buffer_start) as usize;
+            return self
+                .decode(&inner.buffer, offset, lsn)?
+                .map(|(record, _)| record)
+                .ok_or(Error::NoRecord(lsn));
+        }
+
+        drop(inner);
+
+        let segment = self
+            .vfs
+            .open(&segment_path(&self.dir, lsn / SEGMENT_SIZE))?;
+        let offset = lsn % SEGMENT_SIZE;
+
+        let mut header = [0; RECORD_HEADER_SIZE];
+        segment.read_at(&mut header, offset)?;
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+
+        let mut bytes = vec![0; RECORD_HEADER_SIZE + len];
+        segment.read_at(&mut bytes, offset)?;
+
+        self.decode(&bytes, 0, lsn)?
+            .map(|(record, _)| record)
+            .ok_or(Error::NoRecord(lsn))
+    }
+
+    /// Write back every dirty page, so that recovery never has to look at the
+    /// log before this point for anything except undoing transactions that
+    /// are still running. Returns the LSN recovery will start from.
+    pub fn checkpoint(&self, files: &FileManager) -> Result<Lsn, Error> {
+        let begin = self.append(&LogRecord::CheckpointBegin)?;
+
+        files.sync_all()?;
+
+        // The lock is held until `CheckpointEnd` is in the log, the same way that
+        // `append_for` holds it for `Commit`, so that no transaction can commit
+        // before the record but be listed in it as still running.
+        let active = self.active.lock();
+        let running = active.iter().map(|(t, l)| (*t, *l)).collect();
+        let end = self.append(&LogRecord::CheckpointEnd { begin, active: running })?;
+        drop(active);
+        self.flush_to(end)?;
+
+        write_master(&*self.vfs, &self.dir, begin)?;
+
+        Ok(begin)
+    }
+
+    /// Copy the log into `target`, up to everything that's been appended so
+    /// far, and make `checkpoint` the checkpoint that recovery starts from
+    /// there. Returns the LSN that the copy ends at.
+    pub fn backup_to(&self, target: &dyn Vfs, checkpoint: Lsn) -> Result<Lsn, Error> {
+        let end = self.flush()?;
+
+        // The whole log is copied, rather than just from the checkpoint, since
+        // undo might need to go back further and `Wal::open` expects the
+        // segments to start from zero.
+        for segment_no in 0..=end / SEGMENT_SIZE {
+            let path = segment_path(&self.dir, segment_no);
+            let source = self.vfs.open(&path)?;
+
+            // Records are being appended to the last segment as we copy it, but
+            // only the ones before `end` are durable.
+            let len = if segment_no == end / SEGMENT_SIZE {
+                end % SEGMENT_SIZE
+            } else {
+                source.len()?
+            };
+
+            let mut bytes = vec![0; len as usize];
+            source.read_at(&mut bytes, 0)?;
+
+            let copy = target.open(&path)?;
+            copy.truncate(0)?;
+            copy.write_at(&bytes, 0)?;
+            copy.sync()?;
+        }
+
+        write_master(target, &self.dir, checkpoint)?;
+
+        Ok(end)
+    }
+
+    /// The `CheckpointBegin` LSN of the last checkpoint that completed, if any.
+    pub fn last_checkpoint(&self) -> Result<Option<Lsn>, Error> {
+        let Some(master) = open_existing(&*self.vfs, &self.dir.join(MASTER_RECORD))?
+            else { return Ok(None); };
+
+        let mut bytes = [0; 12];
+        master.read_at(&mut bytes, 0)?;
+
+        let lsn = Lsn::from_le_bytes(bytes[..8].try_into().unwrap());
+        let crc = u32::from_le_bytes(bytes[8..].try_into().unwrap());
+
+        // A torn master record is as good as no checkpoint at all: recovery just
+        // has to start from the beginning of the log.
+        if lsn == 0 || crc32fast::hash(&bytes[..8]) != crc {
+            return Ok(None);
+        }
+
+        Ok(Some(lsn))
+    }
+
+    pub(crate) fn segment(&self, segment_no: u64) -> Result<Option<Vec<u8>>, Error> {
+        let path = segment_path(&self.dir, segment_no);
+        let Some(segment) = open_existing(&*self.vfs, &path)?
+            else { return Ok(None); };
+
+        if segment.is_empty()? {
+            return Ok(None);
+        }
+
+        let magic = segment_magic(self.cipher.is_some());
+        Ok(Some(read_segment(&*segment, &path, magic)?))
+    }
+}
+
+impl WalInner {
+    fn next_lsn(&self) -> Lsn {
+        self.buffer_start + self.buffer.len() as u64
+    }
+}
+
+impl WriteAheadLog for Wal {
+    fn flush_to(&self, lsn: Lsn) -> io::Result<Lsn> {
+        Wal::flush_to(self, lsn).map_err(|e| match e {
+            Error::Io(e) => e,
+            e => io::Error::other(e),
+        })
+    }
+}
+
+fn write_master(vfs: &dyn Vfs, dir: &Utf8Path, checkpoint: Lsn) -> Result<(), Error> {
+    let master = vfs.open(&dir.join(MASTER_RECORD))?;
+    let mut bytes = [0; 12];
+    bytes[..8].copy_from_slice(&checkpoint.to_le_bytes());
+    let crc = crc32fast::hash(&bytes[..8]);
+    bytes[8..].copy_from_slice(&crc.to_le_bytes());
+    master.write_at(&bytes, 0)?;
+    master.sync()?;
+    Ok(())
+}
+
+/// Open the file at `path` if there is one, rather than creating it, so that
+/// just reading the log never leaves empty files behind.
+fn open_existing(vfs: &dyn Vfs, path: &Utf8Path) -> Result<Option<Box<dyn VfsFile>>, Error> {
+    if !vfs.exists(path)? {
+        return Ok(None);
+    }
+
+    Ok(Some(vfs.open(path)?))
+}
+
+fn segment_path(dir: &Utf8Path, segment_no: u64) -> Utf8PathBuf {
+    dir.join(format!("{segment_no:016x}.wal"))
+}
+
+fn segment_magic(sealed: bool) -> &'static [u8; 8] {
+    if sealed {
+        SEALED_SEGMENT_MAGIC
+    } else {
+        SEGMENT_MAGIC
+    }
+}
+
+/// Read a whole segment, which has to start with `magic`.
+fn read_segment(segment: &dyn VfsFile, path: &Utf8Path, magic: &[u8; 8]) -> Result<Vec<u8>, Error> {
+    let mut bytes = vec![0; segment.len()? as usize];
+    segment.read_at(&mut bytes, 0)?;
+
+    if bytes.starts_with(magic) {
+        Ok(bytes)
+    } else if bytes.starts_with(SEALED_SEGMENT_MAGIC) {
+        Err(Error::MissingKey(path.to_string()))
+    } else if bytes.starts_with(SEGMENT_MAGIC) {
+        Err(Error::UnexpectedKey(path.to_string()))
+    } else {
+        Err(Error::NotASegment(path.to_string()))
+    }
+}
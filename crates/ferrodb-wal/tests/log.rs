@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use ferrodb_fs::vfs::{CrashMode, CrashVfs, MemoryVfs, Vfs};
+use ferrodb_fs::{FileKind, FileManager, Lsn};
+use ferrodb_wal::{LogRecord, Wal, SEGMENT_SIZE};
+
+fn write(n: u32, len: usize) -> LogRecord {
+    LogRecord::LsmWrite {
+        tree: "t".to_owned(),
+        key: n.to_be_bytes().to_vec(),
+        value: Some(vec![0; len]),
+    }
+}
+
+/// The key of every record from the start of the log, with its LSN.
+fn read(wal: &Wal) -> Vec<(Lsn, u32)> {
+    wal.reader(0)
+        .map(|record| match record.unwrap() {
+            (lsn, LogRecord::LsmWrite { key, .. }) => (lsn, u32::from_be_bytes(key.try_into().unwrap())),
+            (_, record) => panic!("unexpected record {record:?}"),
+        })
+        .collect()
+}
+
+#[test]
+fn records_read_back_in_order_across_segments() {
+    let vfs: Arc<dyn Vfs> = Arc::new(MemoryVfs::default());
+    let wal = Wal::open(vfs.clone(), "wal").unwrap();
+
+    // Big enough that they don't all fit in one segment, some one at a time
+    // and some in batches.
+    let mut lsns = vec![];
+    for n in 0..10 {
+        lsns.push(wal.append(&write(n, 1 << 20)).unwrap());
+    }
+    let batch: Vec<_> = (10..20).map(|n| write(n, 1 << 20)).collect();
+    lsns.extend(wal.append_batch(&batch).unwrap());
+    wal.flush().unwrap();
+
+    assert!(lsns.windows(2).all(|lsns| lsns[0] < lsns[1]));
+    assert!(*lsns.last().unwrap() >= SEGMENT_SIZE);
+    let expected: Vec<_> = lsns.iter().copied().zip(0..).collect();
+    assert_eq!(read(&wal), expected);
+
+    // Reopening picks up after the last record, in the last segment.
+    let next = wal.next_lsn();
+    drop(wal);
+    let wal = Wal::open(vfs, "wal").unwrap();
+    assert_eq!(wal.next_lsn(), next);
+    assert_eq!(read(&wal), expected);
+
+    let lsn = wal.append(&write(20, 10)).unwrap();
+    assert_eq!(lsn, next);
+    wal.flush().unwrap();
+    assert_eq!(read(&wal).last(), Some(&(lsn, 20)));
+}
+
+#[test]
+fn only_flushed_records_survive_a_crash() {
+    let vfs = Arc::new(CrashVfs::default());
+    let wal = Wal::open(vfs.clone(), "wal").unwrap();
+
+    let flushed: Vec<_> = (0..10).map(|n| (wal.append(&write(n, 100)).unwrap(), n)).collect();
+    wal.flush().unwrap();
+    assert_eq!(wal.flushed_lsn(), wal.next_lsn());
+    for n in 10..20 {
+        wal.append(&write(n, 100)).unwrap();
+    }
+    assert!(wal.flushed_lsn() < wal.next_lsn());
+
+    let crashed: Arc<dyn Vfs> = Arc::new(vfs.crash(CrashMode::DropUnsynced));
+    let wal = Wal::open(crashed.clone(), "wal").unwrap();
+    assert_eq!(read(&wal), flushed);
+
+    // What comes after goes where the lost records were.
+    let lsn = wal.append(&write(20, 100)).unwrap();
+    assert_eq!(lsn, flushed.last().unwrap().0 + (flushed[1].0 - flushed[0].0));
+    wal.flush().unwrap();
+    drop(wal);
+    let wal = Wal::open(crashed, "wal").unwrap();
+    assert_eq!(read(&wal).last(), Some(&(lsn, 20)));
+}
+
+#[test]
+fn pages_are_not_written_back_before_their_changes_are_logged() {
+    ferrodb_page::setup(256);
+
+    let vfs = Arc::new(CrashVfs::default());
+    let files = FileManager::new(vfs.clone()).unwrap();
+    let wal = Arc::new(Wal::open(vfs.clone(), "wal").unwrap());
+    files.set_log(wal.clone());
+    let file = files.open("t", FileKind::Generic).unwrap();
+
+    let txn = wal.begin().unwrap();
+    let lsn = wal.next_lsn();
+    txn.modify(&files, file, 1, |body| body[..5].copy_from_slice(b"hello")).unwrap();
+    assert!(wal.flushed_lsn() <= lsn);
+
+    files.sync_all().unwrap();
+    assert!(wal.flushed_lsn() > lsn);
+
+    // So the change is in the log of any crash that has it on disk.
+    let crashed: Arc<dyn Vfs> = Arc::new(vfs.crash(CrashMode::DropUnsynced));
+    let wal = Wal::open(crashed, "wal").unwrap();
+    let logged = wal.reader(0).map(|record| record.unwrap());
+    assert!(logged.into_iter().any(|(at, record)| at == lsn && matches!(record, LogRecord::Update { .. })));
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use ferrodb_fs::vfs::{CrashMode, CrashVfs, Vfs};
+use ferrodb_fs::{FileId, FileKind, FileManager, PageIndex, PAGE_HEADER_SIZE};
+use ferrodb_wal::{recover, Error, Txn, Undo, Wal};
+
+const PAGE_SIZE: usize = 256;
+
+/// A page that's a list of bytes, `[len: u8][items]`, that every transaction
+/// adds to the end of, so they all change the same bytes at the start.
+struct List(Arc<FileManager>);
+
+impl Undo for List {
+    fn undo(&self, txn: &Txn<'_>, file: FileId, page: PageIndex, undo: &[u8]) -> Result<(), Error> {
+        txn.modify(&self.0, file, page, |body| {
+            let len = body[0] as usize;
+            // It's not there anymore if a crash stopped this after it was taken out.
+            if let Some(index) = body[1..=len].iter().position(|item| *item == undo[0]) {
+                body.copy_within(index + 2..=len, index + 1);
+                body[len] = 0;
+                body[0] -= 1;
+            }
+        })
+    }
+}
+
+fn open(vfs: Arc<dyn Vfs>) -> (Arc<Wal>, Arc<FileManager>) {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let files = Arc::new(FileManager::new(vfs.clone()).unwrap());
+    let wal = Arc::new(Wal::open(vfs, "wal").unwrap());
+    files.set_log(wal.clone());
+    wal.set_undo(FileKind::Generic, Arc::new(List(files.clone())));
+
+    recover(&wal, &files, |_, _| {}).unwrap();
+    (wal, files)
+}
+
+fn push(txn: &Txn<'_>, files: &FileManager, item: u8) {
+    let file = files.open("list", FileKind::Generic).unwrap();
+    let page_ref = files.dirty(file, 1).unwrap();
+    let mut buf = page_ref.write();
+
+    txn.modify_logical(files, file, 1, &mut buf, |body| {
+        body[0] += 1;
+        body[body[0] as usize] = item;
+        ((), vec![item])
+    })
+    .unwrap();
+}
+
+fn list(files: &FileManager) -> Vec<u8> {
+    let page_ref = files.latest(files.id("list"), 1).unwrap();
+    let body = &page_ref.read()[PAGE_HEADER_SIZE..];
+    body[1..=body[0] as usize].to_vec()
+}
+
+#[test]
+fn rolling_back_leaves_other_transactions_changes_to_the_page_alone() {
+    let (wal, files) = open(Arc::new(CrashVfs::default()));
+
+    let aborted = wal.begin().unwrap();
+    push(&aborted, &files, b'a');
+    let committed = wal.begin().unwrap();
+    push(&committed, &files, b'b');
+    push(&aborted, &files, b'c');
+    committed.commit().unwrap();
+
+    aborted.abort(&files).unwrap();
+    assert_eq!(list(&files), b"b");
+}
+
+#[test]
+fn recovery_rolls_back_logically_after_any_crash() {
+    let vfs = Arc::new(CrashVfs::default());
+    let (wal, files) = open(vfs.clone());
+
+    let running = wal.begin().unwrap();
+    push(&running, &files, b'a');
+    let committed = wal.begin().unwrap();
+    push(&committed, &files, b'b');
+    committed.commit().unwrap();
+    push(&running, &files, b'c');
+    files.sync_all().unwrap();
+
+    // Recovery rolls back `running`, which can crash partway too.
+    let crashed = Arc::new(vfs.crash(CrashMode::DropUnsynced));
+    let (_wal, files) = open(crashed.clone());
+    assert_eq!(list(&files), b"b");
+    drop(files);
+
+    for events in 0..=crashed.events().len() {
+        let (_wal, files) = open(Arc::new(crashed.crash_at(events, CrashMode::DropUnsynced)));
+        assert_eq!(list(&files), b"b", "Crashed {events} events into recovery");
+    }
+}
+
+#[test]
+fn nested_top_actions_stay_once_theyre_done() {
+    let (wal, files) = open(Arc::new(CrashVfs::default()));
+    let file = files.open("t", FileKind::Generic).unwrap();
+
+    let txn = wal.begin().unwrap();
+    txn.modify(&files, file, 1, |body| body[0] = 1).unwrap();
+    txn.nested_top_action(|| txn.modify(&files, file, 2, |body| body[0] = 2)).unwrap();
+    let failed: Result<(), Error> = txn.nested_top_action(|| {
+        txn.modify(&files, file, 3, |body| body[0] = 3)?;
+        Err(Error::ReadOnly)
+    });
+    assert!(failed.is_err());
+    txn.abort(&files).unwrap();
+
+    let first_byte = |page| files.latest(file, page).unwrap().read()[PAGE_HEADER_SIZE];
+    assert_eq!([first_byte(1), first_byte(2), first_byte(3)], [0, 2, 0]);
+}
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use ferrodb_fs::vfs::{CrashMode, CrashVfs, Vfs};
+use ferrodb_fs::{EncryptionKey, FileKind, FileManager, FileManagerOptions, PAGE_HEADER_SIZE};
+use ferrodb_wal::{recover, Error, Wal, WalOptions};
+
+const PAGE_SIZE: usize = 256;
+
+fn open(vfs: Arc<dyn Vfs>, key: Option<EncryptionKey>) -> Result<(Arc<Wal>, Arc<FileManager>), Error> {
+    ferrodb_page::setup(PAGE_SIZE);
+
+    let files = Arc::new(FileManager::with_options(vfs.clone(), FileManagerOptions {
+        double_write: true,
+        encryption_key: key.clone(),
+        segment_size: 4 * PAGE_SIZE as u64,
+        ..FileManagerOptions::default()
+    })?);
+    let wal = Arc::new(Wal::with_options(vfs, "wal", WalOptions {
+        encryption_key: key,
+        ..WalOptions::default()
+    })?);
+    files.set_log(wal.clone());
+
+    files.repair_torn_pages()?;
+    recover(&wal, &files, |_, _| {})?;
+    Ok((wal, files))
+}
+
+/// Run three transactions against a fresh database: one that commits, one
+/// that's still running at the crash, and one that aborts. Returns how many
+/// events it took for the first one's commit to be durable.
+fn run_workload(vfs: &Arc<CrashVfs>, key: Option<EncryptionKey>) -> usize {
+    let (wal, files) = open(vfs.clone(), key).unwrap();
+    let file = files.open("t", FileKind::Generic).unwrap();
+
+    let committed = wal.begin().unwrap();
+    committed.modify(&files, file, 1, |body| body[0..5].copy_from_slice(b"hello")).unwrap();
+    committed.modify(&files, file, 1, |body| body[100..105].copy_from_slice(b"hello")).unwrap();
+    committed.commit().unwrap();
+    let durable = vfs.events().len();
+
+    files.sync_all().unwrap();
+
+    // Written back before the crash, so undo has to take it back out.
+    let running = wal.begin().unwrap();
+    running.modify(&files, file, 1, |body| body[0..5].copy_from_slice(b"WORLD")).unwrap();
+    running.modify(&files, file, 1, |body| body[200..205].copy_from_slice(b"WORLD")).unwrap();
+    running.modify(&files, file, 2, |body| body[0..3].copy_from_slice(b"abc")).unwrap();
+    files.sync_all().unwrap();
+
+    let aborted = wal.begin().unwrap();
+    aborted.modify(&files, file, 3, |body| body[0..3].copy_from_slice(b"xyz")).unwrap();
+    aborted.abort(&files).unwrap();
+
+    wal.checkpoint(&files).unwrap();
+
+    // The process dies with `running` still running: it never commits or
+    // aborts, so only recovery can roll it back.
+    durable
+}
+
+/// Check that only the committed transaction's changes are there, if they've
+/// been made durable.
+fn check(files: &FileManager, durable: bool, context: &str) {
+    let file = files.id("t");
+    let page = |page| files.latest(file, page).unwrap().read()[PAGE_HEADER_SIZE..].to_vec();
+    let (one, two, three) = (page(1), page(2), page(3));
+
+    match &one[0..5] {
+        b"hello" => {},
+        [0, 0, 0, 0, 0] if !durable => {},
+        other => panic!("{context}: page 1 starts with {other:?}"),
+    }
+    assert_eq!(one[0..5], one[100..105], "{context}: half of a transaction survived");
+    assert_eq!(one[200..205], [0; 5], "{context}: a transaction that never finished survived");
+    assert_eq!(two[0..3], [0; 3], "{context}: a transaction that never finished survived");
+    assert_eq!(three[0..3], [0; 3], "{context}: an aborted transaction survived");
+}
+
+fn crash_modes() -> Vec<CrashMode> {
+    let mut modes = vec![CrashMode::DropUnsynced];
+    for seed in 0..3 {
+        modes.push(CrashMode::TornWrite {
+            sector_size: 64,
+            seed,
+        });
+        modes.push(CrashMode::Reorder { seed });
+    }
+    modes
+}
+
+#[test]
+fn recovers_committed_transactions_after_any_crash() {
+    let vfs = Arc::new(CrashVfs::default());
+    let durable = run_workload(&vfs, None);
+
+    for events in 0..=vfs.events().len() {
+        for mode in crash_modes() {
+            let context = format!("crash after {events} events with {mode:?}");
+            let (_wal, files) = open(Arc::new(vfs.crash_at(events, mode)), None)
+                .unwrap_or_else(|e| panic!("{context}: {e}"));
+
+            check(&files, events >= durable, &context);
+        }
+    }
+}
+
+#[test]
+fn recovers_after_crashing_during_recovery() {
+    let vfs = Arc::new(CrashVfs::default());
+    run_workload(&vfs, None);
+
+    // Recovery has to roll back the transaction that was still running, so it
+    // has plenty to crash in the middle of.
+    let crashed = Arc::new(vfs.crash(CrashMode::DropUnsynced));
+    drop(open(crashed.clone(), None).unwrap());
+
+    for events in 0..=crashed.events().len() {
+        for mode in crash_modes() {
+            let context = format!("crash {events} events into recovery with {mode:?}");
+            let (_wal, files) = open(Arc::new(crashed.crash_at(events, mode)), None)
+                .unwrap_or_else(|e| panic!("{context}: {e}"));
+
+            check(&files, true, &context);
+        }
+    }
+}
+
+#[test]
+fn recovers_an_encrypted_database() {
+    let key = EncryptionKey::new([7; 32]);
+    let vfs = Arc::new(CrashVfs::default());
+    let durable = run_workload(&vfs, Some(key.clone()));
+
+    for events in [durable, vfs.events().len()] {
+        let crashed: Arc<dyn Vfs> = Arc::new(vfs.crash_at(events, CrashMode::DropUnsynced));
+        let (_wal, files) = open(crashed.clone(), Some(key.clone())).unwrap();
+        check(&files, true, &format!("crash after {events} events"));
+        drop(files);
+
+        // Without the key, the log can't even be read.
+        assert!(matches!(open(crashed, None), Err(Error::Fs(_) | Error::MissingKey(_))));
+    }
+}
@@ -1,9 +1,13 @@
 use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use ferrodb_client::spawn_client;
+use ferrodb_fs::vfs::{DiskVfs, MemoryVfs, Vfs};
+use ferrodb_fs::EncryptionKey;
 use ferrodb_protocol::{Transport, DEFAULT_PORT};
-use ferrodb_server::{spawn_server_loop, spawn_server_standalone};
+use ferrodb_server::{spawn_autovacuum, spawn_server_loop, spawn_server_standalone, Database};
 use ferrodb_util::read_write;
 use structopt::StructOpt;
 
@@ -28,6 +32,20 @@ enum Args {
         #[structopt(short, long, default_value = DEFAULT_PORT)]
         /// Port that the server should listen on
         port: u16,
+        #[structopt(short, long, default_value = ".")]
+        /// Directory that the database lives in
+        data_dir: String,
+        #[structopt(short, long)]
+        /// File holding the key to encrypt data files with. Otherwise, the key
+        /// is read from `FERRODB_KEY` if that's set.
+        key_file: Option<String>,
+        #[structopt(long, default_value = "60")]
+        /// Seconds between autovacuum runs, or 0 to turn autovacuum off
+        autovacuum_interval: u64,
+        #[structopt(long)]
+        /// Open the database without writing to it (which also turns
+        /// autovacuum off), so other read-only users can open it too
+        read_only: bool,
     },
     /// Run ferrodb in standalone mode, which will launch a client and server
     /// together.
@@ -36,6 +54,17 @@ enum Args {
         /// Serialization format of messages passed between server and client.
         /// Currently supported: `json`, `bincode`, or `ron`.
         transport: Transport,
+        #[structopt(short, long)]
+        /// Directory that the database lives in. If not given, the database
+        /// only lives in memory.
+        data_dir: Option<String>,
+        #[structopt(short, long)]
+        /// File holding the key to encrypt data files with. Otherwise, the key
+        /// is read from `FERRODB_KEY` if that's set.
+        key_file: Option<String>,
+        #[structopt(long, default_value = "60")]
+        /// Seconds between autovacuum runs, or 0 to turn autovacuum off
+        autovacuum_interval: u64,
     },
 }
 
@@ -79,14 +108,43 @@ fn go() -> Result<()> {
             let client = spawn_client(conn, transport);
             client.join().expect("Client panicked")?;
         },
-        Args::Server { port } => {
-            let server = spawn_server_loop(port);
+        Args::Server {
+            port,
+            data_dir,
+            key_file,
+            autovacuum_interval,
+            read_only,
+        } => {
+            let key = encryption_key(key_file)?;
+            let vfs = Arc::new(DiskVfs::new(data_dir));
+
+            let db = if read_only {
+                Arc::new(Database::open_read_only(vfs, key)?)
+            } else {
+                let db = Arc::new(Database::open(vfs, key)?);
+                start_autovacuum(&db, autovacuum_interval);
+                db
+            };
+
+            let server = spawn_server_loop(port, db);
             server.join().expect("Server panicked")?;
         },
-        Args::Standalone { transport } => {
+        Args::Standalone {
+            transport,
+            data_dir,
+            key_file,
+            autovacuum_interval,
+        } => {
+            let vfs: Arc<dyn Vfs> = match data_dir {
+                Some(data_dir) => Arc::new(DiskVfs::new(data_dir)),
+                None => Arc::new(MemoryVfs::default()),
+            };
+            let db = Arc::new(Database::open(vfs, encryption_key(key_file)?)?);
+            start_autovacuum(&db, autovacuum_interval);
+
             let (conn1, conn2) = read_write();
 
-            let server = spawn_server_standalone(conn2);
+            let server = spawn_server_standalone(conn2, db);
             let client = spawn_client(conn1, transport);
 
             server.join().expect("Server panicked")?;
@@ -96,3 +154,18 @@ fn go() -> Result<()> {
 
     Ok(())
 }
+
+fn start_autovacuum(db: &Arc<Database>, interval: u64) {
+    if interval > 0 {
+        spawn_autovacuum(db.clone(), Duration::from_secs(interval));
+    }
+}
+
+fn encryption_key(key_file: Option<String>) -> Result<Option<EncryptionKey>> {
+    let key = match key_file {
+        Some(key_file) => Some(EncryptionKey::from_file(key_file.as_ref())?),
+        None => EncryptionKey::from_env()?,
+    };
+
+    Ok(key)
+}